@@ -0,0 +1,72 @@
+//! Drives the renderer from an event loop owned by the application instead of `voxel_gi::run`.
+//! Run from the repository root so the assets in `client` are found.
+
+#[cfg(not(target_arch = "wasm32"))]
+fn main() {
+    use instant::Instant;
+    use voxel_gi::{RecreateError, State};
+    use winit::{
+        event::{Event, WindowEvent},
+        event_loop::{ControlFlow, EventLoop},
+        window::WindowBuilder,
+    };
+
+    voxel_gi::init();
+    let event_loop = EventLoop::new();
+    let window = WindowBuilder::new()
+        .with_title("Embedded voxel GI")
+        .with_inner_size(winit::dpi::PhysicalSize::new(1920, 1080))
+        .build(&event_loop)
+        .expect("Failed to create window");
+    let state = pollster::block_on(State::new(window))
+        .unwrap_or_else(|error| panic!("Can't load the scene: {}", error));
+    let mut state = Some(state);
+    let mut last_update = Instant::now();
+
+    event_loop.run(move |event, _, control_flow| {
+        if state.as_ref().is_some_and(State::device_lost) {
+            let lost_state = state.take().unwrap();
+            match pollster::block_on(lost_state.recreate()) {
+                Ok(recreated) => state = Some(recreated),
+                // Tried again on the next event
+                Err(RecreateError::GpuUnavailable(lost_state, _)) => {
+                    state = Some(*lost_state);
+                    return;
+                }
+                Err(error) => panic!("Can't load the scene: {}", error),
+            }
+        }
+        let state = state.as_mut().unwrap();
+
+        match event {
+            Event::WindowEvent { ref event, .. } if !state.input(event) => match event {
+                WindowEvent::Resized(size) => state.resize(*size),
+                WindowEvent::ScaleFactorChanged { new_inner_size, .. } => {
+                    state.resize(**new_inner_size)
+                }
+                WindowEvent::CloseRequested => *control_flow = ControlFlow::Exit,
+                _ => {}
+            },
+            Event::RedrawRequested(_) => {
+                let now = Instant::now();
+                state.update((now - last_update).as_secs_f32());
+                last_update = now;
+                if let Err(error) = state.render() {
+                    if !state.recover_from_surface_error(error) {
+                        *control_flow = ControlFlow::Exit;
+                    }
+                }
+            }
+            Event::MainEventsCleared => {
+                if let Some(window) = state.window() {
+                    window.request_redraw();
+                }
+            }
+            _ => {}
+        }
+    });
+}
+
+// On the web the loop lives in JavaScript, see client/embedded.html
+#[cfg(target_arch = "wasm32")]
+fn main() {}
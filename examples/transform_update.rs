@@ -0,0 +1,50 @@
+//! Times moving a single node of a 1000 node hierarchy, once recomputing only the transforms
+//! below it and once recomputing every transform like a renderer without dirty flags would.
+//! Run with `cargo run --release --example transform_update`.
+
+use cgmath::{Matrix4, Rad, Vector3};
+use instant::Instant;
+use voxel_gi::transform_hierarchy::TransformHierarchy;
+
+const NODE_COUNT: usize = 1000;
+/// Children of every inner node
+const BRANCHING: usize = 4;
+const ITERATIONS: u32 = 10_000;
+
+fn main() {
+    let mut hierarchy = TransformHierarchy::default();
+    for index in 0..NODE_COUNT {
+        let parent = index.checked_sub(1).map(|index| index / BRANCHING);
+        hierarchy.add_node(
+            parent,
+            Matrix4::from_translation(Vector3::new(1.0, 0.0, 0.0)),
+        );
+    }
+    // A node halfway down the tree, with a few dozen descendants
+    let moved_node = 5;
+
+    let mut changed = 0;
+    let start = Instant::now();
+    for iteration in 0..ITERATIONS {
+        hierarchy.set_local_transform(moved_node, Matrix4::from_angle_y(Rad(iteration as f32)));
+        changed = hierarchy.update().len();
+    }
+    let incremental = start.elapsed();
+
+    let start = Instant::now();
+    for iteration in 0..ITERATIONS {
+        hierarchy.set_local_transform(moved_node, Matrix4::from_angle_y(Rad(iteration as f32)));
+        hierarchy.update_all();
+    }
+    let full = start.elapsed();
+
+    let per_update = |total: std::time::Duration| total.as_secs_f64() * 1e6 / ITERATIONS as f64;
+    println!(
+        "{} nodes, {} recomputed per update: {:.2} µs incremental, {:.2} µs full ({:.1}x)",
+        hierarchy.len(),
+        changed,
+        per_update(incremental),
+        per_update(full),
+        full.as_secs_f64() / incremental.as_secs_f64()
+    );
+}
@@ -0,0 +1,171 @@
+use cgmath::{Deg, Euler, Vector3};
+
+use crate::{
+    cpu_profiler::{CpuProfiler, CpuSection},
+    util::query_param,
+};
+
+const DEFAULT_FRAME_COUNT: u32 = 600;
+
+/// Benchmark mode enabled with `?bench=1`, `&bench_frames=N` changes the number of frames. The
+/// camera follows a fixed path through the scene with a fixed time step and input is ignored, so
+/// every run renders the same frames. `&bench_compare_sdf=1` replays the path a second time with
+/// SDF empty space skipping disabled and reports both frame times.
+///
+/// GPU pass timings are only reported where the adapter supports timestamp queries, which wgpu
+/// doesn't expose on the web. Natively `headless=1` renders the benchmark into an offscreen
+/// target without opening a window, see `run`.
+pub struct Benchmark {
+    frame_count: u32,
+    frame: u32,
+    compare_sdf: bool,
+    frame_times_ms: Vec<f64>,
+    /// Whether the SDF skipped empty space in each recorded frame
+    sdf_skipping: Vec<bool>,
+    section_times_ms: Vec<[f64; CpuSection::COUNT]>,
+    /// Every GPU measurement of each pass, empty without timestamp queries
+    pass_times_ms: Vec<(&'static str, Vec<f64>)>,
+}
+
+impl Benchmark {
+    pub fn from_query() -> Option<Self> {
+        if query_param("bench").as_deref() != Some("1") {
+            return None;
+        }
+        let frame_count = match query_param("bench_frames") {
+            Some(value) => value.parse().unwrap_or_else(|_| {
+                log::warn!("Ignoring invalid bench_frames \"{}\"", value);
+                DEFAULT_FRAME_COUNT
+            }),
+            None => DEFAULT_FRAME_COUNT,
+        };
+        let compare_sdf = query_param("bench_compare_sdf").as_deref() == Some("1");
+        let total_frames = frame_count as usize * if compare_sdf { 2 } else { 1 };
+        Some(Self {
+            frame_count,
+            frame: 0,
+            compare_sdf,
+            frame_times_ms: Vec::with_capacity(total_frames),
+            sdf_skipping: Vec::with_capacity(total_frames),
+            section_times_ms: Vec::with_capacity(total_frames),
+            pass_times_ms: Vec::new(),
+        })
+    }
+
+    fn total_frames(&self) -> u32 {
+        if self.compare_sdf {
+            self.frame_count * 2
+        } else {
+            self.frame_count
+        }
+    }
+
+    /// Whether the current frame has to skip empty space with the SDF, `None` leaves the setting
+    /// alone.
+    pub fn sdf_skipping(&self) -> Option<bool> {
+        self.compare_sdf.then_some(self.frame < self.frame_count)
+    }
+
+    /// Camera transform for the current frame, a walk down the length of Sponza's atrium while
+    /// slowly looking from side to side.
+    pub fn camera_transform(&self) -> (Vector3<f32>, Euler<Deg<f32>>) {
+        let frame_count = self.frame_count.max(1);
+        let t = (self.frame % frame_count) as f32 / frame_count as f32;
+        let position = Vector3::new(-9.0 + 18.0 * t, 2.0, 0.0);
+        let yaw = -270.0 + 30.0 * (t * std::f32::consts::TAU).sin();
+        (position, Euler::new(Deg(0.0), Deg(yaw), Deg(0.0)))
+    }
+
+    /// Stores the timings of the frame that was just presented and whether it used SDF skipping.
+    /// Returns `true` once every frame was recorded.
+    pub fn record_frame(
+        &mut self,
+        profiler: &CpuProfiler,
+        frame_time_ms: f64,
+        sdf_skipping: bool,
+    ) -> bool {
+        // The first frame has no complete measurement yet
+        if self.frame > 0 {
+            self.frame_times_ms.push(frame_time_ms);
+            self.sdf_skipping.push(sdf_skipping);
+            self.section_times_ms
+                .push(CpuSection::ALL.map(|section| profiler.last_ms(section)));
+        }
+        self.frame += 1;
+        self.frame >= self.total_frames()
+    }
+
+    /// Stores a GPU measurement of each pass. They lag a few frames behind and not every frame is
+    /// measured, so they're kept apart from the per frame timings.
+    pub fn record_gpu_frame(&mut self, pass_ms: &[(&'static str, f64)]) {
+        if self.frame == 0 {
+            return;
+        }
+        for &(pass, ms) in pass_ms {
+            match self
+                .pass_times_ms
+                .iter_mut()
+                .find(|(name, _)| *name == pass)
+            {
+                Some((_, times)) => times.push(ms),
+                None => self.pass_times_ms.push((pass, vec![ms])),
+            }
+        }
+    }
+
+    /// min, average and 99th percentile of every measurement as JSON, along with the adapter
+    /// they were measured on.
+    pub fn report(&self, adapter: &wgpu::AdapterInfo) -> String {
+        let stats = |mut values: Vec<f64>| {
+            if values.is_empty() {
+                return serde_json::Value::Null;
+            }
+            values.sort_by(f64::total_cmp);
+            let p99_index = ((values.len() as f64 * 0.99).ceil() as usize).saturating_sub(1);
+            serde_json::json!({
+                "min": values[0],
+                "avg": values.iter().sum::<f64>() / values.len() as f64,
+                "p99": values[p99_index],
+            })
+        };
+        let cpu: serde_json::Map<_, _> = CpuSection::ALL
+            .into_iter()
+            .map(|section| {
+                let values = self
+                    .section_times_ms
+                    .iter()
+                    .map(|times| times[section as usize])
+                    .collect();
+                (section.name().to_string(), stats(values))
+            })
+            .collect();
+        // `null` where the adapter can't write timestamps
+        let gpu = (!self.pass_times_ms.is_empty()).then(|| {
+            self.pass_times_ms
+                .iter()
+                .map(|(pass, times)| (pass.to_string(), stats(times.clone())))
+                .collect::<serde_json::Map<_, _>>()
+        });
+        let frame_times_with_sdf = |enabled: bool| {
+            self.frame_times_ms
+                .iter()
+                .zip(&self.sdf_skipping)
+                .filter(|(_, &skipping)| skipping == enabled)
+                .map(|(&time, _)| time)
+                .collect()
+        };
+        let report = serde_json::json!({
+            "adapter": {
+                "name": adapter.name,
+                "backend": format!("{:?}", adapter.backend),
+            },
+            "frames": self.frame_times_ms.len(),
+            "frame_time_ms": stats(self.frame_times_ms.clone()),
+            "sdf_skipping_frame_time_ms": stats(frame_times_with_sdf(true)),
+            "fixed_step_frame_time_ms": stats(frame_times_with_sdf(false)),
+            "cpu_ms": cpu,
+            "gpu_ms": gpu,
+        });
+        serde_json::to_string_pretty(&report).expect("Benchmark report is always serializable")
+    }
+}
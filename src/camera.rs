@@ -1,15 +1,68 @@
-use cgmath::{Vector3, Euler, Deg, Vector2, Zero, Matrix4, num_traits::{ToPrimitive, clamp}, SquareMatrix, Vector4, Point3};
-use winit::{window::Window, event::{WindowEvent, MouseButton, ElementState, KeyboardInput, VirtualKeyCode}};
+use cgmath::{Vector3, Euler, Deg, Vector2, Zero, Matrix4, num_traits::{ToPrimitive, clamp}, SquareMatrix, Matrix, Vector4, Point3, InnerSpace, Transform};
+use std::collections::HashMap;
 
+use winit::{dpi::PhysicalSize, event::{DeviceEvent, WindowEvent, MouseButton, ElementState}};
 
+use crate::{gltf_loader::Aabb, key_bindings::{KeyId, Movement}};
+
+
+/// Maps OpenGL's clip space depth, -1 at the near plane and 1 at the far plane, onto wgpu's 0 to 1.
+/// Only applied through `projection`.
 #[rustfmt::skip]
-pub const OPENGL_TO_WGPU_MATRIX: cgmath::Matrix4<f32> = cgmath::Matrix4::new(
+const OPENGL_TO_WGPU_MATRIX: cgmath::Matrix4<f32> = cgmath::Matrix4::new(
     1.0, 0.0, 0.0, 0.0,
     0.0, 1.0, 0.0, 0.0,
     0.0, 0.0, 0.5, 0.0,
     0.0, 0.0, 0.5, 1.0,
 );
 
+/// Turns a projection built by cgmath, which follows OpenGL, into one for wgpu: the near plane
+/// ends up at depth 0 and the far plane at 1. Every projection the shaders get has to go through
+/// here, and positions are reconstructed from depth with the inverse of the result, otherwise
+/// they disagree by half the depth range.
+pub fn projection(opengl_projection: Matrix4<f32>) -> Matrix4<f32> {
+    OPENGL_TO_WGPU_MATRIX * opengl_projection
+}
+
+/// The six planes bounding what a camera sees, facing inwards, with `x * p + w >= 0` inside.
+#[derive(Debug, Copy, Clone)]
+pub struct Frustum {
+    planes: [Vector4<f32>; 6],
+}
+
+impl Frustum {
+    /// Extracts the planes from the rows of a view projection that went through `projection`,
+    /// so depth goes from 0 to 1 (Gribb and Hartmann).
+    pub fn from_view_projection(view_projection: Matrix4<f32>) -> Self {
+        let [x, y, z, w] = [0, 1, 2, 3].map(|row| view_projection.row(row));
+        Self {
+            planes: [w + x, w - x, w + y, w - y, z, w - z],
+        }
+    }
+
+    /// False only if `bounds` are entirely outside one of the planes. Boxes near a corner of the
+    /// frustum can pass without being visible, which is fine for culling.
+    pub fn intersects(&self, bounds: &Aabb) -> bool {
+        self.planes.iter().all(|plane| {
+            // The corner furthest along the plane's normal
+            let corner = Vector3::new(
+                if plane.x >= 0.0 { bounds.max[0] } else { bounds.min[0] },
+                if plane.y >= 0.0 { bounds.max[1] } else { bounds.min[1] },
+                if plane.z >= 0.0 { bounds.max[2] } else { bounds.min[2] },
+            );
+            plane.truncate().dot(corner) + plane.w >= 0.0
+        })
+    }
+}
+
+/// Longest frame the camera moves for, in seconds
+const MAX_MOVEMENT_DT: f32 = 0.1;
+/// How far above the mirror plane the planar reflection starts, so the reflecting surface itself
+/// and whatever sits exactly on it don't end up in the reflection
+const MIRROR_CLIP_OFFSET: f32 = 0.01;
+/// Fraction of the far plane distance the near plane of a spot light's shadow map sits at
+const SPOT_SHADOW_NEAR_FRACTION: f32 = 0.001;
+
 pub struct ShadowCamera {
     pub position: Point3<f32>,
     pub direction: Vector3<f32>,
@@ -19,10 +72,13 @@ pub struct ShadowCamera {
     right: f32,
     bottom: f32,
     top: f32,
+    /// Shadow map resolution the view is snapped to, `None` disables snapping
+    texel_snapping: Option<u32>,
 }
 
 impl ShadowCamera {
     
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         position: Point3<f32>,
         direction: Vector3<f32>,
@@ -42,7 +98,41 @@ impl ShadowCamera {
             right,
             bottom,
             top,
+            texel_snapping: None,
+        }
+    }
+
+    /// Looks at `bounds` along `direction` through an ortho window that just covers them, so the
+    /// shadow map resolution goes to the scene whatever its scale.
+    pub fn fit_to_bounds(bounds: &Aabb, direction: Vector3<f32>) -> Self {
+        let mut camera = Self::new(bounds.center(), direction, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0);
+        let view = camera.view_mat();
+        let mut min = Vector3::new(f32::MAX, f32::MAX, f32::MAX);
+        let mut max = Vector3::new(f32::MIN, f32::MIN, f32::MIN);
+        for corner in bounds.corners() {
+            let corner = view.transform_point(corner);
+            min = Vector3::new(min.x.min(corner.x), min.y.min(corner.y), min.z.min(corner.z));
+            max = Vector3::new(max.x.max(corner.x), max.y.max(corner.y), max.z.max(corner.z));
         }
+        // A little room so texel snapping and depth precision don't clip the edges, relative
+        // to the size so flat scenes still get a usable window
+        let size = max - min;
+        let margin = size.x.max(size.y).max(size.z).max(f32::EPSILON) * 0.01;
+        // The camera looks down -z, near and far are distances in front of it
+        camera.near = -max.z - margin;
+        camera.far = -min.z + margin;
+        camera.left = min.x - margin;
+        camera.right = max.x + margin;
+        camera.bottom = min.y - margin;
+        camera.top = max.y + margin;
+        camera
+    }
+
+    /// Rounds the position of the ortho window to whole texels of a `map_size` sized shadow map.
+    /// Every texel then keeps covering the same slice of the world while the camera follows
+    /// the view, otherwise shadow edges crawl with sub texel movements.
+    pub fn set_texel_snapping(&mut self, map_size: Option<u32>) {
+        self.texel_snapping = map_size;
     }
 
     pub fn proj_mat(&self) -> Matrix4<f32> {
@@ -50,15 +140,104 @@ impl ShadowCamera {
     }
 
     pub fn view_mat(&self) -> Matrix4<f32> {
-        Matrix4::look_to_rh(self.position, self.direction, Vector3 { x: 0.0, y: 1.0, z: 0.0 })
+        let mut view = Matrix4::look_to_rh(
+            self.position,
+            self.direction,
+            Vector3 { x: 0.0, y: 1.0, z: 0.0 },
+        );
+        if let Some(map_size) = self.texel_snapping {
+            let texel_width = (self.right - self.left) / map_size as f32;
+            let texel_height = (self.top - self.bottom) / map_size as f32;
+            view.w.x = (view.w.x / texel_width).round() * texel_width;
+            view.w.y = (view.w.y / texel_height).round() * texel_height;
+        }
+        view
     }
 
     pub fn get_uniform_data(&self) -> [[f32; 4]; 4] {
-        return (OPENGL_TO_WGPU_MATRIX * self.proj_mat() * self.view_mat()).into();
+        (projection(self.proj_mat()) * self.view_mat()).into()
     }
 
 }
 
+/// View projection of a spot light's shadow map, a square perspective frustum just wide enough
+/// for a cone of `outer_angle` degrees that reaches `far` units from the light.
+pub fn spot_shadow_view_projection(
+    position: Point3<f32>,
+    direction: Vector3<f32>,
+    outer_angle: f32,
+    far: f32,
+) -> Matrix4<f32> {
+    let direction = direction.normalize();
+    // The up vector can't be parallel to the direction
+    let up = if direction.y.abs() > 0.99 { Vector3::unit_z() } else { Vector3::unit_y() };
+    let fov = Deg((outer_angle * 2.0).clamp(1.0, 170.0));
+    let perspective = cgmath::perspective(fov, 1.0, far * SPOT_SHADOW_NEAR_FRACTION, far);
+    projection(perspective) * Matrix4::look_to_rh(position, direction, up)
+}
+
+/// Orthographic cameras looking at the voxel grid along each axis. Voxelization projects every
+/// triangle with the one facing it the most, so no triangle degenerates into a line and leaves
+/// holes in the grid. Independent of the viewer, the grid covers the same volume wherever the main
+/// camera is.
+pub struct VoxelizationCamera {
+    center: Point3<f32>,
+    half_extent: f32,
+}
+
+impl VoxelizationCamera {
+    pub fn new(center: Point3<f32>, half_extent: f32) -> Self {
+        Self {
+            center,
+            half_extent,
+        }
+    }
+
+    /// View projection looking down `axis`, 0 for x, 1 for y and 2 for z.
+    pub fn view_projection(&self, axis: usize) -> Matrix4<f32> {
+        let (direction, up) = match axis {
+            0 => (-Vector3::unit_x(), Vector3::unit_y()),
+            1 => (-Vector3::unit_y(), Vector3::unit_z()),
+            _ => (-Vector3::unit_z(), Vector3::unit_y()),
+        };
+        let extent = self.half_extent;
+        let ortho = cgmath::ortho(-extent, extent, -extent, extent, -extent, extent);
+        projection(ortho) * Matrix4::look_to_rh(self.center, direction, up)
+    }
+
+    /// Matches `VoxelizationCamera` in voxelize.wgsl
+    pub fn get_uniform_data(&self) -> [[[f32; 4]; 4]; 3] {
+        [0, 1, 2].map(|axis| self.view_projection(axis).into())
+    }
+}
+
+/// Matches `Camera` in common.wgsl. Shaders that only project can keep declaring the buffer as a
+/// single `mat4x4`, the near and far planes are there to linearize depth buffer values. The
+/// inverse and the position let fullscreen passes reconstruct view rays.
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct CameraUniform {
+    pub view_projection: [[f32; 4]; 4],
+    pub inverse_view_projection: [[f32; 4]; 4],
+    /// w is always 1
+    pub position: [f32; 4],
+    pub near: f32,
+    pub far: f32,
+    /// 1 for the mirrored camera of the planar reflection, 0 otherwise
+    pub mirror: f32,
+    pub filler: f32,
+}
+
+crate::assert_uniform_layout!(CameraUniform, size: 160, {
+    view_projection: 0,
+    inverse_view_projection: 64,
+    position: 128,
+    near: 144,
+    far: 148,
+    mirror: 152,
+    filler: 156,
+});
+
 pub struct PerspectiveCamera {
     pub position: Vector3<f32>,
     pub rotation: Euler<Deg<f32>>,
@@ -66,36 +245,45 @@ pub struct PerspectiveCamera {
     far: f32,
     fov: Deg<f32>,
     aspect_ratio: f32,
-    movement: Vector3<f32>,
+    held_keys: HashMap<KeyId, Movement>,
     dragging: bool,
-    last_cursor: Vector2<f32>,
+    /// `None` until the cursor moved inside the window, and again after it left, so coming back
+    /// in somewhere else doesn't count as a drag
+    last_cursor: Option<Vector2<f32>>,
+    /// Mouselook from raw mouse motion, dragging is the fallback when the pointer can't be locked
+    pointer_locked: bool,
     speed: f32,
 }
 
 impl PerspectiveCamera {
     pub fn new(
-        window: &Window,
+        size: PhysicalSize<u32>,
         position: Vector3<f32>,
         rotation: Euler<Deg<f32>>,
         near: f32,
         far: f32,
         fov: Deg<f32>,
     ) -> Self {
-        let size = window.inner_size();
         Self {
             position,
             rotation,
             near,
             far,
             fov,
-            aspect_ratio: (size.width as f32) / (size.height as f32),
-            movement: Vector3::zero(),
+            aspect_ratio: (size.width.max(1) as f32) / (size.height.max(1) as f32),
+            held_keys: HashMap::new(),
             dragging: false,
-            last_cursor: Vector2::zero(),
+            last_cursor: None,
+            pointer_locked: false,
             speed: 1.0,
         }
     }
 
+    /// Keeps the projection matching the surface after the window was resized.
+    pub fn resize(&mut self, width: u32, height: u32) {
+        self.aspect_ratio = (width as f32) / (height as f32);
+    }
+
     pub fn proj_mat(&self) -> Matrix4<f32> {
         cgmath::perspective(self.fov, self.aspect_ratio, self.near, self.far)
     }
@@ -104,8 +292,74 @@ impl PerspectiveCamera {
         Matrix4::from(self.rotation) * Matrix4::from_translation(-self.position)
     }
 
-    pub fn get_uniform_data(&self) -> [[f32; 4]; 4] {
-        return (OPENGL_TO_WGPU_MATRIX * self.proj_mat() * self.view_mat()).into();
+    pub fn projection(&self) -> Matrix4<f32> {
+        projection(self.proj_mat())
+    }
+
+    pub fn view_projection(&self) -> Matrix4<f32> {
+        self.projection() * self.view_mat()
+    }
+
+    pub fn get_uniform_data(&self) -> CameraUniform {
+        let view_projection = self.view_projection();
+        CameraUniform {
+            view_projection: view_projection.into(),
+            inverse_view_projection: view_projection
+                .invert()
+                .expect("Camera projection isn't invertible")
+                .into(),
+            position: self.position.extend(1.0).into(),
+            near: self.near,
+            far: self.far,
+            mirror: 0.0,
+            filler: 0.0,
+        }
+    }
+
+    /// The camera mirrored about the horizontal plane at `height`, for rendering the planar
+    /// reflection. Everything below the plane is cut off by moving the near plane onto it, see
+    /// Lengyel's "Oblique View Frustum Depth Projection and Clipping", which keeps early depth
+    /// testing unlike discarding in the shader. X is flipped so the triangles keep their winding,
+    /// the reflection is sampled at `1 - u` to make up for it.
+    pub fn mirrored_uniform_data(&self, height: f32) -> CameraUniform {
+        let reflection = Matrix4::from_translation(Vector3::new(0.0, height, 0.0))
+            * Matrix4::from_nonuniform_scale(1.0, -1.0, 1.0)
+            * Matrix4::from_translation(Vector3::new(0.0, -height, 0.0));
+        let view = self.view_mat();
+        // The mirrored scene is kept below the plane, facing away from the camera
+        let plane = Vector4::new(0.0, -1.0, 0.0, height - MIRROR_CLIP_OFFSET);
+        let plane = view.invert().expect("Camera view isn't invertible").transpose() * plane;
+
+        // Works on the OpenGL projection, the near plane is at depth -1 there
+        let mut oblique = self.proj_mat();
+        let corner = Vector4::new(
+            (plane.x.signum() + oblique.z.x) / oblique.x.x,
+            (plane.y.signum() + oblique.z.y) / oblique.y.y,
+            -1.0,
+            (1.0 + oblique.z.z) / oblique.w.z,
+        );
+        let plane = plane * (2.0 / plane.dot(corner));
+        oblique.x.z = plane.x;
+        oblique.y.z = plane.y;
+        oblique.z.z = plane.z + 1.0;
+        oblique.w.z = plane.w;
+
+        let view_projection = Matrix4::from_nonuniform_scale(-1.0, 1.0, 1.0)
+            * projection(oblique)
+            * view
+            * reflection;
+        CameraUniform {
+            view_projection: view_projection.into(),
+            inverse_view_projection: view_projection
+                .invert()
+                .expect("Mirrored camera projection isn't invertible")
+                .into(),
+            position: [self.position.x, 2.0 * height - self.position.y, self.position.z, 1.0],
+            near: self.near,
+            far: self.far,
+            mirror: 1.0,
+            filler: 0.0,
+        }
     }
 
     pub fn process_event(&mut self, event: &WindowEvent) -> bool {
@@ -125,7 +379,7 @@ impl PerspectiveCamera {
                     return false;
                 }
                 match state {
-                    ElementState::Pressed => self.dragging = true,
+                    ElementState::Pressed => self.dragging = !self.pointer_locked,
                     ElementState::Released => self.dragging = false,
                 }
                 false
@@ -133,65 +387,194 @@ impl PerspectiveCamera {
             WindowEvent::CursorMoved { position, .. } => {
                 let pos =
                     Vector2::<f32>::new(position.x.to_f32().unwrap(), position.y.to_f32().unwrap());
-                let diff = pos - self.last_cursor;
-                if self.dragging {
-                    self.rotation.x += Deg(diff.y / 3.0);
-                    self.rotation.y += Deg(diff.x / 3.0);
-                    self.rotation.x = Deg(clamp(self.rotation.x.0, -90.0, 90.0));
+                if let Some(last_cursor) = self.last_cursor {
+                    if self.dragging {
+                        self.rotate(pos - last_cursor);
+                    }
                 }
-                self.last_cursor = pos;
+                self.last_cursor = Some(pos);
                 false
             }
-            WindowEvent::KeyboardInput {
-                input:
-                    KeyboardInput {
-                        state,
-                        virtual_keycode: Some(keycode),
-                        ..
-                    },
-                ..
-            } => {
-                let speed = if *state == ElementState::Pressed {
-                    self.speed
-                } else {
-                    0.0
-                };
-                match keycode {
-                    VirtualKeyCode::A => {
-                        self.movement.x = -speed;
-                        true
-                    }
-                    VirtualKeyCode::D => {
-                        self.movement.x = speed;
-                        true
-                    }
-                    VirtualKeyCode::W => {
-                        self.movement.z = -speed;
-                        true
-                    }
-                    VirtualKeyCode::S => {
-                        self.movement.z = speed;
-                        true
-                    }
-                    VirtualKeyCode::LShift => {
-                        self.movement.y = -speed;
-                        true
-                    }
-                    VirtualKeyCode::Space => {
-                        self.movement.y = speed;
-                        true
-                    }
-                    _ => false,
-                }
+            WindowEvent::CursorLeft { .. } => {
+                self.last_cursor = None;
+                false
+            }
+            _ => false,
+        }
+    }
+
+    /// Raw mouse motion, which rotates the camera while the pointer is locked.
+    pub fn process_device_event(&mut self, event: &DeviceEvent) -> bool {
+        match event {
+            DeviceEvent::MouseMotion { delta: (x, y) } if self.pointer_locked => {
+                self.rotate(Vector2::new(*x as f32, *y as f32));
+                true
             }
             _ => false,
         }
     }
 
-    pub fn update(&mut self) {
+    pub fn pointer_locked(&self) -> bool {
+        self.pointer_locked
+    }
+
+    /// Switches between mouselook and dragging, has to follow whether the pointer actually is
+    /// locked.
+    pub fn set_pointer_locked(&mut self, locked: bool) {
+        self.pointer_locked = locked;
+        self.dragging = false;
+    }
+
+    /// Turns the camera by a mouse movement in pixels.
+    fn rotate(&mut self, diff: Vector2<f32>) {
+        self.rotation.x += Deg(diff.y / 3.0);
+        self.rotation.y += Deg(diff.x / 3.0);
+        self.rotation.x = Deg(clamp(self.rotation.x.0, -90.0, 90.0));
+    }
+
+    /// Movement is tracked per key, so releasing one of two opposite keys keeps moving towards
+    /// the one still held.
+    pub fn set_movement_key(&mut self, key: KeyId, movement: Movement, pressed: bool) {
+        if pressed {
+            self.held_keys.insert(key, movement);
+        } else {
+            self.held_keys.remove(&key);
+        }
+    }
+
+    #[cfg(target_arch = "wasm32")]
+    pub fn release_movement_keys(&mut self) {
+        self.held_keys.clear();
+    }
+
+    /// Moves the camera by `dt` seconds worth of the held movement keys.
+    pub fn update(&mut self, dt: f32) {
+        // A hitch, or a background tab on the web, shouldn't teleport the camera
+        let dt = dt.min(MAX_MOVEMENT_DT);
+        let mut movement = Vector3::<f32>::zero();
+        for held in self.held_keys.values() {
+            movement += match held {
+                Movement::Forward => Vector3::new(0.0, 0.0, -1.0),
+                Movement::Backward => Vector3::new(0.0, 0.0, 1.0),
+                Movement::Left => Vector3::new(-1.0, 0.0, 0.0),
+                Movement::Right => Vector3::new(1.0, 0.0, 0.0),
+                Movement::Up => Vector3::new(0.0, 1.0, 0.0),
+                Movement::Down => Vector3::new(0.0, -1.0, 0.0),
+            };
+        }
+        if movement != Vector3::zero() {
+            movement = movement.normalize() * self.speed;
+        }
         self.position += (self.view_mat().invert().unwrap()
-            * Vector4::new(self.movement.x, self.movement.y, self.movement.z, 0.0))
+            * Vector4::new(movement.x, movement.y, movement.z, 0.0))
         .xyz()
-            * 0.016;
+            * dt;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn camera_uniform_matches_wgsl() {
+        crate::util::assert_wgsl_layout::<CameraUniform>(
+            &crate::main_shader_source(false),
+            "Camera",
+        );
     }
-}
\ No newline at end of file
+
+    /// Projects `point`, checks that it lands at `expected_depth` and that the inverse view
+    /// projection, what the shaders reconstruct positions with, brings it back.
+    fn assert_depth_round_trip(
+        view_projection: Matrix4<f32>,
+        point: Point3<f32>,
+        expected_depth: f32,
+    ) {
+        let clip = view_projection * point.to_homogeneous();
+        let ndc = clip.truncate() / clip.w;
+        assert!(
+            (ndc.z - expected_depth).abs() < 1e-5,
+            "{:?} landed at depth {}, expected {}",
+            point,
+            ndc.z,
+            expected_depth
+        );
+        let inverse = view_projection.invert().expect("View projection isn't invertible");
+        let back = Point3::from_homogeneous(inverse * ndc.extend(1.0));
+        // Relative to the distance from the origin, f32 depth gets coarse towards the far plane
+        let distance = (point - Point3::new(0.0, 0.0, 0.0)).magnitude();
+        let error = (back - point).magnitude() / distance.max(1.0);
+        assert!(error < 1e-3, "{:?} came back as {:?}", point, back);
+    }
+
+    #[test]
+    fn near_and_far_planes_land_at_depth_0_and_1() {
+        let (near, far) = (0.1, 100.0);
+        let eye = Point3::new(1.0, 2.0, 3.0);
+        let direction = Vector3::new(0.3, -0.2, -1.0).normalize();
+        let view = Matrix4::look_to_rh(eye, direction, Vector3::unit_y());
+        // Off center points, so x and y take part in the round trip too
+        let side = direction.cross(Vector3::unit_y()).normalize();
+        let at = |distance: f32| eye + direction * distance + side * distance * 0.2;
+
+        let perspective =
+            projection(cgmath::perspective(Deg(60.0), 16.0 / 9.0, near, far)) * view;
+        assert_depth_round_trip(perspective, at(near), 0.0);
+        assert_depth_round_trip(perspective, at(far), 1.0);
+
+        let ortho = projection(cgmath::ortho(-50.0, 50.0, -50.0, 50.0, near, far)) * view;
+        assert_depth_round_trip(ortho, at(near), 0.0);
+        assert_depth_round_trip(ortho, at(far), 1.0);
+        assert_depth_round_trip(ortho, at((near + far) / 2.0), 0.5);
+    }
+
+    #[test]
+    fn texel_snapping_ignores_sub_texel_movement() {
+        const MAP_SIZE: u32 = 2048;
+        // 20 units over 2048 texels, a power of two fraction, so the texel centers are exact
+        let texel = 20.0 / MAP_SIZE as f32;
+        let camera_at = |x: f32, y: f32, snapping: Option<u32>| {
+            let mut camera = ShadowCamera::new(
+                Point3::new(x, y, 5.0),
+                -Vector3::unit_z(),
+                0.0,
+                10.0,
+                -10.0,
+                10.0,
+                -10.0,
+                10.0,
+            );
+            camera.set_texel_snapping(snapping);
+            camera.view_mat()
+        };
+        let (x, y) = (100.0 * texel, -37.0 * texel);
+        let snapped = camera_at(x, y, Some(MAP_SIZE));
+        assert_eq!(snapped, camera_at(x + 0.3 * texel, y - 0.4 * texel, Some(MAP_SIZE)));
+        assert_eq!(snapped, camera_at(x - 0.45 * texel, y + 0.2 * texel, Some(MAP_SIZE)));
+        // A whole texel moves the window, and without snapping every movement does
+        assert_ne!(snapped, camera_at(x + texel, y, Some(MAP_SIZE)));
+        assert_ne!(camera_at(x, y, None), camera_at(x + 0.3 * texel, y, None));
+    }
+
+    #[test]
+    fn spot_shadow_frustum_covers_the_cone() {
+        let position = Point3::new(1.0, 4.0, -2.0);
+        // Straight down, where the default up vector would be parallel to the direction
+        let direction = -Vector3::unit_y();
+        let view_projection = spot_shadow_view_projection(position, direction, 30.0, 20.0);
+        let ndc = |point: Point3<f32>| {
+            let clip = view_projection * point.to_homogeneous();
+            clip.truncate() / clip.w
+        };
+        let on_axis = ndc(position + direction * 10.0);
+        assert!(on_axis.x.abs() < 1e-5 && on_axis.y.abs() < 1e-5, "{:?}", on_axis);
+        assert!(on_axis.z > 0.0 && on_axis.z < 1.0, "{:?}", on_axis);
+        // The edge of the cone touches the sides of the frustum
+        let angle = 30.0f32.to_radians();
+        let edge = Vector3::new(angle.sin(), -angle.cos(), 0.0) * 10.0;
+        let on_edge = ndc(position + edge);
+        assert!((on_edge.x.abs().max(on_edge.y.abs()) - 1.0).abs() < 1e-4, "{:?}", on_edge);
+        assert!(ndc(position + direction * 21.0).z > 1.0);
+    }
+}
@@ -0,0 +1,111 @@
+#[cfg(feature = "cpu-profiling")]
+use instant::Instant;
+
+/// Blend factor of the exponential moving average, roughly averages over the last 20 frames.
+#[cfg(feature = "cpu-profiling")]
+const SMOOTHING: f64 = 0.05;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum CpuSection {
+    CameraUpdate = 0,
+    BufferWrites = 1,
+    PostUpdate = 2,
+    EncodeFrameGraph = 3,
+    EncodePost = 4,
+    Submit = 5,
+    Present = 6,
+}
+
+impl CpuSection {
+    pub const COUNT: usize = 7;
+    pub const ALL: [CpuSection; Self::COUNT] = [
+        CpuSection::CameraUpdate,
+        CpuSection::BufferWrites,
+        CpuSection::PostUpdate,
+        CpuSection::EncodeFrameGraph,
+        CpuSection::EncodePost,
+        CpuSection::Submit,
+        CpuSection::Present,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            CpuSection::CameraUpdate => "camera update",
+            CpuSection::BufferWrites => "buffer writes",
+            CpuSection::PostUpdate => "post update",
+            CpuSection::EncodeFrameGraph => "encode frame graph",
+            CpuSection::EncodePost => "encode post",
+            CpuSection::Submit => "queue submit",
+            CpuSection::Present => "present",
+        }
+    }
+}
+
+/// Returned by `CpuProfiler::begin`, empty when the `cpu-profiling` feature is disabled.
+#[derive(Copy, Clone)]
+pub struct TimerStart {
+    #[cfg(feature = "cpu-profiling")]
+    start: Instant,
+}
+
+/// Rolling averages of the CPU time spent in each `CpuSection`. Timings live in a fixed size
+/// array, so measuring doesn't allocate. Without the `cpu-profiling` feature every method
+/// compiles down to nothing and all averages read as zero.
+#[derive(Default)]
+pub struct CpuProfiler {
+    #[cfg(feature = "cpu-profiling")]
+    averages_ms: [f64; CpuSection::COUNT],
+    #[cfg(feature = "cpu-profiling")]
+    last_ms: [f64; CpuSection::COUNT],
+}
+
+impl CpuProfiler {
+    #[inline]
+    pub fn begin(&self) -> TimerStart {
+        TimerStart {
+            #[cfg(feature = "cpu-profiling")]
+            start: Instant::now(),
+        }
+    }
+
+    #[inline]
+    pub fn end(&mut self, section: CpuSection, start: TimerStart) {
+        #[cfg(feature = "cpu-profiling")]
+        {
+            let elapsed_ms = start.start.elapsed().as_secs_f64() * 1000.0;
+            self.last_ms[section as usize] = elapsed_ms;
+            let average = &mut self.averages_ms[section as usize];
+            *average += (elapsed_ms - *average) * SMOOTHING;
+        }
+        #[cfg(not(feature = "cpu-profiling"))]
+        let _ = (section, start);
+    }
+
+    pub fn average_ms(&self, section: CpuSection) -> f64 {
+        #[cfg(feature = "cpu-profiling")]
+        return self.averages_ms[section as usize];
+        #[cfg(not(feature = "cpu-profiling"))]
+        {
+            let _ = section;
+            0.0
+        }
+    }
+
+    /// Latest unsmoothed measurement of `section`.
+    pub fn last_ms(&self, section: CpuSection) -> f64 {
+        #[cfg(feature = "cpu-profiling")]
+        return self.last_ms[section as usize];
+        #[cfg(not(feature = "cpu-profiling"))]
+        {
+            let _ = section;
+            0.0
+        }
+    }
+
+    #[cfg_attr(not(feature = "cpu-profiling"), allow(dead_code))]
+    pub fn averages_ms(&self) -> impl Iterator<Item = (CpuSection, f64)> + '_ {
+        CpuSection::ALL
+            .into_iter()
+            .map(|section| (section, self.average_ms(section)))
+    }
+}
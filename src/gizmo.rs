@@ -0,0 +1,296 @@
+use cgmath::{Deg, Euler, InnerSpace, Matrix3, Vector2};
+use wgpu::util::DeviceExt;
+
+/// Side length of the gizmo viewport in pixels
+const GIZMO_SIZE: f32 = 96.0;
+/// Distance between the gizmo and the corners of the screen in pixels
+const GIZMO_MARGIN: f32 = 16.0;
+/// Length of the axis arrows and radius of their tips, in units of half the gizmo viewport. Have
+/// to match gizmo.wgsl.
+const GIZMO_AXIS_LENGTH: f32 = 0.7;
+const GIZMO_TIP_RADIUS: f32 = 0.16;
+/// Lines of the world grid on each side of the origin, has to match `GRID_HALF_LINES`
+const GRID_HALF_LINES: u32 = 20;
+/// Both directions of grid lines, plus the Y axis
+const GRID_VERTEX_COUNT: u32 = (GRID_HALF_LINES * 2 + 1) * 4 + 2;
+
+/// Matches `GizmoParams` in gizmo.wgsl.
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct GizmoParams {
+    axes: [[f32; 4]; 3],
+}
+
+crate::assert_uniform_layout!(GizmoParams, size: 48, {
+    axes: 0,
+});
+
+/// View space directions of the world axes, which are the columns of the view rotation.
+fn view_axes(rotation: Euler<Deg<f32>>) -> [[f32; 3]; 3] {
+    Matrix3::from(rotation).into()
+}
+
+/// Camera rotation looking at the origin from the positive or negative side of `axis`, with the
+/// yaw kept when looking straight up or down.
+pub fn axis_view_rotation(
+    axis: usize,
+    positive: bool,
+    current: Euler<Deg<f32>>,
+) -> Euler<Deg<f32>> {
+    let sign = if positive { 1.0 } else { -1.0 };
+    match axis {
+        0 => Euler::new(Deg(0.0), Deg(-90.0 * sign), Deg(0.0)),
+        1 => Euler::new(Deg(90.0 * sign), current.y, Deg(0.0)),
+        _ => Euler::new(Deg(0.0), Deg(if positive { 0.0 } else { 180.0 }), Deg(0.0)),
+    }
+}
+
+/// Axis arrows in the top right corner of the screen showing how the camera is rotated, drawn
+/// after tonemapping so the exposure doesn't change their colors. Also draws a grid on the
+/// ground plane through the origin, with the world axes highlighted, into the HDR scene.
+pub struct OrientationGizmo {
+    params_buffer: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
+    gizmo_pipeline: wgpu::RenderPipeline,
+    grid_pipeline: wgpu::RenderPipeline,
+}
+
+impl OrientationGizmo {
+    /// `camera_bind_group_layout` has to start with the view projection of the main camera.
+    pub fn new(
+        device: &wgpu::Device,
+        camera_bind_group_layout: &wgpu::BindGroupLayout,
+        surface_format: wgpu::TextureFormat,
+        scene_format: wgpu::TextureFormat,
+        depth_format: wgpu::TextureFormat,
+    ) -> Self {
+        let params_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Gizmo params buffer"),
+            contents: bytemuck::cast_slice(&[GizmoParams {
+                axes: [
+                    [1.0, 0.0, 0.0, 0.0],
+                    [0.0, 1.0, 0.0, 0.0],
+                    [0.0, 0.0, 1.0, 0.0],
+                ],
+            }]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Gizmo bind group layout"),
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            }],
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Gizmo bind group"),
+            layout: &bind_group_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: params_buffer.as_entire_binding(),
+            }],
+        });
+
+        let module = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Gizmo shader module"),
+            source: wgpu::ShaderSource::Wgsl(
+                concat!(
+                    include_str!("shaders/fullscreen.wgsl"),
+                    include_str!("shaders/gizmo.wgsl")
+                )
+                .into(),
+            ),
+        });
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Gizmo pipeline layout"),
+            bind_group_layouts: &[camera_bind_group_layout, &bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let create_pipeline = |label: &str, vs_entry, fs_entry, topology, format, depth_stencil| {
+            device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: Some(label),
+                layout: Some(&pipeline_layout),
+                vertex: wgpu::VertexState {
+                    module: &module,
+                    entry_point: vs_entry,
+                    buffers: &[],
+                },
+                primitive: wgpu::PrimitiveState {
+                    topology,
+                    ..Default::default()
+                },
+                depth_stencil,
+                multisample: wgpu::MultisampleState::default(),
+                fragment: Some(wgpu::FragmentState {
+                    module: &module,
+                    entry_point: fs_entry,
+                    targets: &[Some(wgpu::ColorTargetState {
+                        format,
+                        blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                        write_mask: wgpu::ColorWrites::ALL,
+                    })],
+                }),
+                multiview: None,
+            })
+        };
+
+        Self {
+            params_buffer,
+            bind_group,
+            gizmo_pipeline: create_pipeline(
+                "Gizmo pipeline",
+                "vs_fullscreen",
+                "fs_gizmo",
+                wgpu::PrimitiveTopology::TriangleList,
+                surface_format,
+                None,
+            ),
+            grid_pipeline: create_pipeline(
+                "World grid pipeline",
+                "vs_grid",
+                "fs_grid",
+                wgpu::PrimitiveTopology::LineList,
+                scene_format,
+                Some(wgpu::DepthStencilState {
+                    format: depth_format,
+                    depth_write_enabled: false,
+                    depth_compare: wgpu::CompareFunction::LessEqual,
+                    stencil: wgpu::StencilState::default(),
+                    bias: wgpu::DepthBiasState::default(),
+                }),
+            ),
+        }
+    }
+
+    /// Has to be called whenever the camera rotated.
+    pub fn update(&self, queue: &wgpu::Queue, rotation: Euler<Deg<f32>>) {
+        let axes = view_axes(rotation).map(|axis| [axis[0], axis[1], axis[2], 0.0]);
+        queue.write_buffer(
+            &self.params_buffer,
+            0,
+            bytemuck::cast_slice(&[GizmoParams { axes }]),
+        );
+    }
+
+    /// Top left corner of the gizmo viewport on a `width` by `height` surface, `None` if the
+    /// surface is too small to fit it.
+    fn origin(width: u32, height: u32) -> Option<Vector2<f32>> {
+        let min_size = GIZMO_SIZE + GIZMO_MARGIN * 2.0;
+        (width as f32 >= min_size && height as f32 >= min_size)
+            .then(|| Vector2::new(width as f32 - GIZMO_SIZE - GIZMO_MARGIN, GIZMO_MARGIN))
+    }
+
+    /// The axis tip under `cursor`, as the axis index and whether it's the positive end. Tips
+    /// closer to the viewer win, same as when they're drawn.
+    pub fn pick(
+        rotation: Euler<Deg<f32>>,
+        cursor: Vector2<f32>,
+        width: u32,
+        height: u32,
+    ) -> Option<(usize, bool)> {
+        let uv = (cursor - Self::origin(width, height)?) / GIZMO_SIZE;
+        if !(0.0..=1.0).contains(&uv.x) || !(0.0..=1.0).contains(&uv.y) {
+            return None;
+        }
+        let p = Vector2::new(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0);
+        view_axes(rotation)
+            .into_iter()
+            .enumerate()
+            .flat_map(|(axis, direction)| {
+                let tip = Vector2::new(direction[0], direction[1]) * GIZMO_AXIS_LENGTH;
+                [
+                    (axis, true, tip, direction[2]),
+                    (axis, false, -tip, -direction[2]),
+                ]
+            })
+            .filter(|(_, _, tip, _)| (p - tip).magnitude2() < GIZMO_TIP_RADIUS.powi(2))
+            .max_by(|a, b| a.3.total_cmp(&b.3))
+            .map(|(axis, positive, _, _)| (axis, positive))
+    }
+
+    /// Draws the world grid into the scene, depth tested against `depth_view`.
+    pub fn record_grid(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        camera_bind_group: &wgpu::BindGroup,
+        color_view: &wgpu::TextureView,
+        depth_view: &wgpu::TextureView,
+    ) {
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("World grid render pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: color_view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: true,
+                },
+            })],
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                view: depth_view,
+                depth_ops: Some(wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: true,
+                }),
+                stencil_ops: None,
+            }),
+        });
+        render_pass.set_pipeline(&self.grid_pipeline);
+        render_pass.set_bind_group(0, camera_bind_group, &[]);
+        render_pass.set_bind_group(1, &self.bind_group, &[]);
+        render_pass.draw(0..GRID_VERTEX_COUNT, 0..1);
+    }
+
+    /// Draws the gizmo on top of `output`, which is `width` by `height` pixels.
+    pub fn record(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        camera_bind_group: &wgpu::BindGroup,
+        output: &wgpu::TextureView,
+        width: u32,
+        height: u32,
+    ) {
+        let Some(origin) = Self::origin(width, height) else {
+            return;
+        };
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Gizmo render pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: output,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: true,
+                },
+            })],
+            depth_stencil_attachment: None,
+        });
+        render_pass.set_viewport(origin.x, origin.y, GIZMO_SIZE, GIZMO_SIZE, 0.0, 1.0);
+        render_pass.set_pipeline(&self.gizmo_pipeline);
+        render_pass.set_bind_group(0, camera_bind_group, &[]);
+        render_pass.set_bind_group(1, &self.bind_group, &[]);
+        render_pass.draw(0..3, 0..1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn params_match_wgsl() {
+        crate::util::assert_wgsl_layout::<GizmoParams>(
+            concat!(
+                include_str!("shaders/fullscreen.wgsl"),
+                include_str!("shaders/gizmo.wgsl")
+            ),
+            "GizmoParams",
+        );
+    }
+}
@@ -1,14 +1,131 @@
-use std::{collections::HashMap, iter::zip, path::Path};
+use std::{
+    cell::Cell,
+    collections::{hash_map::Entry, HashMap},
+    fmt,
+    path::Path,
+};
 
 use crate::{
+    camera::Frustum,
+    mip2d::MipBlitter,
     shader::{Attribute, Shader},
-    texture::Texture, console_log,
+    texture::Texture,
+    texture_atlas::{TextureAtlas, IDENTITY_UV_TRANSFORM},
+    transform_hierarchy::TransformHierarchy,
 };
-use cgmath::{Matrix4, SquareMatrix};
+use cgmath::{ElementWise, InnerSpace, Matrix4, MetricSpace, Point3, Transform, Vector3};
 use futures::future::join_all;
-use gltf::{accessor::Dimensions, buffer::View, Node};
+use gltf::{accessor::Dimensions, buffer::View, mesh::Mode, Node};
 use wgpu::util::DeviceExt;
 
+/// Why a glTF file or one of the files it refers to couldn't be loaded. Displays as a single
+/// line naming the file or accessor at fault.
+#[derive(Debug)]
+pub enum LoadError {
+    /// Reading a file from disk failed, `path` is resolved against the asset directory
+    Io {
+        path: String,
+        source: std::io::Error,
+    },
+    /// Fetching a file on the web failed, error statuses like 404 included
+    Http {
+        url: String,
+        source: reqwest::Error,
+    },
+    GltfParse {
+        path: String,
+        source: gltf::Error,
+    },
+    /// A `data:` URI the loader can't decode
+    DataUri(String),
+    /// A buffer refers to the binary chunk of a GLB file, but there is none
+    MissingBinaryChunk,
+    /// A vertex attribute or index accessor in a format there's no wgpu equivalent for, or one
+    /// without a buffer view
+    UnsupportedAccessor {
+        semantic: String,
+        mesh: Option<String>,
+    },
+    /// Images embedded in a buffer view are named by the view instead of a URI
+    ImageDecode {
+        uri: String,
+        reason: String,
+    },
+    /// A relative path that can't be turned into a URL on the web
+    InvalidUrl {
+        url: String,
+        reason: String,
+    },
+    /// None of the adapters the backends offer can run the renderer
+    NoAdapter,
+    RequestDevice(wgpu::RequestDeviceError),
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LoadError::Io { path, source } => write!(f, "Couldn't read {}: {}", path, source),
+            LoadError::Http { url, source } => write!(f, "Couldn't fetch {}: {}", url, source),
+            LoadError::GltfParse { path, source } => {
+                write!(f, "{} isn't a valid glTF file: {}", path, source)
+            }
+            LoadError::DataUri(reason) => write!(f, "Malformed data URI: {}", reason),
+            LoadError::MissingBinaryChunk => write!(
+                f,
+                "A buffer refers to the GLB binary chunk, but the file has none"
+            ),
+            LoadError::UnsupportedAccessor { semantic, mesh } => write!(
+                f,
+                "Unsupported {} accessor in mesh {}",
+                semantic,
+                mesh.as_deref().unwrap_or("without a name")
+            ),
+            LoadError::ImageDecode { uri, reason } => {
+                write!(f, "Couldn't decode image {}: {}", uri, reason)
+            }
+            LoadError::InvalidUrl { url, reason } => {
+                write!(f, "{} isn't a valid URL: {}", url, reason)
+            }
+            LoadError::NoAdapter => write!(
+                f,
+                "No supported graphics adapter, voxel GI needs WebGPU or an equivalent"
+            ),
+            LoadError::RequestDevice(source) => {
+                write!(f, "Couldn't open the graphics device: {}", source)
+            }
+        }
+    }
+}
+
+impl std::error::Error for LoadError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            LoadError::Io { source, .. } => Some(source),
+            LoadError::Http { source, .. } => Some(source),
+            LoadError::GltfParse { source, .. } => Some(source),
+            LoadError::RequestDevice(source) => Some(source),
+            _ => None,
+        }
+    }
+}
+
+/// Why a pipeline list of a `Scene` couldn't be regenerated or extended.
+#[derive(Debug)]
+pub enum PipelineError {
+    /// No list was generated under this name
+    UnknownList(String),
+}
+
+impl fmt::Display for PipelineError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PipelineError::UnknownList(name) => write!(f, "No pipeline list named \"{}\"", name),
+        }
+    }
+}
+
+impl std::error::Error for PipelineError {}
+
 fn gltf_accessor_to_wgpu(accessor: &gltf::Accessor) -> Option<wgpu::VertexFormat> {
     let normalized = accessor.normalized();
 
@@ -89,6 +206,38 @@ fn gltf_accessor_to_indexformat(accessor: &gltf::Accessor) -> Option<wgpu::Index
     }
 }
 
+/// Fans and line loops are drawn as lists, of the indices `unroll_indices` generates for them.
+fn gltf_mode_to_topology(mode: Mode) -> wgpu::PrimitiveTopology {
+    match mode {
+        Mode::Points => wgpu::PrimitiveTopology::PointList,
+        Mode::Lines | Mode::LineLoop => wgpu::PrimitiveTopology::LineList,
+        Mode::LineStrip => wgpu::PrimitiveTopology::LineStrip,
+        Mode::Triangles | Mode::TriangleFan => wgpu::PrimitiveTopology::TriangleList,
+        Mode::TriangleStrip => wgpu::PrimitiveTopology::TriangleStrip,
+    }
+}
+
+/// Whether wgpu has no topology for `mode`, so primitives using it need `unroll_indices`.
+fn needs_unrolling(mode: Mode) -> bool {
+    matches!(mode, Mode::LineLoop | Mode::TriangleFan)
+}
+
+/// Indices drawing the same lines or triangles as a line loop or triangle fan as a list. Other
+/// modes keep their indices.
+fn unroll_indices(mode: Mode, indices: &[u32]) -> Vec<u32> {
+    match mode {
+        Mode::LineLoop if indices.len() >= 2 => (0..indices.len())
+            .flat_map(|i| [indices[i], indices[(i + 1) % indices.len()]])
+            .collect(),
+        Mode::TriangleFan if indices.len() >= 3 => indices[1..]
+            .windows(2)
+            .flat_map(|edge| [indices[0], edge[0], edge[1]])
+            .collect(),
+        Mode::LineLoop | Mode::TriangleFan => Vec::new(),
+        _ => indices.to_vec(),
+    }
+}
+
 fn get_accessor_component_count(accessor: &gltf::Accessor) -> usize {
     match accessor.dimensions() {
         Dimensions::Scalar => 1,
@@ -113,37 +262,387 @@ fn get_accessor_type_size(accessor: &gltf::Accessor) -> usize {
 }
 
 fn get_default_array_stride(accessor: &gltf::Accessor) -> usize {
-    return get_accessor_component_count(accessor) * get_accessor_type_size(accessor);
+    get_accessor_component_count(accessor) * get_accessor_type_size(accessor)
 }
 
-async fn read_buffer(path: &Path, buffer: gltf::Buffer<'_>) -> Result<Vec<u8>, String> {
+/// Decodes `data:[<mime type>];base64,<data>` URIs, which embed buffers and images in the glTF
+/// file itself. Returns `None` for every other URI.
+fn decode_data_uri(uri: &str) -> Option<Result<Vec<u8>, LoadError>> {
+    let rest = uri.strip_prefix("data:")?;
+    let decoded = match rest.split_once(',') {
+        Some((header, data)) if header.ends_with(";base64") => base64::decode(data)
+            .map_err(|error| LoadError::DataUri(format!("malformed base64: {}", error))),
+        Some(_) => Err(LoadError::DataUri(
+            "only base64 encoded data is supported".to_string(),
+        )),
+        None => Err(LoadError::DataUri("no data".to_string())),
+    };
+    Some(decoded)
+}
+
+/// The MIME type in the header of a data URI, `None` if it has none.
+fn data_uri_mime_type(uri: &str) -> Option<&str> {
+    let header = uri.strip_prefix("data:")?.split(',').next()?;
+    header
+        .split(';')
+        .next()
+        .filter(|mime_type| !mime_type.is_empty())
+}
+
+/// `blob` is the binary chunk of a GLB file, which the first buffer may refer to.
+async fn read_buffer(
+    path: &Path,
+    buffer: gltf::Buffer<'_>,
+    blob: Option<&[u8]>,
+) -> Result<Vec<u8>, LoadError> {
     match buffer.source() {
         gltf::buffer::Source::Uri(uri) => {
-            let bin_path = path.join(uri);
+            if let Some(decoded) = decode_data_uri(uri) {
+                return decoded;
+            }
+            load_binary(&path.join(uri).to_string_lossy()).await
+        }
+        gltf::buffer::Source::Bin => blob
+            .map(<[u8]>::to_vec)
+            .ok_or(LoadError::MissingBinaryChunk),
+    }
+}
+
+/// Resolves `file_name` against the address of the page.
+#[cfg(target_arch = "wasm32")]
+fn format_url(file_name: &str) -> Result<reqwest::Url, LoadError> {
+    let invalid = |reason: String| LoadError::InvalidUrl {
+        url: file_name.to_string(),
+        reason,
+    };
+    let href = web_sys::window()
+        .ok_or_else(|| invalid("there's no page to resolve it against".to_string()))?
+        .location()
+        .href()
+        .map_err(|error| invalid(format!("the page has no address: {:?}", error)))?;
+    let base = reqwest::Url::parse(&href).map_err(|error| invalid(error.to_string()))?;
+    base.join(file_name)
+        .map_err(|error| invalid(error.to_string()))
+}
+
+/// What `Texture::from_url` loads the image at `path` from, an absolute URL on the web and the
+/// path itself natively.
+#[cfg(target_arch = "wasm32")]
+fn image_uri(path: &Path) -> Result<String, LoadError> {
+    Ok(format_url(&path.to_string_lossy())?.to_string())
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn image_uri(path: &Path) -> Result<String, LoadError> {
+    Ok(path.to_string_lossy().into_owned())
+}
+
+/// A number from the material's extras, like the texture LOD bias in `{ "lod_bias": -0.5 }`.
+/// Booleans count as 0 and 1.
+fn material_extra(material: &gltf::Material, key: &str) -> Option<f32> {
+    let extras = material.extras().as_ref()?;
+    let extras = serde_json::from_str::<serde_json::Value>(extras.get()).ok()?;
+    let value = extras.get(key)?;
+    value
+        .as_f64()
+        .or_else(|| value.as_bool().map(|flag| if flag { 1.0 } else { 0.0 }))
+        .map(|value| value as f32)
+}
+
+#[cfg(target_arch = "wasm32")]
+pub async fn load_binary(path: &str) -> Result<Vec<u8>, LoadError> {
+    let url = format_url(path)?;
+    let fetch = async {
+        reqwest::get(url.clone())
+            .await?
+            .error_for_status()?
+            .bytes()
+            .await
+    };
+    match fetch.await {
+        Ok(bytes) => Ok(bytes.to_vec()),
+        Err(source) => Err(LoadError::Http {
+            url: url.to_string(),
+            source,
+        }),
+    }
+}
+
+/// Reads `path` relative to the asset directory, see `util::asset_path`.
+#[cfg(not(target_arch = "wasm32"))]
+pub async fn load_binary(path: &str) -> Result<Vec<u8>, LoadError> {
+    let path = crate::util::asset_path(path);
+    std::fs::read(&path).map_err(|source| LoadError::Io {
+        path: path.display().to_string(),
+        source,
+    })
+}
+
+/// Load time choices of `load_gltf`.
+#[derive(Debug, Copy, Clone, Default)]
+pub struct LoadOptions {
+    /// Repacks normals and tangents into `Snorm16x4`, texture coordinates inside [0, 1] into
+    /// `Unorm16x2` and positions into `Snorm16x4` relative to the primitive's bounds. The
+    /// shaders keep reading `f32` vectors, the hardware converts, and the position dequantization
+    /// is folded into the transform the way KHR_mesh_quantization does it. 16 bit normalized
+    /// positions are more precise than half floats over the same range.
+    pub quantize_vertices: bool,
+    /// Images up to this size on both axes are packed into a shared texture atlas, as long as
+    /// every texture using them clamps to the edge
+    pub atlas_max_size: Option<u32>,
+}
+
+/// Textures the materials of a scene can reference.
+pub struct SceneTextures<'t> {
+    /// One per glTF image
+    pub images: &'t [Texture],
+    pub atlas: Option<&'t TextureAtlas>,
+    pub white: &'t Texture,
+    pub default_normal: &'t Texture,
+}
+
+impl SceneTextures<'_> {
+    /// What to bind for a material texture and the uv transform to sample it with, `fallback`
+    /// stands in for missing textures.
+    fn resolve<'t>(
+        &'t self,
+        info: Option<gltf::Texture>,
+        fallback: &'t Texture,
+    ) -> (&'t Texture, [f32; 4]) {
+        let Some(image_index) = info.map(|texture| texture.source().index()) else {
+            return (fallback, IDENTITY_UV_TRANSFORM);
+        };
+        match self
+            .atlas
+            .and_then(|atlas| Some((atlas, atlas.placement(image_index)?)))
+        {
+            Some((atlas, uv_transform)) => (&atlas.texture, uv_transform),
+            None => (&self.images[image_index], IDENTITY_UV_TRANSFORM),
+        }
+    }
+}
+
+/// Vertex attribute sizes of a scene, to see what quantization saved.
+#[derive(Debug, Copy, Clone, Default)]
+pub struct VertexDataSize {
+    /// As stored in the glTF buffers
+    pub source_bytes: u64,
+    /// As uploaded to the GPU
+    pub uploaded_bytes: u64,
+}
+
+/// Rectangle of the render target a draw covers, in pixels.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Viewport {
+    pub x: f32,
+    pub y: f32,
+    pub width: f32,
+    pub height: f32,
+}
+
+/// Render pass state `Scene::draw_pipelines_with` sets before drawing. The default leaves the
+/// pass alone, which covers the whole target.
+#[derive(Debug, Copy, Clone, Default)]
+pub struct DrawParams {
+    pub viewport: Option<Viewport>,
+    /// x, y, width and height in pixels, has to lie within the target
+    pub scissor: Option<[u32; 4]>,
+    /// Draws the primitives farthest from this world space point first, which blending needs.
+    /// `None` keeps the order of the list.
+    pub sort_origin: Option<Point3<f32>>,
+    /// Skips primitives whose world space bounds are outside it. Primitives without bounds are
+    /// always drawn, `None` draws everything.
+    pub frustum: Option<Frustum>,
+}
+
+fn to_snorm16(value: f32) -> i16 {
+    (value.clamp(-1.0, 1.0) * i16::MAX as f32).round() as i16
+}
+
+fn to_unorm16(value: f32) -> u16 {
+    (value.clamp(0.0, 1.0) * u16::MAX as f32).round() as u16
+}
+
+/// Maps the bounds onto [-1, 1] on every axis, flat axes are only offset.
+fn position_dequantization(bounds: &Aabb) -> (Vector3<f32>, Vector3<f32>) {
+    let min = Vector3::from(bounds.min);
+    let max = Vector3::from(bounds.max);
+    let center = (min + max) / 2.0;
+    let half_extent = (max - min) / 2.0;
+    let scale = half_extent.map(|extent| if extent > 0.0 { extent } else { 1.0 });
+    (center, scale)
+}
+
+/// Any unit vector perpendicular to `normal`, picked the same way shader.wgsl picks the cone
+/// directions.
+fn perpendicular(normal: Vector3<f32>) -> Vector3<f32> {
+    if normal.y.abs() < 0.99 {
+        Vector3::unit_y().cross(normal).normalize()
+    } else {
+        Vector3::unit_x()
+    }
+}
 
-            let url = format_url(bin_path.to_str().unwrap());
-            Ok(reqwest::get(url)
-                .await
-                .unwrap()
-                .bytes()
-                .await
-                .unwrap()
-                .to_vec())
+/// Per vertex tangents for primitives that come without them, using Lengyel's method: the
+/// texture space directions of every triangle are summed up at its corners, then made
+/// orthogonal to the normal. W is the handedness of the bitangent, like in glTF. Vertices
+/// without usable texture coordinates get any tangent perpendicular to their normal.
+fn generate_tangents(
+    positions: &[[f32; 3]],
+    normals: &[[f32; 3]],
+    tex_coords: Option<&[[f32; 2]]>,
+    triangles: &[[usize; 3]],
+) -> Vec<[f32; 4]> {
+    let mut tangents = vec![Vector3::new(0.0, 0.0, 0.0); normals.len()];
+    let mut bitangents = tangents.clone();
+    if let Some(tex_coords) = tex_coords {
+        for &[a, b, c] in triangles {
+            let edge1 = Vector3::from(positions[b]) - Vector3::from(positions[a]);
+            let edge2 = Vector3::from(positions[c]) - Vector3::from(positions[a]);
+            let [du1, dv1] = [0, 1].map(|axis| tex_coords[b][axis] - tex_coords[a][axis]);
+            let [du2, dv2] = [0, 1].map(|axis| tex_coords[c][axis] - tex_coords[a][axis]);
+            let determinant = du1 * dv2 - du2 * dv1;
+            // Stretched to a line or a point in texture space
+            if determinant.abs() < 1e-12 {
+                continue;
+            }
+            let tangent = (edge1 * dv2 - edge2 * dv1) / determinant;
+            let bitangent = (edge2 * du1 - edge1 * du2) / determinant;
+            for vertex in [a, b, c] {
+                tangents[vertex] += tangent;
+                bitangents[vertex] += bitangent;
+            }
         }
-        _ => Err("Builtin buffers are unsupported".to_string()),
     }
+
+    normals
+        .iter()
+        .zip(tangents.iter().zip(&bitangents))
+        .map(|(&normal, (&tangent, &bitangent))| {
+            let normal = Vector3::from(normal).normalize();
+            let tangent = tangent - normal * normal.dot(tangent);
+            if tangent.magnitude2() < 1e-12 || !tangent.magnitude2().is_finite() {
+                return perpendicular(normal).extend(1.0).into();
+            }
+            let tangent = tangent.normalize();
+            let handedness = if normal.cross(tangent).dot(bitangent) < 0.0 {
+                -1.0
+            } else {
+                1.0
+            };
+            tangent.extend(handedness).into()
+        })
+        .collect()
 }
 
-fn format_url(file_name: &str) -> reqwest::Url {
-    let window = web_sys::window().unwrap();
-    let location = window.location();
-    let base = reqwest::Url::parse(&location.href().unwrap()).unwrap();
-    base.join(file_name).unwrap()
+/// Tangents of a primitive that has normals but no tangents, `None` for any other.
+fn missing_tangents<'a, 's, F>(
+    primitive: &gltf::Primitive,
+    reader: &gltf::mesh::Reader<'a, 's, F>,
+) -> Option<Vec<[f32; 4]>>
+where
+    F: Clone + Fn(gltf::Buffer<'a>) -> Option<&'s [u8]>,
+{
+    if primitive.get(&gltf::Semantic::Tangents).is_some() {
+        return None;
+    }
+    let positions: Vec<[f32; 3]> = reader.read_positions()?.collect();
+    let normals: Vec<[f32; 3]> = reader.read_normals()?.collect();
+    let tex_coords: Option<Vec<[f32; 2]>> = reader
+        .read_tex_coords(0)
+        .map(|tex_coords| tex_coords.into_f32().collect());
+    let indices: Vec<usize> = match reader.read_indices() {
+        Some(indices) => indices.into_u32().map(|index| index as usize).collect(),
+        None => (0..positions.len()).collect(),
+    };
+    // Other modes only get tangents perpendicular to their normals
+    let triangles: Vec<[usize; 3]> = if primitive.mode() == gltf::mesh::Mode::Triangles {
+        indices
+            .chunks_exact(3)
+            .map(|triangle| [triangle[0], triangle[1], triangle[2]])
+            .filter(|triangle| triangle.iter().all(|&index| index < normals.len()))
+            .collect()
+    } else {
+        Vec::new()
+    };
+    Some(generate_tangents(
+        &positions,
+        &normals,
+        tex_coords.as_deref(),
+        &triangles,
+    ))
 }
 
-pub async fn load_binary(path: &str) -> anyhow::Result<Vec<u8>> {
-    let url = format_url(path);
-    Ok(reqwest::get(url).await?.bytes().await?.to_vec())
+/// Quantized vertex format and data of an attribute, `None` if it's kept as it is.
+fn quantize_attribute<'a, 's, F>(
+    reader: &gltf::mesh::Reader<'a, 's, F>,
+    semantic: &gltf::Semantic,
+    bounds: Option<&Aabb>,
+) -> Option<(wgpu::VertexFormat, Vec<u8>)>
+where
+    F: Clone + Fn(gltf::Buffer<'a>) -> Option<&'s [u8]>,
+{
+    match semantic {
+        gltf::Semantic::Positions => {
+            let (center, scale) = position_dequantization(bounds?);
+            let data: Vec<[i16; 4]> = reader
+                .read_positions()?
+                .map(|position| {
+                    let normalized = (Vector3::from(position) - center).div_element_wise(scale);
+                    [
+                        to_snorm16(normalized.x),
+                        to_snorm16(normalized.y),
+                        to_snorm16(normalized.z),
+                        0,
+                    ]
+                })
+                .collect();
+            Some((
+                wgpu::VertexFormat::Snorm16x4,
+                bytemuck::cast_slice(&data).to_vec(),
+            ))
+        }
+        gltf::Semantic::Normals => {
+            let data: Vec<[i16; 4]> = reader
+                .read_normals()?
+                .map(|[x, y, z]| [to_snorm16(x), to_snorm16(y), to_snorm16(z), 0])
+                .collect();
+            Some((
+                wgpu::VertexFormat::Snorm16x4,
+                bytemuck::cast_slice(&data).to_vec(),
+            ))
+        }
+        gltf::Semantic::Tangents => {
+            let data: Vec<[i16; 4]> = reader
+                .read_tangents()?
+                .map(|tangent| tangent.map(to_snorm16))
+                .collect();
+            Some((
+                wgpu::VertexFormat::Snorm16x4,
+                bytemuck::cast_slice(&data).to_vec(),
+            ))
+        }
+        gltf::Semantic::TexCoords(0) => {
+            let tex_coords: Vec<[f32; 2]> = reader.read_tex_coords(0)?.into_f32().collect();
+            // Tiling texture coordinates would need a scale and offset of their own
+            if tex_coords
+                .iter()
+                .flatten()
+                .any(|&value| !(0.0..=1.0).contains(&value))
+            {
+                return None;
+            }
+            let data: Vec<[u16; 2]> = tex_coords
+                .iter()
+                .map(|tex_coord| tex_coord.map(to_unorm16))
+                .collect();
+            Some((
+                wgpu::VertexFormat::Unorm16x2,
+                bytemuck::cast_slice(&data).to_vec(),
+            ))
+        }
+        _ => None,
+    }
 }
 
 impl From<&gltf::Semantic> for Attribute {
@@ -190,9 +689,12 @@ impl<'a> VertexBufferLayoutBuilder<'a> {
 }
 
 pub struct IndexData {
+    /// Index into `Scene::generated_buffers` for indices the loader wrote itself, the glTF view
+    /// otherwise
     buffer_id: usize,
     format: wgpu::IndexFormat,
     offset: u64,
+    generated: bool,
 }
 
 pub struct PrimitiveRenderData<'a> {
@@ -200,51 +702,298 @@ pub struct PrimitiveRenderData<'a> {
     used_views: Vec<ViewData>,
     draw_count: u32,
     index_data: Option<IndexData>,
+    topology: wgpu::PrimitiveTopology,
     transform_bind_group_id: usize,
+    /// Textures of the material, shared by every material using the same ones
     material_bind_group_id: usize,
+    /// Dynamic offset of the material's factors in `Scene::material_buffer`
+    material_offset: u32,
+    material_index: Option<usize>,
+    double_sided: bool,
+    bounds: Option<Aabb>,
+    /// `bounds` moved by `transform`, kept up to date by `Scene::update_transforms`
+    world_bounds: Option<Aabb>,
+    transform: Matrix4<f32>,
+    /// Transform buffer and position dequantization of primitives with quantized positions,
+    /// which get a transform of their own
+    dequantized_transform: Option<(wgpu::Buffer, Matrix4<f32>)>,
+    pub alpha_mode: gltf::material::AlphaMode,
+    pub mesh_name: Option<String>,
+}
+
+/// Axis aligned bounding box, in the primitive's local space unless stated otherwise.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Aabb {
+    pub min: [f32; 3],
+    pub max: [f32; 3],
+}
+
+impl Aabb {
+    pub fn corners(&self) -> [Point3<f32>; 8] {
+        let [min, max] = [self.min, self.max];
+        [0, 1, 2, 3, 4, 5, 6, 7].map(|corner| Point3 {
+            x: if corner & 1 == 0 { min[0] } else { max[0] },
+            y: if corner & 2 == 0 { min[1] } else { max[1] },
+            z: if corner & 4 == 0 { min[2] } else { max[2] },
+        })
+    }
+
+    /// The box around the corners of this one moved by `transform`.
+    pub fn transformed(&self, transform: Matrix4<f32>) -> Aabb {
+        let mut corners = self
+            .corners()
+            .into_iter()
+            .map(|corner| transform.transform_point(corner).into());
+        let first = corners.next().unwrap();
+        corners.fold(
+            Aabb {
+                min: first,
+                max: first,
+            },
+            |aabb, corner| {
+                aabb.union(&Aabb {
+                    min: corner,
+                    max: corner,
+                })
+            },
+        )
+    }
+
+    pub fn union(&self, other: &Aabb) -> Aabb {
+        Aabb {
+            min: [0, 1, 2].map(|axis| self.min[axis].min(other.min[axis])),
+            max: [0, 1, 2].map(|axis| self.max[axis].max(other.max[axis])),
+        }
+    }
+
+    pub fn center(&self) -> Point3<f32> {
+        Point3::from([0, 1, 2].map(|axis| (self.min[axis] + self.max[axis]) / 2.0))
+    }
+}
+
+impl<'a> PrimitiveRenderData<'a> {
+    /// glTF index of the material, `None` for primitives drawn with `MaterialData::DEFAULT`.
+    pub fn material_index(&self) -> Option<usize> {
+        self.material_index
+    }
+
+    pub fn double_sided(&self) -> bool {
+        self.double_sided
+    }
+
+    /// Taken from the position accessor's bounds, which glTF requires but some exporters skip.
+    pub fn bounds(&self) -> Option<Aabb> {
+        self.bounds
+    }
+
+    /// Shared by every primitive of the same node, so it works as a sorting key for batching
+    /// transform bind group changes. Primitives with quantized positions get one of their own.
+    pub fn transform_index(&self) -> usize {
+        self.transform_bind_group_id
+    }
+
+    /// Local to world transform of the node the primitive belongs to.
+    pub fn transform(&self) -> Matrix4<f32> {
+        self.transform
+    }
+
+    /// Middle of the primitive in world space, the node's origin without bounds.
+    pub fn world_center(&self) -> Point3<f32> {
+        let center = self
+            .bounds
+            .map_or(Point3::new(0.0, 0.0, 0.0), |bounds| bounds.center());
+        self.transform.transform_point(center)
+    }
+
+    /// One buffer per attribute, the shader location of each is its `Attribute` value.
+    pub fn vertex_buffer_layouts(&self) -> Vec<wgpu::VertexBufferLayout<'_>> {
+        self.layouts.iter().map(|builder| builder.build()).collect()
+    }
+
+    /// Topology the pipelines drawing this primitive have to use, with the strip index format
+    /// wgpu needs for indexed strips.
+    pub fn primitive_topology(&self) -> (wgpu::PrimitiveTopology, Option<wgpu::IndexFormat>) {
+        let strip_index_format = self
+            .index_data
+            .as_ref()
+            .filter(|_| self.topology.is_strip())
+            .map(|index_data| index_data.format);
+        (self.topology, strip_index_format)
+    }
+}
+
+/// Everything needed to draw a primitive with a pipeline created outside of `Scene`, see
+/// `Scene::draws`.
+pub struct DrawInfo<'a> {
+    /// Slot and buffer, matching `PrimitiveRenderData::vertex_buffer_layouts`
+    pub vertex_buffers: Vec<(u32, wgpu::BufferSlice<'a>)>,
+    pub index_buffer: Option<(wgpu::BufferSlice<'a>, wgpu::IndexFormat)>,
+    /// Indices to draw if there is an index buffer, vertices otherwise
+    pub draw_count: u32,
+    /// Laid out as `Scene::transform_bind_group_layout`, needs `material_offset` as its dynamic
+    /// offset
+    pub transform_bind_group: &'a wgpu::BindGroup,
+    pub material_offset: u32,
+    /// Laid out as `Scene::material_bind_group_layout`
+    pub material_bind_group: &'a wgpu::BindGroup,
 }
 
 #[derive(Debug)]
 pub struct ViewData {
+    /// Index into `Scene::generated_buffers` for attributes the loader wrote itself, the glTF
+    /// view otherwise
     pub view_index: usize,
     pub offset: u64,
+    pub generated: bool,
 }
 #[repr(C)]
 #[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
 pub struct MaterialData {
-    base_color_factor: [f32; 4],
-    metallic_factor: f32,
-    roughness_factor: f32,
-    alpha_cut_off: f32,
-    filler: u32,
+    pub base_color_factor: [f32; 4],
+    pub metallic_factor: f32,
+    pub roughness_factor: f32,
+    pub alpha_cut_off: f32,
+    pub lod_bias: f32,
+    /// Scale in xy and offset in zw of the texture coordinates, places atlased textures
+    pub base_color_uv_transform: [f32; 4],
+    pub metallic_roughness_uv_transform: [f32; 4],
+    pub normal_uv_transform: [f32; 4],
+    /// Linear light emitted by the surface, multiplied with the emissive texture. W is unused.
+    pub emissive_factor: [f32; 4],
+    pub emissive_uv_transform: [f32; 4],
+    /// How much of the planar reflection the surface shows at most, 0 to 1. Set with
+    /// `{ "planar_reflection": true }` in the material's extras, the reflecting surfaces have to
+    /// be flat and level.
+    pub planar_reflection: f32,
+    /// 1 if back faces are lit like front faces, which the voxelization can't tell apart
+    pub double_sided: f32,
+    pub filler: [f32; 2],
+}
+
+impl MaterialData {
+    /// Used by primitives without a material, and mirrored by the neutral material debug mode in
+    /// shader.wgsl: mid-gray, fully rough dielectric.
+    pub const DEFAULT: MaterialData = MaterialData {
+        base_color_factor: [0.5, 0.5, 0.5, 1.0],
+        metallic_factor: 0.0,
+        roughness_factor: 1.0,
+        alpha_cut_off: 0.0,
+        lod_bias: 0.0,
+        base_color_uv_transform: IDENTITY_UV_TRANSFORM,
+        metallic_roughness_uv_transform: IDENTITY_UV_TRANSFORM,
+        normal_uv_transform: IDENTITY_UV_TRANSFORM,
+        emissive_factor: [0.0; 4],
+        emissive_uv_transform: IDENTITY_UV_TRANSFORM,
+        planar_reflection: 0.0,
+        double_sided: 0.0,
+        filler: [0.0; 2],
+    };
+}
+
+/// Distance between the materials in `Scene::material_buffer`, the largest uniform offset
+/// alignment WebGPU allows, so it works on every device
+const MATERIAL_STRIDE: u64 = 256;
+
+crate::assert_uniform_layout!(MaterialData, size: 128, {
+    base_color_factor: 0,
+    metallic_factor: 16,
+    roughness_factor: 20,
+    alpha_cut_off: 24,
+    lod_bias: 28,
+    base_color_uv_transform: 32,
+    metallic_roughness_uv_transform: 48,
+    normal_uv_transform: 64,
+    emissive_factor: 80,
+    emissive_uv_transform: 96,
+    planar_reflection: 112,
+    double_sided: 116,
+    filler: 120,
+});
+
+/// A glTF material and where its factors are in `Scene::material_buffer`, kept around so the
+/// factors can be edited at runtime.
+pub struct SceneMaterial {
+    pub name: Option<String>,
+    pub data: MaterialData,
+    offset: u32,
+}
+
+/// Fixed function state a pipeline list was generated with, kept so the list can be regenerated
+/// later on.
+struct PipelineState {
+    targets: Vec<Option<wgpu::ColorTargetState>>,
+    depth_stencil: Option<wgpu::DepthStencilState>,
+    cull_back_face: bool,
+}
+
+/// Regular depth testing and writing without stencil, what most scene passes use.
+pub fn default_depth_stencil(format: wgpu::TextureFormat) -> wgpu::DepthStencilState {
+    wgpu::DepthStencilState {
+        format,
+        depth_write_enabled: true,
+        depth_compare: wgpu::CompareFunction::Less,
+        stencil: wgpu::StencilState::default(),
+        bias: wgpu::DepthBiasState::default(),
+    }
 }
 
 pub struct PipelineData {
     pipeline_list: Vec<wgpu::RenderPipeline>,
+    /// Indices into `Scene::render_datas`, one for each pipeline in `pipeline_list`
+    primitive_indices: Vec<usize>,
+    /// Length of `Scene::render_datas` when the list was generated or last extended
+    covered_primitives: usize,
     bind_group_start_index: u32,
+    layout: wgpu::PipelineLayout,
+    state: PipelineState,
 }
 
 pub struct Scene<'a> {
     pub render_datas: Vec<PrimitiveRenderData<'a>>,
     pipeline_lists: HashMap<String, PipelineData>,
     buffers: HashMap<usize, wgpu::Buffer>,
+    /// Vertex buffers the loader wrote itself, quantized attributes and generated tangents, one
+    /// per attribute
+    generated_buffers: Vec<wgpu::Buffer>,
+    vertex_data_size: VertexDataSize,
+    /// The transform of a node and the factors of a material, picked by dynamic offset
     transform_bind_group_layout: wgpu::BindGroupLayout,
     bind_groups: Vec<wgpu::BindGroup>,
+    /// Textures of a material
     material_bind_group_layout: wgpu::BindGroupLayout,
+    /// Factors of every material, `MATERIAL_STRIDE` apart. The first slot holds the default for
+    /// primitives without a material, glTF material `i` is in slot `i + 1`.
+    material_buffer: wgpu::Buffer,
+    /// Keyed by glTF material index, primitives without a material use an uneditable default
+    materials: HashMap<usize, SceneMaterial>,
+    /// World space bounds of the primitives loaded so far
+    bounds: Option<Aabb>,
+    /// Transforms of the loaded nodes, with `node_data` at the same indices
+    hierarchy: TransformHierarchy,
+    node_data: Vec<NodeData>,
+    /// Index into `hierarchy` by glTF node index
+    node_slots: HashMap<usize, usize>,
+}
+
+/// What has to be rewritten when the world transform of a node changes.
+struct NodeData {
+    transform_buffer: wgpu::Buffer,
+    /// Indices into `Scene::render_datas` of the primitives of the node's mesh
+    primitives: Vec<usize>,
 }
 
 impl Scene<'_> {
     fn create_buffer_if_new(
         device: &wgpu::Device,
         queue: &wgpu::Queue,
-        buffer_contents: &Vec<Vec<u8>>,
+        buffer_contents: &[Vec<u8>],
         buffers: &mut HashMap<usize, wgpu::Buffer>,
         view: &View,
         usage: wgpu::BufferUsages,
     ) {
-        if !buffers.contains_key(&view.index()) {
+        if let Entry::Vacant(entry) = buffers.entry(view.index()) {
             let mut size = view.length();
-            if size % 4 != 0 {
+            if !size.is_multiple_of(4) {
                 size = (size / 4 + 1) * 4;
             }
             let buffer = device.create_buffer(&wgpu::BufferDescriptor {
@@ -254,53 +1003,68 @@ impl Scene<'_> {
                 mapped_at_creation: false,
             });
 
-            queue.write_buffer(
-                &buffer,
-                0,
-                &buffer_contents[view.buffer().index()][view.offset()..view.offset() + size],
-            );
-            buffers.insert(view.index(), buffer);
+            // Views at the very end of a buffer can't be read padded, e.g. in a GLB whose binary
+            // chunk isn't padded to 4 bytes by its exporter
+            let content = &buffer_contents[view.buffer().index()];
+            let end = (view.offset() + size).min(content.len());
+            let mut data = content[view.offset()..end].to_vec();
+            data.resize(size, 0);
+            queue.write_buffer(&buffer, 0, &data);
+            entry.insert(buffer);
         }
     }
 
-    pub fn from_gltf(
+    fn create_transform_bind_group(
         device: &wgpu::Device,
-        queue: &wgpu::Queue,
-        buffer_contents: &Vec<Vec<u8>>,
-        scene: &gltf::Scene,
-        white_texture: &Texture,
-        default_normal_texture: &Texture,
-        images: &Vec<Texture>,
-    ) -> Self {
-        let mut buffers = HashMap::<usize, wgpu::Buffer>::new();
-        let mut render_datas = Vec::new();
-        let mut nodes: Vec<(Node, Matrix4<f32>)> = scene
-            .nodes()
-            .map(|node| (node, Matrix4::identity()))
-            .collect();
+        layout: &wgpu::BindGroupLayout,
+        material_buffer: &wgpu::Buffer,
+        transform: Matrix4<f32>,
+    ) -> (wgpu::BindGroup, wgpu::Buffer) {
+        let transform_content: [[f32; 4]; 4] = transform.into();
 
-        let transform_bind_group_layout =
-            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-                label: None,
-                entries: &[wgpu::BindGroupLayoutEntry {
+        let transform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: None,
+            contents: bytemuck::bytes_of(&transform_content),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: None,
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
                     binding: 0,
-                    visibility: wgpu::ShaderStages::VERTEX,
-                    ty: wgpu::BindingType::Buffer {
-                        ty: wgpu::BufferBindingType::Uniform,
-                        has_dynamic_offset: false,
-                        min_binding_size: None,
-                    },
-                    count: None,
-                }],
-            });
+                    resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+                        buffer: &transform_buffer,
+                        offset: 0,
+                        size: None,
+                    }),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+                        buffer: material_buffer,
+                        offset: 0,
+                        size: wgpu::BufferSize::new(std::mem::size_of::<MaterialData>() as u64),
+                    }),
+                },
+            ],
+        });
+        (bind_group, transform_buffer)
+    }
 
-        let material_bind_group_layout =
+    /// A scene without primitives, `SceneLoader::load_batch` adds them. `material_count` is the
+    /// number of glTF materials they can use.
+    pub fn new(device: &wgpu::Device, material_count: usize) -> Self {
+        // Materials are looked up by dynamic offset instead of a group of their own, which would
+        // be a fifth bind group for the main pass, more than WebGPU guarantees
+        let transform_bind_group_layout =
             device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
                 label: None,
                 entries: &[
                     wgpu::BindGroupLayoutEntry {
                         binding: 0,
-                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        visibility: wgpu::ShaderStages::VERTEX,
                         ty: wgpu::BindingType::Buffer {
                             ty: wgpu::BufferBindingType::Uniform,
                             has_dynamic_offset: false,
@@ -308,6 +1072,32 @@ impl Scene<'_> {
                         },
                         count: None,
                     },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 1,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Uniform,
+                            has_dynamic_offset: true,
+                            min_binding_size: wgpu::BufferSize::new(
+                                std::mem::size_of::<MaterialData>() as u64,
+                            ),
+                        },
+                        count: None,
+                    },
+                ],
+            });
+
+        let material_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Material buffer"),
+            size: (material_count as u64 + 1) * MATERIAL_STRIDE,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let material_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                label: None,
+                entries: &[
                     wgpu::BindGroupLayoutEntry {
                         binding: 1,
                         visibility: wgpu::ShaderStages::FRAGMENT,
@@ -356,202 +1146,206 @@ impl Scene<'_> {
                         ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
                         count: None,
                     },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 7,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Texture {
+                            sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                            view_dimension: wgpu::TextureViewDimension::D2,
+                            multisampled: false,
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 8,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                        count: None,
+                    },
                 ],
             });
 
-        let mut bind_groups = Vec::new();
+        Self {
+            render_datas: Vec::new(),
+            pipeline_lists: HashMap::new(),
+            buffers: HashMap::new(),
+            generated_buffers: Vec::new(),
+            vertex_data_size: VertexDataSize::default(),
+            transform_bind_group_layout,
+            material_bind_group_layout,
+            material_buffer,
+            bind_groups: Vec::new(),
+            materials: HashMap::new(),
+            bounds: None,
+            hierarchy: TransformHierarchy::default(),
+            node_data: Vec::new(),
+            node_slots: HashMap::new(),
+        }
+    }
 
-        while nodes.len() > 0 {
-            let (node, parent_transform) = nodes.pop().unwrap();
+    /// World space box around every primitive loaded so far, `None` while there are none or if
+    /// their position accessors lack the bounds glTF requires. Stays as loaded when nodes move.
+    pub fn bounds(&self) -> Option<Aabb> {
+        self.bounds
+    }
 
-            let local_transform = Matrix4::from(node.transform().matrix());
-            let total_transform = parent_transform * local_transform;
+    /// Moves the node with the given glTF index relative to its parent, it and everything below
+    /// it follow with the next `update_transforms`.
+    pub fn set_node_transform(
+        &mut self,
+        node_index: usize,
+        transform: Matrix4<f32>,
+    ) -> Result<(), String> {
+        let slot = *self
+            .node_slots
+            .get(&node_index)
+            .ok_or_else(|| format!("Node #{} isn't loaded", node_index))?;
+        self.hierarchy.set_local_transform(slot, transform);
+        Ok(())
+    }
 
-            for child in node.children() {
-                nodes.push((child, total_transform));
+    /// Recomputes the world transforms of the nodes that moved since the last call and uploads
+    /// them. Returns the indices of the primitives that moved along, into `render_datas`.
+    pub fn update_transforms(&mut self, queue: &wgpu::Queue) -> Vec<usize> {
+        let mut moved_primitives = Vec::new();
+        for slot in self.hierarchy.update() {
+            let world_transform = self.hierarchy.world_transform(slot);
+            let node_data = &self.node_data[slot];
+            let transform_content: [[f32; 4]; 4] = world_transform.into();
+            queue.write_buffer(
+                &node_data.transform_buffer,
+                0,
+                bytemuck::bytes_of(&transform_content),
+            );
+            for &index in &node_data.primitives {
+                let render_data = &mut self.render_datas[index];
+                render_data.transform = world_transform;
+                render_data.world_bounds = render_data
+                    .bounds
+                    .map(|bounds| bounds.transformed(world_transform));
+                if let Some((buffer, dequantization)) = &render_data.dequantized_transform {
+                    let transform_content: [[f32; 4]; 4] =
+                        (world_transform * dequantization).into();
+                    queue.write_buffer(buffer, 0, bytemuck::bytes_of(&transform_content));
+                }
             }
+            moved_primitives.extend_from_slice(&node_data.primitives);
+        }
+        moved_primitives
+    }
 
-            let transform_content: [[f32; 4]; 4] = total_transform.into();
+    pub fn vertex_data_size(&self) -> VertexDataSize {
+        self.vertex_data_size
+    }
 
-            let transform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-                label: None,
-                contents: bytemuck::bytes_of(&transform_content),
-                usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
-            });
+    fn vertex_buffer(&self, view_data: &ViewData) -> &wgpu::Buffer {
+        if view_data.generated {
+            &self.generated_buffers[view_data.view_index]
+        } else {
+            &self.buffers[&view_data.view_index]
+        }
+    }
 
-            let transform_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-                label: None,
-                layout: &transform_bind_group_layout,
-                entries: &[wgpu::BindGroupEntry {
-                    binding: 0,
-                    resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
-                        buffer: &transform_buffer,
-                        offset: 0,
-                        size: None,
-                    }),
-                }],
-            });
+    fn index_buffer(&self, index_data: &IndexData) -> &wgpu::Buffer {
+        if index_data.generated {
+            &self.generated_buffers[index_data.buffer_id]
+        } else {
+            &self.buffers[&index_data.buffer_id]
+        }
+    }
 
-            let transform_bind_group_id = bind_groups.len();
-            bind_groups.push(transform_bind_group);
+    /// Height of the plane the planar reflection mirrors the scene about, the top of the highest
+    /// primitive with a reflective material. `None` if no material reflects.
+    pub fn planar_reflection_height(&self) -> Option<f32> {
+        self.render_datas
+            .iter()
+            .filter(|render_data| {
+                render_data
+                    .material_index
+                    .and_then(|index| self.materials.get(&index))
+                    .is_some_and(|material| material.data.planar_reflection > 0.0)
+            })
+            .map(|render_data| match render_data.world_bounds {
+                Some(bounds) => bounds.max[1],
+                None => render_data.world_center().y,
+            })
+            .reduce(f32::max)
+    }
 
-            let mesh = match node.mesh() {
-                Some(mesh) => mesh,
-                None => continue,
-            };
-            for primitive in mesh.primitives() {
-                let mut layouts = Vec::<VertexBufferLayoutBuilder>::new();
-                let mut used_views = Vec::<ViewData>::new();
-                let mut draw_count = 0;
+    pub fn material(&self, material_index: usize) -> Option<&SceneMaterial> {
+        self.materials.get(&material_index)
+    }
 
-                let material = primitive.material();
-                let pbr = material.pbr_metallic_roughness();
+    /// Indices of the materials called `name`, glTF doesn't require names to be unique.
+    pub fn find_materials(&self, name: &str) -> Vec<usize> {
+        let mut indices: Vec<usize> = self
+            .materials
+            .iter()
+            .filter(|(_, material)| material.name.as_deref() == Some(name))
+            .map(|(&index, _)| index)
+            .collect();
+        indices.sort_unstable();
+        indices
+    }
 
-                let base_color_texture = match pbr.base_color_texture() {
-                    Some(info) => &images[info.texture().source().index()],
-                    None => white_texture,
-                };
+    /// Replaces the factors of the glTF material at `material_index`, every primitive using it
+    /// picks the change up on the next draw. Textures stay the same.
+    pub fn set_material(&mut self, queue: &wgpu::Queue, material_index: usize, data: MaterialData) {
+        let Some(material) = self.materials.get_mut(&material_index) else {
+            log::warn!("No material #{} to edit", material_index);
+            return;
+        };
+        queue.write_buffer(
+            &self.material_buffer,
+            material.offset as u64,
+            bytemuck::cast_slice(&[data]),
+        );
+        material.data = data;
+    }
 
-                let metallic_roughness_texture = match pbr.metallic_roughness_texture() {
-                    Some(info) => &images[info.texture().source().index()],
-                    None => white_texture,
-                };
+    pub fn transform_bind_group_layout(&self) -> &wgpu::BindGroupLayout {
+        &self.transform_bind_group_layout
+    }
 
-                let normal_texture = match material.normal_texture() {
-                    Some(info) => &images[info.texture().source().index()],
-                    None => default_normal_texture,
-                };
+    pub fn material_bind_group_layout(&self) -> &wgpu::BindGroupLayout {
+        &self.material_bind_group_layout
+    }
 
-                let material_data = MaterialData {
-                    base_color_factor: pbr.base_color_factor(),
-                    metallic_factor: pbr.metallic_factor(),
-                    roughness_factor: pbr.roughness_factor(),
-                    alpha_cut_off: material.alpha_cutoff().unwrap_or(0f32),
-                    filler: 0,
+    /// Every primitive with the resources to draw it, for renderers that build their own pass
+    /// lists instead of using `generate_pipeline`.
+    pub fn draws(&self) -> impl Iterator<Item = (usize, DrawInfo<'_>)> + '_ {
+        self.render_datas
+            .iter()
+            .enumerate()
+            .map(|(index, render_data)| {
+                let vertex_buffers = render_data
+                    .used_views
+                    .iter()
+                    .enumerate()
+                    .map(|(slot, view_data)| {
+                        let buffer = self.vertex_buffer(view_data);
+                        (slot as u32, buffer.slice(view_data.offset..))
+                    })
+                    .collect();
+                let index_buffer = render_data.index_data.as_ref().map(|index_data| {
+                    let buffer = self.index_buffer(index_data);
+                    (buffer.slice(index_data.offset..), index_data.format)
+                });
+                let draw_info = DrawInfo {
+                    vertex_buffers,
+                    index_buffer,
+                    draw_count: render_data.draw_count,
+                    transform_bind_group: &self.bind_groups[render_data.transform_bind_group_id],
+                    material_offset: render_data.material_offset,
+                    material_bind_group: &self.bind_groups[render_data.material_bind_group_id],
                 };
+                (index, draw_info)
+            })
+    }
 
-                let material_buffer =
-                    device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-                        label: None,
-                        contents: bytemuck::cast_slice(&[material_data]),
-                        usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
-                    });
-
-                let material_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-                    label: None,
-                    layout: &material_bind_group_layout,
-                    entries: &[
-                        wgpu::BindGroupEntry {
-                            binding: 0,
-                            resource: material_buffer.as_entire_binding(),
-                        },
-                        wgpu::BindGroupEntry {
-                            binding: 1,
-                            resource: wgpu::BindingResource::TextureView(&base_color_texture.view),
-                        },
-                        wgpu::BindGroupEntry {
-                            binding: 2,
-                            resource: wgpu::BindingResource::Sampler(&base_color_texture.sampler),
-                        },
-                        wgpu::BindGroupEntry {
-                            binding: 3,
-                            resource: wgpu::BindingResource::TextureView(
-                                &metallic_roughness_texture.view,
-                            ),
-                        },
-                        wgpu::BindGroupEntry {
-                            binding: 4,
-                            resource: wgpu::BindingResource::Sampler(
-                                &metallic_roughness_texture.sampler,
-                            ),
-                        },
-                        wgpu::BindGroupEntry {
-                            binding: 5,
-                            resource: wgpu::BindingResource::TextureView(&normal_texture.view),
-                        },
-                        wgpu::BindGroupEntry {
-                            binding: 6,
-                            resource: wgpu::BindingResource::Sampler(&normal_texture.sampler),
-                        },
-                    ],
-                });
-
-                let material_bind_group_id = bind_groups.len();
-                bind_groups.push(material_bind_group);
-
-                for (semantic, accessor) in primitive.attributes() {
-                    let view = match accessor.view() {
-                        Some(view) => view,
-                        None => continue,
-                    };
-                    Self::create_buffer_if_new(
-                        device,
-                        queue,
-                        buffer_contents,
-                        &mut buffers,
-                        &view,
-                        wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
-                    );
-
-                    draw_count = accessor.count() as u32;
-                    layouts.push(VertexBufferLayoutBuilder::new(
-                        view.stride().unwrap_or(get_default_array_stride(&accessor)) as u64,
-                        wgpu::VertexStepMode::Vertex,
-                        vec![wgpu::VertexAttribute {
-                            format: gltf_accessor_to_wgpu(&accessor).unwrap(),
-                            offset: 0,
-                            shader_location: Attribute::from(&semantic) as u32,
-                        }],
-                    ));
-
-                    used_views.push(ViewData {
-                        view_index: view.index(),
-                        offset: accessor.offset() as u64,
-                    });
-                }
-
-                let index_data = match primitive.indices() {
-                    Some(accessor) => {
-                        let view = accessor.view().unwrap();
-                        Self::create_buffer_if_new(
-                            device,
-                            queue,
-                            buffer_contents,
-                            &mut buffers,
-                            &view,
-                            wgpu::BufferUsages::INDEX | wgpu::BufferUsages::COPY_DST,
-                        );
-                        draw_count = accessor.count() as u32;
-                        Some(IndexData {
-                            buffer_id: view.index(),
-                            format: gltf_accessor_to_indexformat(&accessor).unwrap(),
-                            offset: accessor.offset() as u64,
-                        })
-                    }
-                    None => None,
-                };
-
-                render_datas.push(PrimitiveRenderData {
-                    layouts,
-                    used_views,
-                    draw_count,
-                    index_data,
-                    transform_bind_group_id,
-                    material_bind_group_id,
-                });
-            }
-        }
-
-        Self {
-            render_datas,
-            pipeline_lists: HashMap::new(),
-            buffers,
-            transform_bind_group_layout,
-            material_bind_group_layout,
-            bind_groups,
-        }
-    }
-
+    /// Generates one pipeline per primitive, laid out as `bind_group_layouts` followed by the
+    /// transform and material groups. `cull_back_face` leaves double-sided primitives alone.
+    #[allow(clippy::too_many_arguments)]
     pub fn generate_pipeline(
         &mut self,
         device: &wgpu::Device,
@@ -559,114 +1353,289 @@ impl Scene<'_> {
         name: &str,
         bind_group_layouts: &[&wgpu::BindGroupLayout],
         targets: &[Option<wgpu::ColorTargetState>],
-        depth: bool,
+        depth_stencil: Option<wgpu::DepthStencilState>,
         cull_back_face: bool,
+        filter: Option<&dyn Fn(&PrimitiveRenderData) -> bool>,
     ) {
-        let mut pipelines = Vec::<wgpu::RenderPipeline>::new();
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some(format!("{} pipeline layout", name).as_str()),
+            bind_group_layouts: &[
+                bind_group_layouts,
+                &[
+                    &self.transform_bind_group_layout,
+                    &self.material_bind_group_layout,
+                ],
+            ]
+            .concat(),
+            push_constant_ranges: &[],
+        });
+        self.generate_pipeline_with_layout(
+            device,
+            shader,
+            name,
+            layout,
+            bind_group_layouts.len() as u32,
+            targets,
+            depth_stencil,
+            cull_back_face,
+            filter,
+        );
+    }
 
-        for render_data in &self.render_datas {
-            let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-                label: None,
-                bind_group_layouts: &[
-                    bind_group_layouts,
-                    &[
-                        &self.transform_bind_group_layout,
-                        &self.material_bind_group_layout,
-                    ],
-                ]
-                .concat(),
-                push_constant_ranges: &[],
-            });
+    /// Like `generate_pipeline`, but with a pipeline layout created by the caller, which has to
+    /// hold the transform and material layouts at `bind_group_start_index` and the one after it.
+    /// `draw_pipelines` binds them there and leaves every other group to the caller.
+    #[allow(clippy::too_many_arguments)]
+    pub fn generate_pipeline_with_layout(
+        &mut self,
+        device: &wgpu::Device,
+        shader: &Shader,
+        name: &str,
+        layout: wgpu::PipelineLayout,
+        bind_group_start_index: u32,
+        targets: &[Option<wgpu::ColorTargetState>],
+        depth_stencil: Option<wgpu::DepthStencilState>,
+        cull_back_face: bool,
+        filter: Option<&dyn Fn(&PrimitiveRenderData) -> bool>,
+    ) {
+        let primitive_indices: Vec<usize> = self
+            .render_datas
+            .iter()
+            .enumerate()
+            .filter(|(_, render_data)| filter.is_none_or(|filter| filter(render_data)))
+            .map(|(index, _)| index)
+            .collect();
 
-            let layouts: Vec<wgpu::VertexBufferLayout> = render_data
-                .layouts
-                .iter()
-                .map(|builder| builder.build())
-                .collect();
+        let state = PipelineState {
+            targets: targets.to_vec(),
+            depth_stencil,
+            cull_back_face,
+        };
 
-            let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-                label: None,
-                layout: Some(&pipeline_layout),
-                vertex: wgpu::VertexState {
-                    module: &shader.module,
-                    entry_point: &shader.vs_entry,
-                    buffers: &layouts,
-                },
-                primitive: wgpu::PrimitiveState {
-                    topology: wgpu::PrimitiveTopology::TriangleList,
-                    strip_index_format: None,
-                    front_face: wgpu::FrontFace::Ccw,
-                    cull_mode: if cull_back_face {
-                        Some(wgpu::Face::Back)
-                    } else {
-                        None
-                    },
-                    unclipped_depth: false,
-                    polygon_mode: wgpu::PolygonMode::Fill,
-                    conservative: false,
-                },
-                depth_stencil: if depth {
-                    Some(wgpu::DepthStencilState {
-                        format: crate::texture::Texture::DEPTH_FORMAT,
-                        depth_write_enabled: true,
-                        depth_compare: wgpu::CompareFunction::Less,
-                        stencil: wgpu::StencilState::default(),
-                        bias: wgpu::DepthBiasState::default(),
-                    })
-                } else {
-                    None
-                },
-                multisample: wgpu::MultisampleState {
-                    count: 1,
-                    mask: !0,
-                    alpha_to_coverage_enabled: false,
-                },
-                fragment: Some(wgpu::FragmentState {
-                    module: &shader.module,
-                    entry_point: &shader.fs_entry,
-                    targets,
-                }),
-                multiview: None,
-            });
-            pipelines.push(pipeline);
-        }
         self.pipeline_lists.insert(
             name.to_string(),
             PipelineData {
-                pipeline_list: pipelines,
-                bind_group_start_index: bind_group_layouts.len() as u32,
+                pipeline_list: self.create_pipelines(
+                    device,
+                    shader,
+                    &layout,
+                    &state,
+                    &primitive_indices,
+                ),
+                primitive_indices,
+                covered_primitives: self.render_datas.len(),
+                bind_group_start_index,
+                layout,
+                state,
             },
         );
     }
 
+    /// Rebuilds an existing pipeline list in place with a new shader, reusing the pipeline layout
+    /// it was generated with. Passing `targets` replaces the color targets, e.g. after the surface
+    /// format changed.
+    ///
+    /// wgpu doesn't expose asynchronous pipeline creation yet, so this compiles every pipeline of
+    /// the list before returning.
+    pub fn regenerate_pipeline(
+        &mut self,
+        device: &wgpu::Device,
+        shader: &Shader,
+        name: &str,
+        targets: Option<&[Option<wgpu::ColorTargetState>]>,
+    ) -> Result<(), PipelineError> {
+        let mut pipeline_data = self
+            .pipeline_lists
+            .remove(name)
+            .ok_or_else(|| PipelineError::UnknownList(name.to_string()))?;
+        if let Some(targets) = targets {
+            pipeline_data.state.targets = targets.to_vec();
+        }
+        pipeline_data.pipeline_list = self.create_pipelines(
+            device,
+            shader,
+            &pipeline_data.layout,
+            &pipeline_data.state,
+            &pipeline_data.primitive_indices,
+        );
+        self.pipeline_lists.insert(name.to_string(), pipeline_data);
+        Ok(())
+    }
+
+    /// Adds pipelines to an existing list for the primitives a `SceneLoader` loaded since it was
+    /// generated or last extended. `shader` and `filter` should be the ones it was generated with.
+    pub fn extend_pipeline(
+        &mut self,
+        device: &wgpu::Device,
+        shader: &Shader,
+        name: &str,
+        filter: Option<&dyn Fn(&PrimitiveRenderData) -> bool>,
+    ) -> Result<(), PipelineError> {
+        let mut pipeline_data = self
+            .pipeline_lists
+            .remove(name)
+            .ok_or_else(|| PipelineError::UnknownList(name.to_string()))?;
+        let primitive_indices: Vec<usize> = (pipeline_data.covered_primitives
+            ..self.render_datas.len())
+            .filter(|&index| filter.is_none_or(|filter| filter(&self.render_datas[index])))
+            .collect();
+        pipeline_data.pipeline_list.extend(self.create_pipelines(
+            device,
+            shader,
+            &pipeline_data.layout,
+            &pipeline_data.state,
+            &primitive_indices,
+        ));
+        pipeline_data.primitive_indices.extend(primitive_indices);
+        pipeline_data.covered_primitives = self.render_datas.len();
+        self.pipeline_lists.insert(name.to_string(), pipeline_data);
+        Ok(())
+    }
+
+    /// Returns whether a pipeline list with the given name existed.
+    pub fn remove_pipeline(&mut self, name: &str) -> bool {
+        self.pipeline_lists.remove(name).is_some()
+    }
+
+    fn create_pipelines(
+        &self,
+        device: &wgpu::Device,
+        shader: &Shader,
+        layout: &wgpu::PipelineLayout,
+        state: &PipelineState,
+        primitive_indices: &[usize],
+    ) -> Vec<wgpu::RenderPipeline> {
+        primitive_indices
+            .iter()
+            .map(|&index| {
+                let render_data = &self.render_datas[index];
+                let layouts: Vec<wgpu::VertexBufferLayout> = render_data
+                    .layouts
+                    .iter()
+                    .map(|builder| builder.build())
+                    .collect();
+                let (topology, strip_index_format) = render_data.primitive_topology();
+
+                device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                    label: None,
+                    layout: Some(layout),
+                    vertex: wgpu::VertexState {
+                        module: &shader.module,
+                        entry_point: &shader.vs_entry,
+                        buffers: &layouts,
+                    },
+                    primitive: wgpu::PrimitiveState {
+                        topology,
+                        strip_index_format,
+                        front_face: wgpu::FrontFace::Ccw,
+                        // Double-sided materials are seen from behind whatever the list culls
+                        cull_mode: if state.cull_back_face && !render_data.double_sided {
+                            Some(wgpu::Face::Back)
+                        } else {
+                            None
+                        },
+                        unclipped_depth: false,
+                        polygon_mode: wgpu::PolygonMode::Fill,
+                        conservative: false,
+                    },
+                    depth_stencil: state.depth_stencil.clone(),
+                    multisample: wgpu::MultisampleState {
+                        count: 1,
+                        mask: !0,
+                        alpha_to_coverage_enabled: false,
+                    },
+                    fragment: Some(wgpu::FragmentState {
+                        module: &shader.module,
+                        entry_point: shader.fs_entry_for(render_data.alpha_mode),
+                        targets: &state.targets,
+                    }),
+                    multiview: None,
+                })
+            })
+            .collect()
+    }
+
     pub fn draw_pipelines<'a>(&'a self, name: &str, render_pass: &mut wgpu::RenderPass<'a>) {
-        for (pipeline, render_data) in
-            zip(&self.pipeline_lists[name].pipeline_list, &self.render_datas)
-        {
-            render_pass.set_pipeline(&pipeline);
+        self.draw_pipelines_with(name, render_pass, &DrawParams::default());
+    }
+
+    /// Draws the pipeline list `name` into part of the target, e.g. one tile of an atlas. The
+    /// viewport and scissor stay set on the pass afterwards.
+    pub fn draw_pipelines_with<'a>(
+        &'a self,
+        name: &str,
+        render_pass: &mut wgpu::RenderPass<'a>,
+        params: &DrawParams,
+    ) {
+        if let Some(viewport) = params.viewport {
+            render_pass.set_viewport(
+                viewport.x,
+                viewport.y,
+                viewport.width,
+                viewport.height,
+                0.0,
+                1.0,
+            );
+        }
+        if let Some([x, y, width, height]) = params.scissor {
+            render_pass.set_scissor_rect(x, y, width, height);
+        }
+
+        let pipeline_data = &self.pipeline_lists[name];
+        assert!(
+            pipeline_data.pipeline_list.len() == pipeline_data.primitive_indices.len()
+                && pipeline_data
+                    .primitive_indices
+                    .iter()
+                    .all(|&index| index < self.render_datas.len()),
+            "Pipeline list \"{}\" is out of date with the scene's primitives",
+            name
+        );
+
+        let mut draw_order: Vec<usize> = (0..pipeline_data.pipeline_list.len())
+            .filter(|&position| {
+                let render_data = &self.render_datas[pipeline_data.primitive_indices[position]];
+                match (&params.frustum, &render_data.world_bounds) {
+                    (Some(frustum), Some(bounds)) => frustum.intersects(bounds),
+                    _ => true,
+                }
+            })
+            .collect();
+        if let Some(origin) = params.sort_origin {
+            let distance2 = |position: usize| {
+                let index = pipeline_data.primitive_indices[position];
+                self.render_datas[index].world_center().distance2(origin)
+            };
+            draw_order.sort_by(|&a, &b| distance2(b).total_cmp(&distance2(a)));
+        }
+
+        // Materials with the same textures share a bind group, it's only set when it changes
+        let mut bound_material_bind_group_id = None;
+        for position in draw_order {
+            let pipeline = &pipeline_data.pipeline_list[position];
+            let render_data = &self.render_datas[pipeline_data.primitive_indices[position]];
+            render_pass.set_pipeline(pipeline);
             for (slot, view_data) in render_data.used_views.iter().enumerate() {
-                let buffer = &self.buffers[&view_data.view_index];
+                let buffer = self.vertex_buffer(view_data);
                 render_pass.set_vertex_buffer(slot as u32, buffer.slice(&view_data.offset..));
             }
             render_pass.set_bind_group(
-                self.pipeline_lists[name].bind_group_start_index,
+                pipeline_data.bind_group_start_index,
                 &self.bind_groups[render_data.transform_bind_group_id],
-                &[],
-            );
-            render_pass.set_bind_group(
-                self.pipeline_lists[name].bind_group_start_index + 1,
-                &self.bind_groups[render_data.material_bind_group_id],
-                &[],
+                &[render_data.material_offset],
             );
+            if bound_material_bind_group_id != Some(render_data.material_bind_group_id) {
+                render_pass.set_bind_group(
+                    pipeline_data.bind_group_start_index + 1,
+                    &self.bind_groups[render_data.material_bind_group_id],
+                    &[],
+                );
+                bound_material_bind_group_id = Some(render_data.material_bind_group_id);
+            }
 
-            if let Some(IndexData {
-                buffer_id,
-                format,
-                offset,
-            }) = render_data.index_data
-            {
-                let buffer = &self.buffers[&buffer_id];
-                render_pass.set_index_buffer(buffer.slice(offset..), format);
+            if let Some(index_data) = &render_data.index_data {
+                let buffer = self.index_buffer(index_data);
+                render_pass.set_index_buffer(buffer.slice(index_data.offset..), index_data.format);
                 render_pass.draw_indexed(0..render_data.draw_count, 0, 0..1);
             } else {
                 render_pass.draw(0..render_data.draw_count, 0..1)
@@ -675,61 +1644,723 @@ impl Scene<'_> {
     }
 }
 
-pub async fn load_gltf<'a>(
-    device: &wgpu::Device,
-    queue: &wgpu::Queue,
-    path: &str,
-) -> Result<Vec<Scene<'a>>, String> {
-    let white_texture =
-        Texture::create_1_pixel_texture(device, queue, &[255, 255, 255, 255], "white_texture");
-    let default_normal_texture = Texture::create_1_pixel_texture(
-        device,
-        queue,
-        &[128, 128, 255, 255],
-        "default_normal_texture",
-    );
-
-    let bytes = load_binary(path).await.unwrap();
-
-    let gltf = match gltf::Gltf::from_slice(&bytes) {
-        Ok(gltf) => gltf,
-        Err(_) => return Err("Failed to open gltf file".into()),
-    };
+/// Images small enough for the atlas whose textures all clamp to the edge, the glTF default is to
+/// repeat.
+fn atlas_candidates(gltf: &gltf::Gltf, images: &[Texture], max_size: u32) -> Vec<usize> {
+    use gltf::texture::WrappingMode;
 
-    let mut buffer_contents = Vec::new();
-    let parent_dir = Path::new(path).parent().unwrap();
+    let mut clamped = vec![false; images.len()];
+    let mut repeated = vec![false; images.len()];
+    for texture in gltf.textures() {
+        let sampler = texture.sampler();
+        let index = texture.source().index();
+        if sampler.wrap_s() == WrappingMode::ClampToEdge
+            && sampler.wrap_t() == WrappingMode::ClampToEdge
+        {
+            clamped[index] = true;
+        } else {
+            repeated[index] = true;
+        }
+    }
+    (0..images.len())
+        .filter(|&index| clamped[index] && !repeated[index])
+        .filter(|&index| {
+            let texture = &images[index].texture;
+            texture.width() <= max_size && texture.height() <= max_size
+        })
+        .collect()
+}
 
-    for buffer in gltf.buffers() {
-        let content = read_buffer(&parent_dir, buffer).await.unwrap();
-        buffer_contents.push(content);
+/// How far a `SceneLoader` got with the current scene.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct LoadProgress {
+    /// Primitives that can be drawn already
+    pub loaded_primitives: usize,
+    pub total_primitives: usize,
+}
+
+impl LoadProgress {
+    pub fn is_complete(&self) -> bool {
+        self.loaded_primitives == self.total_primitives
     }
+}
 
-    let uris = gltf.images().map(|image| match image.source() {
-        gltf::image::Source::View { .. } => panic!(),
-        gltf::image::Source::Uri { uri, .. } => {
-            format_url(parent_dir.join(uri).to_str().unwrap()).to_string()
-        }
-    });
+/// How far `SceneLoader::open` got with fetching and decoding the files of a glTF.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct FetchProgress {
+    /// Buffers and images that are ready, plus the final step that prepares the images once
+    /// they all are
+    pub loaded_items: usize,
+    pub total_items: usize,
+    /// Bytes of buffer data and decoded image texels loaded so far
+    pub loaded_bytes: usize,
+}
+
+/// Uploads the scenes of a glTF file a batch of primitives at a time, so big scenes can be
+/// drawn while the rest of them is still streaming in. Pipeline lists only cover the primitives
+/// that were loaded when they were generated, `Scene::extend_pipeline` adds the ones loaded since.
+pub struct SceneLoader {
+    document: gltf::Document,
+    buffer_contents: Vec<Vec<u8>>,
+    /// One per glTF image
+    images: Vec<Texture>,
+    atlas: Option<TextureAtlas>,
+    white_texture: Texture,
+    default_normal_texture: Texture,
+    options: LoadOptions,
+    /// Nodes of the current scene left to visit by index, with the slot of their parent in
+    /// `Scene::hierarchy`
+    nodes: Vec<(usize, Option<usize>)>,
+    /// Materials with the same textures share a bind group, keyed by the resolved base color,
+    /// metallic roughness, normal and emissive texture. Atlased images all resolve to the atlas.
+    texture_bind_group_ids: HashMap<[*const Texture; 4], usize>,
+    progress: LoadProgress,
+    progress_callback: Option<Box<dyn FnMut(LoadProgress)>>,
+}
+
+impl SceneLoader {
+    /// Reads the glTF file and decodes its images, none of the scenes are loaded yet. Buffers and
+    /// images are all fetched at once, `on_fetch` is called whenever one of them is done.
+    pub async fn open(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        path: &str,
+        options: LoadOptions,
+        on_fetch: Option<&dyn Fn(FetchProgress)>,
+    ) -> Result<Self, LoadError> {
+        let white_texture =
+            Texture::create_1_pixel_texture(device, queue, &[255, 255, 255, 255], "white_texture");
+        let default_normal_texture = Texture::create_1_pixel_texture(
+            device,
+            queue,
+            &[128, 128, 255, 255],
+            "default_normal_texture",
+        );
+
+        let bytes = load_binary(path).await?;
+        let gltf = gltf::Gltf::from_slice(&bytes).map_err(|source| LoadError::GltfParse {
+            path: path.to_string(),
+            source,
+        })?;
+
+        let parent_dir = Path::new(path).parent().unwrap_or(Path::new(""));
+        let progress = Cell::new(FetchProgress {
+            loaded_items: 0,
+            total_items: gltf.buffers().len() + gltf.images().len() + 1,
+            loaded_bytes: 0,
+        });
+        let report = |bytes: usize| {
+            let mut current = progress.get();
+            current.loaded_items += 1;
+            current.loaded_bytes += bytes;
+            progress.set(current);
+            if let Some(on_fetch) = on_fetch {
+                on_fetch(current);
+            }
+        };
+        let report = &report;
+
+        let blob = gltf.blob.as_deref();
+        let buffer_contents = join_all(gltf.buffers().map(|buffer| async move {
+            let content = read_buffer(parent_dir, buffer, blob).await?;
+            report(content.len());
+            Ok::<_, LoadError>(content)
+        }))
+        .await
+        .into_iter()
+        .collect::<Result<Vec<_>, _>>()?;
+
+        // Borrowed by every image future, the contents move into the loader afterwards
+        let contents = &buffer_contents;
+        let images = join_all(gltf.images().map(|image| async move {
+            let texture = match image.source() {
+                // Images embedded in a buffer view, how GLB files usually carry them
+                gltf::image::Source::View { view, mime_type } => {
+                    let content = &contents[view.buffer().index()];
+                    let bytes = &content[view.offset()..view.offset() + view.length()];
+                    Texture::from_encoded(device, queue, bytes, Some(mime_type), "embedded image")
+                        .map_err(|error| LoadError::ImageDecode {
+                            uri: format!("in buffer view #{}", view.index()),
+                            reason: error.to_string(),
+                        })
+                }
+                gltf::image::Source::Uri { uri, .. } => match decode_data_uri(uri) {
+                    Some(decoded) => {
+                        let mime_type = data_uri_mime_type(uri);
+                        Texture::from_encoded(device, queue, &decoded?, mime_type, "embedded image")
+                            .map_err(|error| LoadError::ImageDecode {
+                                uri: format!("#{} from a data URI", image.index()),
+                                reason: error.to_string(),
+                            })
+                    }
+                    None => {
+                        let uri = image_uri(&parent_dir.join(uri))?;
+                        Texture::from_url(device, queue, uri.as_str(), "loaded image").await
+                    }
+                },
+            }?;
+            let size = texture.texture.size();
+            report(size.width as usize * size.height as usize * 4);
+            Ok::<_, LoadError>(texture)
+        }))
+        .await
+        .into_iter()
+        .collect::<Result<Vec<_>, _>>()?;
 
-    let images = join_all(uris.map(|uri| async move {
-        Texture::from_url(device, queue, uri.as_str(), "loaded image").await
-    }))
-    .await;
+        // Without mips distant textures alias and shimmer, the atlas copies only the first level
+        let mip_blitter = MipBlitter::new(device, wgpu::TextureFormat::Rgba8UnormSrgb);
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Image mipmap encoder"),
+        });
+        for image in &images {
+            mip_blitter.record(device, &mut encoder, &image.texture);
+        }
+        queue.submit(std::iter::once(encoder.finish()));
 
-    let scenes = gltf
-        .scenes()
-        .map(|scene| {
-            Scene::from_gltf(
-                &device,
-                &queue,
-                &buffer_contents,
-                &scene,
-                &white_texture,
-                &default_normal_texture,
+        let atlas = options.atlas_max_size.and_then(|max_size| {
+            let atlas = TextureAtlas::pack(
+                device,
+                queue,
                 &images,
-            )
+                &atlas_candidates(&gltf, &images, max_size),
+            )?;
+            log::info!(
+                "Packed {} of {} images into the texture atlas",
+                atlas.packed_count(),
+                images.len()
+            );
+            Some(atlas)
+        });
+        report(0);
+
+        Ok(Self {
+            document: gltf.document,
+            buffer_contents,
+            images,
+            atlas,
+            white_texture,
+            default_normal_texture,
+            options,
+            nodes: Vec::new(),
+            texture_bind_group_ids: HashMap::new(),
+            progress: LoadProgress::default(),
+            progress_callback: None,
+        })
+    }
+
+    pub fn scene_count(&self) -> usize {
+        self.document.scenes().len()
+    }
+
+    pub fn progress(&self) -> LoadProgress {
+        self.progress
+    }
+
+    /// Called after every batch, e.g. to show a loading bar.
+    pub fn set_progress_callback(&mut self, callback: impl FnMut(LoadProgress) + 'static) {
+        self.progress_callback = Some(Box::new(callback));
+    }
+
+    /// Starts loading the scene at `index`, abandoning the one loaded before. The returned scene
+    /// is empty until `load_batch` fills it.
+    pub fn begin_scene<'a>(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        index: usize,
+    ) -> Scene<'a> {
+        let scene = self.document.scenes().nth(index).unwrap();
+        self.nodes = scene.nodes().map(|node| (node.index(), None)).collect();
+        self.texture_bind_group_ids.clear();
+
+        let mut total_primitives = 0;
+        let mut stack: Vec<Node> = scene.nodes().collect();
+        while let Some(node) = stack.pop() {
+            total_primitives += node.mesh().map_or(0, |mesh| mesh.primitives().len());
+            stack.extend(node.children());
+        }
+        self.progress = LoadProgress {
+            loaded_primitives: 0,
+            total_primitives,
+        };
+
+        let scene = Scene::new(device, self.document.materials().len());
+        queue.write_buffer(
+            &scene.material_buffer,
+            0,
+            bytemuck::cast_slice(&[MaterialData::DEFAULT]),
+        );
+        scene
+    }
+
+    /// Adds at least `max_primitives` primitives of the current scene to `scene`, unless fewer
+    /// are left. Whole meshes are loaded at once, so a batch can end up a bit bigger. After an
+    /// error the scene is incomplete and shouldn't be loaded further.
+    pub fn load_batch(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        scene: &mut Scene,
+        max_primitives: usize,
+    ) -> Result<LoadProgress, LoadError> {
+        let mut loaded_primitives = 0;
+        while loaded_primitives < max_primitives {
+            let Some((node_index, parent_slot)) = self.nodes.pop() else {
+                break;
+            };
+            loaded_primitives += self.load_node(device, queue, scene, node_index, parent_slot)?;
+        }
+        self.progress.loaded_primitives += loaded_primitives;
+
+        if self.progress.is_complete() {
+            log::info!(
+                "{} primitives use {} materials, which share {} texture bind groups",
+                scene.render_datas.len(),
+                scene.materials.len(),
+                self.texture_bind_group_ids.len()
+            );
+        }
+        if let Some(callback) = &mut self.progress_callback {
+            callback(self.progress);
+        }
+        Ok(self.progress)
+    }
+
+    /// Uploads the primitives of one node and queues its children, returns how many primitives
+    /// it had.
+    fn load_node(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        scene: &mut Scene,
+        node_index: usize,
+        parent_slot: Option<usize>,
+    ) -> Result<usize, LoadError> {
+        let node = self.document.nodes().nth(node_index).unwrap();
+        let buffer_contents = &self.buffer_contents;
+        let textures = SceneTextures {
+            images: &self.images,
+            atlas: self.atlas.as_ref(),
+            white: &self.white_texture,
+            default_normal: &self.default_normal_texture,
+        };
+
+        let local_transform = Matrix4::from(node.transform().matrix());
+        let slot = scene.hierarchy.add_node(parent_slot, local_transform);
+        let total_transform = scene.hierarchy.world_transform(slot);
+        scene.node_slots.insert(node_index, slot);
+
+        for child in node.children() {
+            self.nodes.push((child.index(), Some(slot)));
+        }
+
+        let transform_bind_group_id = scene.bind_groups.len();
+        let (transform_bind_group, transform_buffer) = Scene::create_transform_bind_group(
+            device,
+            &scene.transform_bind_group_layout,
+            &scene.material_buffer,
+            total_transform,
+        );
+        scene.bind_groups.push(transform_bind_group);
+        scene.node_data.push(NodeData {
+            transform_buffer,
+            primitives: Vec::new(),
+        });
+
+        let mesh = match node.mesh() {
+            Some(mesh) => mesh,
+            None => return Ok(0),
+        };
+        let unsupported = |semantic: String| LoadError::UnsupportedAccessor {
+            semantic,
+            mesh: mesh.name().map(str::to_string),
+        };
+        for primitive in mesh.primitives() {
+            let mut layouts = Vec::<VertexBufferLayoutBuilder>::new();
+            let mut used_views = Vec::<ViewData>::new();
+            let mut draw_count = 0;
+
+            let material = primitive.material();
+            let pbr = material.pbr_metallic_roughness();
+
+            let (base_color_texture, base_color_uv_transform) = textures.resolve(
+                pbr.base_color_texture().map(|info| info.texture()),
+                textures.white,
+            );
+            let (metallic_roughness_texture, metallic_roughness_uv_transform) = textures.resolve(
+                pbr.metallic_roughness_texture().map(|info| info.texture()),
+                textures.white,
+            );
+            let (normal_texture, normal_uv_transform) = textures.resolve(
+                material.normal_texture().map(|info| info.texture()),
+                textures.default_normal,
+            );
+            // Without a texture the factor alone decides the emission, it defaults to black
+            let (emissive_texture, emissive_uv_transform) = textures.resolve(
+                material.emissive_texture().map(|info| info.texture()),
+                textures.white,
+            );
+            // KHR_materials_emissive_strength goes beyond the [0, 1] the factor is limited to
+            let [emissive_r, emissive_g, emissive_b] = material
+                .emissive_factor()
+                .map(|factor| factor * material.emissive_strength().unwrap_or(1.0));
+
+            // The factors of every material are uploaded once, the first time a primitive uses it
+            let material_offset = match material.index() {
+                Some(index) => {
+                    scene
+                        .materials
+                        .entry(index)
+                        .or_insert_with(|| {
+                            let data = MaterialData {
+                                base_color_factor: pbr.base_color_factor(),
+                                metallic_factor: pbr.metallic_factor(),
+                                roughness_factor: pbr.roughness_factor(),
+                                alpha_cut_off: material.alpha_cutoff().unwrap_or(0f32),
+                                lod_bias: material_extra(&material, "lod_bias").unwrap_or(0.0),
+                                base_color_uv_transform,
+                                metallic_roughness_uv_transform,
+                                normal_uv_transform,
+                                emissive_factor: [emissive_r, emissive_g, emissive_b, 0.0],
+                                emissive_uv_transform,
+                                planar_reflection: material_extra(&material, "planar_reflection")
+                                    .unwrap_or(0.0)
+                                    .clamp(0.0, 1.0),
+                                double_sided: if material.double_sided() { 1.0 } else { 0.0 },
+                                filler: [0.0; 2],
+                            };
+                            let offset = (index as u64 + 1) * MATERIAL_STRIDE;
+                            queue.write_buffer(
+                                &scene.material_buffer,
+                                offset,
+                                bytemuck::cast_slice(&[data]),
+                            );
+                            SceneMaterial {
+                                name: material.name().map(str::to_string),
+                                data,
+                                offset: offset as u32,
+                            }
+                        })
+                        .offset
+                }
+                None => 0,
+            };
+
+            let texture_key = [
+                base_color_texture as *const Texture,
+                metallic_roughness_texture,
+                normal_texture,
+                emissive_texture,
+            ];
+            let material_bind_group_id = match self.texture_bind_group_ids.get(&texture_key) {
+                Some(&material_bind_group_id) => material_bind_group_id,
+                None => {
+                    let material_bind_group =
+                        device.create_bind_group(&wgpu::BindGroupDescriptor {
+                            label: None,
+                            layout: &scene.material_bind_group_layout,
+                            entries: &[
+                                wgpu::BindGroupEntry {
+                                    binding: 1,
+                                    resource: wgpu::BindingResource::TextureView(
+                                        &base_color_texture.view,
+                                    ),
+                                },
+                                wgpu::BindGroupEntry {
+                                    binding: 2,
+                                    resource: wgpu::BindingResource::Sampler(
+                                        &base_color_texture.sampler,
+                                    ),
+                                },
+                                wgpu::BindGroupEntry {
+                                    binding: 3,
+                                    resource: wgpu::BindingResource::TextureView(
+                                        &metallic_roughness_texture.view,
+                                    ),
+                                },
+                                wgpu::BindGroupEntry {
+                                    binding: 4,
+                                    resource: wgpu::BindingResource::Sampler(
+                                        &metallic_roughness_texture.sampler,
+                                    ),
+                                },
+                                wgpu::BindGroupEntry {
+                                    binding: 5,
+                                    resource: wgpu::BindingResource::TextureView(
+                                        &normal_texture.view,
+                                    ),
+                                },
+                                wgpu::BindGroupEntry {
+                                    binding: 6,
+                                    resource: wgpu::BindingResource::Sampler(
+                                        &normal_texture.sampler,
+                                    ),
+                                },
+                                wgpu::BindGroupEntry {
+                                    binding: 7,
+                                    resource: wgpu::BindingResource::TextureView(
+                                        &emissive_texture.view,
+                                    ),
+                                },
+                                wgpu::BindGroupEntry {
+                                    binding: 8,
+                                    resource: wgpu::BindingResource::Sampler(
+                                        &emissive_texture.sampler,
+                                    ),
+                                },
+                            ],
+                        });
+
+                    let material_bind_group_id = scene.bind_groups.len();
+                    scene.bind_groups.push(material_bind_group);
+                    self.texture_bind_group_ids
+                        .insert(texture_key, material_bind_group_id);
+                    material_bind_group_id
+                }
+            };
+
+            let bounds = primitive
+                .get(&gltf::Semantic::Positions)
+                .and_then(|accessor| {
+                    let to_vec3 =
+                        |value: gltf::json::Value| serde_json::from_value::<[f32; 3]>(value).ok();
+                    Some(Aabb {
+                        min: to_vec3(accessor.min()?)?,
+                        max: to_vec3(accessor.max()?)?,
+                    })
+                });
+            if let Some(bounds) = &bounds {
+                let world_bounds = bounds.transformed(total_transform);
+                scene.bounds = Some(match scene.bounds {
+                    Some(scene_bounds) => scene_bounds.union(&world_bounds),
+                    None => world_bounds,
+                });
+            }
+            let reader = primitive.reader(|buffer| {
+                buffer_contents
+                    .get(buffer.index())
+                    .map(|content| content.as_slice())
+            });
+            let mut positions_quantized = false;
+
+            for (semantic, accessor) in primitive.attributes() {
+                let view = match accessor.view() {
+                    Some(view) => view,
+                    None => continue,
+                };
+                draw_count = accessor.count() as u32;
+                let shader_location = Attribute::from(&semantic) as u32;
+                let source_bytes = (accessor.count() * get_default_array_stride(&accessor)) as u64;
+                scene.vertex_data_size.source_bytes += source_bytes;
+
+                let quantized = if self.options.quantize_vertices {
+                    quantize_attribute(&reader, &semantic, bounds.as_ref())
+                } else {
+                    None
+                };
+                if let Some((format, data)) = quantized {
+                    positions_quantized |= semantic == gltf::Semantic::Positions;
+                    scene.vertex_data_size.uploaded_bytes += data.len() as u64;
+                    layouts.push(VertexBufferLayoutBuilder::new(
+                        format.size(),
+                        wgpu::VertexStepMode::Vertex,
+                        vec![wgpu::VertexAttribute {
+                            format,
+                            offset: 0,
+                            shader_location,
+                        }],
+                    ));
+                    used_views.push(ViewData {
+                        view_index: scene.generated_buffers.len(),
+                        offset: 0,
+                        generated: true,
+                    });
+                    scene.generated_buffers.push(device.create_buffer_init(
+                        &wgpu::util::BufferInitDescriptor {
+                            label: Some(format!("Quantized GLTF view #{}", view.index()).as_str()),
+                            contents: &data,
+                            usage: wgpu::BufferUsages::VERTEX,
+                        },
+                    ));
+                    continue;
+                }
+
+                scene.vertex_data_size.uploaded_bytes += source_bytes;
+                Scene::create_buffer_if_new(
+                    device,
+                    queue,
+                    buffer_contents,
+                    &mut scene.buffers,
+                    &view,
+                    wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+                );
+
+                layouts.push(VertexBufferLayoutBuilder::new(
+                    view.stride().unwrap_or(get_default_array_stride(&accessor)) as u64,
+                    wgpu::VertexStepMode::Vertex,
+                    vec![wgpu::VertexAttribute {
+                        format: gltf_accessor_to_wgpu(&accessor)
+                            .ok_or_else(|| unsupported(semantic.to_string()))?,
+                        offset: 0,
+                        shader_location,
+                    }],
+                ));
+
+                used_views.push(ViewData {
+                    view_index: view.index(),
+                    offset: accessor.offset() as u64,
+                    generated: false,
+                });
+            }
+
+            // Normal maps need a tangent frame, the shader can't tell if one is missing
+            if let Some(tangents) = missing_tangents(&primitive, &reader) {
+                let data = bytemuck::cast_slice(&tangents);
+                scene.vertex_data_size.uploaded_bytes += data.len() as u64;
+                layouts.push(VertexBufferLayoutBuilder::new(
+                    wgpu::VertexFormat::Float32x4.size(),
+                    wgpu::VertexStepMode::Vertex,
+                    vec![wgpu::VertexAttribute {
+                        format: wgpu::VertexFormat::Float32x4,
+                        offset: 0,
+                        shader_location: Attribute::Tangents as u32,
+                    }],
+                ));
+                used_views.push(ViewData {
+                    view_index: scene.generated_buffers.len(),
+                    offset: 0,
+                    generated: true,
+                });
+                scene.generated_buffers.push(device.create_buffer_init(
+                    &wgpu::util::BufferInitDescriptor {
+                        label: Some("Generated tangents"),
+                        contents: data,
+                        usage: wgpu::BufferUsages::VERTEX,
+                    },
+                ));
+            }
+
+            // Quantized positions are relative to the bounds, scaling them back up becomes
+            // part of a transform only this primitive uses
+            let (transform_bind_group_id, dequantized_transform) = match bounds.as_ref() {
+                Some(bounds) if positions_quantized => {
+                    let (center, scale) = position_dequantization(bounds);
+                    let dequantization = Matrix4::from_translation(center)
+                        * Matrix4::from_nonuniform_scale(scale.x, scale.y, scale.z);
+                    let (bind_group, buffer) = Scene::create_transform_bind_group(
+                        device,
+                        &scene.transform_bind_group_layout,
+                        &scene.material_buffer,
+                        total_transform * dequantization,
+                    );
+                    scene.bind_groups.push(bind_group);
+                    (scene.bind_groups.len() - 1, Some((buffer, dequantization)))
+                }
+                _ => (transform_bind_group_id, None),
+            };
+
+            let unrolled = needs_unrolling(primitive.mode()).then(|| {
+                let indices: Vec<u32> = match reader.read_indices() {
+                    Some(indices) => indices.into_u32().collect(),
+                    None => (0..draw_count).collect(),
+                };
+                unroll_indices(primitive.mode(), &indices)
+            });
+            let index_data = match (unrolled, primitive.indices()) {
+                (Some(unrolled), _) => {
+                    draw_count = unrolled.len() as u32;
+                    scene.generated_buffers.push(device.create_buffer_init(
+                        &wgpu::util::BufferInitDescriptor {
+                            label: Some("Unrolled GLTF indices"),
+                            contents: bytemuck::cast_slice(&unrolled),
+                            usage: wgpu::BufferUsages::INDEX,
+                        },
+                    ));
+                    Some(IndexData {
+                        buffer_id: scene.generated_buffers.len() - 1,
+                        format: wgpu::IndexFormat::Uint32,
+                        offset: 0,
+                        generated: true,
+                    })
+                }
+                (None, Some(accessor)) => {
+                    let view = accessor
+                        .view()
+                        .ok_or_else(|| unsupported("indices".to_string()))?;
+                    Scene::create_buffer_if_new(
+                        device,
+                        queue,
+                        buffer_contents,
+                        &mut scene.buffers,
+                        &view,
+                        wgpu::BufferUsages::INDEX | wgpu::BufferUsages::COPY_DST,
+                    );
+                    draw_count = accessor.count() as u32;
+                    Some(IndexData {
+                        buffer_id: view.index(),
+                        format: gltf_accessor_to_indexformat(&accessor)
+                            .ok_or_else(|| unsupported("indices".to_string()))?,
+                        offset: accessor.offset() as u64,
+                        generated: false,
+                    })
+                }
+                (None, None) => None,
+            };
+
+            scene.node_data[slot]
+                .primitives
+                .push(scene.render_datas.len());
+            scene.render_datas.push(PrimitiveRenderData {
+                layouts,
+                used_views,
+                draw_count,
+                index_data,
+                topology: gltf_mode_to_topology(primitive.mode()),
+                transform_bind_group_id,
+                material_bind_group_id,
+                material_offset,
+                material_index: material.index(),
+                double_sided: material.double_sided(),
+                bounds,
+                world_bounds: bounds.map(|bounds| bounds.transformed(total_transform)),
+                transform: total_transform,
+                dequantized_transform,
+                alpha_mode: material.alpha_mode(),
+                mesh_name: mesh.name().map(str::to_string),
+            });
+        }
+
+        Ok(mesh.primitives().len())
+    }
+}
+
+/// Loads every scene of the glTF file at `path` in one go.
+pub async fn load_gltf<'a>(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    path: &str,
+    options: LoadOptions,
+    on_fetch: Option<&dyn Fn(FetchProgress)>,
+) -> Result<Vec<Scene<'a>>, LoadError> {
+    let mut loader = SceneLoader::open(device, queue, path, options, on_fetch).await?;
+    (0..loader.scene_count())
+        .map(|index| {
+            let mut scene = loader.begin_scene(device, queue, index);
+            loader.load_batch(device, queue, &mut scene, usize::MAX)?;
+            Ok(scene)
         })
-        .collect();
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    Ok(scenes)
+    #[test]
+    fn material_data_matches_wgsl() {
+        crate::util::assert_wgsl_layout::<MaterialData>(
+            &crate::main_shader_source(false),
+            "MaterialData",
+        );
+    }
 }
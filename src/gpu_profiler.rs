@@ -0,0 +1,151 @@
+use crate::readback::PendingReadback;
+
+/// Most timestamps written per frame, one before the first pass and one after each pass.
+const MAX_TIMESTAMPS: u32 = 64;
+/// Blend factor of the exponential moving average, the same as the CPU profiler's.
+const SMOOTHING: f64 = 0.05;
+
+/// GPU time spent in each pass of the frame, measured with timestamps written between the passes.
+/// Needs `Features::TIMESTAMP_QUERY`, which wgpu only offers natively. Measurements arrive a few
+/// frames late, a new frame is only read back once the previous readback finished.
+pub struct GpuProfiler {
+    query_set: wgpu::QuerySet,
+    resolve_buffer: wgpu::Buffer,
+    /// Nanoseconds per timestamp tick
+    period: f32,
+    /// Names of the spans ended by the timestamps written so far this frame
+    spans: Vec<&'static str>,
+    pending: Option<(Vec<&'static str>, PendingReadback)>,
+    /// Rolling averages in the order the spans were first measured
+    averages_ms: Vec<(&'static str, f64)>,
+}
+
+impl GpuProfiler {
+    /// Returns `None` if the device doesn't have `Features::TIMESTAMP_QUERY`.
+    pub fn new(device: &wgpu::Device, queue: &wgpu::Queue) -> Option<Self> {
+        if !device.features().contains(wgpu::Features::TIMESTAMP_QUERY) {
+            return None;
+        }
+        let query_set = device.create_query_set(&wgpu::QuerySetDescriptor {
+            label: Some("GPU profiler timestamps"),
+            ty: wgpu::QueryType::Timestamp,
+            count: MAX_TIMESTAMPS,
+        });
+        let resolve_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("GPU profiler resolve buffer"),
+            size: MAX_TIMESTAMPS as u64 * 8,
+            usage: wgpu::BufferUsages::QUERY_RESOLVE | wgpu::BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        });
+        Some(Self {
+            query_set,
+            resolve_buffer,
+            period: queue.get_timestamp_period(),
+            spans: Vec::new(),
+            pending: None,
+            averages_ms: Vec::new(),
+        })
+    }
+
+    /// Writes the timestamp the first span starts at.
+    pub fn begin_frame(&mut self, encoder: &mut wgpu::CommandEncoder) {
+        self.spans.clear();
+        encoder.write_timestamp(&self.query_set, 0);
+    }
+
+    /// Ends the span since the previous timestamp, the next one starts here. Spans past
+    /// `MAX_TIMESTAMPS` aren't measured.
+    pub fn end_span(&mut self, encoder: &mut wgpu::CommandEncoder, name: &'static str) {
+        let index = self.spans.len() as u32 + 1;
+        if index < MAX_TIMESTAMPS {
+            encoder.write_timestamp(&self.query_set, index);
+            self.spans.push(name);
+        }
+    }
+
+    /// Resolves the timestamps of the frame, recorded after its last span.
+    pub fn end_frame(&mut self, encoder: &mut wgpu::CommandEncoder) {
+        encoder.resolve_query_set(
+            &self.query_set,
+            0..self.spans.len() as u32 + 1,
+            &self.resolve_buffer,
+            0,
+        );
+    }
+
+    /// Starts reading back the frame unless an earlier one is still on its way, has to be called
+    /// after the encoder `end_frame` recorded into was submitted.
+    pub fn read_back(&mut self, device: &wgpu::Device, queue: &wgpu::Queue) {
+        if self.pending.is_some() || self.spans.is_empty() {
+            return;
+        }
+        let size = (self.spans.len() as u64 + 1) * 8;
+        let readback = PendingReadback::buffer(device, queue, &self.resolve_buffer, 0..size);
+        self.pending = Some((self.spans.clone(), readback));
+    }
+
+    pub fn is_pending(&self) -> bool {
+        self.pending.is_some()
+    }
+
+    /// Milliseconds per span of the frame that was read back, `None` while it's still pending.
+    /// Natively the device has to be polled for the readback to finish.
+    pub fn try_finish(&mut self) -> Option<Vec<(&'static str, f64)>> {
+        let (spans, readback) = self.pending.as_mut()?;
+        let result = readback.try_finish()?;
+        let spans = std::mem::take(spans);
+        self.pending = None;
+        let timestamps: Vec<u64> = match result {
+            Ok(data) => data
+                .chunks_exact(8)
+                .map(|bytes| u64::from_le_bytes(bytes.try_into().unwrap()))
+                .collect(),
+            Err(error) => {
+                log::warn!("Couldn't read back the GPU timestamps: {}", error);
+                return None;
+            }
+        };
+        let span_ms = span_durations_ms(&timestamps, self.period);
+        let measured: Vec<_> = spans.into_iter().zip(span_ms).collect();
+        for &(name, ms) in &measured {
+            match self
+                .averages_ms
+                .iter_mut()
+                .find(|(average_name, _)| *average_name == name)
+            {
+                Some((_, average)) => *average += (ms - *average) * SMOOTHING,
+                None => self.averages_ms.push((name, ms)),
+            }
+        }
+        Some(measured)
+    }
+
+    #[cfg_attr(not(feature = "cpu-profiling"), allow(dead_code))]
+    pub fn averages_ms(&self) -> &[(&'static str, f64)] {
+        &self.averages_ms
+    }
+}
+
+/// Time between consecutive timestamps in milliseconds. Timestamps that went backwards, which
+/// some drivers report across passes, count as zero.
+fn span_durations_ms(timestamps: &[u64], period: f32) -> Vec<f64> {
+    timestamps
+        .windows(2)
+        .map(|pair| pair[1].saturating_sub(pair[0]) as f64 * period as f64 / 1_000_000.0)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn spans_are_the_time_between_timestamps() {
+        assert_eq!(
+            span_durations_ms(&[1_000_000, 3_000_000, 3_500_000, 3_400_000], 1.0),
+            [2.0, 0.5, 0.0]
+        );
+        assert_eq!(span_durations_ms(&[0, 1000], 2.5), [0.0025]);
+        assert!(span_durations_ms(&[42], 1.0).is_empty());
+    }
+}
@@ -0,0 +1,306 @@
+use std::collections::HashMap;
+
+use winit::event::{KeyboardInput, ScanCode, VirtualKeyCode};
+
+use crate::util;
+
+/// Debug actions that can be bound to a key. Camera movement is bound separately, see `Movement`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum Action {
+    ToggleHelp,
+    ToggleDepthOfField,
+    NearerFocus,
+    FartherFocus,
+    NarrowerAperture,
+    WiderAperture,
+    ToggleMotionBlur,
+    ToggleNeutralMaterial,
+    ToggleMipLevels,
+    ToggleSoftShadows,
+    ToggleContactShadows,
+    ToggleShadowSnapping,
+    ToggleShadowInspector,
+    ToggleProbeGi,
+    ToggleLightProbes,
+    ToggleVoxelView,
+    PreviousVoxelLevel,
+    NextVoxelLevel,
+    ToggleGizmo,
+    ToggleWorldGrid,
+    ToggleSdfSkipping,
+    ToggleSdfView,
+    ToggleFog,
+    ToggleFogGi,
+    ToggleConeStepHeatmap,
+    ToggleSsr,
+    ToggleSsrHitMask,
+    DecreaseLodBias,
+    IncreaseLodBias,
+    FreezeFrame,
+    StepFrame,
+    ExportConfig,
+    MoveLightLeft,
+    MoveLightRight,
+    MoveLightForward,
+    MoveLightBack,
+}
+
+impl Action {
+    /// Order the actions are listed in by the help overlay
+    pub const ALL: [Action; 36] = [
+        Action::ToggleHelp,
+        Action::ToggleDepthOfField,
+        Action::NearerFocus,
+        Action::FartherFocus,
+        Action::NarrowerAperture,
+        Action::WiderAperture,
+        Action::ToggleMotionBlur,
+        Action::ToggleNeutralMaterial,
+        Action::ToggleMipLevels,
+        Action::ToggleSoftShadows,
+        Action::ToggleContactShadows,
+        Action::ToggleShadowSnapping,
+        Action::ToggleShadowInspector,
+        Action::ToggleProbeGi,
+        Action::ToggleLightProbes,
+        Action::ToggleVoxelView,
+        Action::PreviousVoxelLevel,
+        Action::NextVoxelLevel,
+        Action::ToggleGizmo,
+        Action::ToggleWorldGrid,
+        Action::ToggleSdfSkipping,
+        Action::ToggleSdfView,
+        Action::ToggleFog,
+        Action::ToggleFogGi,
+        Action::ToggleConeStepHeatmap,
+        Action::ToggleSsr,
+        Action::ToggleSsrHitMask,
+        Action::DecreaseLodBias,
+        Action::IncreaseLodBias,
+        Action::FreezeFrame,
+        Action::StepFrame,
+        Action::ExportConfig,
+        Action::MoveLightLeft,
+        Action::MoveLightRight,
+        Action::MoveLightForward,
+        Action::MoveLightBack,
+    ];
+
+    pub fn description(self) -> &'static str {
+        match self {
+            Action::ToggleHelp => "Toggle this help",
+            Action::ToggleDepthOfField => "Depth of field",
+            Action::NearerFocus => "Depth of field: nearer focus",
+            Action::FartherFocus => "Depth of field: farther focus",
+            Action::NarrowerAperture => "Depth of field: smaller aperture",
+            Action::WiderAperture => "Depth of field: larger aperture",
+            Action::ToggleMotionBlur => "Motion blur",
+            Action::ToggleNeutralMaterial => "Neutral material",
+            Action::ToggleMipLevels => "Mip level view",
+            Action::ToggleSoftShadows => "Soft shadows",
+            Action::ToggleContactShadows => "Contact shadows",
+            Action::ToggleShadowSnapping => "Shadow texel snapping",
+            Action::ToggleShadowInspector => "Shadow map inspector",
+            Action::ToggleProbeGi => "Light probe GI (fast)",
+            Action::ToggleLightProbes => "Light probe view",
+            Action::ToggleVoxelView => "Voxel grid view",
+            Action::PreviousVoxelLevel => "Voxel view: finer mip",
+            Action::NextVoxelLevel => "Voxel view: coarser mip",
+            Action::ToggleGizmo => "Orientation gizmo",
+            Action::ToggleWorldGrid => "World grid",
+            Action::ToggleSdfSkipping => "SDF empty space skipping",
+            Action::ToggleSdfView => "SDF distance view",
+            Action::ToggleFog => "Height fog",
+            Action::ToggleFogGi => "Fog GI inscattering",
+            Action::ToggleConeStepHeatmap => "Cone trace step heatmap",
+            Action::ToggleSsr => "Screen space reflections",
+            Action::ToggleSsrHitMask => "SSR hit mask view",
+            Action::DecreaseLodBias => "Decrease LOD bias",
+            Action::IncreaseLodBias => "Increase LOD bias",
+            Action::FreezeFrame => "Freeze frame",
+            Action::StepFrame => "Step one frame",
+            Action::ExportConfig => "Log scene config",
+            Action::MoveLightLeft => "Move first point light -x",
+            Action::MoveLightRight => "Move first point light +x",
+            Action::MoveLightForward => "Move first point light -z",
+            Action::MoveLightBack => "Move first point light +z",
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum Movement {
+    Forward,
+    Backward,
+    Left,
+    Right,
+    Up,
+    Down,
+}
+
+/// How movement keys are identified, `?movement_keys=virtual` picks `Virtual`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum MovementKeyMode {
+    /// By position, WASD stays in the same place on AZERTY or Dvorak layouts
+    Physical,
+    /// By the symbol the keyboard layout maps the key to
+    Virtual,
+}
+
+/// A key identified either by its position or by its symbol, see `MovementKeyMode`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum KeyId {
+    Physical(ScanCode),
+    Virtual(VirtualKeyCode),
+}
+
+// Physical positions of W, S, A, D, Space and left shift on a QWERTY keyboard. On the web winit
+// derives virtual key codes from `KeyboardEvent.code`, which already is the physical position,
+// while its scan codes are the layout dependent legacy key codes.
+#[cfg(target_arch = "wasm32")]
+fn physical_movement_key(input: &KeyboardInput) -> Option<KeyId> {
+    input.virtual_keycode.map(KeyId::Virtual)
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn physical_movement_key(input: &KeyboardInput) -> Option<KeyId> {
+    Some(KeyId::Physical(input.scancode))
+}
+
+#[cfg(target_arch = "wasm32")]
+const PHYSICAL_MOVEMENT_KEYS: [(KeyId, Movement); 6] = [
+    (KeyId::Virtual(VirtualKeyCode::W), Movement::Forward),
+    (KeyId::Virtual(VirtualKeyCode::S), Movement::Backward),
+    (KeyId::Virtual(VirtualKeyCode::A), Movement::Left),
+    (KeyId::Virtual(VirtualKeyCode::D), Movement::Right),
+    (KeyId::Virtual(VirtualKeyCode::Space), Movement::Up),
+    (KeyId::Virtual(VirtualKeyCode::LShift), Movement::Down),
+];
+
+#[cfg(target_os = "macos")]
+const PHYSICAL_MOVEMENT_KEYS: [(KeyId, Movement); 6] = [
+    (KeyId::Physical(0x0D), Movement::Forward),
+    (KeyId::Physical(0x01), Movement::Backward),
+    (KeyId::Physical(0x00), Movement::Left),
+    (KeyId::Physical(0x02), Movement::Right),
+    (KeyId::Physical(0x31), Movement::Up),
+    (KeyId::Physical(0x38), Movement::Down),
+];
+
+// Set 1 scan codes, used by both Windows and Linux (evdev)
+#[cfg(not(any(target_arch = "wasm32", target_os = "macos")))]
+const PHYSICAL_MOVEMENT_KEYS: [(KeyId, Movement); 6] = [
+    (KeyId::Physical(0x11), Movement::Forward),
+    (KeyId::Physical(0x1F), Movement::Backward),
+    (KeyId::Physical(0x1E), Movement::Left),
+    (KeyId::Physical(0x20), Movement::Right),
+    (KeyId::Physical(0x39), Movement::Up),
+    (KeyId::Physical(0x2A), Movement::Down),
+];
+
+const VIRTUAL_MOVEMENT_KEYS: [(KeyId, Movement); 6] = [
+    (KeyId::Virtual(VirtualKeyCode::W), Movement::Forward),
+    (KeyId::Virtual(VirtualKeyCode::S), Movement::Backward),
+    (KeyId::Virtual(VirtualKeyCode::A), Movement::Left),
+    (KeyId::Virtual(VirtualKeyCode::D), Movement::Right),
+    (KeyId::Virtual(VirtualKeyCode::Space), Movement::Up),
+    (KeyId::Virtual(VirtualKeyCode::LShift), Movement::Down),
+];
+
+/// Action keys (F-keys and such) are always matched by virtual key code, movement keys follow
+/// `movement_key_mode`.
+pub struct KeyBindings {
+    bindings: HashMap<VirtualKeyCode, Action>,
+    movement_key_mode: MovementKeyMode,
+    movement_bindings: HashMap<KeyId, Movement>,
+}
+
+impl Default for KeyBindings {
+    /// Movement keys are matched by position unless the `movement_keys` query parameter asks for
+    /// `virtual`.
+    fn default() -> Self {
+        let mut key_bindings = Self {
+            bindings: HashMap::from([
+                (VirtualKeyCode::F1, Action::ToggleHelp),
+                (VirtualKeyCode::F, Action::ToggleDepthOfField),
+                (VirtualKeyCode::Key5, Action::NearerFocus),
+                (VirtualKeyCode::Key6, Action::FartherFocus),
+                (VirtualKeyCode::Key7, Action::NarrowerAperture),
+                (VirtualKeyCode::Key8, Action::WiderAperture),
+                (VirtualKeyCode::M, Action::ToggleMotionBlur),
+                (VirtualKeyCode::N, Action::ToggleNeutralMaterial),
+                (VirtualKeyCode::L, Action::ToggleMipLevels),
+                (VirtualKeyCode::K, Action::ToggleSoftShadows),
+                (VirtualKeyCode::C, Action::ToggleContactShadows),
+                (VirtualKeyCode::T, Action::ToggleShadowSnapping),
+                (VirtualKeyCode::I, Action::ToggleShadowInspector),
+                (VirtualKeyCode::G, Action::ToggleProbeGi),
+                (VirtualKeyCode::O, Action::ToggleLightProbes),
+                (VirtualKeyCode::V, Action::ToggleVoxelView),
+                (VirtualKeyCode::LBracket, Action::PreviousVoxelLevel),
+                (VirtualKeyCode::RBracket, Action::NextVoxelLevel),
+                (VirtualKeyCode::Q, Action::ToggleGizmo),
+                (VirtualKeyCode::E, Action::ToggleWorldGrid),
+                (VirtualKeyCode::J, Action::ToggleSdfSkipping),
+                (VirtualKeyCode::H, Action::ToggleSdfView),
+                (VirtualKeyCode::U, Action::ToggleFog),
+                (VirtualKeyCode::Y, Action::ToggleFogGi),
+                (VirtualKeyCode::B, Action::ToggleConeStepHeatmap),
+                (VirtualKeyCode::R, Action::ToggleSsr),
+                (VirtualKeyCode::X, Action::ToggleSsrHitMask),
+                (VirtualKeyCode::Minus, Action::DecreaseLodBias),
+                (VirtualKeyCode::Equals, Action::IncreaseLodBias),
+                (VirtualKeyCode::P, Action::FreezeFrame),
+                (VirtualKeyCode::Period, Action::StepFrame),
+                (VirtualKeyCode::F2, Action::ExportConfig),
+                (VirtualKeyCode::Key1, Action::MoveLightLeft),
+                (VirtualKeyCode::Key2, Action::MoveLightRight),
+                (VirtualKeyCode::Key3, Action::MoveLightForward),
+                (VirtualKeyCode::Key4, Action::MoveLightBack),
+            ]),
+            movement_key_mode: MovementKeyMode::Physical,
+            movement_bindings: HashMap::new(),
+        };
+        key_bindings.set_movement_key_mode(
+            match util::query_param("movement_keys").as_deref() {
+                Some("virtual") => MovementKeyMode::Virtual,
+                _ => MovementKeyMode::Physical,
+            },
+        );
+        key_bindings
+    }
+}
+
+impl KeyBindings {
+    pub fn action(&self, key: VirtualKeyCode) -> Option<Action> {
+        self.bindings.get(&key).copied()
+    }
+
+    pub fn key_for(&self, action: Action) -> Option<VirtualKeyCode> {
+        self.bindings
+            .iter()
+            .find(|(_, bound)| **bound == action)
+            .map(|(key, _)| *key)
+    }
+
+    /// Switches how movement keys are matched, resetting them to the default WASD layout.
+    pub fn set_movement_key_mode(&mut self, mode: MovementKeyMode) {
+        self.movement_key_mode = mode;
+        self.movement_bindings = HashMap::from(match mode {
+            MovementKeyMode::Physical => PHYSICAL_MOVEMENT_KEYS,
+            MovementKeyMode::Virtual => VIRTUAL_MOVEMENT_KEYS,
+        });
+    }
+
+    /// Identifies the key of `input` the way movement keys are currently matched.
+    pub fn movement_key_id(&self, input: &KeyboardInput) -> Option<KeyId> {
+        match self.movement_key_mode {
+            MovementKeyMode::Physical => physical_movement_key(input),
+            MovementKeyMode::Virtual => input.virtual_keycode.map(KeyId::Virtual),
+        }
+    }
+
+    pub fn movement(&self, key: KeyId) -> Option<Movement> {
+        self.movement_bindings.get(&key).copied()
+    }
+}
@@ -1,242 +1,1640 @@
+mod bench;
 mod camera;
-mod gltf_loader;
+mod cpu_profiler;
+mod gizmo;
+pub mod gltf_loader;
+mod gpu_profiler;
+#[cfg(target_arch = "wasm32")]
 mod image_future;
-mod shader;
-mod texture;
+mod key_bindings;
+mod logging;
+pub mod mip2d;
+mod outline;
+mod overlay;
+mod post;
+mod probes;
+pub mod readback;
+mod render_graph;
+pub mod scene_config;
+pub mod shader;
+mod shadow;
+mod shadow_atlas;
+mod ssr;
+pub mod texture;
+mod texture_atlas;
+pub mod transform_hierarchy;
 mod util;
+mod voxel_debug;
 mod voxel_texture;
 
-use camera::{PerspectiveCamera, ShadowCamera};
-use cgmath::{Deg, Euler, InnerSpace, Point3, Vector3};
+use std::{
+    cell::{Cell, RefCell},
+    collections::VecDeque,
+    fmt,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use bench::Benchmark;
+use camera::{Frustum, PerspectiveCamera, ShadowCamera, VoxelizationCamera};
+use cgmath::{Deg, EuclideanSpace, Euler, InnerSpace, Matrix4, Point3, Vector2, Vector3};
+use cpu_profiler::{CpuProfiler, CpuSection};
+use gizmo::OrientationGizmo;
+use gltf_loader::Aabb;
+use gpu_profiler::GpuProfiler;
+use instant::Instant;
+use key_bindings::{Action, KeyBindings};
+use outline::Outline;
+use overlay::Overlay;
+use post::{
+    DepthOfFieldPass, FocusPicker, MotionBlurPass, PostChain, PostContext, PostFrame, PostSettings,
+};
+use probes::LightProbes;
+use render_graph::{GraphResources, RenderGraph, TextureSize};
+use scene_config::{PointLightConfig, SceneConfig};
+use serde::Deserialize;
 use shader::Shader;
+use shadow::{ShadowBlur, ShadowInspector, ShadowSettings};
+use shadow_atlas::{ShadowAtlas, ShadowTile};
+use ssr::{Ssr, SsrSettings, SsrTargets};
 use texture::Texture;
+use voxel_debug::VoxelDebug;
 use voxel_texture::VoxelTexture;
 use wasm_bindgen::prelude::*;
 use wgpu::util::DeviceExt;
 use winit::{
-    event::{Event, KeyboardInput, VirtualKeyCode, WindowEvent},
+    dpi::PhysicalPosition,
+    event::{
+        DeviceEvent, ElementState, Event, KeyboardInput, MouseButton, VirtualKeyCode, WindowEvent,
+    },
     event_loop::{ControlFlow, EventLoop},
-    window::{Window, WindowBuilder},
+    window::{CursorGrabMode, Window, WindowBuilder},
 };
 
-struct State<'a> {
-    window: Window,
-    surface: wgpu::Surface,
+/// Consecutive frames that may fail to acquire a surface texture before the surface is recreated
+/// from scratch instead of just being reconfigured.
+const MAX_CONSECUTIVE_SURFACE_ERRORS: u32 = 3;
+/// Simulated time a single step advances by while frozen, in seconds
+const FIXED_STEP_DT: f32 = 1.0 / 60.0;
+/// Primitives uploaded per frame while the scene streams in, `?stream_batch=N` overrides it and 0
+/// loads the whole scene before the first frame
+const STREAM_BATCH_PRIMITIVES: usize = 64;
+/// Loaded when no other model was asked for, or when that one can't be opened
+const DEFAULT_MODEL_PATH: &str = "models/Sponza/glTF/Sponza.gltf";
+
+/// The part of the scene that is still being uploaded, with the shaders its pipeline lists were
+/// generated with so every batch can be added to them. The main shader is kept by `State`.
+struct SceneStream {
+    loader: gltf_loader::SceneLoader,
+    batch_size: usize,
+    shadow_shader: Shader,
+    voxelizer_shader: Shader,
+}
+
+/// Blended primitives don't cast shadows, write the depth prepass or get voxelized, a pane of
+/// glass would block the light going through it like a wall.
+fn is_opaque(primitive: &gltf_loader::PrimitiveRenderData) -> bool {
+    primitive.alpha_mode != gltf::material::AlphaMode::Blend
+}
+
+/// Drawn by the transparent pipeline list after everything opaque, sorted back to front.
+fn is_blended(primitive: &gltf_loader::PrimitiveRenderData) -> bool {
+    !is_opaque(primitive)
+}
+
+/// Everything that outlives the device, carried over to the new `State` when the device is lost.
+#[derive(Default)]
+struct PreservedState {
+    post_settings: PostSettings,
+    shadow_settings: ShadowSettings,
+    key_bindings: KeyBindings,
+    overlay: Option<Overlay>,
+    camera_transform: Option<(Vector3<f32>, Euler<Deg<f32>>)>,
+    model_path: Option<String>,
+    scene_config: Option<SceneConfig>,
+    cone_params: Option<ConeTracingParams>,
+    ssr_settings: Option<SsrSettings>,
+}
+
+/// The whole renderer, for driving it from an event loop of your own instead of `run`. Feed it
+/// window events through `input` and `resize`, then call `update` and `render` once a frame. When
+/// `device_lost` turns true the state has to be replaced with the result of `recreate`.
+pub struct State<'a> {
+    target: Target,
+    device_lost: Arc<AtomicBool>,
+    instance: wgpu::Instance,
+    /// Adapter picked from `backend_preference`, reported by benchmarks and the timings overlay
+    adapter_info: wgpu::AdapterInfo,
+    consecutive_surface_errors: u32,
     device: wgpu::Device,
     queue: wgpu::Queue,
     config: wgpu::SurfaceConfiguration,
     size: winit::dpi::PhysicalSize<u32>,
     camera_buffer: wgpu::Buffer,
+    shadow_camera: ShadowCamera,
+    shadow_atlas: ShadowAtlas,
+    shadow_camera_buffer: wgpu::Buffer,
+    diffuse_camera_bind_group_layout: wgpu::BindGroupLayout,
+    /// References the depth prepass and the planar reflection, so it's recreated on resize
     diffuse_camera_bind_group: wgpu::BindGroup,
+    /// The camera mirrored about the planar reflection's plane, bound in place of the camera while
+    /// rendering the reflection
+    mirror_camera_buffer: wgpu::Buffer,
+    mirror_camera_bind_group: wgpu::BindGroup,
+    /// Bound as the planar reflection while rendering it
+    mirror_reflection_placeholder: Texture,
+    /// See `Scene::planar_reflection_height`, updated every frame
+    planar_reflection_height: Option<f32>,
+    depth_prepass_camera_bind_group: wgpu::BindGroup,
     camera: PerspectiveCamera,
-    depth_texture: Texture,
     scenes: Vec<gltf_loader::Scene<'a>>,
+    diffuse_texture_bind_group_layout: wgpu::BindGroupLayout,
     diffuse_texture_bind_group: wgpu::BindGroup,
+    shadow_depth_texture: Texture,
+    /// Spot lights get camera bind groups of this layout too, see `SpotShadow`
+    shadow_camera_bind_group_layout: wgpu::BindGroupLayout,
+    shadow_camera_bind_group: wgpu::BindGroup,
+    /// Recreated whenever the lights change
+    spot_shadows: Vec<SpotShadow>,
+    shadow_blur: ShadowBlur,
+    shadow_settings: ShadowSettings,
+    shadow_inspector: ShadowInspector,
+    shadow_inspector_visible: bool,
+    light_probes: LightProbes,
+    light_probes_visible: bool,
+    voxel_debug: VoxelDebug,
+    voxel_debug_visible: bool,
+    /// Mip level the voxel debug view walks
+    voxel_debug_level: u32,
+    gizmo: OrientationGizmo,
+    gizmo_visible: bool,
+    world_grid_visible: bool,
+    /// Fogs the background of the main pass, the scene fogs itself
+    sky_fog_pipeline: wgpu::RenderPipeline,
+    voxel_texture: VoxelTexture,
+    voxelization_camera_bind_group: wgpu::BindGroup,
+    voxelizer_texture_bind_group_layout: wgpu::BindGroupLayout,
+    voxelizer_texture_bind_group: wgpu::BindGroup,
+    voxel_grid_info_buffer: wgpu::Buffer,
+    dummy_output: Texture,
+    preprocess_graph: RenderGraph<State<'a>>,
+    frame_graph: RenderGraph<State<'a>>,
+    frame_globals_buffer: wgpu::Buffer,
+    render_settings: RenderSettings,
+    render_settings_buffer: wgpu::Buffer,
+    cone_params: ConeTracingParams,
+    cone_params_buffer: wgpu::Buffer,
+    ssr: Ssr,
+    ssr_settings: SsrSettings,
+    /// Simulated time in seconds, only advances while the frame isn't frozen or when stepping
+    time: f32,
+    frame_index: u32,
+    post_settings: PostSettings,
+    post_chain: PostChain,
+    focus_picker: FocusPicker,
+    cursor_position: PhysicalPosition<f64>,
+    cpu_profiler: CpuProfiler,
+    /// `None` where the device can't write timestamps
+    gpu_profiler: Option<GpuProfiler>,
+    key_bindings: KeyBindings,
+    overlay: Option<Overlay>,
+    outline: Outline,
+    /// The glTF file the scene was loaded from
+    model_path: String,
+    /// Scene tuning the renderer was built with, see `export_config`. Its point lights are kept
+    /// up to date by `set_light` and friends.
+    scene_config: SceneConfig,
+    /// Reallocated by `upload_lights` once it's too small, along with the bind groups using it
+    lights_buffer: wgpu::Buffer,
+    /// Lights `lights_buffer` has room for, the sun included
+    lights_capacity: usize,
+    /// Set when the point lights changed, `update` uploads them again
+    lights_dirty: bool,
+    /// Stops camera input and movement, the scene keeps being presented
+    paused: bool,
+    /// Freezes everything `update` simulates, including time and temporal history, for stepping
+    /// through frames one at a time
+    frozen: bool,
+    step_requested: bool,
+    /// Set when something the voxel grid was built from changed, reruns the preprocess graph
+    voxels_dirty: bool,
+    /// `?revoxelize=amortized` submits one preprocess pass per frame instead of all at once
+    amortize_revoxelization: bool,
+    /// Skips primitives outside the camera's view in the passes it renders, `?culling=0` draws
+    /// everything to compare frame times
+    frustum_culling: bool,
+    /// Rebuilds the voxel grid every this many simulated frames for scenes that change on their
+    /// own, 0 only rebuilds when something changed. Set with `?revoxelize_interval=N`.
+    revoxelize_interval: u32,
+    /// Preprocess passes recorded by `revoxelize` that weren't submitted yet
+    pending_preprocess: VecDeque<wgpu::CommandBuffer>,
+    /// Set until every primitive of the scene is uploaded, `update` loads a batch per frame
+    scene_stream: Option<SceneStream>,
+    /// Shaders the main pass pipelines are generated with, the heatmap one while it's enabled
+    main_shader: Shader,
+    cone_step_heatmap_shader: Shader,
+    /// Colors fragments by how many steps their cones took instead of shading them
+    cone_step_heatmap: bool,
+    /// Set by `?bench=1`, replays a fixed camera path and ignores input until it finishes
+    benchmark: Option<Benchmark>,
 }
 
-#[repr(C)]
+/// Matches `Light` in lights.wgsl. The vec4 position takes the first 16 byte row, the vec3
+/// intensity and the falloff share the second one, the shadow uv transform the third. The spot
+/// direction and the outer cone share the fourth row, the inner cone is packed after the padding
+/// of the fifth. The shadow view projection takes the last four.
+#[repr(C, align(16))]
 #[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
 struct Light {
     position: [f32; 4],
     intensity: [f32; 3],
     falloff: f32,
+    /// Where the light's tile is in the shadow atlas, see `ShadowTile::uv_transform`. Zero for
+    /// lights without shadows.
+    shadow_uv_transform: [f32; 4],
+    /// Normalized direction the spot cone points in
+    spot_direction: [f32; 3],
+    /// Cosine of the angle where the cone has faded out, -1 for lights that aren't spots
+    spot_cos_outer: f32,
+    /// Multiplier of the intensity in the voxel grid, 0 keeps the light out of it
+    gi_intensity: f32,
+    /// Distance in voxel cells the injected light's falloff is clamped to
+    injection_radius: f32,
+    filler: f32,
+    /// Cosine of the angle up to which the cone has its full intensity
+    spot_cos_inner: f32,
+    /// Projects into the light's shadow map, only set for spot lights with a tile. The sun's
+    /// shadow camera has a buffer of its own.
+    shadow_view_projection: [[f32; 4]; 4],
 }
 
-impl Default for Light {
-    fn default() -> Self {
+impl Light {
+    /// Cone values of lights that shine in every direction
+    const NO_SPOT: ([f32; 3], f32, f32) = ([0.0, -1.0, 0.0], -1.0, -1.0);
+
+    fn from_config(light: &PointLightConfig) -> Self {
+        let (spot_direction, spot_cos_outer, spot_cos_inner) = match &light.spot {
+            Some(spot) => {
+                let direction = Vector3::from(spot.direction).normalize();
+                let cos_outer = spot.outer_angle.to_radians().cos();
+                // A cone without a soft edge still needs a sliver of one for smoothstep
+                let cos_inner = spot
+                    .inner_angle
+                    .min(spot.outer_angle)
+                    .to_radians()
+                    .cos()
+                    .max(cos_outer + 1e-4);
+                (direction.into(), cos_outer, cos_inner)
+            }
+            None => Self::NO_SPOT,
+        };
         Self {
-            position: [0.0, 0.0, 0.0, 0.0],
-            intensity: [0.0, 0.0, 0.0],
-            falloff: 0.0,
+            position: [light.position[0], light.position[1], light.position[2], 1.0],
+            intensity: light.intensity,
+            falloff: light.falloff,
+            shadow_uv_transform: [0.0; 4],
+            spot_direction,
+            spot_cos_outer,
+            gi_intensity: light
+                .gi_intensity
+                .unwrap_or(if light.spot.is_some() { 1.0 } else { 0.0 })
+                .max(0.0),
+            injection_radius: light.injection_radius.max(0.0),
+            filler: 0.0,
+            spot_cos_inner,
+            shadow_view_projection: [[0.0; 4]; 4],
         }
     }
 }
-#[repr(C)]
+
+/// Why `State::recreate` couldn't replace the lost device.
+pub enum RecreateError<'a> {
+    /// No device could be opened yet, the lost state is handed back to try again later
+    GpuUnavailable(Box<State<'a>>, gltf_loader::LoadError),
+    /// A new device was opened, but the scene couldn't be loaded onto it
+    Load(gltf_loader::LoadError),
+}
+
+impl fmt::Display for RecreateError<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RecreateError::GpuUnavailable(_, error) | RecreateError::Load(error) => error.fmt(f),
+        }
+    }
+}
+
+/// Why `State::set_light`, `add_light` or `remove_light` refused to change the point lights.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LightError {
+    /// There's no point light with that index, counted like `SceneConfig::lights`
+    IndexOutOfRange { index: usize, count: usize },
+    /// One more point light wouldn't fit in a storage buffer binding
+    Full { count: usize },
+}
+
+impl fmt::Display for LightError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LightError::IndexOutOfRange { index, count } => {
+                write!(f, "No point light #{}, there are {}", index, count)
+            }
+            LightError::Full { count } => write!(
+                f,
+                "{} point lights don't fit in a storage buffer binding",
+                count + 1
+            ),
+        }
+    }
+}
+
+impl std::error::Error for LightError {}
+
+/// Start of `Lights` in lights.wgsl, the runtime sized light array follows it in the storage
+/// buffer. Arrays of structs start on a 16 byte boundary, so the count is packed into the first
+/// row together with the padding that pushes the array there.
+#[repr(C, align(16))]
 #[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
-struct Lights {
+struct LightsHeader {
     filler: [i32; 3],
     count: i32,
-    lights: [Light; 8],
 }
 
-impl<'a> State<'a> {
-    async fn new(window: Window) -> State<'a> {
-        let size = window.inner_size();
+/// Contents of the lights storage buffer, the sun always takes the first slot.
+struct Lights {
+    lights: Vec<Light>,
+}
+
+impl Lights {
+    /// Spot lights with a tile of `shadow_atlas` get a shadow map reaching across
+    /// `scene_bounds`.
+    fn from_config(
+        sun_direction: Vector3<f32>,
+        config: &SceneConfig,
+        shadow_atlas: &ShadowAtlas,
+        scene_bounds: Option<&Aabb>,
+    ) -> Self {
+        let sun = Light {
+            position: [sun_direction.x, sun_direction.y, sun_direction.z, 0.0],
+            intensity: config.sun.intensity,
+            falloff: 0.0,
+            shadow_uv_transform: [0.0; 4],
+            spot_direction: Light::NO_SPOT.0,
+            spot_cos_outer: Light::NO_SPOT.1,
+            // The sun's injection goes through the shadow map, not the light loop
+            gi_intensity: 1.0,
+            injection_radius: 0.0,
+            filler: 0.0,
+            spot_cos_inner: Light::NO_SPOT.2,
+            shadow_view_projection: [[0.0; 4]; 4],
+        };
+        let point_lights = config.lights.iter().map(Light::from_config);
+        let mut lights: Vec<Light> = std::iter::once(sun).chain(point_lights).collect();
+        if let Some(tile) = shadow_atlas.tile(0) {
+            lights[0].shadow_uv_transform = tile.uv_transform(shadow_atlas.size());
+        }
+        for (index, config) in config.lights.iter().enumerate() {
+            let (Some(spot), Some(tile)) = (&config.spot, shadow_atlas.tile(index + 1)) else {
+                continue;
+            };
+            let position = Point3::from(config.position);
+            let light = &mut lights[index + 1];
+            light.shadow_uv_transform = tile.uv_transform(shadow_atlas.size());
+            light.shadow_view_projection = camera::spot_shadow_view_projection(
+                position,
+                spot.direction.into(),
+                spot.outer_angle,
+                spot_shadow_range(position, scene_bounds),
+            )
+            .into();
+        }
+        Self { lights }
+    }
+
+    /// Size of a buffer with room for `capacity` lights, the sun included.
+    fn buffer_size(capacity: usize) -> u64 {
+        (std::mem::size_of::<LightsHeader>() + capacity * std::mem::size_of::<Light>()) as u64
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        let header = LightsHeader {
+            filler: [0, 0, 0],
+            count: self.lights.len() as i32,
+        };
+        let mut bytes = bytemuck::bytes_of(&header).to_vec();
+        bytes.extend_from_slice(bytemuck::cast_slice(&self.lights));
+        bytes
+    }
+}
+
+crate::assert_uniform_layout!(Light, size: 144, {
+    position: 0,
+    intensity: 16,
+    falloff: 28,
+    shadow_uv_transform: 32,
+    spot_direction: 48,
+    spot_cos_outer: 60,
+    gi_intensity: 64,
+    injection_radius: 68,
+    filler: 72,
+    spot_cos_inner: 76,
+    shadow_view_projection: 80,
+});
+crate::assert_uniform_layout!(LightsHeader, size: 16, {
+    filler: 0,
+    count: 12,
+});
+
+/// Far plane of the shadow map of a spot light at `position`, its distance to the farthest
+/// corner of the scene.
+fn spot_shadow_range(position: Point3<f32>, scene_bounds: Option<&Aabb>) -> f32 {
+    scene_bounds.map_or(DEFAULT_SPOT_SHADOW_RANGE, |bounds| {
+        bounds
+            .corners()
+            .iter()
+            .map(|corner| (corner - position).magnitude())
+            .fold(0.1, f32::max)
+    })
+}
+
+/// Hands out the tiles of the shadow atlas, the sun gets the first one and the spot lights share
+/// the rest, brightest first, so once the atlas fills up the dim ones get the smaller tiles or
+/// none. Point lights cast no shadows. Called again whenever the lights change.
+fn allocate_shadow_tiles(config: &SceneConfig) -> ShadowAtlas {
+    let mut shadow_atlas = ShadowAtlas::new(SHADOW_ATLAS_SIZE);
+    shadow_atlas
+        .allocate(0, SUN_SHADOW_MAP_SIZE)
+        .expect("The sun is allocated first, the atlas is empty");
+    let mut spot_lights: Vec<_> = config
+        .lights
+        .iter()
+        .enumerate()
+        .filter(|(_, light)| light.spot.is_some())
+        .collect();
+    let importance = |light: &PointLightConfig| light.intensity.into_iter().fold(0.0, f32::max);
+    spot_lights.sort_by(|(_, a), (_, b)| importance(b).total_cmp(&importance(a)));
+    for (index, _) in spot_lights {
+        shadow_atlas.allocate(index + 1, SPOT_SHADOW_MAP_SIZE);
+    }
+    shadow_atlas
+}
+
+/// Only writes depth, also used by the depth prepass.
+fn create_shadow_shader(device: &wgpu::Device) -> Shader {
+    Shader {
+        vs_entry: "vs_main".to_string(),
+        fs_entry: "fs_main".to_string(),
+        fs_mask_entry: Some("fs_masked".to_string()),
+        fs_blend_entry: None,
+        module: device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Shadow shader module"),
+            source: wgpu::ShaderSource::Wgsl(
+                scene_shader_source(include_str!("shaders/shadow.wgsl")).into(),
+            ),
+        }),
+    }
+}
 
+/// Bind group 0 of the shadow shader, a single view projection.
+fn create_shadow_camera_bind_group_layout(device: &wgpu::Device) -> wgpu::BindGroupLayout {
+    device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+        label: Some("Shadow camera bind group layout"),
+        entries: &[wgpu::BindGroupLayoutEntry {
+            binding: 0,
+            count: None,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Uniform,
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            visibility: wgpu::ShaderStages::VERTEX,
+        }],
+    })
+}
+
+/// Renders the "shadow" pipelines of `scene` into the atlas, one pass per tile drawn with the
+/// tile as viewport and scissor and the camera bind group that goes with it. The first pass
+/// clears the whole atlas.
+fn record_shadow_tiles<'a>(
+    encoder: &mut wgpu::CommandEncoder,
+    scene: &gltf_loader::Scene,
+    atlas_view: &wgpu::TextureView,
+    tiles: impl IntoIterator<Item = (ShadowTile, &'a wgpu::BindGroup)>,
+) {
+    let mut load = wgpu::LoadOp::Clear(1.0);
+    for (tile, camera_bind_group) in tiles {
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Shadow render pass"),
+            color_attachments: &[],
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                view: atlas_view,
+                depth_ops: Some(wgpu::Operations { load, store: true }),
+                stencil_ops: None,
+            }),
+        });
+        load = wgpu::LoadOp::Load;
+        render_pass.set_bind_group(0, camera_bind_group, &[]);
+        scene.draw_pipelines_with("shadow", &mut render_pass, &tile.draw_params());
+    }
+}
+
+/// Camera of a spot light rendering its shadow map into its tile of the atlas.
+struct SpotShadow {
+    /// Slot of the light in the lights buffer, which is what the atlas is keyed by
+    slot: usize,
+    camera_bind_group: wgpu::BindGroup,
+}
+
+/// One `SpotShadow` for every light in `lights` with a tile, except for the sun.
+fn create_spot_shadows(
+    device: &wgpu::Device,
+    layout: &wgpu::BindGroupLayout,
+    lights: &Lights,
+    shadow_atlas: &ShadowAtlas,
+) -> Vec<SpotShadow> {
+    (1..lights.lights.len())
+        .filter(|&slot| shadow_atlas.tile(slot).is_some())
+        .map(|slot| {
+            let buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some("Spot shadow camera buffer"),
+                contents: bytemuck::cast_slice(&[lights.lights[slot].shadow_view_projection]),
+                usage: wgpu::BufferUsages::UNIFORM,
+            });
+            let camera_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+                label: Some("Spot shadow camera bind group"),
+                layout,
+                entries: &[wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: buffer.as_entire_binding(),
+                }],
+            });
+            SpotShadow {
+                slot,
+                camera_bind_group,
+            }
+        })
+        .collect()
+}
+
+/// Backends to try in order, `?backends=vulkan,gl` tries Vulkan and falls back to GL. Without it
+/// every browser WebGPU or native primary backend is tried at once, natively `WGPU_BACKEND=vulkan`
+/// etc. narrows that down, e.g. for capturing with RenderDoc.
+fn backend_preference() -> Vec<wgpu::Backends> {
+    if let Some(list) = util::query_param("backends") {
+        let preference: Vec<wgpu::Backends> = list
+            .split(',')
+            .map(wgpu::util::parse_backends_from_comma_list)
+            .filter(|backends| !backends.is_empty())
+            .collect();
+        if !preference.is_empty() {
+            return preference;
+        }
+        log::warn!("Ignoring \"backends={}\", it names no known backend", list);
+    }
+    vec![if cfg!(target_arch = "wasm32") {
+        wgpu::Backends::BROWSER_WEBGPU
+    } else {
+        wgpu::util::backend_bits_from_env().unwrap_or(wgpu::Backends::PRIMARY)
+    }]
+}
+
+/// Opens the glTF file at `model_path`, or the default model if that fails. Returns the path that
+/// was actually opened.
+async fn open_model(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    model_path: String,
+    options: gltf_loader::LoadOptions,
+) -> Result<(String, gltf_loader::SceneLoader), gltf_loader::LoadError> {
+    let log_progress = |progress: gltf_loader::FetchProgress| {
+        log::info!(
+            "Fetched {} of {} model files, {:.1} MB",
+            progress.loaded_items,
+            progress.total_items,
+            progress.loaded_bytes as f64 / 1e6
+        )
+    };
+    let on_fetch: Option<&dyn Fn(gltf_loader::FetchProgress)> = Some(&log_progress);
+    match gltf_loader::SceneLoader::open(device, queue, &model_path, options, on_fetch).await {
+        Ok(scene_loader) => return Ok((model_path, scene_loader)),
+        Err(error) if model_path != DEFAULT_MODEL_PATH => log::error!(
+            "Can't load {}, falling back to {}: {}",
+            model_path,
+            DEFAULT_MODEL_PATH,
+            error
+        ),
+        Err(error) => return Err(error),
+    }
+    let scene_loader =
+        gltf_loader::SceneLoader::open(device, queue, DEFAULT_MODEL_PATH, options, on_fetch)
+            .await?;
+    Ok((DEFAULT_MODEL_PATH.to_string(), scene_loader))
+}
+
+/// Downlevel backends like GL can't always run the voxelization, which writes the voxel grid
+/// from fragment shaders.
+fn is_adapter_supported(adapter: &wgpu::Adapter) -> bool {
+    adapter.get_downlevel_capabilities().flags.contains(
+        wgpu::DownlevelFlags::COMPUTE_SHADERS | wgpu::DownlevelFlags::FRAGMENT_WRITABLE_STORAGE,
+    )
+}
+
+/// Largest buffer the renderer asks for, big scenes keep their whole geometry in one buffer
+const MAX_BUFFER_SIZE: u64 = 1024 * 1024 * 1024 * 2;
+
+/// The bind group layouts are written against WebGPU's default limits, texture sizes and the
+/// buffer size are taken from the adapter instead. The voxel grid is shrunk to the 3D texture
+/// size later on. Adapters that can't satisfy the result are skipped.
+fn required_limits(adapter_limits: &wgpu::Limits) -> wgpu::Limits {
+    wgpu::Limits {
+        max_buffer_size: adapter_limits.max_buffer_size.min(MAX_BUFFER_SIZE),
+        ..wgpu::Limits::default()
+    }
+    .using_resolution(adapter_limits.clone())
+}
+
+/// A device on the first adapter from `backend_preference` that can run the renderer, and a
+/// surface for the window on the same instance if there is one.
+struct Gpu {
+    instance: wgpu::Instance,
+    surface: Option<wgpu::Surface>,
+    adapter: wgpu::Adapter,
+    device: wgpu::Device,
+    queue: wgpu::Queue,
+    device_lost: Arc<AtomicBool>,
+}
+
+/// What `State` renders into.
+enum Target {
+    /// The surface of a window, presented every frame
+    Window {
+        window: Window,
+        surface: wgpu::Surface,
+    },
+    /// A texture nothing shows, for benchmarking without a display
+    Offscreen(wgpu::Texture),
+}
+
+impl Target {
+    fn window(&self) -> Option<&Window> {
+        match self {
+            Target::Window { window, .. } => Some(window),
+            Target::Offscreen(_) => None,
+        }
+    }
+}
+
+/// The texture of `Target::Offscreen`, in the size and format `config` would give a surface.
+fn create_offscreen_texture(
+    device: &wgpu::Device,
+    config: &wgpu::SurfaceConfiguration,
+) -> wgpu::Texture {
+    device.create_texture(&wgpu::TextureDescriptor {
+        label: Some("Offscreen target"),
+        size: wgpu::Extent3d {
+            width: config.width,
+            height: config.height,
+            depth_or_array_layers: 1,
+        },
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format: config.format,
+        usage: config.usage | wgpu::TextureUsages::COPY_SRC,
+        view_formats: &config.view_formats,
+    })
+}
+
+async fn request_gpu(window: Option<&Window>) -> Result<Gpu, gltf_loader::LoadError> {
+    let mut selected = None;
+    for backends in backend_preference() {
         let instance = wgpu::Instance::new(wgpu::InstanceDescriptor {
-            backends: wgpu::Backends::BROWSER_WEBGPU,
+            backends,
             dx12_shader_compiler: Default::default(),
         });
-        let surface = unsafe { instance.create_surface(&window) }.unwrap();
+        let surface = match window.map(|window| unsafe { instance.create_surface(window) }) {
+            Some(Ok(surface)) => Some(surface),
+            Some(Err(error)) => {
+                log::warn!("Can't create a {:?} surface: {}", backends, error);
+                continue;
+            }
+            None => None,
+        };
         let adapter = instance
             .request_adapter(&wgpu::RequestAdapterOptions {
                 power_preference: wgpu::PowerPreference::default(),
-                compatible_surface: Some(&surface),
+                compatible_surface: surface.as_ref(),
                 force_fallback_adapter: false,
             })
-            .await
-            .unwrap();
+            .await;
+        match adapter {
+            Some(adapter) if !is_adapter_supported(&adapter) => log::warn!(
+                "Skipping {} ({:?}), voxelization needs compute shaders and storage writes \
+                 from fragment shaders",
+                adapter.get_info().name,
+                adapter.get_info().backend
+            ),
+            Some(adapter) => {
+                let limits = required_limits(&adapter.limits());
+                if limits.check_limits(&adapter.limits()) {
+                    selected = Some((instance, surface, adapter, limits));
+                    break;
+                }
+                log::warn!(
+                    "Skipping {} ({:?}), its limits are below WebGPU's defaults",
+                    adapter.get_info().name,
+                    adapter.get_info().backend
+                );
+            }
+            None => log::warn!("No {:?} adapter", backends),
+        }
+    }
+    let (instance, surface, adapter, limits) = selected.ok_or(gltf_loader::LoadError::NoAdapter)?;
+    let adapter_info = adapter.get_info();
+    log::info!("Using {} ({:?})", adapter_info.name, adapter_info.backend);
+    ADAPTER_STATE.with(|adapter_state| {
+        *adapter_state.borrow_mut() = AdapterState {
+            name: adapter_info.name.clone(),
+            backend: format!("{:?}", adapter_info.backend),
+        }
+    });
 
-        let mut limits = wgpu::Limits::default();
-        limits.max_buffer_size = 1024 * 1024 * 1024 * 2;
+    let (device, queue) = adapter
+        .request_device(
+            &wgpu::DeviceDescriptor {
+                // Per pass GPU timings where the adapter can write timestamps
+                features: adapter.features() & wgpu::Features::TIMESTAMP_QUERY,
+                limits,
+                label: None,
+            },
+            None,
+        )
+        .await
+        .map_err(gltf_loader::LoadError::RequestDevice)?;
 
-        let (device, queue) = adapter
-            .request_device(
-                &wgpu::DeviceDescriptor {
-                    features: wgpu::Features::empty(),
-                    limits,
-                    label: None,
-                },
-                None,
-            )
-            .await
-            .unwrap();
+    let device_lost = Arc::new(AtomicBool::new(false));
+    {
+        let device_lost = device_lost.clone();
+        device.on_uncaptured_error(Box::new(move |error| {
+            // wgpu 0.16 has no device lost callback (`set_device_lost_callback` came in 0.19)
+            // and `wgpu::Error` only has the OutOfMemory and Validation variants. Natively a
+            // lost device arrives as a validation error wrapping wgpu-core's
+            // `DeviceError::Lost` ("Parent device is lost"), a type wgpu doesn't re-export so
+            // it can't be downcast to, and on the web it's whatever message the browser gives.
+            // Matching the message is the only way to tell it apart from a genuine bug, which is
+            // only logged since the frames after it usually still render.
+            if matches!(error, wgpu::Error::OutOfMemory { .. })
+                || error.to_string().contains("lost")
+            {
+                log::error!("Device lost: {}", error);
+                device_lost.store(true, Ordering::Relaxed);
+            } else {
+                log::error!("wgpu error: {}", error);
+            }
+        }));
+    }
 
-        let surface_caps = surface.get_capabilities(&adapter);
-            
-        let config = wgpu::SurfaceConfiguration {
-            usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
-            format: surface_caps.formats[0],
-            width: size.width,
-            height: size.height,
-            present_mode: wgpu::PresentMode::AutoNoVsync,
-            alpha_mode: wgpu::CompositeAlphaMode::Auto,
-            view_formats: vec![wgpu::TextureFormat::Bgra8UnormSrgb],
-        };
+    Ok(Gpu {
+        instance,
+        surface,
+        adapter,
+        device,
+        queue,
+        device_lost,
+    })
+}
+
+/// Builds the source of a shader that draws the scene, prepending the shared structs.
+fn scene_shader_source(source: &str) -> String {
+    format!(
+        "{}{}{}",
+        include_str!("shaders/common.wgsl"),
+        include_str!("shaders/lights.wgsl"),
+        source
+    )
+}
 
-        surface.configure(&device, &config);
+/// Source of the main pass shader. The cone step heatmap is a constant rather than a render
+/// settings flag, so the regular variant doesn't even count the steps.
+fn main_shader_source(cone_step_heatmap: bool) -> String {
+    format!(
+        "const CONE_STEP_HEATMAP: bool = {};\n{}",
+        cone_step_heatmap,
+        scene_shader_source(include_str!("shaders/shader.wgsl"))
+    )
+}
 
-        let shadow_shader_module = device.create_shader_module(wgpu::ShaderModuleDescriptor {
-            label: Some("Shadow shader module"),
-            source: wgpu::ShaderSource::Wgsl(include_str!("shaders/shadow.wgsl").into()),
-        });
+/// Buffers group 1 of the voxelization and main passes read the lighting from.
+struct LightingBuffers<'b> {
+    lights: &'b wgpu::Buffer,
+    voxel_grid_info: &'b wgpu::Buffer,
+    frame_globals: &'b wgpu::Buffer,
+    render_settings: &'b wgpu::Buffer,
+    /// Only read by the main pass
+    cone_params: &'b wgpu::Buffer,
+}
 
-        let voxelizer_shader_module = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+/// Voxelization has no depth test, so one entry point handles the alpha cutoff of every material.
+fn create_voxelizer_shader(device: &wgpu::Device) -> Shader {
+    Shader {
+        vs_entry: "vs_main".to_string(),
+        fs_entry: "fs_main".to_string(),
+        fs_mask_entry: None,
+        fs_blend_entry: None,
+        module: device.create_shader_module(wgpu::ShaderModuleDescriptor {
             label: Some("Voxelizer shader module"),
-            source: wgpu::ShaderSource::Wgsl(include_str!("shaders/voxelize.wgsl").into()),
-        });
-
-        let shader_module = device.create_shader_module(wgpu::ShaderModuleDescriptor {
-            label: Some("Shader module"),
-            source: wgpu::ShaderSource::Wgsl(include_str!("shaders/shader.wgsl").into()),
-        });
+            source: wgpu::ShaderSource::Wgsl(
+                scene_shader_source(include_str!("shaders/voxelize.wgsl")).into(),
+            ),
+        }),
+    }
+}
 
-        let shadow_shader = Shader {
-            vs_entry: "vs_main".to_string(),
-            fs_entry: "fs_main".to_string(),
-            module: shadow_shader_module,
-        };
+/// Bind group 0 of the voxelization pass, the projections along the three axes and the shadow
+/// camera's view projection.
+fn create_voxelization_camera_bind_group_layout(device: &wgpu::Device) -> wgpu::BindGroupLayout {
+    device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+        label: Some("Voxelization camera bind group layout"),
+        entries: &[
+            wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                count: None,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                visibility: wgpu::ShaderStages::VERTEX,
+            },
+            wgpu::BindGroupLayoutEntry {
+                binding: 1,
+                count: None,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                visibility: wgpu::ShaderStages::VERTEX,
+            },
+        ],
+    })
+}
 
-        let voxelizer_shader = Shader {
-            vs_entry: "vs_main".to_string(),
-            fs_entry: "fs_main".to_string(),
-            module: voxelizer_shader_module,
-        };
+/// Layout of `create_voxelizer_texture_bind_group`.
+fn create_voxelizer_texture_bind_group_layout(device: &wgpu::Device) -> wgpu::BindGroupLayout {
+    device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+        label: Some("Voxelizer texture bind group layout"),
+        entries: &[
+            wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                count: None,
+                ty: wgpu::BindingType::Texture {
+                    sample_type: wgpu::TextureSampleType::Depth,
+                    view_dimension: wgpu::TextureViewDimension::D2,
+                    multisampled: false,
+                },
+                visibility: wgpu::ShaderStages::FRAGMENT,
+            },
+            wgpu::BindGroupLayoutEntry {
+                binding: 1,
+                count: None,
+                ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Comparison),
+                visibility: wgpu::ShaderStages::FRAGMENT,
+            },
+            wgpu::BindGroupLayoutEntry {
+                binding: 2,
+                count: None,
+                ty: wgpu::BindingType::StorageTexture {
+                    access: wgpu::StorageTextureAccess::WriteOnly,
+                    format: wgpu::TextureFormat::Rgba16Float,
+                    view_dimension: wgpu::TextureViewDimension::D3,
+                },
+                visibility: wgpu::ShaderStages::FRAGMENT,
+            },
+            wgpu::BindGroupLayoutEntry {
+                binding: 3,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Storage { read_only: true },
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            },
+            wgpu::BindGroupLayoutEntry {
+                binding: 4,
+                visibility: wgpu::ShaderStages::VERTEX_FRAGMENT,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            },
+        ],
+    })
+}
 
-        let shader = Shader {
-            vs_entry: "vs_main".to_string(),
-            fs_entry: "fs_main".to_string(),
-            module: shader_module,
-        };
+/// Generates the "voxelization" pipelines of the opaque primitives. They rasterize into a
+/// `Rgba8Uint` target the size of a grid face, which only sets the fragment count.
+fn generate_voxelization_pipeline(
+    device: &wgpu::Device,
+    scene: &mut gltf_loader::Scene,
+    shader: &Shader,
+    camera_layout: &wgpu::BindGroupLayout,
+    texture_layout: &wgpu::BindGroupLayout,
+) {
+    scene.generate_pipeline(
+        device,
+        shader,
+        "voxelization",
+        &[camera_layout, texture_layout],
+        &[Some(wgpu::ColorTargetState {
+            format: wgpu::TextureFormat::Rgba8Uint,
+            blend: None,
+            write_mask: wgpu::ColorWrites::empty(),
+        })],
+        None,
+        false,
+        Some(&is_opaque),
+    );
+}
 
-        let shadow_camera_bind_group_layout =
-            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-                label: Some("Shadow camera bind group layout"),
-                entries: &[wgpu::BindGroupLayoutEntry {
-                    binding: 0,
-                    count: None,
-                    ty: wgpu::BindingType::Buffer {
-                        ty: wgpu::BufferBindingType::Uniform,
-                        has_dynamic_offset: false,
-                        min_binding_size: None,
-                    },
-                    visibility: wgpu::ShaderStages::VERTEX,
-                }],
-            });
+/// Bind group 1 of the voxelization pass, recreated when the lights buffer grows.
+fn create_voxelizer_texture_bind_group(
+    device: &wgpu::Device,
+    layout: &wgpu::BindGroupLayout,
+    shadow_depth_texture: &Texture,
+    voxel_texture: &VoxelTexture,
+    buffers: &LightingBuffers,
+) -> wgpu::BindGroup {
+    device.create_bind_group(&wgpu::BindGroupDescriptor {
+        label: Some("Voxelizer texture bind group"),
+        layout,
+        entries: &[
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::TextureView(&shadow_depth_texture.view),
+            },
+            wgpu::BindGroupEntry {
+                binding: 1,
+                resource: wgpu::BindingResource::Sampler(&shadow_depth_texture.sampler),
+            },
+            wgpu::BindGroupEntry {
+                binding: 2,
+                resource: wgpu::BindingResource::TextureView(voxel_texture.get_mip_0()),
+            },
+            wgpu::BindGroupEntry {
+                binding: 3,
+                resource: buffers.lights.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 4,
+                resource: buffers.voxel_grid_info.as_entire_binding(),
+            },
+        ],
+    })
+}
 
-        let diffuse_camera_bind_group_layout =
-            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-                label: Some("Diffuse camera bind group layout"),
-                entries: &[
-                    wgpu::BindGroupLayoutEntry {
-                        binding: 0,
-                        count: None,
-                        ty: wgpu::BindingType::Buffer {
-                            ty: wgpu::BufferBindingType::Uniform,
-                            has_dynamic_offset: false,
-                            min_binding_size: None,
-                        },
-                        visibility: wgpu::ShaderStages::VERTEX,
-                    },
-                    wgpu::BindGroupLayoutEntry {
-                        binding: 1,
-                        count: None,
-                        ty: wgpu::BindingType::Buffer {
-                            ty: wgpu::BufferBindingType::Uniform,
-                            has_dynamic_offset: false,
-                            min_binding_size: None,
-                        },
-                        visibility: wgpu::ShaderStages::VERTEX,
-                    },
-                ],
-            });
+/// Bind group 1 of the main pass and the sky fog, recreated when the lights buffer grows.
+fn create_diffuse_texture_bind_group(
+    device: &wgpu::Device,
+    layout: &wgpu::BindGroupLayout,
+    shadow_depth_texture: &Texture,
+    voxel_texture: &VoxelTexture,
+    shadow_blur: &ShadowBlur,
+    light_probes: &LightProbes,
+    buffers: &LightingBuffers,
+) -> wgpu::BindGroup {
+    device.create_bind_group(&wgpu::BindGroupDescriptor {
+        label: Some("Diffuse texture bind group"),
+        layout,
+        entries: &[
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::TextureView(&shadow_depth_texture.view),
+            },
+            wgpu::BindGroupEntry {
+                binding: 1,
+                resource: wgpu::BindingResource::Sampler(&shadow_depth_texture.sampler),
+            },
+            wgpu::BindGroupEntry {
+                binding: 2,
+                resource: wgpu::BindingResource::TextureView(&voxel_texture.main_view),
+            },
+            wgpu::BindGroupEntry {
+                binding: 3,
+                resource: wgpu::BindingResource::Sampler(&voxel_texture.sampler),
+            },
+            wgpu::BindGroupEntry {
+                binding: 4,
+                resource: buffers.lights.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 5,
+                resource: buffers.frame_globals.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 6,
+                resource: buffers.voxel_grid_info.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 7,
+                resource: buffers.render_settings.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 8,
+                resource: wgpu::BindingResource::TextureView(&shadow_blur.moments_view),
+            },
+            wgpu::BindGroupEntry {
+                binding: 9,
+                resource: wgpu::BindingResource::TextureView(&light_probes.views[0]),
+            },
+            wgpu::BindGroupEntry {
+                binding: 10,
+                resource: wgpu::BindingResource::TextureView(&light_probes.views[1]),
+            },
+            wgpu::BindGroupEntry {
+                binding: 11,
+                resource: wgpu::BindingResource::TextureView(&light_probes.views[2]),
+            },
+            wgpu::BindGroupEntry {
+                binding: 12,
+                resource: wgpu::BindingResource::TextureView(&voxel_texture.sdf_view),
+            },
+            wgpu::BindGroupEntry {
+                binding: 13,
+                resource: wgpu::BindingResource::TextureView(&voxel_texture.anisotropic_view),
+            },
+            wgpu::BindGroupEntry {
+                binding: 14,
+                resource: buffers.cone_params.as_entire_binding(),
+            },
+        ],
+    })
+}
 
-        let voxel_texture = VoxelTexture::new(
-            &device,
-            wgpu::Extent3d {
-                width: 512,
-                height: 512,
-                depth_or_array_layers: 512,
+/// Bind group 0 of the scene passes: the camera, the shadow camera, the depth prepass the
+/// contact shadows march through and the planar reflection mirror materials show.
+fn create_diffuse_camera_bind_group(
+    device: &wgpu::Device,
+    layout: &wgpu::BindGroupLayout,
+    camera_buffer: &wgpu::Buffer,
+    shadow_camera_buffer: &wgpu::Buffer,
+    prepass_depth: &Texture,
+    planar_reflection: &Texture,
+) -> wgpu::BindGroup {
+    device.create_bind_group(&wgpu::BindGroupDescriptor {
+        label: Some("Camera bind group"),
+        layout,
+        entries: &[
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: camera_buffer.as_entire_binding(),
             },
-            "Voxel texture",
-        );
+            wgpu::BindGroupEntry {
+                binding: 1,
+                resource: shadow_camera_buffer.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 2,
+                resource: wgpu::BindingResource::TextureView(&prepass_depth.view),
+            },
+            wgpu::BindGroupEntry {
+                binding: 3,
+                resource: wgpu::BindingResource::TextureView(&planar_reflection.view),
+            },
+        ],
+    })
+}
 
-        let dummy_output =
-            Texture::create_target_texture(&device, 512, 512, "Dummy target texture");
+/// Per-frame values shared by every shader, matches `FrameGlobals` in common.wgsl.
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct FrameGlobals {
+    resolution: [f32; 2],
+    time: f32,
+    frame_index: u32,
+}
 
-        let voxelizer_texture_bind_group_layout =
-            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-                label: Some("Voxelizer texture bind group layout"),
-                entries: &[
-                    wgpu::BindGroupLayoutEntry {
-                        binding: 0,
-                        count: None,
-                        ty: wgpu::BindingType::Texture {
-                            sample_type: wgpu::TextureSampleType::Depth,
-                            view_dimension: wgpu::TextureViewDimension::D2,
-                            multisampled: false,
+// vec2<f32> + f32 + u32 packs into a single 16 byte row on the WGSL side
+crate::assert_uniform_layout!(FrameGlobals, size: 16, {
+    resolution: 0,
+    time: 8,
+    frame_index: 12,
+});
+
+/// Debug switches for the main pass, matches `RenderSettings` in common.wgsl.
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct RenderSettings {
+    flags: u32,
+    /// Added to the LOD of base color samples, on top of the per material bias
+    lod_bias: f32,
+    /// Number of depth buffer samples a contact shadow ray takes
+    contact_shadow_steps: u32,
+    /// World space length of contact shadow rays
+    contact_shadow_distance: f32,
+    /// Light scattered towards the camera by the fog, on top of the GI inscattering
+    fog_color: [f32; 3],
+    /// Extinction per world unit at `fog_base_height`
+    fog_density: f32,
+    /// How quickly the fog thins out going up
+    fog_height_falloff: f32,
+    fog_base_height: f32,
+    /// Cone steps per fragment the heatmap shows in its brightest color
+    cone_step_heatmap_max: f32,
+    filler: f32,
+}
+
+impl Default for RenderSettings {
+    fn default() -> Self {
+        Self {
+            flags: 0,
+            lod_bias: 0.0,
+            contact_shadow_steps: 16,
+            contact_shadow_distance: 0.3,
+            fog_color: [0.5, 0.55, 0.65],
+            fog_density: 0.04,
+            fog_height_falloff: 0.3,
+            fog_base_height: 0.0,
+            // Seven cones of eight steps each, the default `ConeTracingParams`
+            cone_step_heatmap_max: 56.0,
+            filler: 0.0,
+        }
+    }
+}
+
+impl RenderSettings {
+    /// Replaces every material with `MaterialData::DEFAULT` to inspect lighting on its own
+    const NEUTRAL_MATERIAL: u32 = 1;
+    /// Colors fragments by the mip level their base color is sampled from
+    const MIP_LEVELS: u32 = 2;
+    /// Variance shadow mapping on the blurred shadow moments instead of a single comparison
+    const SOFT_SHADOWS: u32 = 4;
+    /// Marches short rays towards the sun through the depth prepass to darken contact areas the
+    /// shadow map is too coarse for
+    const CONTACT_SHADOWS: u32 = 8;
+    /// Indirect diffuse from the baked light probes instead of tracing cones, for slow GPUs
+    const PROBE_GI: u32 = 16;
+    /// Cones jump through empty space using the voxel distance field
+    const SDF_SKIPPING: u32 = 32;
+    /// Shows the distance field instead of the lit scene
+    const SDF_DISTANCE: u32 = 64;
+    /// Exponential height fog over the scene and the background
+    const FOG: u32 = 128;
+    /// Adds voxel radiance sampled along the view ray to the fog color
+    const FOG_GI_INSCATTER: u32 = 256;
+    const LOD_BIAS_STEP: f32 = 0.25;
+
+    fn has_flag(&self, flag: u32) -> bool {
+        self.flags & flag != 0
+    }
+
+    fn toggle_flag(&mut self, flag: u32) {
+        self.flags ^= flag;
+    }
+
+    fn set_flag(&mut self, flag: u32, enabled: bool) {
+        if enabled {
+            self.flags |= flag;
+        } else {
+            self.flags &= !flag;
+        }
+    }
+}
+
+crate::assert_uniform_layout!(RenderSettings, size: 48, {
+    flags: 0,
+    lod_bias: 4,
+    contact_shadow_steps: 8,
+    contact_shadow_distance: 12,
+    fog_color: 16,
+    fog_density: 28,
+    fog_height_falloff: 32,
+    fog_base_height: 36,
+    cone_step_heatmap_max: 40,
+    filler: 44,
+});
+
+/// Quality of the indirect diffuse cones, matches `ConeTracingParams` in common.wgsl. Changing
+/// these through `State::set_cone_params` takes effect the next frame, no shader is recompiled.
+#[repr(C)]
+#[derive(Debug, Copy, Clone, PartialEq, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct ConeTracingParams {
+    /// Cones traced per fragment, spread over the hemisphere around the normal. Seven keeps the
+    /// hand tuned pattern of one cone along the normal and a ring of six around it.
+    pub cone_count: u32,
+    /// Half angle of each cone in radians
+    pub aperture: f32,
+    /// World space distance between the samples of a cone
+    pub step_size: f32,
+    /// Cones stop after this world space distance
+    pub max_distance: f32,
+    /// Ahead of `gi_intensity`, a vec3 at the end would be aligned to 16 bytes in WGSL
+    pub filler: [u32; 3],
+    /// Scale of the indirect diffuse light
+    pub gi_intensity: f32,
+}
+
+impl Default for ConeTracingParams {
+    fn default() -> Self {
+        Self {
+            cone_count: 7,
+            aperture: std::f32::consts::FRAC_PI_6,
+            step_size: 0.3,
+            // Eight samples per cone
+            max_distance: 2.5,
+            gi_intensity: 1.0,
+            filler: [0; 3],
+        }
+    }
+}
+
+impl ConeTracingParams {
+    /// Cones the shader traces at most, more would only make the GPU time out
+    pub const MAX_CONES: u32 = 64;
+    /// Furthest the cones reach, cones leaving the voxel grid stop early anyway
+    pub const MAX_DISTANCE: f32 = 100.0;
+    /// Samples a cone takes at most, the step size grows to stay under it
+    pub const MAX_STEPS: u32 = 256;
+
+    /// Applies the `cone_count`, `cone_aperture`, `cone_step` and `cone_distance` query
+    /// parameters.
+    fn from_query() -> Self {
+        let mut params = Self::default();
+        if let Some(count) = util::query_param("cone_count").and_then(|count| count.parse().ok()) {
+            params.cone_count = count;
+        }
+        if let Some(aperture) =
+            util::query_param("cone_aperture").and_then(|aperture| aperture.parse().ok())
+        {
+            params.aperture = aperture;
+        }
+        if let Some(step) = util::query_param("cone_step").and_then(|step| step.parse().ok()) {
+            params.step_size = step;
+        }
+        if let Some(distance) =
+            util::query_param("cone_distance").and_then(|distance| distance.parse().ok())
+        {
+            params.max_distance = distance;
+        }
+        params.clamped()
+    }
+
+    /// Keeps the cones finite, a zero step size would never leave the loop and a tiny one or a
+    /// huge distance would take long enough for the GPU to time out.
+    fn clamped(mut self) -> Self {
+        self.cone_count = self.cone_count.clamp(1, Self::MAX_CONES);
+        self.aperture = self.aperture.clamp(0.01, 1.5);
+        self.max_distance = self.max_distance.clamp(0.0, Self::MAX_DISTANCE);
+        self.step_size = self
+            .step_size
+            .max(0.01)
+            .max(self.max_distance / Self::MAX_STEPS as f32);
+        self.gi_intensity = self.gi_intensity.max(0.0);
+        self
+    }
+}
+
+crate::assert_uniform_layout!(ConeTracingParams, size: 32, {
+    cone_count: 0,
+    aperture: 4,
+    step_size: 8,
+    max_distance: 12,
+    filler: 16,
+    gi_intensity: 28,
+});
+
+/// Format of the main depth buffer, the stencil is used by the selection outline. Switching to
+/// `Texture::DEPTH_FORMAT` saves memory but disables the outline. The shadow map always uses
+/// `Texture::DEPTH_FORMAT`.
+const MAIN_DEPTH_FORMAT: wgpu::TextureFormat = Texture::DEPTH_STENCIL_FORMAT;
+
+/// Pipeline list of the blended primitives, drawn by the main pass after the opaque ones
+const TRANSPARENT_PIPELINE: &str = "transparent";
+/// Blends over the scene and leaves the G-buffer to what's behind, since the screen space
+/// reflections can't handle more than one surface per pixel
+const TRANSPARENT_TARGETS: [Option<wgpu::ColorTargetState>; 2] = [
+    Some(wgpu::ColorTargetState {
+        format: Texture::HDR_FORMAT,
+        blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+        write_mask: wgpu::ColorWrites::ALL,
+    }),
+    Some(wgpu::ColorTargetState {
+        format: ssr::GBUFFER_FORMAT,
+        blend: None,
+        write_mask: wgpu::ColorWrites::empty(),
+    }),
+];
+
+/// Width and height of the shadow atlas every shadow casting light renders into, a quarter goes
+/// to the sun and the rest is shared by the spot lights
+const SHADOW_ATLAS_SIZE: u32 = 4096;
+/// Tile size the sun asks the shadow atlas for, it gets a smaller one if that's taken
+const SUN_SHADOW_MAP_SIZE: u32 = 2048;
+/// Tile size spot lights ask the shadow atlas for
+const SPOT_SHADOW_MAP_SIZE: u32 = 1024;
+/// Far plane of spot light shadow maps in scenes without position bounds
+const DEFAULT_SPOT_SHADOW_RANGE: f32 = 50.0;
+
+/// Distance the light move actions push the first point light per key press
+const LIGHT_NUDGE: f32 = 0.5;
+/// Factor the depth of field focus and aperture actions scale their setting by per key press
+const DOF_STEP: f32 = 1.25;
+/// How long `run` waits before looking for a graphics device again after the last attempt at
+/// replacing a lost one found none
+const DEVICE_RETRY_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Placement of the voxel grid and the sky cones fall back to once they leave it, matches
+/// `VoxelGridInfo` in common.wgsl.
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct VoxelGridInfo {
+    center: [f32; 3],
+    half_extent: f32,
+    sky_color: [f32; 3],
+    resolution: f32,
+    ground_color: [f32; 3],
+    edge_fade: f32,
+}
+
+crate::assert_uniform_layout!(VoxelGridInfo, size: 48, {
+    center: 0,
+    half_extent: 12,
+    sky_color: 16,
+    resolution: 28,
+    ground_color: 32,
+    edge_fade: 44,
+});
+
+impl<'a> State<'a> {
+    /// Takes over `window` and renders the scene picked by the `model` query parameter into it.
+    /// Fails if neither that scene nor the default one can be loaded.
+    pub async fn new(window: Window) -> Result<State<'a>, gltf_loader::LoadError> {
+        let size = window.inner_size();
+        Self::create(Some(window), size, PreservedState::default()).await
+    }
+
+    /// Like `new`, but renders the .gltf or .glb file at `model_path` instead. The path is
+    /// resolved like the query parameter, relative to the page on the web.
+    pub async fn with_model(
+        window: Window,
+        model_path: String,
+    ) -> Result<State<'a>, gltf_loader::LoadError> {
+        let preserved = PreservedState {
+            model_path: Some(model_path),
+            ..Default::default()
+        };
+        let size = window.inner_size();
+        Self::create(Some(window), size, preserved).await
+    }
+
+    /// Renders into an offscreen texture of the given size instead of a window, for running the
+    /// benchmark where there's no display. `model_path` works like in `with_model`.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub async fn headless(
+        size: winit::dpi::PhysicalSize<u32>,
+        model_path: Option<String>,
+    ) -> Result<State<'a>, gltf_loader::LoadError> {
+        let preserved = PreservedState {
+            model_path,
+            ..Default::default()
+        };
+        Self::create(None, size, preserved).await
+    }
+
+    /// Rebuilds every GPU resource on a new device after the old one was lost. Assets are fetched
+    /// again and the preprocess passes are rerun, settings and the camera are kept. Hands the
+    /// state back if there's no device to be had yet.
+    pub async fn recreate(self) -> Result<State<'a>, RecreateError<'a>> {
+        let gpu = match request_gpu(self.target.window()).await {
+            Ok(gpu) => gpu,
+            Err(error) => return Err(RecreateError::GpuUnavailable(Box::new(self), error)),
+        };
+        let preserved = PreservedState {
+            post_settings: self.post_settings,
+            shadow_settings: self.shadow_settings,
+            key_bindings: self.key_bindings,
+            overlay: self.overlay,
+            camera_transform: Some((self.camera.position, self.camera.rotation)),
+            model_path: Some(self.model_path),
+            scene_config: Some(self.scene_config),
+            cone_params: Some(self.cone_params),
+            ssr_settings: Some(self.ssr_settings),
+        };
+        let (window, size) = match self.target {
+            Target::Window { window, .. } => {
+                let size = window.inner_size();
+                (Some(window), size)
+            }
+            Target::Offscreen(_) => (None, self.size),
+        };
+        Self::create_with_gpu(window, size, gpu, preserved)
+            .await
+            .map_err(RecreateError::Load)
+    }
+
+    /// Renders into `window`, or into an offscreen texture of `size` without one.
+    async fn create(
+        window: Option<Window>,
+        size: winit::dpi::PhysicalSize<u32>,
+        preserved: PreservedState,
+    ) -> Result<State<'a>, gltf_loader::LoadError> {
+        let gpu = request_gpu(window.as_ref()).await?;
+        Self::create_with_gpu(window, size, gpu, preserved).await
+    }
+
+    async fn create_with_gpu(
+        window: Option<Window>,
+        size: winit::dpi::PhysicalSize<u32>,
+        gpu: Gpu,
+        preserved: PreservedState,
+    ) -> Result<State<'a>, gltf_loader::LoadError> {
+        let Gpu {
+            instance,
+            surface,
+            adapter,
+            device,
+            queue,
+            device_lost,
+        } = gpu;
+        let adapter_info = adapter.get_info();
+        // A hidden canvas reports 0x0, which isn't a valid surface size. `resize` ignores those, so
+        // the surface catches up once the window is shown.
+        let size = winit::dpi::PhysicalSize::new(size.width.max(1), size.height.max(1));
+
+        let formats = match &surface {
+            Some(surface) => surface.get_capabilities(&adapter).formats,
+            None => vec![
+                wgpu::TextureFormat::Rgba8Unorm,
+                wgpu::TextureFormat::Rgba16Float,
+            ],
+        };
+        // HDR output is opt in with `?hdr=1`, surfaces that can't present floats stay SDR
+        let hdr_requested = util::query_param("hdr").as_deref() == Some("1");
+        let hdr_output = hdr_requested && formats.contains(&wgpu::TextureFormat::Rgba16Float);
+        if hdr_requested && !hdr_output {
+            log::warn!("Surface doesn't support Rgba16Float, falling back to SDR output");
+        }
+
+        // The tonemapper encodes to sRGB itself, the browser prefers a linear format anyway but
+        // native surfaces tend to list the sRGB variant first
+        let sdr_format = formats
+            .iter()
+            .copied()
+            .find(|format| !format.is_srgb())
+            .unwrap_or(formats[0]);
+        let config = wgpu::SurfaceConfiguration {
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+            format: if hdr_output {
+                wgpu::TextureFormat::Rgba16Float
+            } else {
+                sdr_format
+            },
+            width: size.width,
+            height: size.height,
+            present_mode: wgpu::PresentMode::AutoNoVsync,
+            alpha_mode: wgpu::CompositeAlphaMode::Auto,
+            view_formats: if hdr_output {
+                Vec::new()
+            } else {
+                vec![sdr_format.add_srgb_suffix()]
+            },
+        };
+
+        let target = match (window, surface) {
+            (Some(window), Some(surface)) => {
+                surface.configure(&device, &config);
+                Target::Window { window, surface }
+            }
+            _ => Target::Offscreen(create_offscreen_texture(&device, &config)),
+        };
+
+        let create_main_shader = |cone_step_heatmap: bool| Shader {
+            vs_entry: "vs_main".to_string(),
+            fs_entry: "fs_main".to_string(),
+            fs_mask_entry: Some("fs_masked".to_string()),
+            fs_blend_entry: Some("fs_blended".to_string()),
+            module: device.create_shader_module(wgpu::ShaderModuleDescriptor {
+                label: Some("Shader module"),
+                source: wgpu::ShaderSource::Wgsl(main_shader_source(cone_step_heatmap).into()),
+            }),
+        };
+
+        let shadow_shader = create_shadow_shader(&device);
+
+        let voxelizer_shader = create_voxelizer_shader(&device);
+
+        let shader = create_main_shader(false);
+        let cone_step_heatmap_shader = create_main_shader(true);
+
+        let shadow_camera_bind_group_layout = create_shadow_camera_bind_group_layout(&device);
+
+        let diffuse_camera_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                label: Some("Diffuse camera bind group layout"),
+                entries: &[
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 0,
+                        count: None,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
                         },
-                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        visibility: wgpu::ShaderStages::VERTEX_FRAGMENT,
                     },
                     wgpu::BindGroupLayoutEntry {
                         binding: 1,
                         count: None,
-                        ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Comparison),
-                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        visibility: wgpu::ShaderStages::VERTEX,
                     },
                     wgpu::BindGroupLayoutEntry {
                         binding: 2,
                         count: None,
-                        ty: wgpu::BindingType::StorageTexture {
-                            access: wgpu::StorageTextureAccess::WriteOnly,
-                            format: wgpu::TextureFormat::Rgba16Float,
-                            view_dimension: wgpu::TextureViewDimension::D3,
+                        ty: wgpu::BindingType::Texture {
+                            sample_type: wgpu::TextureSampleType::Depth,
+                            view_dimension: wgpu::TextureViewDimension::D2,
+                            multisampled: false,
                         },
                         visibility: wgpu::ShaderStages::FRAGMENT,
                     },
                     wgpu::BindGroupLayoutEntry {
                         binding: 3,
-                        visibility: wgpu::ShaderStages::FRAGMENT,
-                        ty: wgpu::BindingType::Buffer {
-                            ty: wgpu::BufferBindingType::Uniform,
-                            has_dynamic_offset: false,
-                            min_binding_size: None,
-                        },
                         count: None,
+                        ty: wgpu::BindingType::Texture {
+                            sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                            view_dimension: wgpu::TextureViewDimension::D2,
+                            multisampled: false,
+                        },
+                        visibility: wgpu::ShaderStages::FRAGMENT,
                     },
                 ],
             });
 
+        let voxelization_camera_bind_group_layout =
+            create_voxelization_camera_bind_group_layout(&device);
+
+        // `?quantize=1` packs vertex attributes into 16 bit formats, `?atlas=256` packs images
+        // up to 256x256 into a shared texture
+        let load_options = gltf_loader::LoadOptions {
+            quantize_vertices: util::query_param("quantize").as_deref() == Some("1"),
+            atlas_max_size: util::query_param("atlas").and_then(|size| size.parse().ok()),
+        };
+        // `?model=models/Foo/foo.glb` loads another .gltf or .glb file
+        let model_path = preserved
+            .model_path
+            .or_else(|| util::query_param("model"))
+            .unwrap_or_else(|| DEFAULT_MODEL_PATH.to_string());
+        let (model_path, mut scene_loader) =
+            open_model(&device, &queue, model_path, load_options).await?;
+        let mut scene_config = match preserved.scene_config {
+            Some(scene_config) => scene_config,
+            None => SceneConfig::load(&model_path).await,
+        };
+        scene_config.voxel_grid.fit_to_limits(&device.limits());
+
+        let voxel_resolution = scene_config.voxel_grid.resolution;
+        let voxel_texture = VoxelTexture::new(
+            &device,
+            wgpu::Extent3d {
+                width: voxel_resolution,
+                height: voxel_resolution,
+                depth_or_array_layers: voxel_resolution,
+            },
+            scene_config.voxel_grid.max_mip_levels,
+            "Voxel texture",
+        );
+
+        // Voxelization rasterizes one fragment per cell, the output itself is never read
+        let dummy_output = Texture::create_target_texture(
+            &device,
+            voxel_resolution,
+            voxel_resolution,
+            "Dummy target texture",
+        );
+
+        let voxelizer_texture_bind_group_layout =
+            create_voxelizer_texture_bind_group_layout(&device);
+
+        let probe_layout_entry = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Texture {
+                sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                view_dimension: wgpu::TextureViewDimension::D3,
+                multisampled: false,
+            },
+            count: None,
+        };
         let diffuse_texture_bind_group_layout =
             device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
                 label: Some("Diffuse texture bind group layout"),
@@ -276,6 +1674,79 @@ impl<'a> State<'a> {
                     wgpu::BindGroupLayoutEntry {
                         binding: 4,
                         visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Storage { read_only: true },
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 5,
+                        visibility: wgpu::ShaderStages::VERTEX_FRAGMENT,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 6,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 7,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 8,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Texture {
+                            sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                            view_dimension: wgpu::TextureViewDimension::D2,
+                            multisampled: false,
+                        },
+                        count: None,
+                    },
+                    probe_layout_entry(9),
+                    probe_layout_entry(10),
+                    probe_layout_entry(11),
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 12,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Texture {
+                            sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                            view_dimension: wgpu::TextureViewDimension::D3,
+                            multisampled: false,
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 13,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Texture {
+                            sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                            view_dimension: wgpu::TextureViewDimension::D3,
+                            multisampled: false,
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 14,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
                         ty: wgpu::BindingType::Buffer {
                             ty: wgpu::BufferBindingType::Uniform,
                             has_dynamic_offset: false,
@@ -286,65 +1757,75 @@ impl<'a> State<'a> {
                 ],
             });
 
-        let camera = PerspectiveCamera::new(
-            &window,
-            Vector3 {
-                x: -1.8,
-                y: 3.155,
-                z: -0.3,
-            },
-            Euler::new(Deg(0.0), Deg(-270.0), Deg(0.0)),
+        let [yaw, pitch, roll] = scene_config.camera.rotation;
+        let mut camera = PerspectiveCamera::new(
+            size,
+            scene_config.camera.position.into(),
+            Euler::new(Deg(yaw), Deg(pitch), Deg(roll)),
             0.01,
             1000.0,
             Deg(90.0),
         );
+        if let Some((position, rotation)) = preserved.camera_transform {
+            camera.position = position;
+            camera.rotation = rotation;
+        }
 
-        let shadow_camera = ShadowCamera::new(
-            Point3 {
-                x: 0.0,
-                y: 0.0,
-                z: 0.0,
-            },
-            Vector3::new(1.0, -6.0, 2.0).normalize(),
-            -30.0,
-            30.0,
-            -30.0,
-            30.0,
-            -30.0,
-            30.0,
-        );
-
-        let lights = Lights {
-            count: 3,
-            lights: [
-                Light {
-                    position: [
-                        shadow_camera.direction.x,
-                        shadow_camera.direction.y,
-                        shadow_camera.direction.z,
-                        0.0,
-                    ],
-                    intensity: [30.0, 30.0, 30.0],
-                    falloff: 0.0,
-                },
-                Light {
-                    position: [-9.87, 1.3, -0.22, 1.0],
-                    intensity: [0.0, 0.0, 20.0],
-                    falloff: 2.0,
-                },
-                Light {
-                    position: [8.7, 1.6, -0.3, 1.0],
-                    intensity: [10.0, 10.0, 10.0],
-                    falloff: 2.0,
+        scene_loader.set_progress_callback(|progress| {
+            log::info!(
+                "Loaded {} of {} primitives",
+                progress.loaded_primitives,
+                progress.total_primitives
+            )
+        });
+        // Benchmarks time the whole scene from their first frame on
+        let default_batch_size = if util::query_param("bench").as_deref() == Some("1") {
+            0
+        } else {
+            STREAM_BATCH_PRIMITIVES
+        };
+        let batch_size = util::query_param("stream_batch")
+            .and_then(|batch_size| batch_size.parse().ok())
+            .unwrap_or(default_batch_size);
+        let mut scene = scene_loader.begin_scene(&device, &queue, 0);
+        let first_batch = if batch_size == 0 {
+            usize::MAX
+        } else {
+            batch_size
+        };
+        scene_loader.load_batch(&device, &queue, &mut scene, first_batch)?;
+        let mut scenes = vec![scene];
+
+        let sun_direction = Vector3::from(scene_config.sun.direction).normalize();
+        let mut shadow_camera = match scenes[0].bounds() {
+            Some(bounds) => ShadowCamera::fit_to_bounds(&bounds, sun_direction),
+            // Fits Sponza, for models without position bounds
+            None => ShadowCamera::new(
+                Point3 {
+                    x: 0.0,
+                    y: 0.0,
+                    z: 0.0,
                 },
-                Light::default(),
-                Light::default(),
-                Light::default(),
-                Light::default(),
-                Light::default(),
-            ],
-            filler: [0, 0, 0],
+                sun_direction,
+                -30.0,
+                30.0,
+                -30.0,
+                30.0,
+                -30.0,
+                30.0,
+            ),
         };
+        let shadow_atlas = allocate_shadow_tiles(&scene_config);
+        if preserved.shadow_settings.texel_snapping {
+            shadow_camera.set_texel_snapping(shadow_atlas.tile(0).map(|tile| tile.size));
+        }
+
+        let lights = Lights::from_config(
+            shadow_camera.direction,
+            &scene_config,
+            &shadow_atlas,
+            scenes[0].bounds().as_ref(),
+        );
 
         let camera_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
             label: Some("Camera buffer"),
@@ -352,98 +1833,172 @@ impl<'a> State<'a> {
             usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
         });
 
+        let mirror_camera_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Mirror camera buffer"),
+            contents: bytemuck::cast_slice(&[camera.get_uniform_data()]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
         let shadow_camera_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
             label: Some("Shadow camera buffer"),
             contents: bytemuck::cast_slice(&[shadow_camera.get_uniform_data()]),
             usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
         });
 
-        let lights_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+        let voxelization_camera = VoxelizationCamera::new(
+            scene_config.voxel_grid.center.into(),
+            scene_config.voxel_grid.half_extent,
+        );
+        let voxelization_camera_buffer =
+            device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some("Voxelization camera buffer"),
+                contents: bytemuck::cast_slice(&[voxelization_camera.get_uniform_data()]),
+                usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            });
+
+        let lights_capacity = lights.lights.len().max(scene_config.max_lights + 1);
+        let lights_buffer = device.create_buffer(&wgpu::BufferDescriptor {
             label: Some("Lights buffer"),
-            contents: bytemuck::cast_slice(&[lights]),
+            size: Lights::buffer_size(lights_capacity),
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        queue.write_buffer(&lights_buffer, 0, &lights.to_bytes());
+
+        let voxel_grid_info_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Voxel grid info buffer"),
+            contents: bytemuck::cast_slice(&[VoxelGridInfo {
+                center: scene_config.voxel_grid.center,
+                half_extent: scene_config.voxel_grid.half_extent,
+                sky_color: scene_config.voxel_grid.sky_color,
+                resolution: voxel_resolution as f32,
+                ground_color: scene_config.voxel_grid.ground_color,
+                edge_fade: scene_config.voxel_grid.edge_fade,
+            }]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        let mut render_settings = RenderSettings::default();
+        if let Some(max) =
+            util::query_param("cone_step_heatmap_max").and_then(|max| max.parse().ok())
+        {
+            render_settings.cone_step_heatmap_max = max;
+        }
+        // Empty space skipping is off unless `?sdf_skipping=1` asks for it, the key toggles it
+        render_settings.set_flag(
+            RenderSettings::SDF_SKIPPING,
+            util::query_param("sdf_skipping").as_deref() == Some("1"),
+        );
+        let render_settings_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Render settings buffer"),
+            contents: bytemuck::cast_slice(&[render_settings]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        let cone_params = preserved
+            .cone_params
+            .unwrap_or_else(ConeTracingParams::from_query);
+        let cone_params_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Cone tracing params buffer"),
+            contents: bytemuck::cast_slice(&[cone_params]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        let frame_globals_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Frame globals buffer"),
+            contents: bytemuck::cast_slice(&[FrameGlobals {
+                resolution: [config.width as f32, config.height as f32],
+                time: 0.0,
+                frame_index: 0,
+            }]),
             usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
         });
 
         let shadow_depth_texture = Texture::create_depth_texture(
             &device,
-            2048,
-            2048,
+            SHADOW_ATLAS_SIZE,
+            SHADOW_ATLAS_SIZE,
+            Texture::DEPTH_FORMAT,
             Some(wgpu::CompareFunction::Less),
             "Shadow depth texture",
         );
-        let depth_texture = Texture::create_depth_texture(
+        let shadow_settings = preserved.shadow_settings;
+        let shadow_blur = ShadowBlur::new(&device, &shadow_depth_texture, &shadow_settings);
+        let shadow_inspector = ShadowInspector::new(
             &device,
-            config.width,
-            config.height,
-            Some(wgpu::CompareFunction::LessEqual),
-            "Depth texture",
+            &shadow_depth_texture,
+            shadow_camera.get_uniform_data().into(),
+            &diffuse_camera_bind_group_layout,
+            config.format,
         );
 
-        let diffuse_camera_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            label: Some("Camera bind group"),
-            layout: &diffuse_camera_bind_group_layout,
-            entries: &[
-                wgpu::BindGroupEntry {
-                    binding: 0,
-                    resource: camera_buffer.as_entire_binding(),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 1,
-                    resource: shadow_camera_buffer.as_entire_binding(),
-                },
-            ],
-        });
+        let light_probes = LightProbes::new(
+            &device,
+            &voxel_texture,
+            &voxel_grid_info_buffer,
+            &frame_globals_buffer,
+            &diffuse_camera_bind_group_layout,
+            Texture::HDR_FORMAT,
+            MAIN_DEPTH_FORMAT,
+        );
+        let voxel_debug = VoxelDebug::new(
+            &device,
+            &voxel_texture,
+            &voxel_grid_info_buffer,
+            &diffuse_camera_bind_group_layout,
+            Texture::HDR_FORMAT,
+        );
+        let gizmo = OrientationGizmo::new(
+            &device,
+            &diffuse_camera_bind_group_layout,
+            config.format,
+            Texture::HDR_FORMAT,
+            MAIN_DEPTH_FORMAT,
+        );
 
-        let voxelizer_texture_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            label: Some("Voxelizer texture bind group"),
-            layout: &voxelizer_texture_bind_group_layout,
-            entries: &[
-                wgpu::BindGroupEntry {
-                    binding: 0,
-                    resource: wgpu::BindingResource::TextureView(&shadow_depth_texture.view),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 1,
-                    resource: wgpu::BindingResource::Sampler(&shadow_depth_texture.sampler),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 2,
-                    resource: wgpu::BindingResource::TextureView(&voxel_texture.get_mip_0()),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 3,
-                    resource: lights_buffer.as_entire_binding(),
-                },
-            ],
-        });
+        let post_chain = PostChain::new(&device, config.width, config.height, config.format);
+        let focus_picker = FocusPicker::new(&device);
+        let gpu_profiler = GpuProfiler::new(&device, &queue);
 
-        let diffuse_texture_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            label: Some("Diffuse texture bind group"),
-            layout: &diffuse_texture_bind_group_layout,
+        let voxelization_camera_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Voxelization camera bind group"),
+            layout: &voxelization_camera_bind_group_layout,
             entries: &[
                 wgpu::BindGroupEntry {
                     binding: 0,
-                    resource: wgpu::BindingResource::TextureView(&shadow_depth_texture.view),
+                    resource: voxelization_camera_buffer.as_entire_binding(),
                 },
                 wgpu::BindGroupEntry {
                     binding: 1,
-                    resource: wgpu::BindingResource::Sampler(&shadow_depth_texture.sampler),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 2,
-                    resource: wgpu::BindingResource::TextureView(&voxel_texture.main_view),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 3,
-                    resource: wgpu::BindingResource::Sampler(&voxel_texture.sampler),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 4,
-                    resource: lights_buffer.as_entire_binding(),
+                    resource: shadow_camera_buffer.as_entire_binding(),
                 },
             ],
         });
 
+        let lighting_buffers = LightingBuffers {
+            lights: &lights_buffer,
+            voxel_grid_info: &voxel_grid_info_buffer,
+            frame_globals: &frame_globals_buffer,
+            render_settings: &render_settings_buffer,
+            cone_params: &cone_params_buffer,
+        };
+        let voxelizer_texture_bind_group = create_voxelizer_texture_bind_group(
+            &device,
+            &voxelizer_texture_bind_group_layout,
+            &shadow_depth_texture,
+            &voxel_texture,
+            &lighting_buffers,
+        );
+        let diffuse_texture_bind_group = create_diffuse_texture_bind_group(
+            &device,
+            &diffuse_texture_bind_group_layout,
+            &shadow_depth_texture,
+            &voxel_texture,
+            &shadow_blur,
+            &light_probes,
+            &lighting_buffers,
+        );
+
         let shadow_camera_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
             label: Some("Shadow camera bind group"),
             layout: &shadow_camera_bind_group_layout,
@@ -452,16 +2007,23 @@ impl<'a> State<'a> {
                 resource: shadow_camera_buffer.as_entire_binding(),
             }],
         });
-
-        let model = "Sponza";
-
-        let mut scenes = gltf_loader::load_gltf(
+        let spot_shadows = create_spot_shadows(
             &device,
-            &queue,
-            format!("models/{}/glTF/{}.gltf", model, model).as_str(),
-        )
-        .await
-        .unwrap();
+            &shadow_camera_bind_group_layout,
+            &lights,
+            &shadow_atlas,
+        );
+
+        // The depth prepass reuses the shadow shader, which only needs the view projection
+        let depth_prepass_camera_bind_group =
+            device.create_bind_group(&wgpu::BindGroupDescriptor {
+                label: Some("Depth prepass camera bind group"),
+                layout: &shadow_camera_bind_group_layout,
+                entries: &[wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: camera_buffer.as_entire_binding(),
+                }],
+            });
 
         scenes[0].generate_pipeline(
             &device,
@@ -469,141 +2031,841 @@ impl<'a> State<'a> {
             "shadow",
             &[&shadow_camera_bind_group_layout],
             &[],
+            Some(gltf_loader::default_depth_stencil(Texture::DEPTH_FORMAT)),
             true,
-            true,
+            Some(&is_opaque),
         );
 
         scenes[0].generate_pipeline(
             &device,
+            &shadow_shader,
+            "depth_prepass",
+            &[&shadow_camera_bind_group_layout],
+            &[],
+            Some(gltf_loader::default_depth_stencil(Texture::DEPTH_FORMAT)),
+            true,
+            Some(&is_opaque),
+        );
+
+        generate_voxelization_pipeline(
+            &device,
+            &mut scenes[0],
             &voxelizer_shader,
-            "voxelization",
+            &voxelization_camera_bind_group_layout,
+            &voxelizer_texture_bind_group_layout,
+        );
+
+        scenes[0].generate_pipeline(
+            &device,
+            &shader,
+            "main",
             &[
                 &diffuse_camera_bind_group_layout,
-                &voxelizer_texture_bind_group_layout,
+                &diffuse_texture_bind_group_layout,
             ],
-            &[Some(wgpu::ColorTargetState {
-                format: wgpu::TextureFormat::Rgba8Uint,
-                blend: None,
-                write_mask: wgpu::ColorWrites::empty(),
-            })],
-            false,
-            false,
+            &[
+                Some(wgpu::ColorTargetState {
+                    format: Texture::HDR_FORMAT,
+                    blend: Some(wgpu::BlendState::REPLACE),
+                    write_mask: wgpu::ColorWrites::ALL,
+                }),
+                Some(wgpu::ColorTargetState {
+                    format: ssr::GBUFFER_FORMAT,
+                    blend: None,
+                    write_mask: wgpu::ColorWrites::ALL,
+                }),
+            ],
+            Some(gltf_loader::default_depth_stencil(MAIN_DEPTH_FORMAT)),
+            true,
+            Some(&is_opaque),
         );
 
         scenes[0].generate_pipeline(
             &device,
             &shader,
-            "main",
+            TRANSPARENT_PIPELINE,
             &[
                 &diffuse_camera_bind_group_layout,
                 &diffuse_texture_bind_group_layout,
             ],
-            &[Some(wgpu::ColorTargetState {
-                format: config.format,
-                blend: Some(wgpu::BlendState::REPLACE),
-                write_mask: wgpu::ColorWrites::ALL,
-            })],
-            true,
+            &TRANSPARENT_TARGETS,
+            Some(wgpu::DepthStencilState {
+                depth_write_enabled: false,
+                ..gltf_loader::default_depth_stencil(MAIN_DEPTH_FORMAT)
+            }),
             true,
+            Some(&is_blended),
         );
 
-        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
-            label: Some("Preprocess encoder"),
+        let sky_fog_pipeline_layout =
+            device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some("Sky fog pipeline layout"),
+                bind_group_layouts: &[
+                    &diffuse_camera_bind_group_layout,
+                    &diffuse_texture_bind_group_layout,
+                ],
+                push_constant_ranges: &[],
+            });
+        let sky_fog_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Sky fog pipeline"),
+            layout: Some(&sky_fog_pipeline_layout),
+            vertex: wgpu::VertexState {
+                module: &shader.module,
+                entry_point: "vs_sky",
+                buffers: &[],
+            },
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: Some(wgpu::DepthStencilState {
+                depth_write_enabled: false,
+                depth_compare: wgpu::CompareFunction::LessEqual,
+                ..gltf_loader::default_depth_stencil(MAIN_DEPTH_FORMAT)
+            }),
+            multisample: wgpu::MultisampleState::default(),
+            fragment: Some(wgpu::FragmentState {
+                module: &shader.module,
+                entry_point: "fs_sky",
+                targets: &[
+                    Some(wgpu::ColorTargetState {
+                        format: Texture::HDR_FORMAT,
+                        blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                        write_mask: wgpu::ColorWrites::COLOR,
+                    }),
+                    // The background keeps the cleared G-buffer
+                    Some(wgpu::ColorTargetState {
+                        format: ssr::GBUFFER_FORMAT,
+                        blend: None,
+                        write_mask: wgpu::ColorWrites::empty(),
+                    }),
+                ],
+            }),
+            multiview: None,
         });
 
-        {
-            let mut shadow_render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
-                label: Some("Shadow render pass"),
-                color_attachments: &[],
-                depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
-                    view: &shadow_depth_texture.view,
-                    depth_ops: Some(wgpu::Operations {
-                        load: wgpu::LoadOp::Clear(1.0),
-                        store: true,
-                    }),
-                    stencil_ops: None,
-                }),
-            });
-            shadow_render_pass.set_bind_group(0, &shadow_camera_bind_group, &[]);
-            scenes[0].draw_pipelines("shadow", &mut shadow_render_pass);
-        }
-        {
-            let mut voxelization_render_pass =
-                encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
-                    label: Some("Voxelization render pass"),
-                    color_attachments: &[Some(wgpu::RenderPassColorAttachment {
-                        view: &dummy_output.view,
-                        resolve_target: None,
-                        ops: wgpu::Operations {
-                            load: wgpu::LoadOp::Load,
-                            store: false,
-                        },
-                    })],
-                    depth_stencil_attachment: None,
-                });
-            voxelization_render_pass.set_bind_group(0, &diffuse_camera_bind_group, &[]);
-            voxelization_render_pass.set_bind_group(1, &voxelizer_texture_bind_group, &[]);
-            scenes[0].draw_pipelines("voxelization", &mut voxelization_render_pass);
-        }
+        let scene_stream = if scene_loader.progress().is_complete() {
+            State::log_vertex_data_size(&scenes[0]);
+            None
+        } else {
+            Some(SceneStream {
+                loader: scene_loader,
+                batch_size,
+                shadow_shader,
+                voxelizer_shader,
+            })
+        };
+
+        let mut preprocess_graph = RenderGraph::new(config.width, config.height);
+        preprocess_graph.add_pass("Shadow", &[], &["shadow_map"], State::record_shadow_pass);
+        preprocess_graph.add_pass(
+            "Voxelization",
+            &["shadow_map"],
+            &["voxels"],
+            State::record_voxelization_pass,
+        );
+        preprocess_graph.add_pass(
+            "Shadow blur",
+            &["shadow_map"],
+            &["shadow_moments"],
+            |state: &State, _, encoder| state.shadow_blur.record(encoder),
+        );
+        preprocess_graph.add_pass(
+            "Voxel mipmaps",
+            &["voxels"],
+            &["voxels"],
+            |state: &State, _, encoder| state.voxel_texture.run_generate_mipmaps(encoder),
+        );
+        preprocess_graph.add_pass(
+            "Voxel SDF",
+            &["voxels"],
+            &["voxel_sdf"],
+            |state: &State, _, encoder| state.voxel_texture.run_generate_sdf(encoder),
+        );
+        preprocess_graph.add_pass(
+            "Light probe bake",
+            &["voxels"],
+            &["probes"],
+            |state: &State, _, encoder| state.light_probes.record_bake(encoder),
+        );
+
+        let mut frame_graph = RenderGraph::new(config.width, config.height);
+        frame_graph.add_texture(&device, "depth", TextureSize::Surface, |width, height| {
+            Texture::target_descriptor(width, height, MAIN_DEPTH_FORMAT, "Depth texture")
+        });
+        frame_graph.add_texture(
+            &device,
+            "prepass_depth",
+            TextureSize::Surface,
+            |width, height| {
+                Texture::target_descriptor(
+                    width,
+                    height,
+                    Texture::DEPTH_FORMAT,
+                    "Depth prepass texture",
+                )
+            },
+        );
+        frame_graph.add_texture(
+            &device,
+            "planar_reflection",
+            TextureSize::Surface,
+            |width, height| {
+                Texture::target_descriptor(width, height, Texture::HDR_FORMAT, "Planar reflection")
+            },
+        );
+        frame_graph.add_texture(
+            &device,
+            "planar_reflection_depth",
+            TextureSize::Surface,
+            |width, height| {
+                Texture::target_descriptor(
+                    width,
+                    height,
+                    MAIN_DEPTH_FORMAT,
+                    "Planar reflection depth texture",
+                )
+            },
+        );
+        frame_graph.add_texture(&device, "gbuffer", TextureSize::Surface, |width, height| {
+            Texture::target_descriptor(width, height, Texture::HDR_FORMAT, "G-buffer")
+        });
+        frame_graph.add_texture(
+            &device,
+            "ssr_reflection",
+            TextureSize::Downscaled(ssr::REFLECTION_SCALE),
+            |width, height| {
+                Texture::target_descriptor(
+                    width,
+                    height,
+                    Texture::HDR_FORMAT,
+                    "SSR reflection texture",
+                )
+            },
+        );
+        frame_graph.add_pass(
+            "Depth prepass",
+            &[],
+            &["prepass_depth"],
+            State::record_depth_prepass,
+        );
+        // Writes the G-buffer only because the main pipelines have it as their second target, the
+        // main pass clears it again
+        frame_graph.add_pass(
+            "Planar reflection",
+            &[
+                "voxels",
+                "shadow_map",
+                "shadow_moments",
+                "prepass_depth",
+                "probes",
+                "voxel_sdf",
+            ],
+            &["planar_reflection", "planar_reflection_depth", "gbuffer"],
+            State::record_planar_reflection_pass,
+        );
+        frame_graph.add_pass(
+            "Main",
+            &[
+                "voxels",
+                "shadow_map",
+                "shadow_moments",
+                "prepass_depth",
+                "planar_reflection",
+                "probes",
+                "voxel_sdf",
+            ],
+            &["scene_color", "depth", "gbuffer"],
+            State::record_main_pass,
+        );
+        frame_graph.add_pass(
+            "SSR trace",
+            &["scene_color", "gbuffer", "prepass_depth", "voxels"],
+            &["ssr_reflection"],
+            |state: &State, resources, encoder| {
+                if state.ssr_settings.enabled {
+                    state.ssr.record_trace(
+                        encoder,
+                        &state.diffuse_camera_bind_group,
+                        &state.diffuse_texture_bind_group,
+                        &resources.texture("ssr_reflection").view,
+                    );
+                }
+            },
+        );
+        frame_graph.add_pass(
+            "SSR composite",
+            &["ssr_reflection", "gbuffer", "prepass_depth"],
+            &["scene_color"],
+            |state: &State, _, encoder| {
+                if state.ssr_settings.enabled {
+                    state.ssr.record_composite(
+                        encoder,
+                        &state.diffuse_camera_bind_group,
+                        &state.diffuse_texture_bind_group,
+                        state.post_chain.scene_view(),
+                    );
+                }
+            },
+        );
+        frame_graph.add_pass(
+            "Outline",
+            &["scene_color", "depth"],
+            &["scene_color", "depth"],
+            |state: &State, resources, encoder| {
+                state.outline.record(
+                    &state.scenes[0],
+                    &state.diffuse_camera_bind_group,
+                    state.post_chain.scene_view(),
+                    &resources.texture("depth").view,
+                    encoder,
+                )
+            },
+        );
+        frame_graph.add_pass(
+            "Light probe debug",
+            &["scene_color", "depth"],
+            &["scene_color", "depth"],
+            |state: &State, resources, encoder| {
+                if state.light_probes_visible {
+                    state.light_probes.record_debug(
+                        encoder,
+                        &state.diffuse_camera_bind_group,
+                        state.post_chain.scene_view(),
+                        &resources.texture("depth").view,
+                    );
+                }
+            },
+        );
+        frame_graph.add_pass(
+            "World grid",
+            &["scene_color", "depth"],
+            &["scene_color", "depth"],
+            |state: &State, resources, encoder| {
+                if state.world_grid_visible {
+                    state.gizmo.record_grid(
+                        encoder,
+                        &state.diffuse_camera_bind_group,
+                        state.post_chain.scene_view(),
+                        &resources.texture("depth").view,
+                    );
+                }
+            },
+        );
+        frame_graph.add_pass(
+            "Voxel debug",
+            &["voxels", "scene_color"],
+            &["scene_color"],
+            |state: &State, _, encoder| {
+                if state.voxel_debug_visible {
+                    state.voxel_debug.record(
+                        encoder,
+                        &state.diffuse_camera_bind_group,
+                        state.post_chain.scene_view(),
+                    );
+                }
+            },
+        );
+        // Post effects read the depth after the graph ran, and the prepass and planar reflection
+        // are referenced by the camera bind group every scene pass binds
+        frame_graph.mark_external("depth");
+        frame_graph.mark_external("prepass_depth");
+        frame_graph.mark_external("planar_reflection");
+        // `?aliasing=0` allocates every transient texture separately, to rule aliasing out
+        frame_graph.set_aliasing(util::query_param("aliasing").as_deref() != Some("0"));
+        frame_graph.allocate(&device);
+        log::debug!(
+            "Render graph aliasing saves {:.1} MiB",
+            frame_graph.aliased_bytes() as f64 / (1024.0 * 1024.0)
+        );
+        let outline = Outline::new(&device, MAIN_DEPTH_FORMAT);
 
-        voxel_texture.run_generate_mipmaps(&mut encoder);
+        let ssr_settings = preserved
+            .ssr_settings
+            .unwrap_or_else(SsrSettings::from_query);
+        let ssr = Ssr::new(
+            &device,
+            &shader,
+            &diffuse_camera_bind_group_layout,
+            &diffuse_texture_bind_group_layout,
+            &ssr_settings,
+            &SsrTargets {
+                scene_color: post_chain.scene_view(),
+                gbuffer: frame_graph.resources().texture("gbuffer"),
+                reflection: frame_graph.resources().texture("ssr_reflection"),
+            },
+        );
 
-        queue.submit(std::iter::once(encoder.finish()));
+        let diffuse_camera_bind_group = create_diffuse_camera_bind_group(
+            &device,
+            &diffuse_camera_bind_group_layout,
+            &camera_buffer,
+            &shadow_camera_buffer,
+            frame_graph.resources().texture("prepass_depth"),
+            frame_graph.resources().texture("planar_reflection"),
+        );
+        let mirror_reflection_placeholder = Texture::create_1_pixel_texture(
+            &device,
+            &queue,
+            &[0, 0, 0, 255],
+            "Mirror reflection placeholder",
+        );
+        let mirror_camera_bind_group = create_diffuse_camera_bind_group(
+            &device,
+            &diffuse_camera_bind_group_layout,
+            &mirror_camera_buffer,
+            &shadow_camera_buffer,
+            frame_graph.resources().texture("prepass_depth"),
+            &mirror_reflection_placeholder,
+        );
 
-        State {
-            window,
-            surface,
+        let state = State {
+            target,
+            device_lost,
+            instance,
+            adapter_info,
+            consecutive_surface_errors: 0,
             device,
             queue,
             config,
             size,
             camera_buffer,
+            shadow_camera,
+            shadow_atlas,
+            shadow_camera_buffer,
+            diffuse_camera_bind_group_layout,
             diffuse_camera_bind_group,
+            mirror_camera_buffer,
+            mirror_camera_bind_group,
+            mirror_reflection_placeholder,
+            planar_reflection_height: None,
+            depth_prepass_camera_bind_group,
+            diffuse_texture_bind_group_layout,
             diffuse_texture_bind_group,
             camera,
             scenes,
-            depth_texture,
+            shadow_depth_texture,
+            shadow_camera_bind_group_layout,
+            shadow_camera_bind_group,
+            spot_shadows,
+            shadow_blur,
+            shadow_settings,
+            shadow_inspector,
+            shadow_inspector_visible: false,
+            light_probes,
+            light_probes_visible: false,
+            voxel_debug,
+            voxel_debug_visible: false,
+            voxel_debug_level: 0,
+            gizmo,
+            gizmo_visible: false,
+            world_grid_visible: false,
+            sky_fog_pipeline,
+            voxel_texture,
+            voxelization_camera_bind_group,
+            voxelizer_texture_bind_group_layout,
+            voxelizer_texture_bind_group,
+            voxel_grid_info_buffer,
+            dummy_output,
+            preprocess_graph,
+            frame_graph,
+            frame_globals_buffer,
+            render_settings,
+            render_settings_buffer,
+            cone_params,
+            cone_params_buffer,
+            ssr,
+            ssr_settings,
+            time: 0.0,
+            frame_index: 0,
+            post_settings: preserved.post_settings,
+            post_chain,
+            focus_picker,
+            cursor_position: PhysicalPosition::new(0.0, 0.0),
+            cpu_profiler: CpuProfiler::default(),
+            gpu_profiler,
+            key_bindings: preserved.key_bindings,
+            overlay: preserved.overlay.or_else(|| Overlay::new("voxel-gi")),
+            model_path,
+            scene_config,
+            lights_buffer,
+            lights_capacity,
+            lights_dirty: false,
+            outline,
+            paused: false,
+            frozen: false,
+            step_requested: false,
+            voxels_dirty: false,
+            amortize_revoxelization: util::query_param("revoxelize").as_deref()
+                == Some("amortized"),
+            frustum_culling: util::query_param("culling").as_deref() != Some("0"),
+            revoxelize_interval: util::query_param("revoxelize_interval")
+                .and_then(|interval| interval.parse().ok())
+                .unwrap_or(0),
+            pending_preprocess: VecDeque::new(),
+            scene_stream,
+            main_shader: shader,
+            cone_step_heatmap_shader,
+            cone_step_heatmap: false,
+            benchmark: Benchmark::from_query(),
+        };
+
+        state.queue.submit(
+            state
+                .preprocess_graph
+                .record_separately(&state, &state.device),
+        );
+
+        Ok(state)
+    }
+
+    /// Writes `lights` to the lights buffer, first doubling its capacity until they fit. The
+    /// bind groups are recreated with the new buffer.
+    fn upload_lights(&mut self, lights: &Lights) {
+        if lights.lights.len() > self.lights_capacity {
+            while self.lights_capacity < lights.lights.len() {
+                self.lights_capacity *= 2;
+            }
+            log::info!(
+                "Growing the lights buffer to {} lights",
+                self.lights_capacity
+            );
+            self.lights_buffer = self.device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some("Lights buffer"),
+                size: Lights::buffer_size(self.lights_capacity),
+                usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+                mapped_at_creation: false,
+            });
+            let lighting_buffers = LightingBuffers {
+                lights: &self.lights_buffer,
+                voxel_grid_info: &self.voxel_grid_info_buffer,
+                frame_globals: &self.frame_globals_buffer,
+                render_settings: &self.render_settings_buffer,
+                cone_params: &self.cone_params_buffer,
+            };
+            self.voxelizer_texture_bind_group = create_voxelizer_texture_bind_group(
+                &self.device,
+                &self.voxelizer_texture_bind_group_layout,
+                &self.shadow_depth_texture,
+                &self.voxel_texture,
+                &lighting_buffers,
+            );
+            self.diffuse_texture_bind_group = create_diffuse_texture_bind_group(
+                &self.device,
+                &self.diffuse_texture_bind_group_layout,
+                &self.shadow_depth_texture,
+                &self.voxel_texture,
+                &self.shadow_blur,
+                &self.light_probes,
+                &lighting_buffers,
+            );
         }
+        self.queue
+            .write_buffer(&self.lights_buffer, 0, &lights.to_bytes());
     }
 
-    fn resize(&mut self, new_size: winit::dpi::PhysicalSize<u32>) {
-        if new_size.width > 0 && new_size.height > 0 {
-            self.size = new_size;
-            self.config.width = new_size.width;
-            self.config.height = new_size.height;
-            self.surface.configure(&self.device, &self.config)
+    /// Applies the texel snapping setting to the sun's shadow camera and uploads it, the shadow
+    /// map itself is rendered again with the voxels.
+    fn upload_shadow_camera(&mut self) {
+        let sun_shadow_tile = self.shadow_atlas.tile(0);
+        self.shadow_camera.set_texel_snapping(
+            sun_shadow_tile
+                .filter(|_| self.shadow_settings.texel_snapping)
+                .map(|tile| tile.size),
+        );
+        let shadow_view_projection = self.shadow_camera.get_uniform_data();
+        self.queue.write_buffer(
+            &self.shadow_camera_buffer,
+            0,
+            bytemuck::cast_slice(&[shadow_view_projection]),
+        );
+        self.shadow_inspector
+            .set_shadow_view_projection(&self.queue, shadow_view_projection.into());
+        self.voxels_dirty = true;
+    }
+
+    fn log_vertex_data_size(scene: &gltf_loader::Scene) {
+        let vertex_data_size = scene.vertex_data_size();
+        log::info!(
+            "Vertex data: {:.1} MiB uploaded, {:.1} MiB in the glTF buffers",
+            vertex_data_size.uploaded_bytes as f64 / (1024.0 * 1024.0),
+            vertex_data_size.source_bytes as f64 / (1024.0 * 1024.0)
+        );
+    }
+
+    /// Uploads the next batch of a streaming scene and adds it to the pipeline lists, so it's
+    /// drawn from this frame on. The voxel grid is rebuilt once the whole scene is there, until
+    /// then only direct light reaches the new primitives.
+    fn stream_scene(&mut self) {
+        let Some(stream) = &mut self.scene_stream else {
+            return;
+        };
+        let main_shader = if self.cone_step_heatmap {
+            &self.cone_step_heatmap_shader
+        } else {
+            &self.main_shader
+        };
+        let scene = &mut self.scenes[0];
+        let loaded = stream
+            .loader
+            .load_batch(&self.device, &self.queue, scene, stream.batch_size);
+        let opaque: Option<&dyn Fn(&gltf_loader::PrimitiveRenderData) -> bool> = Some(&is_opaque);
+        for (name, shader, filter) in [
+            ("shadow", &stream.shadow_shader, opaque),
+            ("depth_prepass", &stream.shadow_shader, opaque),
+            ("voxelization", &stream.voxelizer_shader, opaque),
+            ("main", main_shader, opaque),
+            (TRANSPARENT_PIPELINE, main_shader, Some(&is_blended)),
+        ] {
+            if let Err(error) = scene.extend_pipeline(&self.device, shader, name, filter) {
+                log::error!("Can't draw the streamed primitives: {}", error);
+                if let Some(overlay) = &mut self.overlay {
+                    overlay.flash(&format!("Can't draw the streamed primitives: {}", error));
+                }
+            }
+        }
+
+        // What loaded before the error stays, the rest of the scene is left out
+        let progress = match loaded {
+            Ok(progress) => progress,
+            Err(error) => {
+                log::error!("Stopped loading the scene: {}", error);
+                self.scene_stream = None;
+                self.voxels_dirty = true;
+                if let Some(overlay) = &mut self.overlay {
+                    overlay.flash(&format!("Stopped loading the scene: {}", error));
+                }
+                return;
+            }
+        };
+        if progress.is_complete() {
+            Self::log_vertex_data_size(scene);
+            // The first batch only covered part of the scene, the spot lights' shadow maps are
+            // fitted to it with the lights
+            if let Some(bounds) = scene.bounds() {
+                self.shadow_camera =
+                    ShadowCamera::fit_to_bounds(&bounds, self.shadow_camera.direction);
+                self.upload_shadow_camera();
+                self.lights_dirty = true;
+            }
+            self.scene_stream = None;
+            self.voxels_dirty = true;
+            if let Some(overlay) = &mut self.overlay {
+                overlay.flash(&format!("Loaded {} primitives", progress.total_primitives));
+            }
         }
     }
 
-    fn input(&mut self, event: &WindowEvent) -> bool {
-        self.camera.process_event(event)
+    /// Rebuilds the voxel grid with everything derived from it before the next frame, for scenes
+    /// that changed in ways the renderer doesn't track itself. Light and material edits made
+    /// through `State` already do this.
+    pub fn revoxelize(&mut self) {
+        self.voxels_dirty = true;
+    }
+
+    /// Rebuilds the voxel grid every `frames` simulated frames, e.g. 1 for animated geometry or
+    /// lights changed through the scene itself. 0 turns it off. With `revoxelize=amortized` an
+    /// interval shorter than the preprocess graph restarts rebuilds before they finish.
+    pub fn set_revoxelize_interval(&mut self, frames: u32) {
+        self.revoxelize_interval = frames;
     }
 
-    fn update(&mut self) {
-        self.camera.update();
+    pub fn cone_params(&self) -> ConeTracingParams {
+        self.cone_params
+    }
+
+    /// Changes how the indirect diffuse cones are traced from the next frame on. Out of range
+    /// values are clamped, e.g. the cone count to `ConeTracingParams::MAX_CONES`.
+    pub fn set_cone_params(&mut self, params: ConeTracingParams) {
+        self.cone_params = params.clamped();
         self.queue.write_buffer(
-            &self.camera_buffer,
+            &self.cone_params_buffer,
             0,
-            bytemuck::cast_slice(&[self.camera.get_uniform_data()]),
+            bytemuck::cast_slice(&[self.cone_params]),
         );
     }
 
-    fn render(&mut self) -> Result<(), wgpu::SurfaceError> {
-        let output = self.surface.get_current_texture()?;
-        let view = output
-            .texture
-            .create_view(&wgpu::TextureViewDescriptor::default());
-        let mut encoder = self
-            .device
-            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
-                label: Some("Render encoder"),
+    /// Records the rebuild of the voxel grid. In amortized mode the passes are queued and
+    /// `render` submits one per frame, so frames in between shade with a partially rebuilt grid.
+    /// A rebuild that is still queued gets replaced.
+    fn submit_revoxelization(&mut self) {
+        let command_buffers = self.preprocess_graph.record_separately(self, &self.device);
+        if self.amortize_revoxelization {
+            self.pending_preprocess = command_buffers.into();
+        } else {
+            self.pending_preprocess.clear();
+            self.queue.submit(command_buffers);
+        }
+    }
+
+    /// Renders every shadow casting light into its tile of the atlas, see `record_shadow_tiles`.
+    fn record_shadow_pass(&self, _: &GraphResources, encoder: &mut wgpu::CommandEncoder) {
+        let sun = (0, &self.shadow_camera_bind_group);
+        let spots = self
+            .spot_shadows
+            .iter()
+            .map(|spot| (spot.slot, &spot.camera_bind_group));
+        let tiles = std::iter::once(sun)
+            .chain(spots)
+            .filter_map(|(slot, camera_bind_group)| {
+                Some((self.shadow_atlas.tile(slot)?, camera_bind_group))
             });
+        record_shadow_tiles(
+            encoder,
+            &self.scenes[0],
+            &self.shadow_depth_texture.view,
+            tiles,
+        );
+    }
 
+    fn record_depth_prepass(&self, resources: &GraphResources, encoder: &mut wgpu::CommandEncoder) {
+        // Only contact shadows and the screen space reflections read the prepass
+        if !self
+            .render_settings
+            .has_flag(RenderSettings::CONTACT_SHADOWS)
+            && !self.ssr_settings.enabled
         {
-            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
-                label: Some("Main render pass"),
-                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
-                    view: &view,
+            return;
+        }
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Depth prepass"),
+            color_attachments: &[],
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                view: &resources.texture("prepass_depth").view,
+                depth_ops: Some(wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(1.0),
+                    store: true,
+                }),
+                stencil_ops: None,
+            }),
+        });
+        render_pass.set_bind_group(0, &self.depth_prepass_camera_bind_group, &[]);
+        self.scenes[0].draw_pipelines_with(
+            "depth_prepass",
+            &mut render_pass,
+            &gltf_loader::DrawParams {
+                frustum: self.view_frustum(self.camera.view_projection()),
+                ..Default::default()
+            },
+        );
+    }
+
+    /// What the passes seeing the scene through `view_projection` cull against, `None` with
+    /// culling turned off.
+    fn view_frustum(&self, view_projection: Matrix4<f32>) -> Option<Frustum> {
+        self.frustum_culling
+            .then(|| Frustum::from_view_projection(view_projection))
+    }
+
+    fn record_voxelization_pass(&self, _: &GraphResources, encoder: &mut wgpu::CommandEncoder) {
+        // Cleared in the same command buffer, so amortized rebuilds never shade a frame with an
+        // empty grid
+        self.voxel_texture.clear(encoder);
+        let mut voxelization_render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Voxelization render pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: &self.dummy_output.view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: false,
+                },
+            })],
+            depth_stencil_attachment: None,
+        });
+        voxelization_render_pass.set_bind_group(0, &self.voxelization_camera_bind_group, &[]);
+        voxelization_render_pass.set_bind_group(1, &self.voxelizer_texture_bind_group, &[]);
+        self.scenes[0].draw_pipelines("voxelization", &mut voxelization_render_pass);
+    }
+
+    /// Renders the scene mirrored about the plane of the reflective materials with the main
+    /// pipelines, for the main pass to sample. Only cleared without a reflective material or
+    /// while the camera is below the plane.
+    fn record_planar_reflection_pass(
+        &self,
+        resources: &GraphResources,
+        encoder: &mut wgpu::CommandEncoder,
+    ) {
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Planar reflection render pass"),
+            color_attachments: &[
+                Some(wgpu::RenderPassColorAttachment {
+                    view: &resources.texture("planar_reflection").view,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                        store: true,
+                    },
+                }),
+                Some(wgpu::RenderPassColorAttachment {
+                    view: &resources.texture("gbuffer").view,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT),
+                        store: false,
+                    },
+                }),
+            ],
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                view: &resources.texture("planar_reflection_depth").view,
+                depth_ops: Some(wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(1.0),
+                    store: false,
+                }),
+                stencil_ops: MAIN_DEPTH_FORMAT
+                    .has_stencil_aspect()
+                    .then_some(wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(0),
+                        store: false,
+                    }),
+            }),
+        });
+        let Some(height) = self
+            .planar_reflection_height
+            .filter(|&height| self.camera.position.y > height)
+        else {
+            return;
+        };
+
+        let frustum = self.view_frustum(
+            self.camera
+                .mirrored_uniform_data(height)
+                .view_projection
+                .into(),
+        );
+        render_pass.set_bind_group(0, &self.mirror_camera_bind_group, &[]);
+        render_pass.set_bind_group(1, &self.diffuse_texture_bind_group, &[]);
+        self.scenes[0].draw_pipelines_with(
+            "main",
+            &mut render_pass,
+            &gltf_loader::DrawParams {
+                frustum,
+                ..Default::default()
+            },
+        );
+        if self.render_settings.has_flag(RenderSettings::FOG) {
+            render_pass.set_pipeline(&self.sky_fog_pipeline);
+            render_pass.draw(0..3, 0..1);
+        }
+        let mirrored_position = Point3::new(
+            self.camera.position.x,
+            2.0 * height - self.camera.position.y,
+            self.camera.position.z,
+        );
+        self.scenes[0].draw_pipelines_with(
+            TRANSPARENT_PIPELINE,
+            &mut render_pass,
+            &gltf_loader::DrawParams {
+                sort_origin: Some(mirrored_position),
+                frustum,
+                ..Default::default()
+            },
+        );
+    }
+
+    fn record_main_pass(&self, resources: &GraphResources, encoder: &mut wgpu::CommandEncoder) {
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Main render pass"),
+            color_attachments: &[
+                Some(wgpu::RenderPassColorAttachment {
+                    view: self.post_chain.scene_view(),
                     resolve_target: None,
                     ops: wgpu::Operations {
                         load: wgpu::LoadOp::Clear(wgpu::Color {
@@ -614,71 +2876,1517 @@ impl<'a> State<'a> {
                         }),
                         store: true,
                     },
-                })],
-                depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
-                    view: &self.depth_texture.view,
-                    depth_ops: Some(wgpu::Operations {
-                        load: wgpu::LoadOp::Clear(1.0),
+                }),
+                Some(wgpu::RenderPassColorAttachment {
+                    view: &resources.texture("gbuffer").view,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT),
                         store: true,
-                    }),
-                    stencil_ops: None,
+                    },
                 }),
-            });
-
-            render_pass.set_bind_group(0, &self.diffuse_camera_bind_group, &[]);
-            render_pass.set_bind_group(1, &self.diffuse_texture_bind_group, &[]);
-            self.scenes[0].draw_pipelines("main", &mut render_pass);
-        }
-
+            ],
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                view: &resources.texture("depth").view,
+                depth_ops: Some(wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(1.0),
+                    store: true,
+                }),
+                stencil_ops: MAIN_DEPTH_FORMAT
+                    .has_stencil_aspect()
+                    .then_some(wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(0),
+                        store: true,
+                    }),
+            }),
+        });
+
+        let frustum = self.view_frustum(self.camera.view_projection());
+        render_pass.set_bind_group(0, &self.diffuse_camera_bind_group, &[]);
+        render_pass.set_bind_group(1, &self.diffuse_texture_bind_group, &[]);
+        self.scenes[0].draw_pipelines_with(
+            "main",
+            &mut render_pass,
+            &gltf_loader::DrawParams {
+                frustum,
+                ..Default::default()
+            },
+        );
+        if self.render_settings.has_flag(RenderSettings::FOG) {
+            render_pass.set_pipeline(&self.sky_fog_pipeline);
+            render_pass.draw(0..3, 0..1);
+        }
+        // Blended primitives don't write depth, the fog behind them was already added
+        self.scenes[0].draw_pipelines_with(
+            TRANSPARENT_PIPELINE,
+            &mut render_pass,
+            &gltf_loader::DrawParams {
+                sort_origin: Some(Point3::from_vec(self.camera.position)),
+                frustum,
+                ..Default::default()
+            },
+        );
+    }
+
+    /// `None` for a `headless` state.
+    pub fn window(&self) -> Option<&Window> {
+        self.target.window()
+    }
+
+    /// Has to be called with the new inner size whenever the window was resized or its scale
+    /// factor changed. Zero sized windows are ignored.
+    pub fn resize(&mut self, new_size: winit::dpi::PhysicalSize<u32>) {
+        if new_size.width > 0 && new_size.height > 0 {
+            self.size = new_size;
+            self.config.width = new_size.width;
+            self.config.height = new_size.height;
+            match &mut self.target {
+                Target::Window { surface, .. } => surface.configure(&self.device, &self.config),
+                Target::Offscreen(texture) => {
+                    *texture = create_offscreen_texture(&self.device, &self.config)
+                }
+            }
+            self.camera.resize(new_size.width, new_size.height);
+            // `render` may resize after `update` uploaded the camera, the old projection would be
+            // used for a frame
+            self.queue.write_buffer(
+                &self.camera_buffer,
+                0,
+                bytemuck::cast_slice(&[self.camera.get_uniform_data()]),
+            );
+            if let Some(height) = self.planar_reflection_height {
+                self.queue.write_buffer(
+                    &self.mirror_camera_buffer,
+                    0,
+                    bytemuck::cast_slice(&[self.camera.mirrored_uniform_data(height)]),
+                );
+            }
+            self.post_chain
+                .resize(&self.device, new_size.width, new_size.height);
+            self.frame_graph
+                .resize(&self.device, new_size.width, new_size.height);
+            log::debug!(
+                "Render graph aliasing saves {:.1} MiB",
+                self.frame_graph.aliased_bytes() as f64 / (1024.0 * 1024.0)
+            );
+            self.diffuse_camera_bind_group = create_diffuse_camera_bind_group(
+                &self.device,
+                &self.diffuse_camera_bind_group_layout,
+                &self.camera_buffer,
+                &self.shadow_camera_buffer,
+                self.frame_graph.resources().texture("prepass_depth"),
+                self.frame_graph.resources().texture("planar_reflection"),
+            );
+            self.mirror_camera_bind_group = create_diffuse_camera_bind_group(
+                &self.device,
+                &self.diffuse_camera_bind_group_layout,
+                &self.mirror_camera_buffer,
+                &self.shadow_camera_buffer,
+                self.frame_graph.resources().texture("prepass_depth"),
+                &self.mirror_reflection_placeholder,
+            );
+            self.ssr.resize(
+                &self.device,
+                &SsrTargets {
+                    scene_color: self.post_chain.scene_view(),
+                    gbuffer: self.frame_graph.resources().texture("gbuffer"),
+                    reflection: self.frame_graph.resources().texture("ssr_reflection"),
+                },
+            );
+        }
+    }
+
+    /// Reads back mip `level` of the voxel grid along with its side length, see
+    /// `VoxelTexture::read_level` for the layout. Only covers revoxelizations that were
+    /// submitted, with `revoxelize=amortized` that takes a few frames after a change.
+    pub async fn read_voxels(
+        &self,
+        level: u32,
+    ) -> Result<(u32, Vec<[f32; 4]>), wgpu::BufferAsyncError> {
+        let cells = self
+            .voxel_texture
+            .read_level(&self.device, &self.queue, level)
+            .await?;
+        Ok((self.voxel_texture.level_size(level), cells))
+    }
+
+    pub fn device_lost(&self) -> bool {
+        self.device_lost.load(Ordering::Relaxed)
+    }
+
+    /// Tries to get the surface back into a usable state after `render` failed. Returns `false` if
+    /// rendering can't continue.
+    pub fn recover_from_surface_error(&mut self, error: wgpu::SurfaceError) -> bool {
+        if error == wgpu::SurfaceError::OutOfMemory {
+            return false;
+        }
+        // Only surfaces fail like this, an offscreen target never does
+        let Some(window_size) = self.target.window().map(Window::inner_size) else {
+            return true;
+        };
+
+        self.consecutive_surface_errors += 1;
+        if self.consecutive_surface_errors >= MAX_CONSECUTIVE_SURFACE_ERRORS {
+            log::warn!(
+                "Surface failed {} frames in a row ({:?}), recreating it",
+                self.consecutive_surface_errors,
+                error
+            );
+            self.consecutive_surface_errors = 0;
+            if let Target::Window { window, surface } = &mut self.target {
+                match unsafe { self.instance.create_surface(&*window) } {
+                    Ok(new_surface) => *surface = new_surface,
+                    // The old surface is kept, the next run of errors tries again
+                    Err(error) => {
+                        log::error!("Can't recreate the surface: {}", error);
+                        return true;
+                    }
+                }
+            }
+            self.resize(window_size);
+            return true;
+        }
+
+        match error {
+            // Outdated is routinely reported while the canvas is being resized
+            wgpu::SurfaceError::Lost | wgpu::SurfaceError::Outdated => self.resize(window_size),
+            // Skip the frame, the next redraw tries again
+            wgpu::SurfaceError::Timeout => {}
+            wgpu::SurfaceError::OutOfMemory => unreachable!(),
+        }
+        true
+    }
+
+    /// Returns whether the event was consumed, the caller handles the rest.
+    pub fn input(&mut self, event: &WindowEvent) -> bool {
+        if self.benchmark.is_some() {
+            return false;
+        }
+        match event {
+            WindowEvent::CursorMoved { position, .. } => {
+                self.cursor_position = *position;
+            }
+            WindowEvent::MouseInput {
+                button: MouseButton::Middle,
+                state: ElementState::Pressed,
+                ..
+            } => {
+                // Focus on whatever is under the cursor, `update` picks up the distance once it's
+                // read back from the last frame's depth
+                let depth_view = self
+                    .frame_graph
+                    .resources()
+                    .texture("depth")
+                    .create_depth_only_view();
+                self.focus_picker.pick(
+                    &self.device,
+                    &self.queue,
+                    &depth_view,
+                    self.camera.projection(),
+                    [
+                        self.cursor_position.x as f32 / self.config.width as f32,
+                        self.cursor_position.y as f32 / self.config.height as f32,
+                    ],
+                );
+                return true;
+            }
+            WindowEvent::MouseInput {
+                button: MouseButton::Left,
+                state: ElementState::Pressed,
+                ..
+            } if !self.paused && !self.camera.pointer_locked() => {
+                // Clicking an axis tip of the gizmo looks along that axis instead
+                let cursor =
+                    Vector2::new(self.cursor_position.x as f32, self.cursor_position.y as f32);
+                let picked_axis = self.gizmo_visible.then(|| {
+                    OrientationGizmo::pick(
+                        self.camera.rotation,
+                        cursor,
+                        self.config.width,
+                        self.config.height,
+                    )
+                });
+                if let Some((axis, positive)) = picked_axis.flatten() {
+                    self.camera.rotation =
+                        gizmo::axis_view_rotation(axis, positive, self.camera.rotation);
+                    return true;
+                }
+                if self.lock_pointer() {
+                    return true;
+                }
+            }
+            WindowEvent::Focused(false) => {
+                self.release_pointer_lock();
+            }
+            WindowEvent::KeyboardInput { input, .. } if !self.paused => {
+                if let Some(key) = self.key_bindings.movement_key_id(input) {
+                    if let Some(movement) = self.key_bindings.movement(key) {
+                        self.camera.set_movement_key(
+                            key,
+                            movement,
+                            input.state == ElementState::Pressed,
+                        );
+                        return true;
+                    }
+                }
+                if let (ElementState::Pressed, Some(keycode)) = (input.state, input.virtual_keycode)
+                {
+                    if let Some(action) = self.key_bindings.action(keycode) {
+                        self.run_action(action);
+                        return true;
+                    }
+                }
+            }
+            _ => {}
+        }
+        if self.paused {
+            return false;
+        }
+        self.camera.process_event(event)
+    }
+
+    /// Raw device input, only mouse motion while the pointer is locked is used.
+    pub fn device_input(&mut self, event: &DeviceEvent) -> bool {
+        if self.benchmark.is_some() || self.paused {
+            return false;
+        }
+        // The browser releases the lock on its own, e.g. when Escape is pressed, without the
+        // page seeing the key
+        #[cfg(target_arch = "wasm32")]
+        if self.camera.pointer_locked()
+            && web_sys::window()
+                .and_then(|window| window.document())
+                .map_or(true, |document| document.pointer_lock_element().is_none())
+        {
+            self.camera.set_pointer_locked(false);
+            if let Some(window) = self.target.window() {
+                window.set_cursor_visible(true);
+            }
+        }
+        self.camera.process_device_event(event)
+    }
+
+    /// Locks the pointer to the window for mouselook. Returns whether that worked, dragging is
+    /// used to look around otherwise.
+    fn lock_pointer(&mut self) -> bool {
+        // Not every platform can lock the cursor in place, keeping it inside the window is enough
+        // since mouselook only uses the raw motion
+        let Some(window) = self.target.window() else {
+            return false;
+        };
+        let result = window
+            .set_cursor_grab(CursorGrabMode::Locked)
+            .or_else(|_| window.set_cursor_grab(CursorGrabMode::Confined));
+        match result {
+            Ok(()) => {
+                window.set_cursor_visible(false);
+                self.camera.set_pointer_locked(true);
+                true
+            }
+            Err(error) => {
+                log::debug!(
+                    "Pointer lock unavailable, dragging to look around: {}",
+                    error
+                );
+                false
+            }
+        }
+    }
+
+    /// Returns whether the pointer was locked.
+    fn release_pointer_lock(&mut self) -> bool {
+        if !self.camera.pointer_locked() {
+            return false;
+        }
+        if let Some(window) = self.target.window() {
+            if let Err(error) = window.set_cursor_grab(CursorGrabMode::None) {
+                log::warn!("Couldn't release the pointer: {}", error);
+            }
+            window.set_cursor_visible(true);
+        }
+        self.camera.set_pointer_locked(false);
+        true
+    }
+
+    #[cfg(target_arch = "wasm32")]
+    fn toggle_pause(&mut self) {
+        self.paused = !self.paused;
+        if self.paused {
+            self.camera.release_movement_keys();
+        }
+        self.refresh_status();
+    }
+
+    fn refresh_status(&self) {
+        let status = if self.paused {
+            Some("Paused, press Escape to resume")
+        } else if self.frozen {
+            Some("Frozen, press P to resume or . to step")
+        } else {
+            None
+        };
+        if let Some(overlay) = &self.overlay {
+            overlay.set_status(status);
+        }
+    }
+
+    /// The scene config with the current camera as the spawn point, as the contents of a
+    /// `<model>.vxgi.json` sidecar.
+    fn export_config(&self) -> String {
+        let mut scene_config = self.scene_config.clone();
+        scene_config.camera.position = self.camera.position.into();
+        scene_config.camera.rotation = [
+            self.camera.rotation.x.0,
+            self.camera.rotation.y.0,
+            self.camera.rotation.z.0,
+        ];
+        scene_config.to_json()
+    }
+
+    fn run_action(&mut self, action: Action) {
+        match action {
+            Action::ToggleHelp => {
+                if let Some(overlay) = &mut self.overlay {
+                    overlay.toggle_help();
+                }
+            }
+            Action::ToggleDepthOfField => self.post_settings.toggle_effect(DepthOfFieldPass::NAME),
+            Action::NearerFocus => self.post_settings.dof_focus_distance /= DOF_STEP,
+            Action::FartherFocus => self.post_settings.dof_focus_distance *= DOF_STEP,
+            Action::NarrowerAperture => self.post_settings.dof_aperture /= DOF_STEP,
+            Action::WiderAperture => self.post_settings.dof_aperture *= DOF_STEP,
+            Action::ToggleMotionBlur => self.post_settings.toggle_effect(MotionBlurPass::NAME),
+            Action::ToggleNeutralMaterial => self
+                .render_settings
+                .toggle_flag(RenderSettings::NEUTRAL_MATERIAL),
+            Action::ToggleMipLevels => self.render_settings.toggle_flag(RenderSettings::MIP_LEVELS),
+            Action::ToggleSoftShadows => self.shadow_settings.soft = !self.shadow_settings.soft,
+            Action::ToggleShadowSnapping => {
+                self.shadow_settings.texel_snapping = !self.shadow_settings.texel_snapping;
+                self.upload_shadow_camera();
+            }
+            Action::ToggleContactShadows => self
+                .render_settings
+                .toggle_flag(RenderSettings::CONTACT_SHADOWS),
+            Action::ToggleShadowInspector => {
+                self.shadow_inspector_visible = !self.shadow_inspector_visible
+            }
+            Action::ToggleProbeGi => self.render_settings.toggle_flag(RenderSettings::PROBE_GI),
+            Action::ToggleLightProbes => self.light_probes_visible = !self.light_probes_visible,
+            Action::ToggleVoxelView => self.voxel_debug_visible = !self.voxel_debug_visible,
+            Action::PreviousVoxelLevel | Action::NextVoxelLevel => {
+                let last_level = self.voxel_texture.mip_level_count() - 1;
+                self.voxel_debug_level = if action == Action::NextVoxelLevel {
+                    (self.voxel_debug_level + 1).min(last_level)
+                } else {
+                    self.voxel_debug_level.saturating_sub(1)
+                };
+                self.voxel_debug
+                    .set_level(&self.queue, self.voxel_debug_level);
+            }
+            Action::ToggleGizmo => self.gizmo_visible = !self.gizmo_visible,
+            Action::ToggleWorldGrid => self.world_grid_visible = !self.world_grid_visible,
+            Action::ToggleSdfSkipping => self
+                .render_settings
+                .toggle_flag(RenderSettings::SDF_SKIPPING),
+            Action::ToggleSdfView => self
+                .render_settings
+                .toggle_flag(RenderSettings::SDF_DISTANCE),
+            Action::ToggleFog => self.render_settings.toggle_flag(RenderSettings::FOG),
+            Action::ToggleSsr => {
+                self.ssr_settings.enabled = !self.ssr_settings.enabled;
+            }
+            Action::ToggleSsrHitMask => {
+                self.ssr_settings.show_hit_mask = !self.ssr_settings.show_hit_mask;
+                self.ssr.update(&self.queue, &self.ssr_settings);
+            }
+            Action::ToggleConeStepHeatmap => {
+                self.cone_step_heatmap = !self.cone_step_heatmap;
+                // Every pipeline of the main pass is compiled again with the other variant
+                let shader = if self.cone_step_heatmap {
+                    &self.cone_step_heatmap_shader
+                } else {
+                    &self.main_shader
+                };
+                for name in ["main", TRANSPARENT_PIPELINE] {
+                    if let Err(error) =
+                        self.scenes[0].regenerate_pipeline(&self.device, shader, name, None)
+                    {
+                        log::error!("Can't switch the cone step heatmap: {}", error);
+                        if let Some(overlay) = &mut self.overlay {
+                            overlay
+                                .flash(&format!("Can't switch the cone step heatmap: {}", error));
+                        }
+                    }
+                }
+            }
+            Action::ToggleFogGi => self
+                .render_settings
+                .toggle_flag(RenderSettings::FOG_GI_INSCATTER),
+            Action::DecreaseLodBias => {
+                self.render_settings.lod_bias -= RenderSettings::LOD_BIAS_STEP
+            }
+            Action::IncreaseLodBias => {
+                self.render_settings.lod_bias += RenderSettings::LOD_BIAS_STEP
+            }
+            Action::FreezeFrame => {
+                self.frozen = !self.frozen;
+                self.refresh_status();
+            }
+            Action::StepFrame => {
+                self.step_requested = true;
+                if !self.frozen {
+                    self.frozen = true;
+                    self.refresh_status();
+                }
+            }
+            Action::ExportConfig => {
+                util::print_to_console(&self.export_config());
+                if let Some(overlay) = &mut self.overlay {
+                    overlay.flash("Scene config written to the console");
+                }
+            }
+            Action::MoveLightLeft
+            | Action::MoveLightRight
+            | Action::MoveLightForward
+            | Action::MoveLightBack => {
+                let offset = match action {
+                    Action::MoveLightLeft => [-LIGHT_NUDGE, 0.0, 0.0],
+                    Action::MoveLightRight => [LIGHT_NUDGE, 0.0, 0.0],
+                    Action::MoveLightForward => [0.0, 0.0, -LIGHT_NUDGE],
+                    _ => [0.0, 0.0, LIGHT_NUDGE],
+                };
+                if let Some(light) = self.scene_config.lights.first() {
+                    let mut light = light.clone();
+                    for (component, offset) in light.position.iter_mut().zip(offset) {
+                        *component += offset;
+                    }
+                    self.set_light(0, light)
+                        .expect("The first point light exists");
+                }
+            }
+        }
+
+        if let Some(action_state) = self.action_state(action) {
+            if let Some(overlay) = &mut self.overlay {
+                overlay.flash(&format!("{}: {}", action.description(), action_state));
+            }
+        }
+        self.refresh_help();
+    }
+
+    /// Current state of the mode an action toggles, `None` for actions without one.
+    fn action_state(&self, action: Action) -> Option<String> {
+        let on_off = |enabled: bool| if enabled { "on" } else { "off" }.to_string();
+        match action {
+            Action::ToggleHelp | Action::ExportConfig => None,
+            Action::MoveLightLeft
+            | Action::MoveLightRight
+            | Action::MoveLightForward
+            | Action::MoveLightBack => Some(match self.scene_config.lights.first() {
+                Some(light) => format!("{:?}", light.position),
+                None => "no point lights".to_string(),
+            }),
+            Action::FreezeFrame => Some(on_off(self.frozen)),
+            // The frame the step renders, `frame_index` advances after it's uploaded
+            Action::StepFrame => Some(format!("frame {}", self.frame_index)),
+            Action::ToggleDepthOfField => Some(on_off(
+                self.post_settings.is_effect_enabled(DepthOfFieldPass::NAME),
+            )),
+            Action::NearerFocus | Action::FartherFocus => {
+                Some(format!("{:.2} m", self.post_settings.dof_focus_distance))
+            }
+            Action::NarrowerAperture | Action::WiderAperture => {
+                Some(format!("{:.2}", self.post_settings.dof_aperture))
+            }
+            Action::ToggleMotionBlur => Some(on_off(
+                self.post_settings.is_effect_enabled(MotionBlurPass::NAME),
+            )),
+            Action::ToggleNeutralMaterial => Some(on_off(
+                self.render_settings
+                    .has_flag(RenderSettings::NEUTRAL_MATERIAL),
+            )),
+            Action::ToggleMipLevels => Some(on_off(
+                self.render_settings.has_flag(RenderSettings::MIP_LEVELS),
+            )),
+            Action::ToggleSoftShadows => Some(on_off(self.shadow_settings.soft)),
+            Action::ToggleShadowSnapping => Some(on_off(self.shadow_settings.texel_snapping)),
+            Action::ToggleContactShadows => Some(on_off(
+                self.render_settings
+                    .has_flag(RenderSettings::CONTACT_SHADOWS),
+            )),
+            Action::ToggleShadowInspector => Some(on_off(self.shadow_inspector_visible)),
+            Action::ToggleProbeGi => Some(on_off(
+                self.render_settings.has_flag(RenderSettings::PROBE_GI),
+            )),
+            Action::ToggleLightProbes => Some(on_off(self.light_probes_visible)),
+            Action::ToggleVoxelView => Some(on_off(self.voxel_debug_visible)),
+            Action::PreviousVoxelLevel | Action::NextVoxelLevel => Some(format!(
+                "mip {} ({}³)",
+                self.voxel_debug_level,
+                self.voxel_texture.level_size(self.voxel_debug_level)
+            )),
+            Action::ToggleGizmo => Some(on_off(self.gizmo_visible)),
+            Action::ToggleWorldGrid => Some(on_off(self.world_grid_visible)),
+            Action::ToggleSdfSkipping => Some(on_off(
+                self.render_settings.has_flag(RenderSettings::SDF_SKIPPING),
+            )),
+            Action::ToggleSdfView => Some(on_off(
+                self.render_settings.has_flag(RenderSettings::SDF_DISTANCE),
+            )),
+            Action::ToggleFog => Some(on_off(self.render_settings.has_flag(RenderSettings::FOG))),
+            Action::ToggleConeStepHeatmap => Some(on_off(self.cone_step_heatmap)),
+            Action::ToggleSsr => Some(on_off(self.ssr_settings.enabled)),
+            Action::ToggleSsrHitMask => Some(on_off(self.ssr_settings.show_hit_mask)),
+            Action::ToggleFogGi => Some(on_off(
+                self.render_settings
+                    .has_flag(RenderSettings::FOG_GI_INSCATTER),
+            )),
+            Action::DecreaseLodBias | Action::IncreaseLodBias => {
+                Some(format!("{:+.2}", self.render_settings.lod_bias))
+            }
+        }
+    }
+
+    fn refresh_help(&self) {
+        let overlay = match &self.overlay {
+            Some(overlay) if overlay.help_visible() => overlay,
+            _ => return,
+        };
+
+        let mut text = String::new();
+        for action in Action::ALL {
+            let key = match self.key_bindings.key_for(action) {
+                Some(key) => format!("{:?}", key),
+                None => "-".to_string(),
+            };
+            text += &format!("{:<8}{}", key, action.description());
+            if let Some(action_state) = self.action_state(action) {
+                text += &format!(" ({})", action_state);
+            }
+            text += "\n";
+        }
+        text += "\nWASD    Move\nSpace   Up\nLShift  Down\nLMB     Look around\n";
+        text += "Wheel   Movement speed\nMMB     Focus depth of field\n";
+        overlay.set_help_text(&text);
+    }
+
+    fn apply_material_edit(&mut self, material_name: &str, edit: &MaterialEdit) {
+        let scene = &mut self.scenes[0];
+        let indices = scene.find_materials(material_name);
+        if indices.is_empty() {
+            log::warn!("No material named \"{}\" to edit", material_name);
+            return;
+        }
+        for index in indices {
+            let Some(material) = scene.material(index) else {
+                continue;
+            };
+            let mut data = material.data;
+            if let Some(base_color_factor) = edit.base_color_factor {
+                data.base_color_factor = base_color_factor;
+            }
+            if let Some(metallic_factor) = edit.metallic_factor {
+                data.metallic_factor = metallic_factor;
+            }
+            if let Some(roughness_factor) = edit.roughness_factor {
+                data.roughness_factor = roughness_factor;
+            }
+            if let Some([r, g, b]) = edit.emissive_factor {
+                data.emissive_factor = [r, g, b, 0.0];
+            }
+            if let Some(planar_reflection) = edit.planar_reflection {
+                data.planar_reflection = planar_reflection.clamp(0.0, 1.0);
+            }
+            scene.set_material(&self.queue, index, data);
+        }
+        // The voxels store the lit base color, so color bleeding has to be rebuilt
+        self.voxels_dirty = true;
+        if let Some(overlay) = &mut self.overlay {
+            overlay.flash(&format!("Edited {}", material_name));
+        }
+    }
+
+    fn apply_app_command(&mut self, command: AppCommand) {
+        match command {
+            AppCommand::Camera {
+                position,
+                pitch,
+                yaw,
+            } => {
+                self.camera.position = position.into();
+                self.camera.rotation = Euler::new(Deg(pitch), Deg(yaw), Deg(0.0));
+            }
+            AppCommand::Light {
+                index,
+                position,
+                intensity,
+            } => {
+                let Some(light) = self.scene_config.lights.get(index) else {
+                    log::warn!("No point light #{} to change", index);
+                    return;
+                };
+                let light = PointLightConfig {
+                    position,
+                    intensity,
+                    ..light.clone()
+                };
+                if let Err(error) = self.set_light(index, light) {
+                    log::warn!("{}", error);
+                }
+            }
+            AppCommand::Exposure(exposure) => self.post_settings.exposure = exposure.max(0.0),
+            AppCommand::DofFocusDistance(distance) => {
+                self.post_settings.dof_focus_distance = distance.max(0.0)
+            }
+            AppCommand::DofAperture(aperture) => {
+                self.post_settings.dof_aperture = aperture.max(0.0)
+            }
+        }
+    }
+
+    /// Moves the glTF node `node_index` relative to its parent, the nodes below it follow.
+    /// Applied by the next `update`.
+    pub fn set_node_transform(
+        &mut self,
+        node_index: usize,
+        transform: Matrix4<f32>,
+    ) -> Result<(), String> {
+        self.scenes[0].set_node_transform(node_index, transform)
+    }
+
+    /// Moves or recolors point light `index`, counted like `SceneConfig::lights`.
+    pub fn set_light(&mut self, index: usize, light: PointLightConfig) -> Result<(), LightError> {
+        let count = self.scene_config.lights.len();
+        let slot = self
+            .scene_config
+            .lights
+            .get_mut(index)
+            .ok_or(LightError::IndexOutOfRange { index, count })?;
+        *slot = light;
+        self.lights_dirty = true;
+        Ok(())
+    }
+
+    /// Adds a point light and returns its index. The lights buffer grows as needed, this only
+    /// fails once it would be bigger than a storage buffer binding may be.
+    pub fn add_light(&mut self, light: PointLightConfig) -> Result<usize, LightError> {
+        let max_binding_size = self.device.limits().max_storage_buffer_binding_size as u64;
+        let count = self.scene_config.lights.len();
+        if Lights::buffer_size(count + 2) > max_binding_size {
+            return Err(LightError::Full { count });
+        }
+        self.scene_config.lights.push(light);
+        self.lights_dirty = true;
+        Ok(self.scene_config.lights.len() - 1)
+    }
+
+    /// Removes point light `index`, the ones after it move down a slot.
+    pub fn remove_light(&mut self, index: usize) -> Result<PointLightConfig, LightError> {
+        let count = self.scene_config.lights.len();
+        if index >= count {
+            return Err(LightError::IndexOutOfRange { index, count });
+        }
+        self.lights_dirty = true;
+        Ok(self.scene_config.lights.remove(index))
+    }
+
+    /// Removes every point light, the sun stays.
+    pub fn clear_lights(&mut self) {
+        self.scene_config.lights.clear();
+        self.lights_dirty = true;
+    }
+
+    pub fn lights(&self) -> &[PointLightConfig] {
+        &self.scene_config.lights
+    }
+
+    /// Advances the simulation by `dt` seconds of real time and uploads everything the next
+    /// `render` needs.
+    pub fn update(&mut self, dt: f32) {
+        self.stream_scene();
+
+        if self.focus_picker.is_pending() {
+            // Natively the readback only finishes when the device is polled
+            self.device.poll(wgpu::Maintain::Poll);
+            if let Some(distance) = self.focus_picker.try_finish() {
+                self.post_settings.dof_focus_distance = distance;
+                if let Some(overlay) = &mut self.overlay {
+                    overlay.flash(&format!("Focus distance: {:.2} m", distance));
+                }
+            }
+        }
+        if let Some(gpu_profiler) = self.gpu_profiler.as_mut().filter(|gpu| gpu.is_pending()) {
+            self.device.poll(wgpu::Maintain::Poll);
+            if let Some(span_ms) = gpu_profiler.try_finish() {
+                if let Some(benchmark) = &mut self.benchmark {
+                    benchmark.record_gpu_frame(&span_ms);
+                }
+            }
+        }
+
+        if let Some(mesh_name) = PENDING_SELECTION.with(|pending| pending.borrow_mut().take()) {
+            self.outline.select(
+                &self.device,
+                &mut self.scenes[0],
+                &self.diffuse_camera_bind_group_layout,
+                mesh_name,
+            );
+            if let Some(overlay) = &mut self.overlay {
+                let message = match self.outline.selected_mesh() {
+                    Some(mesh_name) => format!("Selected {}", mesh_name),
+                    None => "Selection cleared".to_string(),
+                };
+                overlay.flash(&message);
+            }
+        }
+
+        let edits =
+            PENDING_MATERIAL_EDITS.with(|pending| std::mem::take(&mut *pending.borrow_mut()));
+        for (material_name, edit) in edits {
+            self.apply_material_edit(&material_name, &edit);
+        }
+
+        let commands =
+            PENDING_APP_COMMANDS.with(|pending| std::mem::take(&mut *pending.borrow_mut()));
+        for command in commands {
+            self.apply_app_command(command);
+        }
+
+        // There's no partial revoxelization, anything moving rebuilds the whole grid
+        if !self.scenes[0].update_transforms(&self.queue).is_empty() {
+            self.voxels_dirty = true;
+        }
+
+        if std::mem::take(&mut self.lights_dirty) {
+            // Lights moving to another slot or getting brighter or dimmer change who gets which
+            // tile, the sun keeps its own
+            self.shadow_atlas = allocate_shadow_tiles(&self.scene_config);
+            let sun_direction = Vector3::from(self.scene_config.sun.direction).normalize();
+            let lights = Lights::from_config(
+                sun_direction,
+                &self.scene_config,
+                &self.shadow_atlas,
+                self.scenes[0].bounds().as_ref(),
+            );
+            self.upload_lights(&lights);
+            self.spot_shadows = create_spot_shadows(
+                &self.device,
+                &self.shadow_camera_bind_group_layout,
+                &lights,
+                &self.shadow_atlas,
+            );
+            // The voxels are lit when they are built, the bounce light would keep the old lights
+            self.voxels_dirty = true;
+        }
+
+        let advance = !self.frozen || std::mem::take(&mut self.step_requested);
+        let fixed_step = self.frozen || self.benchmark.is_some();
+        let step_dt = if fixed_step { FIXED_STEP_DT } else { dt };
+        if advance {
+            self.time += step_dt;
+        }
+
+        if let Some(benchmark) = &mut self.benchmark {
+            let sdf_skipping = self.render_settings.has_flag(RenderSettings::SDF_SKIPPING);
+            if benchmark.record_frame(&self.cpu_profiler, dt as f64 * 1000.0, sdf_skipping) {
+                util::print_to_console(&benchmark.report(&self.adapter_info));
+                self.benchmark = None;
+                if let Some(overlay) = &mut self.overlay {
+                    overlay.flash("Benchmark report written to the console");
+                }
+            }
+        }
+
+        let timer = self.cpu_profiler.begin();
+        if let Some(benchmark) = &self.benchmark {
+            (self.camera.position, self.camera.rotation) = benchmark.camera_transform();
+            if let Some(sdf_skipping) = benchmark.sdf_skipping() {
+                self.render_settings
+                    .set_flag(RenderSettings::SDF_SKIPPING, sdf_skipping);
+            }
+        } else if advance && !self.paused {
+            self.camera.update(step_dt);
+        }
+        self.cpu_profiler.end(CpuSection::CameraUpdate, timer);
+        CAMERA_STATE.with(|camera_state| {
+            camera_state.set(CameraState {
+                x: self.camera.position.x,
+                y: self.camera.position.y,
+                z: self.camera.position.z,
+                pitch: self.camera.rotation.x.0,
+                yaw: self.camera.rotation.y.0,
+            })
+        });
+
+        let timer = self.cpu_profiler.begin();
+        self.render_settings
+            .set_flag(RenderSettings::SOFT_SHADOWS, self.shadow_settings.soft);
+        self.queue.write_buffer(
+            &self.camera_buffer,
+            0,
+            bytemuck::cast_slice(&[self.camera.get_uniform_data()]),
+        );
+        self.planar_reflection_height = self.scenes[0].planar_reflection_height();
+        if let Some(height) = self.planar_reflection_height {
+            self.queue.write_buffer(
+                &self.mirror_camera_buffer,
+                0,
+                bytemuck::cast_slice(&[self.camera.mirrored_uniform_data(height)]),
+            );
+        }
+        if self.gizmo_visible {
+            self.gizmo.update(&self.queue, self.camera.rotation);
+        }
+        self.queue.write_buffer(
+            &self.frame_globals_buffer,
+            0,
+            bytemuck::cast_slice(&[FrameGlobals {
+                resolution: [self.config.width as f32, self.config.height as f32],
+                time: self.time,
+                frame_index: self.frame_index,
+            }]),
+        );
+        self.queue.write_buffer(
+            &self.render_settings_buffer,
+            0,
+            bytemuck::cast_slice(&[self.render_settings]),
+        );
+        self.cpu_profiler.end(CpuSection::BufferWrites, timer);
+        if let Some(overlay) = &mut self.overlay {
+            overlay.update();
+        }
+        if !advance {
+            // Post effects keep the history of the last simulated frame, so a frozen frame looks
+            // exactly like it did when it was stepped to
+            return;
+        }
+        self.frame_index = self.frame_index.wrapping_add(1);
+        if self.revoxelize_interval > 0 && self.frame_index.is_multiple_of(self.revoxelize_interval)
+        {
+            self.voxels_dirty = true;
+        }
+
+        let timer = self.cpu_profiler.begin();
+        self.post_chain.update(
+            &self.queue,
+            &self.post_settings,
+            &PostFrame {
+                view_projection: self.camera.view_projection(),
+                projection: self.camera.projection(),
+            },
+        );
+        self.cpu_profiler.end(CpuSection::PostUpdate, timer);
+
+        #[cfg(feature = "cpu-profiling")]
+        if self.frame_index.is_multiple_of(30) {
+            CPU_TIMINGS.with(|timings| timings.set(CpuTimings::from_profiler(&self.cpu_profiler)));
+            if let Some(overlay) = &self.overlay {
+                let adapter = format!(
+                    "{} ({:?})",
+                    self.adapter_info.name, self.adapter_info.backend
+                );
+                let text = std::iter::once(adapter)
+                    .chain(
+                        self.cpu_profiler
+                            .averages_ms()
+                            .map(|(section, average_ms)| {
+                                format!("CPU {:<18} {:6.3} ms", section.name(), average_ms)
+                            }),
+                    )
+                    .chain(
+                        self.gpu_profiler
+                            .iter()
+                            .flat_map(GpuProfiler::averages_ms)
+                            .map(|(pass, average_ms)| {
+                                format!("GPU {:<18} {:6.3} ms", pass, average_ms)
+                            }),
+                    )
+                    .collect::<Vec<_>>()
+                    .join("\n");
+                overlay.set_timings(Some(&text));
+            }
+        }
+        #[cfg(feature = "cpu-profiling")]
+        if self.frame_index.is_multiple_of(300) {
+            for (section, average_ms) in self.cpu_profiler.averages_ms() {
+                log::info!("CPU {}: {:.3} ms", section.name(), average_ms);
+            }
+            for (pass, average_ms) in self.gpu_profiler.iter().flat_map(GpuProfiler::averages_ms) {
+                log::info!("GPU {}: {:.3} ms", pass, average_ms);
+            }
+        }
+    }
+
+    /// Errors can usually be recovered from with `recover_from_surface_error`.
+    pub fn render(&mut self) -> Result<(), wgpu::SurfaceError> {
+        // Canvas size changes don't always come with a resize event on the web, the depth and
+        // post targets would be left at the old size
+        if let Some(window_size) = self.target.window().map(Window::inner_size) {
+            if window_size != self.size {
+                self.resize(window_size);
+            }
+        }
+        if std::mem::take(&mut self.voxels_dirty) {
+            self.submit_revoxelization();
+        }
+        if let Some(command_buffer) = self.pending_preprocess.pop_front() {
+            self.queue.submit(std::iter::once(command_buffer));
+        }
+
+        let (output, view) = match &self.target {
+            Target::Window { surface, .. } => {
+                let output = surface.get_current_texture()?;
+                let view = output
+                    .texture
+                    .create_view(&wgpu::TextureViewDescriptor::default());
+                (Some(output), view)
+            }
+            Target::Offscreen(texture) => (
+                None,
+                texture.create_view(&wgpu::TextureViewDescriptor::default()),
+            ),
+        };
+        let mut encoder = self
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("Render encoder"),
+            });
+
+        // Taken for the frame, the graph's passes borrow the whole state
+        let mut gpu_profiler = self.gpu_profiler.take();
+        if let Some(gpu_profiler) = &mut gpu_profiler {
+            gpu_profiler.begin_frame(&mut encoder);
+        }
+        let timer = self.cpu_profiler.begin();
+        self.frame_graph
+            .execute(self, &mut encoder, |name, encoder| {
+                if let Some(gpu_profiler) = &mut gpu_profiler {
+                    gpu_profiler.end_span(encoder, name);
+                }
+            });
+        self.cpu_profiler.end(CpuSection::EncodeFrameGraph, timer);
+
+        let timer = self.cpu_profiler.begin();
+        let depth_view = self
+            .frame_graph
+            .resources()
+            .texture("depth")
+            .create_depth_only_view();
+        self.post_chain.record(
+            &PostContext {
+                device: &self.device,
+                depth: &depth_view,
+                frame_globals: &self.frame_globals_buffer,
+            },
+            &mut encoder,
+            &view,
+        );
+        if self.shadow_inspector_visible {
+            self.shadow_inspector.record(
+                &mut encoder,
+                &self.diffuse_camera_bind_group,
+                &view,
+                self.config.width,
+                self.config.height,
+            );
+        }
+        // After tonemapping, so the exposure doesn't change the axis colors
+        if self.gizmo_visible {
+            self.gizmo.record(
+                &mut encoder,
+                &self.diffuse_camera_bind_group,
+                &view,
+                self.config.width,
+                self.config.height,
+            );
+        }
+        self.cpu_profiler.end(CpuSection::EncodePost, timer);
+        if let Some(gpu_profiler) = &mut gpu_profiler {
+            gpu_profiler.end_span(&mut encoder, "post");
+            gpu_profiler.end_frame(&mut encoder);
+        }
+
+        let timer = self.cpu_profiler.begin();
         self.queue.submit(std::iter::once(encoder.finish()));
-        output.present();
+        self.cpu_profiler.end(CpuSection::Submit, timer);
+        if let Some(gpu_profiler) = &mut gpu_profiler {
+            gpu_profiler.read_back(&self.device, &self.queue);
+        }
+        self.gpu_profiler = gpu_profiler;
+
+        let timer = self.cpu_profiler.begin();
+        if let Some(output) = output {
+            output.present();
+        }
+        self.cpu_profiler.end(CpuSection::Present, timer);
+        self.consecutive_surface_errors = 0;
+
+        Ok(())
+    }
+}
+
+/// Changes the log filter without reloading the page, takes the same syntax as the `log` query
+/// parameter, e.g. `debug` or `warn,gltf_loader=debug`.
+#[wasm_bindgen]
+pub fn set_log_level(filter: &str) {
+    logging::set_filter(filter);
+}
+
+thread_local! {
+    /// Selection requested from JavaScript, applied by the next `State::update`
+    static PENDING_SELECTION: RefCell<Option<Option<String>>> = const { RefCell::new(None) };
+}
+
+/// Outlines every primitive of the mesh with the given glTF name, `undefined` clears the
+/// selection.
+#[wasm_bindgen]
+pub fn select_mesh(mesh_name: Option<String>) {
+    PENDING_SELECTION.with(|pending| *pending.borrow_mut() = Some(mesh_name));
+}
+
+/// Material factors to change, missing fields keep their current value.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct MaterialEdit {
+    base_color_factor: Option<[f32; 4]>,
+    metallic_factor: Option<f32>,
+    roughness_factor: Option<f32>,
+    /// Emitted radiance, with the emissive strength already multiplied in
+    emissive_factor: Option<[f32; 3]>,
+    /// Strength of the planar reflection, 0 to 1
+    planar_reflection: Option<f32>,
+}
+
+thread_local! {
+    /// Material edits requested from JavaScript, applied by the next `State::update`
+    static PENDING_MATERIAL_EDITS: RefCell<Vec<(String, MaterialEdit)>> =
+        const { RefCell::new(Vec::new()) };
+}
+
+/// Edits every material with the given glTF name, `edit` is a JSON object with any of
+/// `base_color_factor`, `metallic_factor`, `roughness_factor`, `emissive_factor` and
+/// `planar_reflection`, e.g.
+/// `set_material("fabric_c", '{"roughness_factor": 0.2}')`.
+#[wasm_bindgen]
+pub fn set_material(material_name: String, edit: &str) {
+    match serde_json::from_str::<MaterialEdit>(edit) {
+        Ok(edit) => {
+            PENDING_MATERIAL_EDITS.with(|pending| pending.borrow_mut().push((material_name, edit)))
+        }
+        Err(error) => log::warn!("Ignoring material edit {}: {}", edit, error),
+    }
+}
+
+/// Changes requested through a `VxgiApp` handle.
+#[derive(Debug)]
+enum AppCommand {
+    Camera {
+        position: [f32; 3],
+        pitch: f32,
+        yaw: f32,
+    },
+    Light {
+        index: usize,
+        position: [f32; 3],
+        intensity: [f32; 3],
+    },
+    Exposure(f32),
+    DofFocusDistance(f32),
+    DofAperture(f32),
+}
 
-        Ok(())
+thread_local! {
+    /// Commands sent from JavaScript, applied by the next `State::update`
+    static PENDING_APP_COMMANDS: RefCell<Vec<AppCommand>> = const { RefCell::new(Vec::new()) };
+    /// Camera as of the last `State::update`, read back by `VxgiApp::get_camera_state`
+    static CAMERA_STATE: Cell<CameraState> = Cell::new(CameraState::default());
+    /// Refreshed every 30 frames, read back by `VxgiApp::get_cpu_timings`
+    static CPU_TIMINGS: Cell<CpuTimings> = Cell::new(CpuTimings::default());
+    /// Adapter the last `State` was created on, read back by `VxgiApp::get_adapter_state`
+    static ADAPTER_STATE: RefCell<AdapterState> = RefCell::new(AdapterState::default());
+}
+
+/// Camera position and angles in degrees, as returned to JavaScript.
+#[wasm_bindgen]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct CameraState {
+    pub x: f32,
+    pub y: f32,
+    pub z: f32,
+    pub pitch: f32,
+    pub yaw: f32,
+}
+
+/// Name and backend of the adapter the renderer picked from `backend_preference`, as returned to
+/// JavaScript.
+#[wasm_bindgen(getter_with_clone)]
+#[derive(Debug, Default, Clone)]
+pub struct AdapterState {
+    pub name: String,
+    pub backend: String,
+}
+
+/// Rolling averages of the CPU time spent in each part of the frame in milliseconds, as returned
+/// to JavaScript. All zero without the `cpu-profiling` feature.
+#[wasm_bindgen]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct CpuTimings {
+    pub camera_update: f64,
+    pub buffer_writes: f64,
+    pub post_update: f64,
+    pub encode_frame_graph: f64,
+    pub encode_post: f64,
+    pub submit: f64,
+    pub present: f64,
+}
+
+impl CpuTimings {
+    #[cfg_attr(not(feature = "cpu-profiling"), allow(dead_code))]
+    fn from_profiler(profiler: &CpuProfiler) -> Self {
+        Self {
+            camera_update: profiler.average_ms(CpuSection::CameraUpdate),
+            buffer_writes: profiler.average_ms(CpuSection::BufferWrites),
+            post_update: profiler.average_ms(CpuSection::PostUpdate),
+            encode_frame_graph: profiler.average_ms(CpuSection::EncodeFrameGraph),
+            encode_post: profiler.average_ms(CpuSection::EncodePost),
+            submit: profiler.average_ms(CpuSection::Submit),
+            present: profiler.average_ms(CpuSection::Present),
+        }
     }
 }
 
-#[cfg_attr(target_arch = "wasm32", wasm_bindgen(start))]
-pub async fn run() {
-    std::panic::set_hook(Box::new(console_error_panic_hook::hook));
-    console_log::init_with_level(log::Level::Warn).expect("Couldn't initialize logger");
+/// Lets the page hosting the renderer control it while `run` owns the event loop, e.g.
+/// `new VxgiApp().set_camera(0, 2, 5, -10, 0)` from the browser console. Every handle talks to
+/// the same renderer, changes show up from the next frame on.
+#[wasm_bindgen]
+#[derive(Debug, Default)]
+pub struct VxgiApp {}
 
-    let event_loop = EventLoop::new();
-    let window = WindowBuilder::new()
-        .build(&event_loop)
-        .expect("Failed to create window");
+#[wasm_bindgen]
+impl VxgiApp {
+    #[wasm_bindgen(constructor)]
+    pub fn new() -> VxgiApp {
+        VxgiApp {}
+    }
 
-    use winit::dpi::PhysicalSize;
-    window.set_inner_size(PhysicalSize::new(1920, 1080));
+    /// Moves the camera to `x`, `y`, `z` looking in the direction given by `pitch` and `yaw` in
+    /// degrees.
+    pub fn set_camera(&self, x: f32, y: f32, z: f32, pitch: f32, yaw: f32) {
+        Self::push(AppCommand::Camera {
+            position: [x, y, z],
+            pitch,
+            yaw,
+        });
+    }
+
+    /// Moves point light `index` and changes its color and intensity, the falloff and the spot
+    /// cone are kept.
+    #[allow(clippy::too_many_arguments)]
+    pub fn set_light(&self, index: usize, x: f32, y: f32, z: f32, r: f32, g: f32, b: f32) {
+        Self::push(AppCommand::Light {
+            index,
+            position: [x, y, z],
+            intensity: [r, g, b],
+        });
+    }
+
+    /// Multiplier applied to the scene before tonemapping, 1 is the default.
+    pub fn set_exposure(&self, exposure: f32) {
+        Self::push(AppCommand::Exposure(exposure));
+    }
+
+    /// Distance of the depth of field's focus plane along the view direction.
+    pub fn set_dof_focus_distance(&self, distance: f32) {
+        Self::push(AppCommand::DofFocusDistance(distance));
+    }
+
+    /// Scale of the depth of field's circle of confusion, bigger blurs more.
+    pub fn set_dof_aperture(&self, aperture: f32) {
+        Self::push(AppCommand::DofAperture(aperture));
+    }
+
+    /// Camera as of the last frame.
+    pub fn get_camera_state(&self) -> CameraState {
+        CAMERA_STATE.with(Cell::get)
+    }
+
+    /// Adapter the renderer runs on, empty until it has started.
+    pub fn get_adapter_state(&self) -> AdapterState {
+        ADAPTER_STATE.with(|adapter_state| adapter_state.borrow().clone())
+    }
+
+    /// CPU frame time breakdown, all zero unless built with the `cpu-profiling` feature.
+    pub fn get_cpu_timings(&self) -> CpuTimings {
+        CPU_TIMINGS.with(Cell::get)
+    }
+
+    fn push(command: AppCommand) {
+        PENDING_APP_COMMANDS.with(|pending| pending.borrow_mut().push(command));
+    }
+}
 
+/// Installs the panic hook on the web and the logger, has to be called once before creating a
+/// `State` outside of `run`.
+pub fn init() {
+    #[cfg(target_arch = "wasm32")]
+    std::panic::set_hook(Box::new(console_error_panic_hook::hook));
+    logging::init();
+}
+
+/// Logs why the scene couldn't be loaded and shows it on top of the element with the id
+/// `parent_id`, where the page would otherwise stay blank.
+fn report_load_error(error: &gltf_loader::LoadError, parent_id: &str) {
+    log::error!("Can't load the scene: {}", error);
+    if let Some(overlay) = Overlay::new(parent_id) {
+        overlay.set_status(Some(&format!("Can't load the scene: {}", error)));
+    }
+}
+
+/// Puts the canvas of `window` into the element with the id `parent_id`.
+#[cfg(target_arch = "wasm32")]
+pub fn attach_canvas(window: &Window, parent_id: &str) {
     use winit::platform::web::WindowExtWebSys;
     web_sys::window()
         .and_then(|win| win.document())
         .and_then(|doc| {
-            let dst = doc.get_element_by_id("voxel-gi")?;
+            let dst = doc.get_element_by_id(parent_id)?;
             let canvas = web_sys::Element::from(window.canvas());
             canvas.set_id("webgpu");
             dst.append_child(&canvas).ok()?;
             Some(())
         })
         .expect("Couldn't append canvas to document body.");
+}
+
+/// Renderer driven by a loop on the JavaScript side, see `client/embedded.html`. Window events
+/// only reach the renderer through `run`'s event loop, so the camera stays where the scene config
+/// put it.
+#[cfg(target_arch = "wasm32")]
+#[wasm_bindgen]
+pub struct Renderer {
+    state: State<'static>,
+}
+
+#[cfg(target_arch = "wasm32")]
+#[wasm_bindgen]
+impl Renderer {
+    /// Creates the canvas inside the element with the id `parent_id`. Rejects with the reason
+    /// if the scene can't be loaded.
+    pub async fn create(parent_id: String) -> Result<Renderer, JsValue> {
+        init();
+        // The event loop is only needed to create the window, it never runs
+        let event_loop = EventLoop::new();
+        let window = WindowBuilder::new()
+            .build(&event_loop)
+            .expect("Failed to create window");
+        attach_canvas(&window, &parent_id);
+        match State::new(window).await {
+            Ok(state) => Ok(Renderer { state }),
+            Err(error) => {
+                report_load_error(&error, &parent_id);
+                Err(JsValue::from_str(&error.to_string()))
+            }
+        }
+    }
+
+    pub fn resize(&mut self, width: u32, height: u32) {
+        let size = winit::dpi::PhysicalSize::new(width, height);
+        if let Some(window) = self.state.window() {
+            window.set_inner_size(size);
+        }
+        self.state.resize(size);
+    }
+
+    /// Renders a frame `dt` seconds after the previous one. Returns `false` once the renderer
+    /// can't continue, e.g. because the device was lost.
+    pub fn frame(&mut self, dt: f32) -> bool {
+        if self.state.device_lost() {
+            return false;
+        }
+        // The first requestAnimationFrame timestamp can be older than the performance.now() the
+        // page started counting from, the camera and the clock shouldn't run backwards
+        self.state.update(dt.max(0.0));
+        match self.state.render() {
+            Ok(()) => true,
+            Err(error) => self.state.recover_from_surface_error(error),
+        }
+    }
+}
+
+/// `State::recreate` as polled by `run`'s native event loop.
+#[cfg(not(target_arch = "wasm32"))]
+type RecreateFuture = std::pin::Pin<
+    Box<dyn std::future::Future<Output = Result<State<'static>, RecreateError<'static>>>>,
+>;
+
+/// Polls the rebuild once. Nothing wakes the event loop when it can make progress, the next event
+/// polls it again.
+#[cfg(not(target_arch = "wasm32"))]
+fn poll_recreation(
+    future: &mut RecreateFuture,
+) -> Option<Result<State<'static>, RecreateError<'static>>> {
+    use futures::FutureExt;
+    let mut context = std::task::Context::from_waker(futures::task::noop_waker_ref());
+    match future.poll_unpin(&mut context) {
+        std::task::Poll::Ready(result) => Some(result),
+        std::task::Poll::Pending => None,
+    }
+}
+
+/// Settings for `run`, e.g. `run(Object.assign(new RunOptions(), { model_path: "..." }))`.
+#[wasm_bindgen(getter_with_clone)]
+#[derive(Debug, Default, Clone)]
+pub struct RunOptions {
+    /// .gltf or .glb file to render, overrides the `model` query parameter
+    pub model_path: Option<String>,
+}
+
+#[wasm_bindgen]
+impl RunOptions {
+    #[wasm_bindgen(constructor)]
+    pub fn new() -> RunOptions {
+        RunOptions::default()
+    }
+}
+
+/// Opens a window and renders into it until it's closed, on the web the canvas is put into the
+/// `voxel-gi` element. Without `options` the query parameters decide what's rendered. Natively
+/// `headless=1` runs the benchmark into an offscreen target instead and returns once it's done.
+#[cfg_attr(target_arch = "wasm32", wasm_bindgen)]
+pub async fn run(options: Option<RunOptions>) {
+    init();
+
+    #[cfg(not(target_arch = "wasm32"))]
+    if util::query_param("headless").as_deref() == Some("1") {
+        run_headless(options.unwrap_or_default()).await;
+        return;
+    }
+
+    let event_loop = EventLoop::new();
+    let window = WindowBuilder::new()
+        .build(&event_loop)
+        .expect("Failed to create window");
+
+    use winit::dpi::PhysicalSize;
+    window.set_inner_size(PhysicalSize::new(1920, 1080));
+
+    #[cfg(target_arch = "wasm32")]
+    attach_canvas(&window, "voxel-gi");
+
+    let options = options.unwrap_or_default();
+    let state = match options.model_path {
+        Some(model_path) => State::with_model(window, model_path).await,
+        None => State::new(window).await,
+    };
+    let state = match state {
+        Ok(state) => state,
+        Err(error) => {
+            report_load_error(&error, "voxel-gi");
+            return;
+        }
+    };
+    let mut state = Some(state);
+    let mut last_update = Instant::now();
+    // After the device was lost the state is rebuilt by a task on the web, which hands it back
+    // through `recreated`. Natively the event loop polls the rebuild itself between events.
+    #[cfg(target_arch = "wasm32")]
+    let recreated = std::rc::Rc::new(RefCell::new(None));
+    #[cfg(not(target_arch = "wasm32"))]
+    let mut recreation: Option<RecreateFuture> = None;
+    // Set while waiting to look for a device again
+    let mut retry_at: Option<Instant> = None;
 
-    let mut state = State::new(window).await;
+    event_loop.run(move |event, _, control_flow| {
+        #[cfg(target_arch = "wasm32")]
+        let finished = recreated.borrow_mut().take();
+        #[cfg(not(target_arch = "wasm32"))]
+        let finished = recreation.as_mut().and_then(poll_recreation);
+        if let Some(result) = finished {
+            #[cfg(not(target_arch = "wasm32"))]
+            {
+                recreation = None;
+            }
+            match result {
+                Ok(recreated_state) => {
+                    recreated_state.refresh_status();
+                    state = Some(recreated_state);
+                }
+                Err(RecreateError::GpuUnavailable(lost_state, error)) => {
+                    log::error!("Can't get a graphics device back, retrying: {}", error);
+                    if let Some(overlay) = &lost_state.overlay {
+                        overlay.set_status(Some(&format!(
+                            "Lost the graphics device, retrying: {}",
+                            error
+                        )));
+                    }
+                    state = Some(*lost_state);
+                    retry_at = Some(Instant::now() + DEVICE_RETRY_INTERVAL);
+                }
+                Err(RecreateError::Load(error)) => {
+                    report_load_error(&error, "voxel-gi");
+                    // The error stays on the page on the web, natively there's nothing left to show
+                    #[cfg(not(target_arch = "wasm32"))]
+                    {
+                        *control_flow = ControlFlow::Exit;
+                    }
+                    return;
+                }
+            }
+        }
+        let current_state = match state.as_mut() {
+            Some(current_state) => current_state,
+            None => return,
+        };
+        if current_state.device_lost() {
+            if retry_at.is_some_and(|retry_at| Instant::now() < retry_at) {
+                return;
+            }
+            retry_at = None;
+            let lost_state = state.take().unwrap();
+            #[cfg(target_arch = "wasm32")]
+            {
+                let recreated = recreated.clone();
+                wasm_bindgen_futures::spawn_local(async move {
+                    *recreated.borrow_mut() = Some(lost_state.recreate().await);
+                });
+            }
+            #[cfg(not(target_arch = "wasm32"))]
+            {
+                recreation = Some(Box::pin(lost_state.recreate()));
+            }
+            return;
+        }
+        let state = current_state;
 
-    event_loop.run(move |event, _, control_flow| match event {
-        Event::WindowEvent {
-            window_id,
-            ref event,
-        } => {
-            if window_id == state.window.id() && !state.input(event) {
+        match event {
+            Event::WindowEvent {
+                window_id,
+                ref event,
+            } if state
+                .window()
+                .is_some_and(|window| window.id() == window_id)
+                && !state.input(event) =>
+            {
                 match event {
                     WindowEvent::KeyboardInput {
                         input:
                             KeyboardInput {
+                                state: ElementState::Pressed,
                                 virtual_keycode: Some(VirtualKeyCode::Escape),
                                 ..
                             },
                         ..
-                    } => *control_flow = ControlFlow::Exit,
+                    } if !state.release_pointer_lock() => {
+                        // Exiting the event loop on the web would leave a frozen canvas
+                        // behind, so Escape pauses there instead
+                        #[cfg(target_arch = "wasm32")]
+                        state.toggle_pause();
+                        #[cfg(not(target_arch = "wasm32"))]
+                        {
+                            *control_flow = ControlFlow::Exit;
+                        }
+                    }
                     WindowEvent::Resized(physical_size) => {
                         state.resize(*physical_size);
                     }
@@ -689,19 +4397,607 @@ pub async fn run() {
                     _ => {}
                 }
             }
+            Event::DeviceEvent { ref event, .. } => {
+                state.device_input(event);
+            }
+            Event::RedrawRequested(window_id)
+                if state
+                    .window()
+                    .is_some_and(|window| window.id() == window_id) =>
+            {
+                let now = Instant::now();
+                state.update((now - last_update).as_secs_f32());
+                last_update = now;
+                if let Err(error) = state.render() {
+                    if !state.recover_from_surface_error(error) {
+                        *control_flow = ControlFlow::Exit;
+                    }
+                }
+            }
+            Event::MainEventsCleared => {
+                if let Some(window) = state.window() {
+                    window.request_redraw();
+                }
+            }
+            _ => {}
+        }
+    })
+}
+
+/// Renders benchmark frames offscreen as fast as the GPU allows until the report is written.
+#[cfg(not(target_arch = "wasm32"))]
+async fn run_headless(options: RunOptions) {
+    use winit::dpi::PhysicalSize;
+    let mut state = match State::headless(PhysicalSize::new(1920, 1080), options.model_path).await {
+        Ok(state) => state,
+        Err(error) => {
+            report_load_error(&error, "voxel-gi");
+            return;
+        }
+    };
+    if state.benchmark.is_none() {
+        log::warn!("Nothing to render headless without bench=1");
+        return;
+    }
+    let mut last_update = Instant::now();
+    while state.benchmark.is_some() {
+        let now = Instant::now();
+        state.update((now - last_update).as_secs_f32());
+        last_update = now;
+        if let Err(error) = state.render() {
+            log::error!("Headless frame failed: {}", error);
+            return;
+        }
+        // Waiting for the frame keeps the measured frame time honest without a swapchain to
+        // throttle submissions
+        state.device.poll(wgpu::Maintain::Wait);
+        if state.device_lost() {
+            log::error!("Lost the graphics device during the headless benchmark");
+            return;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cgmath::SquareMatrix;
+
+    #[test]
+    fn frame_globals_match_wgsl() {
+        util::assert_wgsl_layout::<FrameGlobals>(&main_shader_source(false), "FrameGlobals");
+    }
+
+    #[test]
+    fn lighting_uniforms_match_wgsl() {
+        let source = main_shader_source(false);
+        util::assert_wgsl_layout::<Light>(&source, "Light");
+        util::assert_wgsl_layout::<LightsHeader>(&source, "Lights");
+        util::assert_wgsl_layout::<VoxelGridInfo>(&source, "VoxelGridInfo");
+    }
+
+    #[test]
+    fn render_uniforms_match_wgsl() {
+        let source = main_shader_source(false);
+        util::assert_wgsl_layout::<RenderSettings>(&source, "RenderSettings");
+        util::assert_wgsl_layout::<ConeTracingParams>(&source, "ConeTracingParams");
+    }
+
+    #[test]
+    fn cone_params_are_clamped_to_a_bounded_step_count() {
+        let params = ConeTracingParams {
+            cone_count: 1000,
+            step_size: 0.0,
+            max_distance: f32::INFINITY,
+            ..ConeTracingParams::default()
+        }
+        .clamped();
+        assert_eq!(params.cone_count, ConeTracingParams::MAX_CONES);
+        assert_eq!(params.max_distance, ConeTracingParams::MAX_DISTANCE);
+        assert!(params.max_distance / params.step_size <= ConeTracingParams::MAX_STEPS as f32);
+
+        let params = ConeTracingParams {
+            step_size: -1.0,
+            max_distance: -1.0,
+            ..ConeTracingParams::default()
+        }
+        .clamped();
+        assert_eq!(params.max_distance, 0.0);
+        assert_eq!(params.step_size, 0.01);
+
+        let default = ConeTracingParams::default();
+        assert_eq!(default.clamped(), default);
+    }
+
+    #[test]
+    fn brightest_spot_lights_get_the_biggest_shadow_tiles() {
+        let light = |brightness: f32, spot: bool| PointLightConfig {
+            position: [0.0; 3],
+            intensity: [0.0, brightness, 0.0],
+            falloff: 2.0,
+            spot: spot.then(|| scene_config::SpotConfig {
+                direction: [0.0, -1.0, 0.0],
+                inner_angle: 20.0,
+                outer_angle: 30.0,
+            }),
+            gi_intensity: None,
+            injection_radius: 0.0,
+        };
+        // Dimmest first, so config order alone would hand the big tiles to the wrong lights. The
+        // sun leaves room for 12 spot lights at full size and nothing else.
+        let mut lights: Vec<_> = (0..14).map(|i| light(i as f32 + 1.0, true)).collect();
+        lights.insert(3, light(100.0, false));
+        let config = SceneConfig {
+            lights,
+            ..SceneConfig::default()
+        };
+
+        let atlas = allocate_shadow_tiles(&config);
+
+        assert_eq!(
+            atlas.tile(0).map(|tile| tile.size),
+            Some(SUN_SHADOW_MAP_SIZE)
+        );
+        assert_eq!(atlas.tile(4), None, "Point lights cast no shadows");
+        let spot_slots: Vec<usize> = (1..=15).filter(|&slot| slot != 4).collect();
+        for (rank, &slot) in spot_slots.iter().rev().enumerate() {
+            let expected_size = (rank < 12).then_some(SPOT_SHADOW_MAP_SIZE);
+            assert_eq!(
+                atlas.tile(slot).map(|tile| tile.size),
+                expected_size,
+                "Slot {}",
+                slot
+            );
+        }
+
+        let tiles: Vec<ShadowTile> = (0..=15).filter_map(|slot| atlas.tile(slot)).collect();
+        for (i, a) in tiles.iter().enumerate() {
+            for b in &tiles[i + 1..] {
+                let overlap = a.x < b.x + b.size
+                    && b.x < a.x + a.size
+                    && a.y < b.y + b.size
+                    && b.y < a.y + a.size;
+                assert!(!overlap, "{:?} overlaps {:?}", a, b);
+            }
+        }
+    }
+
+    /// Writes a glTF file with a single quad facing +z at height `z`, spanning `-half_size` to
+    /// `half_size` on x and y, and returns its path.
+    fn write_quad_gltf(name: &str, z: f32, half_size: f32, base_color: [f32; 4]) -> String {
+        let positions = [[-1.0, -1.0], [1.0, -1.0], [1.0, 1.0], [-1.0, 1.0]]
+            .map(|[x, y]| [x * half_size, y * half_size, z]);
+        let normals = [[0.0f32, 0.0, 1.0]; 4];
+        let tex_coords = [[0.0f32, 1.0], [1.0, 1.0], [1.0, 0.0], [0.0, 0.0]];
+        let indices: [u16; 6] = [0, 1, 2, 0, 2, 3];
+        let mut bytes = bytemuck::cast_slice(&positions).to_vec();
+        bytes.extend_from_slice(bytemuck::cast_slice(&normals));
+        bytes.extend_from_slice(bytemuck::cast_slice(&tex_coords));
+        bytes.extend_from_slice(bytemuck::cast_slice(&indices));
+        let gltf = serde_json::json!({
+            "asset": { "version": "2.0" },
+            "scene": 0,
+            "scenes": [{ "nodes": [0] }],
+            "nodes": [{ "mesh": 0 }],
+            "meshes": [{
+                "primitives": [{
+                    "attributes": { "POSITION": 0, "NORMAL": 1, "TEXCOORD_0": 2 },
+                    "indices": 3,
+                    "material": 0,
+                }],
+            }],
+            "materials": [{
+                "pbrMetallicRoughness": { "baseColorFactor": base_color },
+                "emissiveFactor": [0.1, 0.2, 0.3],
+            }],
+            "buffers": [{
+                "byteLength": bytes.len(),
+                "uri": format!(
+                    "data:application/octet-stream;base64,{}",
+                    base64::encode(&bytes)
+                ),
+            }],
+            "bufferViews": [
+                { "buffer": 0, "byteOffset": 0, "byteLength": 48 },
+                { "buffer": 0, "byteOffset": 48, "byteLength": 48 },
+                { "buffer": 0, "byteOffset": 96, "byteLength": 32 },
+                { "buffer": 0, "byteOffset": 128, "byteLength": 12 },
+            ],
+            "accessors": [
+                {
+                    "bufferView": 0,
+                    "componentType": 5126,
+                    "count": 4,
+                    "type": "VEC3",
+                    "min": [-half_size, -half_size, z],
+                    "max": [half_size, half_size, z],
+                },
+                { "bufferView": 1, "componentType": 5126, "count": 4, "type": "VEC3" },
+                { "bufferView": 2, "componentType": 5126, "count": 4, "type": "VEC2" },
+                { "bufferView": 3, "componentType": 5123, "count": 6, "type": "SCALAR" },
+            ],
+        });
+        let path = std::env::temp_dir().join(name);
+        std::fs::write(&path, gltf.to_string()).unwrap();
+        path.to_str().unwrap().to_string()
+    }
+
+    /// Reads back a `size` by `size` depth texture to within a hundredth. The GL backend can't
+    /// copy depth textures to buffers or load from them, so a compute shader counts how many of
+    /// a hundred evenly spaced depths compare less than or equal instead.
+    fn read_depth(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        view: &wgpu::TextureView,
+        size: u32,
+    ) -> Vec<f32> {
+        let module = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Test depth copy shader module"),
+            source: wgpu::ShaderSource::Wgsl(
+                "@group(0) @binding(0)
+                var depth: texture_depth_2d;
+                @group(0) @binding(1)
+                var depth_sampler: sampler_comparison;
+                @group(0) @binding(2)
+                var<storage, read_write> output: array<f32>;
+
+                @compute @workgroup_size(1)
+                fn main(@builtin(global_invocation_id) id: vec3<u32>) {
+                    var size = textureDimensions(depth);
+                    var uv = (vec2<f32>(id.xy) + 0.5) / vec2<f32>(size);
+                    var passed = 0.0;
+                    for (var i = 0; i < 100; i++) {
+                        var reference = (f32(i) + 0.5) / 100.0;
+                        passed += textureSampleCompareLevel(depth, depth_sampler, uv, reference);
+                    }
+                    output[id.y * size.x + id.x] = passed / 100.0;
+                }"
+                .into(),
+            ),
+        });
+        let pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("Test depth copy pipeline"),
+            layout: None,
+            module: &module,
+            entry_point: "main",
+        });
+        let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Test depth copy buffer"),
+            size: (size * size * 4) as u64,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        });
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            compare: Some(wgpu::CompareFunction::LessEqual),
+            ..Default::default()
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Test depth copy bind group"),
+            layout: &pipeline.get_bind_group_layout(0),
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: buffer.as_entire_binding(),
+                },
+            ],
+        });
+        let mut encoder = device.create_command_encoder(&Default::default());
+        {
+            let mut compute_pass = encoder.begin_compute_pass(&Default::default());
+            compute_pass.set_pipeline(&pipeline);
+            compute_pass.set_bind_group(0, &bind_group, &[]);
+            compute_pass.dispatch_workgroups(size, size, 1);
         }
-        Event::RedrawRequested(window_id) if window_id == state.window.id() => {
-            state.update();
-            match state.render() {
-                Ok(_) => {}
-                Err(wgpu::SurfaceError::Lost) => state.resize(state.size),
-                Err(wgpu::SurfaceError::OutOfMemory) => *control_flow = ControlFlow::Exit,
-                Err(e) => eprintln!("{:?}", e),
+        queue.submit(Some(encoder.finish()));
+        let bytes = pollster::block_on(readback::read_buffer(
+            device,
+            queue,
+            &buffer,
+            0..buffer.size(),
+        ))
+        .unwrap();
+        bytemuck::cast_slice(&bytes).to_vec()
+    }
+
+    #[test]
+    fn shadow_tiles_only_cover_their_part_of_the_atlas() {
+        let Some((device, queue)) = util::test_device() else {
+            return;
+        };
+        const ATLAS_SIZE: u32 = 64;
+        // The quad covers the middle half of every tile, pixels 12 to 19 of 32
+        let path = write_quad_gltf("voxel_gi_shadow_tile_test.gltf", 0.0, 0.25, [1.0; 4]);
+        let mut scenes = pollster::block_on(gltf_loader::load_gltf(
+            &device,
+            &queue,
+            &path,
+            gltf_loader::LoadOptions::default(),
+            None,
+        ))
+        .unwrap();
+        let scene = &mut scenes[0];
+        let shader = create_shadow_shader(&device);
+        let camera_layout = create_shadow_camera_bind_group_layout(&device);
+        scene.generate_pipeline(
+            &device,
+            &shader,
+            "shadow",
+            &[&camera_layout],
+            &[],
+            Some(gltf_loader::default_depth_stencil(Texture::DEPTH_FORMAT)),
+            true,
+            Some(&is_opaque),
+        );
+
+        let atlas = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Test shadow atlas"),
+            size: wgpu::Extent3d {
+                width: ATLAS_SIZE,
+                height: ATLAS_SIZE,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: Texture::DEPTH_FORMAT,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+            view_formats: &[],
+        });
+        let atlas_view = atlas.create_view(&Default::default());
+        // Every tile sees the quad at a depth of its own
+        let tiles = [(0, 0, 0.2), (32, 0, 0.4), (0, 32, 0.6), (32, 32, 0.8)];
+        let camera_bind_groups: Vec<wgpu::BindGroup> = tiles
+            .iter()
+            .map(|&(_, _, depth)| {
+                let view_projection: [[f32; 4]; 4] =
+                    Matrix4::from_translation(Vector3::new(0.0, 0.0, depth)).into();
+                let buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                    label: Some("Test shadow camera buffer"),
+                    contents: bytemuck::cast_slice(&[view_projection]),
+                    usage: wgpu::BufferUsages::UNIFORM,
+                });
+                device.create_bind_group(&wgpu::BindGroupDescriptor {
+                    label: Some("Test shadow camera bind group"),
+                    layout: &camera_layout,
+                    entries: &[wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: buffer.as_entire_binding(),
+                    }],
+                })
+            })
+            .collect();
+
+        let mut encoder = device.create_command_encoder(&Default::default());
+        record_shadow_tiles(
+            &mut encoder,
+            scene,
+            &atlas_view,
+            tiles
+                .iter()
+                .zip(&camera_bind_groups)
+                .map(|(&(x, y, _), bind_group)| (ShadowTile { x, y, size: 32 }, bind_group)),
+        );
+        queue.submit(Some(encoder.finish()));
+
+        let depths = read_depth(&device, &queue, &atlas_view, ATLAS_SIZE);
+        for (x, y, depth) in tiles {
+            let depth_at = |dx: u32, dy: u32| depths[((y + dy) * ATLAS_SIZE + x + dx) as usize];
+            for (dx, dy) in [(12, 12), (16, 16), (19, 19)] {
+                assert!(
+                    (depth_at(dx, dy) - depth).abs() < 0.011,
+                    "Tile at ({}, {}) has a depth of {} at ({}, {}), expected {}",
+                    x,
+                    y,
+                    depth_at(dx, dy),
+                    dx,
+                    dy,
+                    depth
+                );
             }
+            for (dx, dy) in [(0, 0), (11, 16), (20, 16), (16, 31)] {
+                assert_eq!(
+                    depth_at(dx, dy),
+                    1.0,
+                    "Tile at ({}, {}) isn't cleared",
+                    x,
+                    y
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn voxelization_fills_the_cells_under_a_quad() {
+        // wgpu's GL backend binds 3D storage textures without their layers, every store lands in
+        // the first depth slice there
+        let slice = if util::test_adapter()
+            .is_some_and(|adapter| adapter.get_info().backend == wgpu::Backend::Gl)
+        {
+            0
+        } else {
+            16
+        };
+        let Some((device, queue)) = util::test_device() else {
+            return;
+        };
+        const RESOLUTION: u32 = 32;
+        // A 2 unit wide grid around the origin, 1/16 units per cell. The quad covers cells 12 to
+        // 19 on x and y, the pixel centers on its edges fall outside of it, and sits in slice 16.
+        let path = write_quad_gltf(
+            "voxel_gi_voxelization_test.gltf",
+            0.03,
+            0.25,
+            [0.5, 0.25, 1.0, 1.0],
+        );
+        let mut scenes = pollster::block_on(gltf_loader::load_gltf(
+            &device,
+            &queue,
+            &path,
+            gltf_loader::LoadOptions::default(),
+            None,
+        ))
+        .unwrap();
+        let scene = &mut scenes[0];
+
+        let shader = create_voxelizer_shader(&device);
+        let camera_layout = create_voxelization_camera_bind_group_layout(&device);
+        let texture_layout = create_voxelizer_texture_bind_group_layout(&device);
+        generate_voxelization_pipeline(&device, scene, &shader, &camera_layout, &texture_layout);
+
+        let voxel_texture = VoxelTexture::new(
+            &device,
+            wgpu::Extent3d {
+                width: RESOLUTION,
+                height: RESOLUTION,
+                depth_or_array_layers: RESOLUTION,
+            },
+            None,
+            "Test voxel texture",
+        );
+        let dummy_output =
+            Texture::create_target_texture(&device, RESOLUTION, RESOLUTION, "Test dummy target");
+        // Cleared to the far plane below, so the sun reaches the quad unshadowed
+        let shadow_depth_texture = Texture::create_depth_texture(
+            &device,
+            1,
+            1,
+            Texture::DEPTH_FORMAT,
+            Some(wgpu::CompareFunction::Less),
+            "Test shadow depth texture",
+        );
+
+        let uniform = |label, contents: &[u8]| {
+            device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some(label),
+                contents,
+                usage: wgpu::BufferUsages::UNIFORM,
+            })
+        };
+        let voxelization_camera = VoxelizationCamera::new(Point3::origin(), 1.0);
+        let camera_buffer = uniform(
+            "Test voxelization camera buffer",
+            bytemuck::cast_slice(&[voxelization_camera.get_uniform_data()]),
+        );
+        let identity: [[f32; 4]; 4] = Matrix4::identity().into();
+        let shadow_camera_buffer =
+            uniform("Test shadow camera buffer", bytemuck::cast_slice(&identity));
+        let voxel_grid_info_buffer = uniform(
+            "Test voxel grid info buffer",
+            bytemuck::bytes_of(&VoxelGridInfo {
+                center: [0.0; 3],
+                half_extent: 1.0,
+                resolution: RESOLUTION as f32,
+                ..bytemuck::Zeroable::zeroed()
+            }),
+        );
+        // Only the voxel grid info and the lights are read by the voxelization pass
+        let unused_buffer = uniform("Test unused buffer", &[0; 256]);
+        // The sun shines straight down -z onto the quad
+        let sun = Light {
+            position: [0.0, 0.0, -1.0, 0.0],
+            intensity: [1.0, 2.0, 0.5],
+            spot_direction: Light::NO_SPOT.0,
+            spot_cos_outer: Light::NO_SPOT.1,
+            spot_cos_inner: Light::NO_SPOT.2,
+            ..bytemuck::Zeroable::zeroed()
+        };
+        let lights_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Test lights buffer"),
+            contents: &Lights { lights: vec![sun] }.to_bytes(),
+            usage: wgpu::BufferUsages::STORAGE,
+        });
+
+        let camera_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Test voxelization camera bind group"),
+            layout: &camera_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: camera_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: shadow_camera_buffer.as_entire_binding(),
+                },
+            ],
+        });
+        let texture_bind_group = create_voxelizer_texture_bind_group(
+            &device,
+            &texture_layout,
+            &shadow_depth_texture,
+            &voxel_texture,
+            &LightingBuffers {
+                lights: &lights_buffer,
+                voxel_grid_info: &voxel_grid_info_buffer,
+                frame_globals: &unused_buffer,
+                render_settings: &unused_buffer,
+                cone_params: &unused_buffer,
+            },
+        );
+
+        let mut encoder = device.create_command_encoder(&Default::default());
+        encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Test shadow clear pass"),
+            color_attachments: &[],
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                view: &shadow_depth_texture.view,
+                depth_ops: Some(wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(1.0),
+                    store: true,
+                }),
+                stencil_ops: None,
+            }),
+        });
+        voxel_texture.clear(&mut encoder);
+        {
+            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Test voxelization render pass"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view: &dummy_output.view,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Load,
+                        store: false,
+                    },
+                })],
+                depth_stencil_attachment: None,
+            });
+            render_pass.set_bind_group(0, &camera_bind_group, &[]);
+            render_pass.set_bind_group(1, &texture_bind_group, &[]);
+            scene.draw_pipelines("voxelization", &mut render_pass);
         }
-        Event::MainEventsCleared => {
-            state.window.request_redraw();
+        queue.submit(Some(encoder.finish()));
+
+        let cells = pollster::block_on(voxel_texture.read_level(&device, &queue, 0)).unwrap();
+        let size = RESOLUTION as usize;
+        // Albedo times the sun's intensity plus the emission
+        let expected = [0.5 * 1.0 + 0.1, 0.25 * 2.0 + 0.2, 1.0 * 0.5 + 0.3, 1.0];
+        for (index, cell) in cells.iter().enumerate() {
+            let (x, y, z) = (index % size, index / size % size, index / (size * size));
+            let covered = (12..20).contains(&x) && (12..20).contains(&y) && z == slice;
+            if covered {
+                for (channel, expected) in cell.iter().zip(expected) {
+                    assert!(
+                        (channel - expected).abs() < 0.01,
+                        "Cell ({}, {}, {}) is {:?}, expected {:?}",
+                        x,
+                        y,
+                        z,
+                        cell,
+                        expected
+                    );
+                }
+            } else {
+                assert_eq!(*cell, [0.0; 4], "Cell ({}, {}, {}) isn't empty", x, y, z);
+            }
         }
-        _ => {}
-    })
+    }
 }
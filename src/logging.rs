@@ -0,0 +1,115 @@
+use std::sync::RwLock;
+
+use log::{LevelFilter, Log, Metadata, Record};
+
+/// Logger forwarding to the browser console (stderr natively), with a default level and per-module overrides.
+struct FilteredLogger {
+    filters: RwLock<Filters>,
+}
+
+struct Filters {
+    default: LevelFilter,
+    /// Module paths relative to the crate root, e.g. `gltf_loader`
+    modules: Vec<(String, LevelFilter)>,
+}
+
+impl Filters {
+    /// Parses `warn`, `gltf_loader=debug,texture=warn` or a mix of both. Unknown levels are
+    /// skipped with a warning.
+    fn parse(spec: &str) -> Self {
+        let mut filters = Filters {
+            default: LevelFilter::Warn,
+            modules: Vec::new(),
+        };
+        for directive in spec
+            .split(',')
+            .map(str::trim)
+            .filter(|part| !part.is_empty())
+        {
+            let (module, level) = match directive.split_once('=') {
+                Some((module, level)) => (Some(module.trim()), level.trim()),
+                None => (None, directive),
+            };
+            let level = match level.parse::<LevelFilter>() {
+                Ok(level) => level,
+                Err(_) => {
+                    log::warn!("Unknown log level \"{}\"", level);
+                    continue;
+                }
+            };
+            match module {
+                Some(module) => filters.modules.push((module.to_string(), level)),
+                None => filters.default = level,
+            }
+        }
+        filters
+    }
+
+    fn level_for(&self, target: &str) -> LevelFilter {
+        let module_path = target
+            .strip_prefix(concat!(env!("CARGO_CRATE_NAME"), "::"))
+            .unwrap_or(target);
+        // The longest matching module wins, so `post=warn,post::dof=debug` works as expected
+        self.modules
+            .iter()
+            .filter(|(module, _)| {
+                module_path == module
+                    || module_path
+                        .strip_prefix(module.as_str())
+                        .is_some_and(|rest| rest.starts_with("::"))
+            })
+            .max_by_key(|(module, _)| module.len())
+            .map_or(self.default, |(_, level)| *level)
+    }
+
+    fn max_level(&self) -> LevelFilter {
+        self.modules
+            .iter()
+            .map(|(_, level)| *level)
+            .fold(self.default, Ord::max)
+    }
+}
+
+impl Log for FilteredLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= self.filters.read().unwrap().level_for(metadata.target())
+    }
+
+    fn log(&self, record: &Record) {
+        if self.enabled(record.metadata()) {
+            #[cfg(target_arch = "wasm32")]
+            console_log::log(record);
+            #[cfg(not(target_arch = "wasm32"))]
+            eprintln!(
+                "{:<5} {}: {}",
+                record.level(),
+                record.target(),
+                record.args()
+            );
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+static LOGGER: FilteredLogger = FilteredLogger {
+    filters: RwLock::new(Filters {
+        default: LevelFilter::Warn,
+        modules: Vec::new(),
+    }),
+};
+
+/// Installs the logger, taking the filter from the `log` query parameter if present
+/// (`?log=debug` or `?log=warn,gltf_loader=debug`).
+pub fn init() {
+    log::set_logger(&LOGGER).expect("Couldn't initialize logger");
+    let spec = crate::util::query_param("log");
+    set_filter(spec.as_deref().unwrap_or("warn"));
+}
+
+/// Replaces the current filter, see `init` for the syntax.
+pub fn set_filter(spec: &str) {
+    let filters = Filters::parse(spec);
+    log::set_max_level(filters.max_level());
+    *LOGGER.filters.write().unwrap() = filters;
+}
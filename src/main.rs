@@ -0,0 +1,5 @@
+fn main() {
+    // On the web `run` is started from JavaScript by client/index.html
+    #[cfg(not(target_arch = "wasm32"))]
+    pollster::block_on(voxel_gi::run(None));
+}
@@ -0,0 +1,564 @@
+/// Output texels each workgroup covers along x and y, has to match `mip_2d.wgsl`
+const WORKGROUP_SIZE: u32 = 8;
+
+/// How the source texels under each texel of the next mip level are combined.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Reduction {
+    Average,
+    /// Conservative reduction for depth pyramids, each texel keeps the farthest depth under it
+    Max,
+    /// Average weighted by inverse luma, for the first level of a bloom chain where single very
+    /// bright pixels would otherwise flicker
+    KarisAverage,
+}
+
+impl Reduction {
+    fn shader_constant(self) -> u32 {
+        match self {
+            Reduction::Average => 0,
+            Reduction::Max => 1,
+            Reduction::KarisAverage => 2,
+        }
+    }
+}
+
+/// Name of `format` in WGSL storage texture declarations.
+fn storage_format_name(format: wgpu::TextureFormat) -> &'static str {
+    match format {
+        wgpu::TextureFormat::Rgba8Unorm => "rgba8unorm",
+        wgpu::TextureFormat::Rgba16Float => "rgba16float",
+        wgpu::TextureFormat::Rgba32Float => "rgba32float",
+        wgpu::TextureFormat::R32Float => "r32float",
+        wgpu::TextureFormat::Rg32Float => "rg32float",
+        _ => panic!("{:?} can't be written from a compute shader", format),
+    }
+}
+
+/// Compute downsampler building the mip chain of 2D textures, the 2D counterpart of
+/// `VoxelTexture::run_generate_mipmaps`. One downsampler is created per format and reduction,
+/// every texture it reduces gets a `MipChain2d` of its own.
+pub struct Downsampler2d {
+    pipeline: wgpu::ComputePipeline,
+    bind_group_layout: wgpu::BindGroupLayout,
+    format: wgpu::TextureFormat,
+    label: String,
+}
+
+impl Downsampler2d {
+    /// `format` has to be writable from compute shaders, sRGB formats aren't.
+    pub fn new(
+        device: &wgpu::Device,
+        format: wgpu::TextureFormat,
+        reduction: Reduction,
+        label: &str,
+    ) -> Self {
+        let module = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some(format!("{} shader module", label).as_str()),
+            source: wgpu::ShaderSource::Wgsl(
+                format!(
+                    "const REDUCTION: u32 = {}u;\n\
+                     @group(0) @binding(1)\n\
+                     var output_texture: texture_storage_2d<{}, write>;\n{}",
+                    reduction.shader_constant(),
+                    storage_format_name(format),
+                    include_str!("shaders/mip_2d.wgsl")
+                )
+                .into(),
+            ),
+        });
+
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some(format!("{} bind group layout", label).as_str()),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Texture {
+                        // Only read with textureLoad, so 32 bit float formats work too
+                        sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::StorageTexture {
+                        access: wgpu::StorageTextureAccess::WriteOnly,
+                        format,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                    },
+                    count: None,
+                },
+            ],
+        });
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some(format!("{} pipeline layout", label).as_str()),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some(format!("{} pipeline", label).as_str()),
+            layout: Some(&pipeline_layout),
+            module: &module,
+            entry_point: "comp_main",
+        });
+
+        Self {
+            pipeline,
+            bind_group_layout,
+            format,
+            label: label.to_string(),
+        }
+    }
+
+    /// Bind groups reducing every mip level of `texture` into the next one. The texture needs
+    /// `TEXTURE_BINDING` and `STORAGE_BINDING` usage and the format the downsampler was made for,
+    /// only its first array layer is reduced.
+    pub fn create_chain(&self, device: &wgpu::Device, texture: &wgpu::Texture) -> MipChain2d {
+        assert_eq!(
+            texture.format(),
+            self.format,
+            "{} can't reduce a {:?} texture",
+            self.label,
+            texture.format()
+        );
+
+        let views: Vec<wgpu::TextureView> = (0..texture.mip_level_count())
+            .map(|level| {
+                texture.create_view(&wgpu::TextureViewDescriptor {
+                    label: Some(format!("{} view mip #{}", self.label, level).as_str()),
+                    format: Some(self.format),
+                    dimension: Some(wgpu::TextureViewDimension::D2),
+                    aspect: wgpu::TextureAspect::All,
+                    base_mip_level: level,
+                    mip_level_count: Some(1),
+                    base_array_layer: 0,
+                    array_layer_count: Some(1),
+                })
+            })
+            .collect();
+
+        let levels = views
+            .windows(2)
+            .enumerate()
+            .map(|(i, pair)| {
+                let output_level = i as u32 + 1;
+                let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+                    label: Some(format!("{} bind group #{}", self.label, i).as_str()),
+                    layout: &self.bind_group_layout,
+                    entries: &[
+                        wgpu::BindGroupEntry {
+                            binding: 0,
+                            resource: wgpu::BindingResource::TextureView(&pair[0]),
+                        },
+                        wgpu::BindGroupEntry {
+                            binding: 1,
+                            resource: wgpu::BindingResource::TextureView(&pair[1]),
+                        },
+                    ],
+                });
+                let size = (
+                    (texture.width() >> output_level).max(1),
+                    (texture.height() >> output_level).max(1),
+                );
+                (bind_group, size)
+            })
+            .collect();
+
+        MipChain2d { levels }
+    }
+
+    /// Fills every level of `chain` from the one above it, level 0 has to be written already.
+    pub fn record(&self, encoder: &mut wgpu::CommandEncoder, chain: &MipChain2d) {
+        let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some(format!("{} compute pass", self.label).as_str()),
+        });
+        compute_pass.set_pipeline(&self.pipeline);
+        for (bind_group, (width, height)) in &chain.levels {
+            compute_pass.set_bind_group(0, bind_group, &[]);
+            compute_pass.dispatch_workgroups(
+                width.div_ceil(WORKGROUP_SIZE),
+                height.div_ceil(WORKGROUP_SIZE),
+                1,
+            );
+        }
+    }
+}
+
+/// Per level bind groups of one texture, see `Downsampler2d::create_chain`. Has to be recreated
+/// along with the texture.
+pub struct MipChain2d {
+    /// Bind group writing each level after the first, with the size of that level
+    levels: Vec<(wgpu::BindGroup, (u32, u32))>,
+}
+
+/// Builds mip chains with render passes instead of compute, for formats that can't be storage
+/// textures, like the sRGB material textures. Every level is a bilinear downsample of the one
+/// above it, so odd sized levels lose their last row or column unlike with `Downsampler2d`.
+pub struct MipBlitter {
+    pipeline: wgpu::RenderPipeline,
+    bind_group_layout: wgpu::BindGroupLayout,
+    sampler: wgpu::Sampler,
+    format: wgpu::TextureFormat,
+}
+
+impl MipBlitter {
+    pub fn new(device: &wgpu::Device, format: wgpu::TextureFormat) -> Self {
+        let module = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Mip blit shader module"),
+            source: wgpu::ShaderSource::Wgsl(
+                concat!(
+                    include_str!("shaders/fullscreen.wgsl"),
+                    include_str!("shaders/mip_blit.wgsl")
+                )
+                .into(),
+            ),
+        });
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Mip blit bind group layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+            ],
+        });
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Mip blit pipeline layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Mip blit pipeline"),
+            layout: Some(&pipeline_layout),
+            vertex: wgpu::VertexState {
+                module: &module,
+                entry_point: "vs_fullscreen",
+                buffers: &[],
+            },
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+            fragment: Some(wgpu::FragmentState {
+                module: &module,
+                entry_point: "fs_blit",
+                targets: &[Some(wgpu::ColorTargetState {
+                    format,
+                    blend: None,
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+            }),
+            multiview: None,
+        });
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("Mip blit sampler"),
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+
+        Self {
+            pipeline,
+            bind_group_layout,
+            sampler,
+            format,
+        }
+    }
+
+    /// Fills every level of `texture` after the first from the one above it. The texture needs
+    /// `TEXTURE_BINDING` and `RENDER_ATTACHMENT` usage and the format the blitter was made for.
+    pub fn record(
+        &self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        texture: &wgpu::Texture,
+    ) {
+        assert_eq!(
+            texture.format(),
+            self.format,
+            "Mip blitter can't downsample a {:?} texture",
+            texture.format()
+        );
+
+        let views: Vec<wgpu::TextureView> = (0..texture.mip_level_count())
+            .map(|level| {
+                texture.create_view(&wgpu::TextureViewDescriptor {
+                    label: Some(format!("Mip blit view mip #{}", level).as_str()),
+                    base_mip_level: level,
+                    mip_level_count: Some(1),
+                    ..Default::default()
+                })
+            })
+            .collect();
+
+        for pair in views.windows(2) {
+            let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+                label: Some("Mip blit bind group"),
+                layout: &self.bind_group_layout,
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: wgpu::BindingResource::TextureView(&pair[0]),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 1,
+                        resource: wgpu::BindingResource::Sampler(&self.sampler),
+                    },
+                ],
+            });
+            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Mip blit render pass"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view: &pair[1],
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT),
+                        store: true,
+                    },
+                })],
+                depth_stencil_attachment: None,
+            });
+            render_pass.set_pipeline(&self.pipeline);
+            render_pass.set_bind_group(0, &bind_group, &[]);
+            render_pass.draw(0..3, 0..1);
+        }
+    }
+}
+
+/// Levels of a full mip chain for a `width` by `height` texture, down to 1x1.
+pub fn full_mip_level_count(width: u32, height: u32) -> u32 {
+    u32::BITS - width.max(height).max(1).leading_zeros()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        readback,
+        util::{test_adapter, test_device},
+    };
+
+    type Texel = [f32; 4];
+
+    /// Builds the full mip chain of a `width` by `height` Rgba32Float texture holding `texels` and
+    /// reads back every level.
+    fn reduce_on_gpu(
+        reduction: Reduction,
+        width: u32,
+        height: u32,
+        texels: &[Texel],
+    ) -> Vec<Vec<Texel>> {
+        let Some((device, queue)) = test_device() else {
+            return Vec::new();
+        };
+        let format = wgpu::TextureFormat::Rgba32Float;
+        let size = wgpu::Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1,
+        };
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Mip test texture"),
+            size,
+            mip_level_count: full_mip_level_count(width, height),
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING
+                | wgpu::TextureUsages::STORAGE_BINDING
+                | wgpu::TextureUsages::COPY_SRC
+                | wgpu::TextureUsages::COPY_DST,
+            view_formats: &[],
+        });
+        queue.write_texture(
+            texture.as_image_copy(),
+            bytemuck::cast_slice(texels),
+            wgpu::ImageDataLayout {
+                offset: 0,
+                bytes_per_row: Some(width * 16),
+                rows_per_image: Some(height),
+            },
+            size,
+        );
+
+        let downsampler = Downsampler2d::new(&device, format, reduction, "Mip test");
+        let chain = downsampler.create_chain(&device, &texture);
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Mip test encoder"),
+        });
+        downsampler.record(&mut encoder, &chain);
+        queue.submit(std::iter::once(encoder.finish()));
+
+        (0..texture.mip_level_count())
+            .map(|level| {
+                let data = pollster::block_on(readback::read_texture(
+                    &device,
+                    &queue,
+                    &texture,
+                    level,
+                    0..1,
+                ))
+                .unwrap();
+                bytemuck::cast_slice(&data).to_vec()
+            })
+            .collect()
+    }
+
+    fn luma(texel: Texel) -> f32 {
+        0.2126 * texel[0] + 0.7152 * texel[1] + 0.0722 * texel[2]
+    }
+
+    /// What `mip_2d.wgsl` should produce for the level after a `width` by `height` one.
+    fn reduce_on_cpu(reduction: Reduction, width: u32, height: u32, input: &[Texel]) -> Vec<Texel> {
+        let (output_width, output_height) = ((width / 2).max(1), (height / 2).max(1));
+        let footprint = |position: u32, input_size: u32, output_size: u32| {
+            if input_size % 2 == 1 && input_size > 1 && position == output_size - 1 {
+                3
+            } else {
+                2
+            }
+        };
+        let mut output = Vec::new();
+        for y in 0..output_height {
+            for x in 0..output_width {
+                let mut sum = [0.0; 4];
+                let mut total_weight = 0.0;
+                let mut maximum = [f32::MIN; 4];
+                for source_y in 2 * y..2 * y + footprint(y, height, output_height) {
+                    for source_x in 2 * x..2 * x + footprint(x, width, output_width) {
+                        let texel = input
+                            [(source_y.min(height - 1) * width + source_x.min(width - 1)) as usize];
+                        let weight = match reduction {
+                            Reduction::KarisAverage => 1.0 / (1.0 + luma(texel)),
+                            _ => 1.0,
+                        };
+                        for channel in 0..4 {
+                            sum[channel] += texel[channel] * weight;
+                            maximum[channel] = maximum[channel].max(texel[channel]);
+                        }
+                        total_weight += weight;
+                    }
+                }
+                output.push(match reduction {
+                    Reduction::Max => maximum,
+                    _ => sum.map(|channel| channel / total_weight),
+                });
+            }
+        }
+        output
+    }
+
+    fn assert_close(actual: &[Texel], expected: &[Texel], what: &str) {
+        assert_eq!(actual.len(), expected.len(), "{}", what);
+        for (index, (actual, expected)) in actual.iter().zip(expected).enumerate() {
+            for channel in 0..4 {
+                assert!(
+                    (actual[channel] - expected[channel]).abs() < 1e-4,
+                    "{} texel {}: {:?} instead of {:?}",
+                    what,
+                    index,
+                    actual,
+                    expected
+                );
+            }
+        }
+    }
+
+    /// Reduces textures with even, odd and single texel wide sides and compares every level with
+    /// the reduction done on the CPU. wgpu's GL backend ignores the mip range of sampled views,
+    /// every reduction after the first reads level 0 there, so only the first level is checked.
+    fn check_reduction(reduction: Reduction) {
+        let whole_chain = test_adapter()
+            .is_some_and(|adapter| adapter.get_info().backend != wgpu::Backend::Gl);
+        for (width, height) in [(4, 4), (5, 3), (7, 7), (6, 1)] {
+            let texels: Vec<Texel> = (0..width * height)
+                .map(|index| {
+                    let value = (index * 37 % 17) as f32 / 4.0;
+                    [value, 4.0 - value, value * 0.5, 1.0]
+                })
+                .collect();
+            let levels = reduce_on_gpu(reduction, width, height, &texels);
+            if levels.is_empty() {
+                return;
+            }
+            assert_close(&levels[0], &texels, "Level 0");
+
+            let (mut level_width, mut level_height) = (width, height);
+            let checked_levels = if whole_chain { levels.len() } else { 2 };
+            for (level, pair) in levels[..checked_levels].windows(2).enumerate() {
+                let expected = reduce_on_cpu(reduction, level_width, level_height, &pair[0]);
+                assert_close(
+                    &pair[1],
+                    &expected,
+                    &format!(
+                        "{:?} of {}x{}, level {}",
+                        reduction,
+                        width,
+                        height,
+                        level + 1
+                    ),
+                );
+                level_width = (level_width / 2).max(1);
+                level_height = (level_height / 2).max(1);
+            }
+        }
+    }
+
+    #[test]
+    fn average_reduction() {
+        check_reduction(Reduction::Average);
+        // The last column of an odd sized level goes into the last texel of the next one
+        let texels = [[1.0; 4], [2.0; 4], [6.0; 4]];
+        let levels = reduce_on_gpu(Reduction::Average, 3, 1, &texels);
+        if !levels.is_empty() {
+            assert_close(&levels[1], &[[3.0; 4]], "Average of 3x1");
+        }
+    }
+
+    #[test]
+    fn max_reduction() {
+        check_reduction(Reduction::Max);
+        let texels = [[1.0; 4], [7.0; 4], [2.0; 4], [3.0; 4], [4.0; 4], [9.0; 4]];
+        let levels = reduce_on_gpu(Reduction::Max, 3, 2, &texels);
+        if !levels.is_empty() {
+            assert_close(&levels[1], &[[9.0; 4]], "Max of 3x2");
+        }
+    }
+
+    #[test]
+    fn karis_average_reduction() {
+        check_reduction(Reduction::KarisAverage);
+        // A single bright texel gets a quarter of the weight of the three black ones
+        let texels = [
+            [0.0, 0.0, 0.0, 1.0],
+            [0.0, 0.0, 0.0, 1.0],
+            [0.0, 0.0, 0.0, 1.0],
+            [3.0, 3.0, 3.0, 1.0],
+        ];
+        let levels = reduce_on_gpu(Reduction::KarisAverage, 2, 2, &texels);
+        if !levels.is_empty() {
+            let bright = 0.75 / 3.25;
+            assert_close(
+                &levels[1],
+                &[[bright, bright, bright, 1.0]],
+                "Karis average of 2x2",
+            );
+        }
+    }
+}
@@ -0,0 +1,177 @@
+use crate::{
+    gltf_loader::{PrimitiveRenderData, Scene},
+    shader::Shader,
+    texture::Texture,
+};
+
+const MASK_PIPELINE: &str = "outline_mask";
+const OUTLINE_PIPELINE: &str = "outline";
+const SELECTION_STENCIL_REFERENCE: u32 = 1;
+
+/// Draws an outline around the primitives of the selected mesh. The selection is first written
+/// to the stencil buffer, then a slightly expanded copy is drawn wherever the stencil is unset.
+/// Pipelines only exist while something is selected, so no selection costs nothing.
+pub struct Outline {
+    mask_shader: Shader,
+    outline_shader: Shader,
+    depth_format: wgpu::TextureFormat,
+    selected_mesh: Option<String>,
+}
+
+impl Outline {
+    pub fn new(device: &wgpu::Device, depth_format: wgpu::TextureFormat) -> Self {
+        let create_shader = |vs_entry: &str, fs_entry: &str| Shader {
+            vs_entry: vs_entry.to_string(),
+            fs_entry: fs_entry.to_string(),
+            fs_mask_entry: None,
+            fs_blend_entry: None,
+            module: device.create_shader_module(wgpu::ShaderModuleDescriptor {
+                label: Some("Outline shader module"),
+                source: wgpu::ShaderSource::Wgsl(include_str!("shaders/outline.wgsl").into()),
+            }),
+        };
+        Self {
+            mask_shader: create_shader("vs_mask", "fs_mask"),
+            outline_shader: create_shader("vs_outline", "fs_outline"),
+            depth_format,
+            selected_mesh: None,
+        }
+    }
+
+    pub fn selected_mesh(&self) -> Option<&str> {
+        self.selected_mesh.as_deref()
+    }
+
+    /// Selects every primitive of the mesh called `mesh_name`, `None` clears the selection.
+    /// `camera_bind_group_layout` is bound to group 0, it has to start with the view projection.
+    pub fn select(
+        &mut self,
+        device: &wgpu::Device,
+        scene: &mut Scene,
+        camera_bind_group_layout: &wgpu::BindGroupLayout,
+        mesh_name: Option<String>,
+    ) {
+        scene.remove_pipeline(MASK_PIPELINE);
+        scene.remove_pipeline(OUTLINE_PIPELINE);
+        self.selected_mesh = None;
+
+        let Some(mesh_name) = mesh_name else {
+            return;
+        };
+        if !self.depth_format.has_stencil_aspect() {
+            log::warn!(
+                "Can't outline \"{}\", the depth format {:?} has no stencil",
+                mesh_name,
+                self.depth_format
+            );
+            return;
+        }
+        let is_selected = |primitive: &PrimitiveRenderData| {
+            primitive.mesh_name.as_deref() == Some(mesh_name.as_str())
+        };
+        if !scene.render_datas.iter().any(is_selected) {
+            log::warn!("No mesh named \"{}\" to select", mesh_name);
+            return;
+        }
+
+        let stencil_state = |compare, pass_op, write_mask| {
+            let face = wgpu::StencilFaceState {
+                compare,
+                fail_op: wgpu::StencilOperation::Keep,
+                depth_fail_op: wgpu::StencilOperation::Keep,
+                pass_op,
+            };
+            Some(wgpu::DepthStencilState {
+                format: self.depth_format,
+                // Outline the whole object even where it's occluded
+                depth_write_enabled: false,
+                depth_compare: wgpu::CompareFunction::Always,
+                stencil: wgpu::StencilState {
+                    front: face,
+                    back: face,
+                    read_mask: !0,
+                    write_mask,
+                },
+                bias: wgpu::DepthBiasState::default(),
+            })
+        };
+
+        scene.generate_pipeline(
+            device,
+            &self.mask_shader,
+            MASK_PIPELINE,
+            &[camera_bind_group_layout],
+            &[Some(wgpu::ColorTargetState {
+                format: Texture::HDR_FORMAT,
+                blend: None,
+                write_mask: wgpu::ColorWrites::empty(),
+            })],
+            stencil_state(
+                wgpu::CompareFunction::Always,
+                wgpu::StencilOperation::Replace,
+                !0,
+            ),
+            false,
+            Some(&is_selected),
+        );
+        scene.generate_pipeline(
+            device,
+            &self.outline_shader,
+            OUTLINE_PIPELINE,
+            &[camera_bind_group_layout],
+            &[Some(wgpu::ColorTargetState {
+                format: Texture::HDR_FORMAT,
+                blend: Some(wgpu::BlendState::REPLACE),
+                write_mask: wgpu::ColorWrites::ALL,
+            })],
+            stencil_state(
+                wgpu::CompareFunction::NotEqual,
+                wgpu::StencilOperation::Keep,
+                0,
+            ),
+            false,
+            Some(&is_selected),
+        );
+        self.selected_mesh = Some(mesh_name);
+    }
+
+    pub fn record(
+        &self,
+        scene: &Scene,
+        camera_bind_group: &wgpu::BindGroup,
+        color_view: &wgpu::TextureView,
+        depth_view: &wgpu::TextureView,
+        encoder: &mut wgpu::CommandEncoder,
+    ) {
+        if self.selected_mesh.is_none() {
+            return;
+        }
+
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Outline render pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: color_view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: true,
+                },
+            })],
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                view: depth_view,
+                depth_ops: Some(wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: true,
+                }),
+                stencil_ops: Some(wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(0),
+                    store: false,
+                }),
+            }),
+        });
+        render_pass.set_bind_group(0, camera_bind_group, &[]);
+        render_pass.set_stencil_reference(SELECTION_STENCIL_REFERENCE);
+        scene.draw_pipelines(MASK_PIPELINE, &mut render_pass);
+        scene.draw_pipelines(OUTLINE_PIPELINE, &mut render_pass);
+    }
+}
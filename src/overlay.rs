@@ -0,0 +1,125 @@
+use std::time::Duration;
+
+use instant::Instant;
+
+const FLASH_DURATION: Duration = Duration::from_millis(1500);
+
+const PANEL_STYLE: &str = "position: absolute; margin: 0; padding: 8px 12px; \
+    background: rgba(0, 0, 0, 0.6); color: white; font: 14px monospace; pointer-events: none;";
+
+/// Text drawn on top of the canvas as plain DOM elements, used for the key binding help, for
+/// briefly showing the name of a mode after it changes, for persistent status messages and for
+/// the CPU frame time breakdown.
+pub struct Overlay {
+    help: web_sys::Element,
+    flash: web_sys::Element,
+    status: web_sys::Element,
+    timings: web_sys::Element,
+    help_visible: bool,
+    flash_until: Option<Instant>,
+}
+
+impl Overlay {
+    /// Returns `None` if the page has no element with the given id to attach to, and always
+    /// natively where there is no DOM.
+    pub fn new(parent_id: &str) -> Option<Self> {
+        if cfg!(not(target_arch = "wasm32")) {
+            return None;
+        }
+        let document = web_sys::window()?.document()?;
+        let parent = document.get_element_by_id(parent_id)?;
+
+        let help = document.create_element("pre").ok()?;
+        help.set_attribute("style", &format!("{} top: 8px; left: 8px;", PANEL_STYLE))
+            .ok()?;
+        let flash = document.create_element("div").ok()?;
+        flash
+            .set_attribute("style", &format!("{} top: 8px; right: 8px;", PANEL_STYLE))
+            .ok()?;
+        let status = document.create_element("div").ok()?;
+        status
+            .set_attribute(
+                "style",
+                &format!(
+                    "{} bottom: 8px; left: 50%; transform: translateX(-50%);",
+                    PANEL_STYLE
+                ),
+            )
+            .ok()?;
+        let timings = document.create_element("pre").ok()?;
+        timings
+            .set_attribute(
+                "style",
+                &format!("{} bottom: 8px; right: 8px;", PANEL_STYLE),
+            )
+            .ok()?;
+        parent.append_child(&help).ok()?;
+        parent.append_child(&flash).ok()?;
+        parent.append_child(&status).ok()?;
+        parent.append_child(&timings).ok()?;
+
+        let overlay = Self {
+            help,
+            flash,
+            status,
+            timings,
+            help_visible: false,
+            flash_until: None,
+        };
+        Self::set_visible(&overlay.help, false);
+        Self::set_visible(&overlay.flash, false);
+        Self::set_visible(&overlay.status, false);
+        Self::set_visible(&overlay.timings, false);
+        Some(overlay)
+    }
+
+    pub fn help_visible(&self) -> bool {
+        self.help_visible
+    }
+
+    pub fn toggle_help(&mut self) {
+        self.help_visible = !self.help_visible;
+        Self::set_visible(&self.help, self.help_visible);
+    }
+
+    pub fn set_help_text(&self, text: &str) {
+        self.help.set_text_content(Some(text));
+    }
+
+    /// Shows `message` for a short while, replacing the previous one.
+    pub fn flash(&mut self, message: &str) {
+        self.flash.set_text_content(Some(message));
+        Self::set_visible(&self.flash, true);
+        self.flash_until = Some(Instant::now() + FLASH_DURATION);
+    }
+
+    /// Shows `message` until it's cleared with `None`.
+    pub fn set_status(&self, message: Option<&str>) {
+        self.status.set_text_content(message);
+        Self::set_visible(&self.status, message.is_some());
+    }
+
+    /// Shows the CPU frame time breakdown until it's cleared with `None`.
+    #[cfg_attr(not(feature = "cpu-profiling"), allow(dead_code))]
+    pub fn set_timings(&self, text: Option<&str>) {
+        self.timings.set_text_content(text);
+        Self::set_visible(&self.timings, text.is_some());
+    }
+
+    pub fn update(&mut self) {
+        if let Some(flash_until) = self.flash_until {
+            if Instant::now() >= flash_until {
+                Self::set_visible(&self.flash, false);
+                self.flash_until = None;
+            }
+        }
+    }
+
+    fn set_visible(element: &web_sys::Element, visible: bool) {
+        if visible {
+            let _ = element.remove_attribute("hidden");
+        } else {
+            let _ = element.set_attribute("hidden", "");
+        }
+    }
+}
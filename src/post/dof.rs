@@ -0,0 +1,485 @@
+use cgmath::{Matrix4, SquareMatrix};
+use wgpu::util::DeviceExt;
+
+use super::{
+    create_fullscreen_pipeline, draw_fullscreen, sampler_entry, texture_entry, uniform_entry,
+    PostContext, PostEffect, PostFrame, PostSettings,
+};
+use crate::{readback::PendingReadback, texture::Texture};
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct DofParams {
+    inv_projection: [[f32; 4]; 4],
+    focus_distance: f32,
+    aperture: f32,
+    max_radius: f32,
+    filler: u32,
+}
+
+crate::assert_uniform_layout!(DofParams, size: 80, {
+    inv_projection: 0,
+    focus_distance: 64,
+    aperture: 68,
+    max_radius: 72,
+    filler: 76,
+});
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct FocusPick {
+    inv_projection: [[f32; 4]; 4],
+    uv: [f32; 2],
+    filler: [u32; 2],
+}
+
+crate::assert_uniform_layout!(FocusPick, size: 80, {
+    inv_projection: 0,
+    uv: 64,
+    filler: 72,
+});
+
+fn create_shader_module(device: &wgpu::Device) -> wgpu::ShaderModule {
+    device.create_shader_module(wgpu::ShaderModuleDescriptor {
+        label: Some("Depth of field shader module"),
+        source: wgpu::ShaderSource::Wgsl(
+            concat!(
+                include_str!("../shaders/common.wgsl"),
+                include_str!("../shaders/fullscreen.wgsl"),
+                include_str!("../shaders/dof.wgsl")
+            )
+            .into(),
+        ),
+    })
+}
+
+/// Looks up the distance of whatever is under a pixel on the GPU and reads it back, so a click
+/// can set `PostSettings::dof_focus_distance` once instead of the focus following the pixel.
+pub struct FocusPicker {
+    pipeline: wgpu::ComputePipeline,
+    bind_group_layout: wgpu::BindGroupLayout,
+    pick_buffer: wgpu::Buffer,
+    distance_buffer: wgpu::Buffer,
+    pending: Option<PendingReadback>,
+}
+
+impl FocusPicker {
+    pub fn new(device: &wgpu::Device) -> Self {
+        let module = create_shader_module(device);
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Focus pick bind group layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Depth,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 5,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 6,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Storage { read_only: false },
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+        });
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Focus pick pipeline layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("Focus pick pipeline"),
+            layout: Some(&pipeline_layout),
+            module: &module,
+            entry_point: "cs_pick_focus",
+        });
+        let pick_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Focus pick buffer"),
+            size: std::mem::size_of::<FocusPick>() as u64,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let distance_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Picked focus distance buffer"),
+            size: 4,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        });
+        Self {
+            pipeline,
+            bind_group_layout,
+            pick_buffer,
+            distance_buffer,
+            pending: None,
+        }
+    }
+
+    /// Starts looking up the distance at `uv` in `depth`, a depth buffer rendered with
+    /// `projection`. A pick that is still pending is replaced.
+    pub fn pick(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        depth: &wgpu::TextureView,
+        projection: Matrix4<f32>,
+        uv: [f32; 2],
+    ) {
+        queue.write_buffer(
+            &self.pick_buffer,
+            0,
+            bytemuck::cast_slice(&[FocusPick {
+                inv_projection: projection.invert().unwrap_or(Matrix4::identity()).into(),
+                uv,
+                filler: [0; 2],
+            }]),
+        );
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Focus pick bind group"),
+            layout: &self.bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::TextureView(depth),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 5,
+                    resource: self.pick_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 6,
+                    resource: self.distance_buffer.as_entire_binding(),
+                },
+            ],
+        });
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Focus pick encoder"),
+        });
+        {
+            let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                label: Some("Focus pick pass"),
+            });
+            compute_pass.set_pipeline(&self.pipeline);
+            compute_pass.set_bind_group(0, &bind_group, &[]);
+            compute_pass.dispatch_workgroups(1, 1, 1);
+        }
+        queue.submit(std::iter::once(encoder.finish()));
+        self.pending = Some(PendingReadback::buffer(
+            device,
+            queue,
+            &self.distance_buffer,
+            0..4,
+        ));
+    }
+
+    /// The picked distance once it's back on the CPU, call it once a frame.
+    pub fn try_finish(&mut self) -> Option<f32> {
+        let result = self.pending.as_mut()?.try_finish()?;
+        self.pending = None;
+        match result {
+            Ok(bytes) => Some(bytemuck::pod_read_unaligned(&bytes)),
+            Err(error) => {
+                log::warn!("Couldn't read back the focus distance: {}", error);
+                None
+            }
+        }
+    }
+
+    pub fn is_pending(&self) -> bool {
+        self.pending.is_some()
+    }
+}
+
+/// Half resolution depth of field: circle of confusion from the depth buffer, a gather blur and a
+/// full resolution composite.
+pub struct DepthOfFieldPass {
+    coc_pipeline: wgpu::RenderPipeline,
+    blur_pipeline: wgpu::RenderPipeline,
+    composite_pipeline: wgpu::RenderPipeline,
+    coc_bind_group_layout: wgpu::BindGroupLayout,
+    blur_bind_group_layout: wgpu::BindGroupLayout,
+    composite_bind_group_layout: wgpu::BindGroupLayout,
+    params_buffer: wgpu::Buffer,
+    sampler: wgpu::Sampler,
+    coc_texture: Texture,
+    blur_texture: Texture,
+}
+
+impl DepthOfFieldPass {
+    pub const NAME: &'static str = "depth_of_field";
+
+    pub fn new(device: &wgpu::Device, width: u32, height: u32) -> Self {
+        let module = create_shader_module(device);
+
+        let coc_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                label: Some("Depth of field CoC bind group layout"),
+                entries: &[
+                    texture_entry(0, wgpu::TextureSampleType::Float { filterable: true }),
+                    sampler_entry(1),
+                    texture_entry(2, wgpu::TextureSampleType::Depth),
+                    uniform_entry(3),
+                ],
+            });
+
+        let blur_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                label: Some("Depth of field blur bind group layout"),
+                entries: &[
+                    texture_entry(0, wgpu::TextureSampleType::Float { filterable: true }),
+                    sampler_entry(1),
+                    uniform_entry(3),
+                ],
+            });
+
+        let composite_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                label: Some("Depth of field composite bind group layout"),
+                entries: &[
+                    texture_entry(0, wgpu::TextureSampleType::Float { filterable: true }),
+                    sampler_entry(1),
+                    uniform_entry(3),
+                    texture_entry(4, wgpu::TextureSampleType::Float { filterable: true }),
+                ],
+            });
+
+        let coc_pipeline = create_fullscreen_pipeline(
+            device,
+            "Depth of field CoC",
+            &module,
+            "fs_coc",
+            &coc_bind_group_layout,
+            Texture::HDR_FORMAT,
+        );
+        let blur_pipeline = create_fullscreen_pipeline(
+            device,
+            "Depth of field blur",
+            &module,
+            "fs_blur",
+            &blur_bind_group_layout,
+            Texture::HDR_FORMAT,
+        );
+        let composite_pipeline = create_fullscreen_pipeline(
+            device,
+            "Depth of field composite",
+            &module,
+            "fs_composite",
+            &composite_bind_group_layout,
+            Texture::HDR_FORMAT,
+        );
+
+        let params_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Depth of field params buffer"),
+            contents: bytemuck::cast_slice(&[DofParams {
+                inv_projection: Matrix4::identity().into(),
+                focus_distance: 0.0,
+                aperture: 0.0,
+                max_radius: 0.0,
+                filler: 0,
+            }]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("Depth of field sampler"),
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+
+        let (coc_texture, blur_texture) = Self::create_half_res_textures(device, width, height);
+
+        Self {
+            coc_pipeline,
+            blur_pipeline,
+            composite_pipeline,
+            coc_bind_group_layout,
+            blur_bind_group_layout,
+            composite_bind_group_layout,
+            params_buffer,
+            sampler,
+            coc_texture,
+            blur_texture,
+        }
+    }
+
+    fn create_half_res_textures(
+        device: &wgpu::Device,
+        width: u32,
+        height: u32,
+    ) -> (Texture, Texture) {
+        let width = (width / 2).max(1);
+        let height = (height / 2).max(1);
+        (
+            Texture::create_hdr_target_texture(device, width, height, "Depth of field CoC texture"),
+            Texture::create_hdr_target_texture(
+                device,
+                width,
+                height,
+                "Depth of field blur texture",
+            ),
+        )
+    }
+}
+
+impl PostEffect for DepthOfFieldPass {
+    fn name(&self) -> &'static str {
+        Self::NAME
+    }
+
+    fn resize(&mut self, device: &wgpu::Device, width: u32, height: u32) {
+        (self.coc_texture, self.blur_texture) =
+            Self::create_half_res_textures(device, width, height);
+    }
+
+    fn update(&mut self, queue: &wgpu::Queue, settings: &PostSettings, frame: &PostFrame) -> bool {
+        queue.write_buffer(
+            &self.params_buffer,
+            0,
+            bytemuck::cast_slice(&[DofParams {
+                inv_projection: frame
+                    .projection
+                    .invert()
+                    .unwrap_or(Matrix4::identity())
+                    .into(),
+                focus_distance: settings.dof_focus_distance,
+                aperture: settings.dof_aperture,
+                max_radius: settings.dof_max_radius,
+                filler: 0,
+            }]),
+        );
+        true
+    }
+
+    fn record(
+        &self,
+        context: &PostContext,
+        encoder: &mut wgpu::CommandEncoder,
+        input: &wgpu::TextureView,
+        output: &wgpu::TextureView,
+    ) {
+        let device = context.device;
+        let coc_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Depth of field CoC bind group"),
+            layout: &self.coc_bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(input),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&self.sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::TextureView(context.depth),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: self.params_buffer.as_entire_binding(),
+                },
+            ],
+        });
+        draw_fullscreen(
+            encoder,
+            "Depth of field CoC render pass",
+            &self.coc_pipeline,
+            &coc_bind_group,
+            &self.coc_texture.view,
+        );
+
+        let blur_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Depth of field blur bind group"),
+            layout: &self.blur_bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&self.coc_texture.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&self.sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: self.params_buffer.as_entire_binding(),
+                },
+            ],
+        });
+        draw_fullscreen(
+            encoder,
+            "Depth of field blur render pass",
+            &self.blur_pipeline,
+            &blur_bind_group,
+            &self.blur_texture.view,
+        );
+
+        let composite_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Depth of field composite bind group"),
+            layout: &self.composite_bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(input),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&self.sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: self.params_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 4,
+                    resource: wgpu::BindingResource::TextureView(&self.blur_texture.view),
+                },
+            ],
+        });
+        draw_fullscreen(
+            encoder,
+            "Depth of field composite render pass",
+            &self.composite_pipeline,
+            &composite_bind_group,
+            output,
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SOURCE: &str = concat!(
+        include_str!("../shaders/common.wgsl"),
+        include_str!("../shaders/fullscreen.wgsl"),
+        include_str!("../shaders/dof.wgsl")
+    );
+
+    #[test]
+    fn params_match_wgsl() {
+        crate::util::assert_wgsl_layout::<DofParams>(SOURCE, "DofParams");
+        crate::util::assert_wgsl_layout::<FocusPick>(SOURCE, "FocusPick");
+    }
+}
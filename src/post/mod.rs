@@ -0,0 +1,318 @@
+mod dof;
+mod motion_blur;
+mod tonemap;
+
+use cgmath::Matrix4;
+
+use crate::texture::Texture;
+
+pub use dof::{DepthOfFieldPass, FocusPicker};
+pub use motion_blur::MotionBlurPass;
+pub use tonemap::TonemapPass;
+
+pub struct PostEffectConfig {
+    pub name: String,
+    pub enabled: bool,
+}
+
+pub struct PostSettings {
+    /// Effects run in this order, effects that aren't listed are skipped
+    pub effects: Vec<PostEffectConfig>,
+    pub motion_blur_strength: f32,
+    /// Longest blur allowed, in pixels
+    pub motion_blur_max_radius: f32,
+    pub motion_blur_max_samples: u32,
+    /// Distance of the focus plane along the view direction, middle clicking sets it to the
+    /// distance of whatever is under the cursor
+    pub dof_focus_distance: f32,
+    pub dof_aperture: f32,
+    /// Largest circle of confusion, in half resolution pixels
+    pub dof_max_radius: f32,
+    /// Dithers the final image to hide banding, the vignette and grain below are off by default
+    /// so reference images stay comparable
+    pub dither: bool,
+    pub vignette: bool,
+    /// How much the corners are darkened, 1 makes them black
+    pub vignette_strength: f32,
+    pub grain: bool,
+    /// Relative strength of the film grain noise
+    pub grain_strength: f32,
+    /// Luminance of diffuse white on HDR displays in nits, the rest up to the display's peak
+    /// is left for highlights. Unused for SDR output.
+    pub paper_white_nits: f32,
+    /// Multiplies the scene before tonemapping
+    pub exposure: f32,
+}
+
+impl Default for PostSettings {
+    fn default() -> Self {
+        Self {
+            effects: vec![
+                PostEffectConfig {
+                    name: MotionBlurPass::NAME.to_string(),
+                    enabled: true,
+                },
+                PostEffectConfig {
+                    name: DepthOfFieldPass::NAME.to_string(),
+                    enabled: false,
+                },
+            ],
+            motion_blur_strength: 0.5,
+            motion_blur_max_radius: 32.0,
+            motion_blur_max_samples: 16,
+            dof_focus_distance: 5.0,
+            dof_aperture: 8.0,
+            dof_max_radius: 8.0,
+            dither: true,
+            vignette: false,
+            vignette_strength: 0.4,
+            grain: false,
+            grain_strength: 0.1,
+            // BT.2408 reference white
+            paper_white_nits: 203.0,
+            exposure: 1.0,
+        }
+    }
+}
+
+impl PostSettings {
+    pub fn is_effect_enabled(&self, name: &str) -> bool {
+        self.effects
+            .iter()
+            .any(|effect| effect.name == name && effect.enabled)
+    }
+
+    pub fn toggle_effect(&mut self, name: &str) {
+        if let Some(effect) = self.effects.iter_mut().find(|effect| effect.name == name) {
+            effect.enabled = !effect.enabled;
+        }
+    }
+}
+
+/// Camera data the effects need, gathered once per frame.
+pub struct PostFrame {
+    pub view_projection: Matrix4<f32>,
+    pub projection: Matrix4<f32>,
+}
+
+/// Resources owned by the renderer that effects may read while recording.
+pub struct PostContext<'a> {
+    pub device: &'a wgpu::Device,
+    pub depth: &'a wgpu::TextureView,
+    pub frame_globals: &'a wgpu::Buffer,
+}
+
+pub trait PostEffect {
+    fn name(&self) -> &'static str;
+
+    fn resize(&mut self, _device: &wgpu::Device, _width: u32, _height: u32) {}
+
+    /// Called every frame the effect is turned off instead of `update`. Effects keeping data from
+    /// earlier frames drop it, it would be stale by the time the effect is turned back on.
+    fn reset_history(&mut self) {}
+
+    /// Uploads per-frame data, returns false if the effect has nothing to do this frame.
+    fn update(&mut self, queue: &wgpu::Queue, settings: &PostSettings, frame: &PostFrame) -> bool;
+
+    fn record(
+        &self,
+        context: &PostContext,
+        encoder: &mut wgpu::CommandEncoder,
+        input: &wgpu::TextureView,
+        output: &wgpu::TextureView,
+    );
+}
+
+/// Runs the enabled effects over the HDR scene color, ping-ponging between two intermediates, and
+/// finishes with the output effect writing to the swapchain.
+pub struct PostChain {
+    effects: Vec<Box<dyn PostEffect>>,
+    output_effect: Box<dyn PostEffect>,
+    active_effects: Vec<usize>,
+    targets: [Texture; 2],
+}
+
+impl PostChain {
+    pub fn new(
+        device: &wgpu::Device,
+        width: u32,
+        height: u32,
+        output_format: wgpu::TextureFormat,
+    ) -> Self {
+        let effects: Vec<Box<dyn PostEffect>> = vec![
+            Box::new(MotionBlurPass::new(device)),
+            Box::new(DepthOfFieldPass::new(device, width, height)),
+        ];
+
+        Self {
+            active_effects: Vec::with_capacity(effects.len()),
+            effects,
+            output_effect: Box::new(TonemapPass::new(device, output_format)),
+            targets: Self::create_targets(device, width, height),
+        }
+    }
+
+    fn create_targets(device: &wgpu::Device, width: u32, height: u32) -> [Texture; 2] {
+        [
+            Texture::create_hdr_target_texture(device, width, height, "Post target #0"),
+            Texture::create_hdr_target_texture(device, width, height, "Post target #1"),
+        ]
+    }
+
+    /// The HDR target the scene should be rendered into.
+    pub fn scene_view(&self) -> &wgpu::TextureView {
+        &self.targets[0].view
+    }
+
+    pub fn resize(&mut self, device: &wgpu::Device, width: u32, height: u32) {
+        self.targets = Self::create_targets(device, width, height);
+        for effect in &mut self.effects {
+            effect.resize(device, width, height);
+        }
+        self.output_effect.resize(device, width, height);
+    }
+
+    pub fn update(&mut self, queue: &wgpu::Queue, settings: &PostSettings, frame: &PostFrame) {
+        self.active_effects.clear();
+        for config in settings.effects.iter().filter(|config| config.enabled) {
+            let index = match self
+                .effects
+                .iter()
+                .position(|effect| effect.name() == config.name)
+            {
+                Some(index) => index,
+                None => continue,
+            };
+            if self.effects[index].update(queue, settings, frame) {
+                self.active_effects.push(index);
+            }
+        }
+        for effect in &mut self.effects {
+            let enabled = settings
+                .effects
+                .iter()
+                .any(|config| config.enabled && config.name == effect.name());
+            if !enabled {
+                effect.reset_history();
+            }
+        }
+        self.output_effect.update(queue, settings, frame);
+    }
+
+    pub fn record(
+        &self,
+        context: &PostContext,
+        encoder: &mut wgpu::CommandEncoder,
+        output: &wgpu::TextureView,
+    ) {
+        let mut current = 0;
+        for &index in &self.active_effects {
+            self.effects[index].record(
+                context,
+                encoder,
+                &self.targets[current].view,
+                &self.targets[1 - current].view,
+            );
+            current = 1 - current;
+        }
+        self.output_effect
+            .record(context, encoder, &self.targets[current].view, output);
+    }
+}
+
+fn create_fullscreen_pipeline(
+    device: &wgpu::Device,
+    label: &str,
+    module: &wgpu::ShaderModule,
+    fs_entry: &str,
+    bind_group_layout: &wgpu::BindGroupLayout,
+    format: wgpu::TextureFormat,
+) -> wgpu::RenderPipeline {
+    let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+        label: Some(format!("{} pipeline layout", label).as_str()),
+        bind_group_layouts: &[bind_group_layout],
+        push_constant_ranges: &[],
+    });
+
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some(format!("{} pipeline", label).as_str()),
+        layout: Some(&pipeline_layout),
+        vertex: wgpu::VertexState {
+            module,
+            entry_point: "vs_fullscreen",
+            buffers: &[],
+        },
+        primitive: wgpu::PrimitiveState::default(),
+        depth_stencil: None,
+        multisample: wgpu::MultisampleState::default(),
+        fragment: Some(wgpu::FragmentState {
+            module,
+            entry_point: fs_entry,
+            targets: &[Some(wgpu::ColorTargetState {
+                format,
+                blend: None,
+                write_mask: wgpu::ColorWrites::ALL,
+            })],
+        }),
+        multiview: None,
+    })
+}
+
+fn draw_fullscreen(
+    encoder: &mut wgpu::CommandEncoder,
+    label: &str,
+    pipeline: &wgpu::RenderPipeline,
+    bind_group: &wgpu::BindGroup,
+    output: &wgpu::TextureView,
+) {
+    let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+        label: Some(label),
+        color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+            view: output,
+            resolve_target: None,
+            ops: wgpu::Operations {
+                load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                store: true,
+            },
+        })],
+        depth_stencil_attachment: None,
+    });
+    render_pass.set_pipeline(pipeline);
+    render_pass.set_bind_group(0, bind_group, &[]);
+    render_pass.draw(0..3, 0..1);
+}
+
+fn texture_entry(binding: u32, sample_type: wgpu::TextureSampleType) -> wgpu::BindGroupLayoutEntry {
+    wgpu::BindGroupLayoutEntry {
+        binding,
+        visibility: wgpu::ShaderStages::FRAGMENT,
+        ty: wgpu::BindingType::Texture {
+            sample_type,
+            view_dimension: wgpu::TextureViewDimension::D2,
+            multisampled: false,
+        },
+        count: None,
+    }
+}
+
+fn sampler_entry(binding: u32) -> wgpu::BindGroupLayoutEntry {
+    wgpu::BindGroupLayoutEntry {
+        binding,
+        visibility: wgpu::ShaderStages::FRAGMENT,
+        ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+        count: None,
+    }
+}
+
+fn uniform_entry(binding: u32) -> wgpu::BindGroupLayoutEntry {
+    wgpu::BindGroupLayoutEntry {
+        binding,
+        visibility: wgpu::ShaderStages::FRAGMENT,
+        ty: wgpu::BindingType::Buffer {
+            ty: wgpu::BufferBindingType::Uniform,
+            has_dynamic_offset: false,
+            min_binding_size: None,
+        },
+        count: None,
+    }
+}
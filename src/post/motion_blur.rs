@@ -0,0 +1,211 @@
+use cgmath::{Matrix4, SquareMatrix};
+use wgpu::util::DeviceExt;
+
+use super::{
+    create_fullscreen_pipeline, draw_fullscreen, sampler_entry, texture_entry, uniform_entry,
+    PostContext, PostEffect, PostFrame, PostSettings,
+};
+use crate::texture::Texture;
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct MotionBlurParams {
+    inv_view_projection: [[f32; 4]; 4],
+    prev_view_projection: [[f32; 4]; 4],
+    strength: f32,
+    max_radius: f32,
+    max_samples: u32,
+    filler: u32,
+}
+
+crate::assert_uniform_layout!(MotionBlurParams, size: 144, {
+    inv_view_projection: 0,
+    prev_view_projection: 64,
+    strength: 128,
+    max_radius: 132,
+    max_samples: 136,
+    filler: 140,
+});
+
+/// Blurs the HDR image along the per-pixel screen-space velocity caused by camera motion.
+pub struct MotionBlurPass {
+    pipeline: wgpu::RenderPipeline,
+    bind_group_layout: wgpu::BindGroupLayout,
+    params_buffer: wgpu::Buffer,
+    sampler: wgpu::Sampler,
+    prev_view_projection: Option<Matrix4<f32>>,
+}
+
+impl MotionBlurPass {
+    pub const NAME: &'static str = "motion_blur";
+
+    pub fn new(device: &wgpu::Device) -> Self {
+        let module = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Motion blur shader module"),
+            source: wgpu::ShaderSource::Wgsl(
+                concat!(
+                    include_str!("../shaders/common.wgsl"),
+                    include_str!("../shaders/fullscreen.wgsl"),
+                    include_str!("../shaders/motion_blur.wgsl")
+                )
+                .into(),
+            ),
+        });
+
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Motion blur bind group layout"),
+            entries: &[
+                texture_entry(0, wgpu::TextureSampleType::Float { filterable: true }),
+                sampler_entry(1),
+                texture_entry(2, wgpu::TextureSampleType::Depth),
+                uniform_entry(3),
+                uniform_entry(4),
+            ],
+        });
+
+        let pipeline = create_fullscreen_pipeline(
+            device,
+            "Motion blur",
+            &module,
+            "fs_main",
+            &bind_group_layout,
+            Texture::HDR_FORMAT,
+        );
+
+        let params_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Motion blur params buffer"),
+            contents: bytemuck::cast_slice(&[MotionBlurParams {
+                inv_view_projection: Matrix4::identity().into(),
+                prev_view_projection: Matrix4::identity().into(),
+                strength: 0.0,
+                max_radius: 0.0,
+                max_samples: 0,
+                filler: 0,
+            }]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("Motion blur sampler"),
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+
+        Self {
+            pipeline,
+            bind_group_layout,
+            params_buffer,
+            sampler,
+            prev_view_projection: None,
+        }
+    }
+}
+
+impl PostEffect for MotionBlurPass {
+    fn name(&self) -> &'static str {
+        Self::NAME
+    }
+
+    /// The projection changes along with the aspect ratio, blurring the first frame after a
+    /// resize by that would smear the whole image.
+    fn resize(&mut self, _device: &wgpu::Device, _width: u32, _height: u32) {
+        self.prev_view_projection = None;
+    }
+
+    /// The camera may have moved any distance while the effect was off, the first frame after
+    /// turning it back on isn't blurred.
+    fn reset_history(&mut self) {
+        self.prev_view_projection = None;
+    }
+
+    /// The pass is skipped entirely when the camera didn't move.
+    fn update(&mut self, queue: &wgpu::Queue, settings: &PostSettings, frame: &PostFrame) -> bool {
+        let view_projection = frame.view_projection;
+        let prev_view_projection = self.prev_view_projection.unwrap_or(view_projection);
+        let active = settings.motion_blur_strength > 0.0 && view_projection != prev_view_projection;
+
+        if active {
+            queue.write_buffer(
+                &self.params_buffer,
+                0,
+                bytemuck::cast_slice(&[MotionBlurParams {
+                    inv_view_projection: view_projection
+                        .invert()
+                        .unwrap_or(Matrix4::identity())
+                        .into(),
+                    prev_view_projection: prev_view_projection.into(),
+                    strength: settings.motion_blur_strength,
+                    max_radius: settings.motion_blur_max_radius,
+                    max_samples: settings.motion_blur_max_samples.max(2),
+                    filler: 0,
+                }]),
+            );
+        }
+        self.prev_view_projection = Some(view_projection);
+        active
+    }
+
+    fn record(
+        &self,
+        context: &PostContext,
+        encoder: &mut wgpu::CommandEncoder,
+        input: &wgpu::TextureView,
+        output: &wgpu::TextureView,
+    ) {
+        let bind_group = context
+            .device
+            .create_bind_group(&wgpu::BindGroupDescriptor {
+                label: Some("Motion blur bind group"),
+                layout: &self.bind_group_layout,
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: wgpu::BindingResource::TextureView(input),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 1,
+                        resource: wgpu::BindingResource::Sampler(&self.sampler),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 2,
+                        resource: wgpu::BindingResource::TextureView(context.depth),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 3,
+                        resource: self.params_buffer.as_entire_binding(),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 4,
+                        resource: context.frame_globals.as_entire_binding(),
+                    },
+                ],
+            });
+        draw_fullscreen(
+            encoder,
+            "Motion blur render pass",
+            &self.pipeline,
+            &bind_group,
+            output,
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn params_match_wgsl() {
+        crate::util::assert_wgsl_layout::<MotionBlurParams>(
+            concat!(
+                include_str!("../shaders/common.wgsl"),
+                include_str!("../shaders/fullscreen.wgsl"),
+                include_str!("../shaders/motion_blur.wgsl")
+            ),
+            "MotionBlurParams",
+        );
+    }
+}
@@ -0,0 +1,195 @@
+use wgpu::util::DeviceExt;
+
+use super::{
+    create_fullscreen_pipeline, draw_fullscreen, sampler_entry, texture_entry, uniform_entry,
+    PostContext, PostEffect, PostFrame, PostSettings,
+};
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct TonemapParams {
+    dither_levels: f32,
+    vignette_strength: f32,
+    grain_strength: f32,
+    srgb_output: u32,
+    max_brightness: f32,
+    exposure: f32,
+    filler: [f32; 2],
+}
+
+crate::assert_uniform_layout!(TonemapParams, size: 32, {
+    dither_levels: 0,
+    vignette_strength: 4,
+    grain_strength: 8,
+    srgb_output: 12,
+    max_brightness: 16,
+    exposure: 20,
+    filler: 24,
+});
+
+/// Peak luminance assumed for HDR displays, WebGPU doesn't tell us the real one.
+const HDR_PEAK_NITS: f32 = 1000.0;
+
+/// Maps the HDR image to the display range and writes it to the swapchain. Finishes with optional
+/// vignette and film grain, then dithers to hide banding from the 8 bit output. With an
+/// `Rgba16Float` output the display is HDR, paper white maps to 1 and highlights roll off towards
+/// the display's peak instead of 1, and there is no banding to dither away.
+pub struct TonemapPass {
+    pipeline: wgpu::RenderPipeline,
+    bind_group_layout: wgpu::BindGroupLayout,
+    params_buffer: wgpu::Buffer,
+    sampler: wgpu::Sampler,
+    srgb_output: bool,
+    hdr_output: bool,
+}
+
+impl TonemapPass {
+    pub const NAME: &'static str = "tonemap";
+
+    pub fn new(device: &wgpu::Device, output_format: wgpu::TextureFormat) -> Self {
+        let module = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Tonemap shader module"),
+            source: wgpu::ShaderSource::Wgsl(
+                concat!(
+                    include_str!("../shaders/common.wgsl"),
+                    include_str!("../shaders/fullscreen.wgsl"),
+                    include_str!("../shaders/tonemap.wgsl")
+                )
+                .into(),
+            ),
+        });
+
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Tonemap bind group layout"),
+            entries: &[
+                texture_entry(0, wgpu::TextureSampleType::Float { filterable: true }),
+                sampler_entry(1),
+                uniform_entry(2),
+                uniform_entry(3),
+            ],
+        });
+
+        let pipeline = create_fullscreen_pipeline(
+            device,
+            "Tonemap",
+            &module,
+            "fs_main",
+            &bind_group_layout,
+            output_format,
+        );
+
+        let params_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Tonemap params buffer"),
+            contents: bytemuck::cast_slice(&[TonemapParams {
+                dither_levels: 0.0,
+                vignette_strength: 0.0,
+                grain_strength: 0.0,
+                srgb_output: 0,
+                max_brightness: 1.0,
+                exposure: 1.0,
+                filler: [0.0; 2],
+            }]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("Tonemap sampler"),
+            ..Default::default()
+        });
+
+        Self {
+            pipeline,
+            bind_group_layout,
+            params_buffer,
+            sampler,
+            srgb_output: output_format.is_srgb(),
+            hdr_output: output_format == wgpu::TextureFormat::Rgba16Float,
+        }
+    }
+}
+
+impl PostEffect for TonemapPass {
+    fn name(&self) -> &'static str {
+        Self::NAME
+    }
+
+    fn update(&mut self, queue: &wgpu::Queue, settings: &PostSettings, _frame: &PostFrame) -> bool {
+        let strength = |enabled: bool, strength: f32| if enabled { strength } else { 0.0 };
+        let max_brightness = if self.hdr_output {
+            (HDR_PEAK_NITS / settings.paper_white_nits).max(1.0)
+        } else {
+            1.0
+        };
+        queue.write_buffer(
+            &self.params_buffer,
+            0,
+            bytemuck::cast_slice(&[TonemapParams {
+                dither_levels: strength(settings.dither && !self.hdr_output, 255.0),
+                vignette_strength: strength(settings.vignette, settings.vignette_strength),
+                grain_strength: strength(settings.grain, settings.grain_strength),
+                srgb_output: self.srgb_output as u32,
+                max_brightness,
+                exposure: settings.exposure,
+                filler: [0.0; 2],
+            }]),
+        );
+        true
+    }
+
+    fn record(
+        &self,
+        context: &PostContext,
+        encoder: &mut wgpu::CommandEncoder,
+        input: &wgpu::TextureView,
+        output: &wgpu::TextureView,
+    ) {
+        let bind_group = context
+            .device
+            .create_bind_group(&wgpu::BindGroupDescriptor {
+                label: Some("Tonemap bind group"),
+                layout: &self.bind_group_layout,
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: wgpu::BindingResource::TextureView(input),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 1,
+                        resource: wgpu::BindingResource::Sampler(&self.sampler),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 2,
+                        resource: self.params_buffer.as_entire_binding(),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 3,
+                        resource: context.frame_globals.as_entire_binding(),
+                    },
+                ],
+            });
+        draw_fullscreen(
+            encoder,
+            "Tonemap render pass",
+            &self.pipeline,
+            &bind_group,
+            output,
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn params_match_wgsl() {
+        crate::util::assert_wgsl_layout::<TonemapParams>(
+            concat!(
+                include_str!("../shaders/common.wgsl"),
+                include_str!("../shaders/fullscreen.wgsl"),
+                include_str!("../shaders/tonemap.wgsl")
+            ),
+            "TonemapParams",
+        );
+    }
+}
@@ -0,0 +1,287 @@
+use crate::voxel_texture::VoxelTexture;
+
+/// Probes along each axis of the voxel grid
+pub const PROBE_GRID_RESOLUTION: u32 = 16;
+const PROBE_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
+const BAKE_WORKGROUP_SIZE: u32 = 4;
+
+/// Coarse grid of L1 spherical harmonics light probes baked from the voxel radiance. Shading from
+/// the probes costs three filtered texture reads instead of tracing cones, at the price of losing
+/// everything smaller than a probe cell. Each color channel has its own texture, so the hardware
+/// does the trilinear interpolation between probes.
+pub struct LightProbes {
+    /// Red, green and blue coefficients, bound to the main pass
+    pub views: [wgpu::TextureView; 3],
+    bake_pipeline: wgpu::ComputePipeline,
+    bake_bind_group: wgpu::BindGroup,
+    debug_pipeline: wgpu::RenderPipeline,
+    debug_bind_group: wgpu::BindGroup,
+}
+
+impl LightProbes {
+    /// `camera_bind_group_layout` has to start with the view projection of the main camera, the
+    /// debug view draws into `color_format` and tests against `depth_format`.
+    pub fn new(
+        device: &wgpu::Device,
+        voxel_texture: &VoxelTexture,
+        voxel_grid_info_buffer: &wgpu::Buffer,
+        frame_globals_buffer: &wgpu::Buffer,
+        camera_bind_group_layout: &wgpu::BindGroupLayout,
+        color_format: wgpu::TextureFormat,
+        depth_format: wgpu::TextureFormat,
+    ) -> Self {
+        let views = ["red", "green", "blue"].map(|channel| {
+            device
+                .create_texture(&wgpu::TextureDescriptor {
+                    label: Some(format!("Light probe {} texture", channel).as_str()),
+                    size: wgpu::Extent3d {
+                        width: PROBE_GRID_RESOLUTION,
+                        height: PROBE_GRID_RESOLUTION,
+                        depth_or_array_layers: PROBE_GRID_RESOLUTION,
+                    },
+                    mip_level_count: 1,
+                    sample_count: 1,
+                    dimension: wgpu::TextureDimension::D3,
+                    format: PROBE_FORMAT,
+                    usage: wgpu::TextureUsages::STORAGE_BINDING
+                        | wgpu::TextureUsages::TEXTURE_BINDING,
+                    view_formats: &[PROBE_FORMAT],
+                })
+                .create_view(&wgpu::TextureViewDescriptor::default())
+        });
+
+        let probe_texture_entry = |binding, visibility| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility,
+            ty: wgpu::BindingType::Texture {
+                sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                view_dimension: wgpu::TextureViewDimension::D3,
+                multisampled: false,
+            },
+            count: None,
+        };
+        let probe_storage_entry = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::StorageTexture {
+                access: wgpu::StorageTextureAccess::WriteOnly,
+                format: PROBE_FORMAT,
+                view_dimension: wgpu::TextureViewDimension::D3,
+            },
+            count: None,
+        };
+        let uniform_entry = |binding, visibility| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Uniform,
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+
+        let bake_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                label: Some("Light probe bake bind group layout"),
+                entries: &[
+                    probe_texture_entry(0, wgpu::ShaderStages::COMPUTE),
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 1,
+                        visibility: wgpu::ShaderStages::COMPUTE,
+                        ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                        count: None,
+                    },
+                    uniform_entry(2, wgpu::ShaderStages::COMPUTE),
+                    probe_storage_entry(3),
+                    probe_storage_entry(4),
+                    probe_storage_entry(5),
+                ],
+            });
+        let bake_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Light probe bake bind group"),
+            layout: &bake_bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&voxel_texture.main_view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&voxel_texture.sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: voxel_grid_info_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: wgpu::BindingResource::TextureView(&views[0]),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 4,
+                    resource: wgpu::BindingResource::TextureView(&views[1]),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 5,
+                    resource: wgpu::BindingResource::TextureView(&views[2]),
+                },
+            ],
+        });
+        let bake_module = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Light probe bake shader module"),
+            source: wgpu::ShaderSource::Wgsl(
+                concat!(
+                    include_str!("shaders/common.wgsl"),
+                    include_str!("shaders/probe_bake.wgsl")
+                )
+                .into(),
+            ),
+        });
+        let bake_pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Light probe bake pipeline layout"),
+            bind_group_layouts: &[&bake_bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let bake_pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("Light probe bake pipeline"),
+            layout: Some(&bake_pipeline_layout),
+            module: &bake_module,
+            entry_point: "bake",
+        });
+
+        let debug_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                label: Some("Light probe debug bind group layout"),
+                entries: &[
+                    probe_texture_entry(0, wgpu::ShaderStages::VERTEX),
+                    probe_texture_entry(1, wgpu::ShaderStages::VERTEX),
+                    probe_texture_entry(2, wgpu::ShaderStages::VERTEX),
+                    uniform_entry(3, wgpu::ShaderStages::VERTEX),
+                    uniform_entry(4, wgpu::ShaderStages::VERTEX),
+                ],
+            });
+        let debug_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Light probe debug bind group"),
+            layout: &debug_bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&views[0]),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(&views[1]),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::TextureView(&views[2]),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: voxel_grid_info_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 4,
+                    resource: frame_globals_buffer.as_entire_binding(),
+                },
+            ],
+        });
+        let debug_module = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Light probe debug shader module"),
+            source: wgpu::ShaderSource::Wgsl(
+                concat!(
+                    include_str!("shaders/common.wgsl"),
+                    include_str!("shaders/probe_debug.wgsl")
+                )
+                .into(),
+            ),
+        });
+        let debug_pipeline_layout =
+            device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some("Light probe debug pipeline layout"),
+                bind_group_layouts: &[camera_bind_group_layout, &debug_bind_group_layout],
+                push_constant_ranges: &[],
+            });
+        let debug_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Light probe debug pipeline"),
+            layout: Some(&debug_pipeline_layout),
+            vertex: wgpu::VertexState {
+                module: &debug_module,
+                entry_point: "vs_probe",
+                buffers: &[],
+            },
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: Some(wgpu::DepthStencilState {
+                format: depth_format,
+                depth_write_enabled: false,
+                depth_compare: wgpu::CompareFunction::LessEqual,
+                stencil: wgpu::StencilState::default(),
+                bias: wgpu::DepthBiasState::default(),
+            }),
+            multisample: wgpu::MultisampleState::default(),
+            fragment: Some(wgpu::FragmentState {
+                module: &debug_module,
+                entry_point: "fs_probe",
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: color_format,
+                    blend: None,
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+            }),
+            multiview: None,
+        });
+
+        Self {
+            views,
+            bake_pipeline,
+            bake_bind_group,
+            debug_pipeline,
+            debug_bind_group,
+        }
+    }
+
+    /// Projects the voxel radiance onto the probes, has to run after the voxel mipmaps are built.
+    pub fn record_bake(&self, encoder: &mut wgpu::CommandEncoder) {
+        let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some("Light probe bake compute pass"),
+        });
+        compute_pass.set_pipeline(&self.bake_pipeline);
+        compute_pass.set_bind_group(0, &self.bake_bind_group, &[]);
+        let workgroups = PROBE_GRID_RESOLUTION.div_ceil(BAKE_WORKGROUP_SIZE);
+        compute_pass.dispatch_workgroups(workgroups, workgroups, workgroups);
+    }
+
+    /// Draws every probe as a dot colored by its average radiance, depth tested against the scene.
+    pub fn record_debug(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        camera_bind_group: &wgpu::BindGroup,
+        color_view: &wgpu::TextureView,
+        depth_view: &wgpu::TextureView,
+    ) {
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Light probe debug render pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: color_view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: true,
+                },
+            })],
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                view: depth_view,
+                depth_ops: Some(wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: true,
+                }),
+                stencil_ops: None,
+            }),
+        });
+        render_pass.set_pipeline(&self.debug_pipeline);
+        render_pass.set_bind_group(0, camera_bind_group, &[]);
+        render_pass.set_bind_group(1, &self.debug_bind_group, &[]);
+        render_pass.draw(0..6, 0..PROBE_GRID_RESOLUTION.pow(3));
+    }
+}
@@ -0,0 +1,319 @@
+use std::ops::Range;
+
+use futures::channel::oneshot;
+
+/// Bytes per row of a texture copy rounded up to `COPY_BYTES_PER_ROW_ALIGNMENT`, buffers that
+/// textures are copied into have to use this stride. A 300 pixel wide RGBA8 texture has 1200
+/// bytes of pixels per row but takes 1280 in the buffer, `PendingReadback` strips the padding
+/// again once the data is mapped.
+pub fn padded_bytes_per_row(unpadded_bytes_per_row: u32) -> u32 {
+    let alignment = wgpu::COPY_BYTES_PER_ROW_ALIGNMENT;
+    unpadded_bytes_per_row.div_ceil(alignment) * alignment
+}
+
+/// Rows of a texture copy, the padding has to be stripped once the data is mapped.
+struct RowLayout {
+    unpadded_bytes_per_row: u32,
+    padded_bytes_per_row: u32,
+}
+
+/// A copy into a mappable staging buffer that was submitted and is waiting for the GPU. Either
+/// await `finish` or call `try_finish` once a frame, which works from the event loop where
+/// nothing can be awaited.
+pub struct PendingReadback {
+    staging_buffer: wgpu::Buffer,
+    rows: Option<RowLayout>,
+    receiver: oneshot::Receiver<Result<(), wgpu::BufferAsyncError>>,
+}
+
+impl PendingReadback {
+    /// Copies `range` of `buffer`, which needs `COPY_SRC` usage. The range has to be a multiple
+    /// of `COPY_BUFFER_ALIGNMENT`.
+    pub fn buffer(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        buffer: &wgpu::Buffer,
+        range: Range<u64>,
+    ) -> Self {
+        let size = range.end - range.start;
+        let staging_buffer = create_staging_buffer(device, size);
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Buffer readback encoder"),
+        });
+        encoder.copy_buffer_to_buffer(buffer, range.start, &staging_buffer, 0, size);
+        queue.submit(std::iter::once(encoder.finish()));
+        Self::map(staging_buffer, None)
+    }
+
+    /// Copies one mip level of `texture`, which needs `COPY_SRC` usage. `layer` is the array
+    /// layer of 2D textures and the depth slice of 3D ones. Only uncompressed color formats and
+    /// `Depth32Float` can be copied.
+    pub fn texture(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        texture: &wgpu::Texture,
+        mip_level: u32,
+        layer: u32,
+    ) -> Self {
+        Self::texture_layers(device, queue, texture, mip_level, layer..layer + 1)
+    }
+
+    /// Like `texture`, but copies a range of layers or depth slices in a single copy. They're
+    /// stored one after another once the data is mapped.
+    pub fn texture_layers(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        texture: &wgpu::Texture,
+        mip_level: u32,
+        layers: Range<u32>,
+    ) -> Self {
+        let format = texture.format();
+        assert_eq!(
+            format.block_dimensions(),
+            (1, 1),
+            "Can't read back compressed format {:?}",
+            format
+        );
+        let texel_size = format
+            .block_size(None)
+            .unwrap_or_else(|| panic!("Can't read back {:?} without picking an aspect", format));
+        let width = (texture.width() >> mip_level).max(1);
+        let height = (texture.height() >> mip_level).max(1);
+        let rows = RowLayout {
+            unpadded_bytes_per_row: width * texel_size,
+            padded_bytes_per_row: padded_bytes_per_row(width * texel_size),
+        };
+
+        let layer_count = layers.end - layers.start;
+        let staging_buffer = create_staging_buffer(
+            device,
+            rows.padded_bytes_per_row as u64 * height as u64 * layer_count as u64,
+        );
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Texture readback encoder"),
+        });
+        encoder.copy_texture_to_buffer(
+            wgpu::ImageCopyTexture {
+                texture,
+                mip_level,
+                origin: wgpu::Origin3d {
+                    x: 0,
+                    y: 0,
+                    z: layers.start,
+                },
+                aspect: wgpu::TextureAspect::All,
+            },
+            wgpu::ImageCopyBuffer {
+                buffer: &staging_buffer,
+                layout: wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: Some(rows.padded_bytes_per_row),
+                    rows_per_image: Some(height),
+                },
+            },
+            wgpu::Extent3d {
+                width,
+                height,
+                depth_or_array_layers: layer_count,
+            },
+        );
+        queue.submit(std::iter::once(encoder.finish()));
+        Self::map(staging_buffer, Some(rows))
+    }
+
+    fn map(staging_buffer: wgpu::Buffer, rows: Option<RowLayout>) -> Self {
+        let (sender, receiver) = oneshot::channel();
+        staging_buffer
+            .slice(..)
+            .map_async(wgpu::MapMode::Read, move |result| {
+                // The receiver is gone if the readback was dropped, nobody cares about the result
+                let _ = sender.send(result);
+            });
+        Self {
+            staging_buffer,
+            rows,
+            receiver,
+        }
+    }
+
+    /// Returns the data once the map finished, `None` while it's still pending. Natively the
+    /// device has to be polled for that to ever happen, the browser does it on its own.
+    pub fn try_finish(&mut self) -> Option<Result<Vec<u8>, wgpu::BufferAsyncError>> {
+        match self.receiver.try_recv() {
+            Ok(None) => None,
+            Ok(Some(result)) => Some(result.map(|()| self.take_data())),
+            Err(oneshot::Canceled) => Some(Err(wgpu::BufferAsyncError)),
+        }
+    }
+
+    pub async fn finish(
+        mut self,
+        device: &wgpu::Device,
+    ) -> Result<Vec<u8>, wgpu::BufferAsyncError> {
+        // Blocks until the copy is done, on the web this does nothing and the promise behind
+        // map_async resolves on its own
+        device.poll(wgpu::Maintain::Wait);
+        match (&mut self.receiver).await {
+            Ok(result) => result.map(|()| self.take_data()),
+            Err(oneshot::Canceled) => Err(wgpu::BufferAsyncError),
+        }
+    }
+
+    fn take_data(&self) -> Vec<u8> {
+        let data = {
+            let mapped = self.staging_buffer.slice(..).get_mapped_range();
+            match &self.rows {
+                Some(rows) => mapped
+                    .chunks(rows.padded_bytes_per_row as usize)
+                    .flat_map(|row| &row[..rows.unpadded_bytes_per_row as usize])
+                    .copied()
+                    .collect(),
+                None => mapped.to_vec(),
+            }
+        };
+        self.staging_buffer.unmap();
+        data
+    }
+}
+
+fn create_staging_buffer(device: &wgpu::Device, size: u64) -> wgpu::Buffer {
+    device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("Readback staging buffer"),
+        size,
+        usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+        mapped_at_creation: false,
+    })
+}
+
+/// Reads back one mip level of a texture, see `PendingReadback::texture_layers`.
+pub async fn read_texture(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    texture: &wgpu::Texture,
+    mip_level: u32,
+    layers: Range<u32>,
+) -> Result<Vec<u8>, wgpu::BufferAsyncError> {
+    PendingReadback::texture_layers(device, queue, texture, mip_level, layers)
+        .finish(device)
+        .await
+}
+
+/// Reads back part of a buffer, see `PendingReadback::buffer`.
+pub async fn read_buffer(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    buffer: &wgpu::Buffer,
+    range: Range<u64>,
+) -> Result<Vec<u8>, wgpu::BufferAsyncError> {
+    PendingReadback::buffer(device, queue, buffer, range)
+        .finish(device)
+        .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::test_device;
+
+    /// Texture with a distinct byte at every position of every layer, written without padding.
+    fn known_texture(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        format: wgpu::TextureFormat,
+        size: wgpu::Extent3d,
+    ) -> (wgpu::Texture, Vec<u8>) {
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Readback test texture"),
+            size,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format,
+            usage: wgpu::TextureUsages::COPY_SRC | wgpu::TextureUsages::COPY_DST,
+            view_formats: &[],
+        });
+        let bytes_per_row = size.width * format.block_size(None).unwrap();
+        let contents: Vec<u8> = (0..bytes_per_row * size.height * size.depth_or_array_layers)
+            .map(|index| (index % 251) as u8)
+            .collect();
+        queue.write_texture(
+            texture.as_image_copy(),
+            &contents,
+            wgpu::ImageDataLayout {
+                offset: 0,
+                bytes_per_row: Some(bytes_per_row),
+                rows_per_image: Some(size.height),
+            },
+            size,
+        );
+        (texture, contents)
+    }
+
+    #[test]
+    fn rows_are_padded_to_the_copy_alignment() {
+        assert_eq!(padded_bytes_per_row(1200), 1280);
+        assert_eq!(padded_bytes_per_row(1280), 1280);
+        assert_eq!(padded_bytes_per_row(4), 256);
+    }
+
+    #[test]
+    fn texture_rows_come_back_without_padding() {
+        let Some((device, queue)) = test_device() else {
+            return;
+        };
+        for (width, height) in [(300, 200), (256, 4), (3, 5)] {
+            let (texture, contents) = known_texture(
+                &device,
+                &queue,
+                wgpu::TextureFormat::Rgba8Unorm,
+                wgpu::Extent3d {
+                    width,
+                    height,
+                    depth_or_array_layers: 1,
+                },
+            );
+            let data = pollster::block_on(read_texture(&device, &queue, &texture, 0, 0..1))
+                .unwrap();
+            assert!(data == contents, "{}x{} texture read back wrong", width, height);
+        }
+    }
+
+    #[test]
+    fn texture_layers_come_back_one_after_another() {
+        let Some((device, queue)) = test_device() else {
+            return;
+        };
+        let (texture, contents) = known_texture(
+            &device,
+            &queue,
+            wgpu::TextureFormat::R32Float,
+            wgpu::Extent3d {
+                width: 7,
+                height: 3,
+                depth_or_array_layers: 3,
+            },
+        );
+        let layer_size = 7 * 3 * 4;
+        let data = pollster::block_on(read_texture(&device, &queue, &texture, 0, 1..3)).unwrap();
+        assert_eq!(data, contents[layer_size..]);
+        let data = pollster::block_on(read_texture(&device, &queue, &texture, 0, 0..1)).unwrap();
+        assert_eq!(data, contents[..layer_size]);
+    }
+
+    #[test]
+    fn buffer_range_comes_back() {
+        let Some((device, queue)) = test_device() else {
+            return;
+        };
+        let contents: Vec<u8> = (0..64).collect();
+        let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Readback test buffer"),
+            size: contents.len() as u64,
+            usage: wgpu::BufferUsages::COPY_SRC | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        queue.write_buffer(&buffer, 0, &contents);
+        let data = pollster::block_on(read_buffer(&device, &queue, &buffer, 16..48)).unwrap();
+        assert_eq!(data, contents[16..48]);
+    }
+}
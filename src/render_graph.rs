@@ -0,0 +1,505 @@
+use std::collections::HashMap;
+
+use crate::texture::Texture;
+
+pub enum TextureSize {
+    /// Follows the surface, recreated whenever it's resized
+    Surface,
+    /// Follows the surface at a fraction of its size, rounded up
+    Downscaled(u32),
+}
+
+type DescribeFn = Box<dyn Fn(u32, u32) -> wgpu::TextureDescriptor<'static>>;
+
+struct TransientTexture {
+    name: &'static str,
+    size: TextureSize,
+    /// Descriptor of the texture at the given size, it's created with `Texture::from_descriptor`
+    describe: DescribeFn,
+    /// Used outside of the graph's passes, so it can't share memory with anything
+    external: bool,
+}
+
+/// Textures created and owned by the graph. Several names can refer to the same texture when
+/// aliasing is enabled.
+pub struct GraphResources {
+    textures: Vec<Texture>,
+    slots: HashMap<&'static str, usize>,
+}
+
+impl GraphResources {
+    pub fn texture(&self, name: &str) -> &Texture {
+        let slot = self
+            .slots
+            .get(name)
+            .unwrap_or_else(|| panic!("Render graph texture \"{}\" doesn't exist", name));
+        &self.textures[*slot]
+    }
+}
+
+/// What has to match for two transient textures to be backed by the same one.
+#[derive(PartialEq, Eq)]
+struct TextureKey {
+    size: wgpu::Extent3d,
+    dimension: wgpu::TextureDimension,
+    format: wgpu::TextureFormat,
+    usage: wgpu::TextureUsages,
+    mip_level_count: u32,
+    sample_count: u32,
+}
+
+impl TextureKey {
+    fn of(descriptor: &wgpu::TextureDescriptor) -> Self {
+        Self {
+            size: descriptor.size,
+            dimension: descriptor.dimension,
+            format: descriptor.format,
+            usage: descriptor.usage,
+            mip_level_count: descriptor.mip_level_count,
+            sample_count: descriptor.sample_count,
+        }
+    }
+
+    /// Size of the base level, depth formats with a driver chosen layout count as 4 bytes per
+    /// texel
+    fn byte_size(&self) -> u64 {
+        let texel_size = self.format.block_size(None).unwrap_or(4) as u64;
+        self.size.width as u64
+            * self.size.height as u64
+            * self.size.depth_or_array_layers as u64
+            * self.sample_count as u64
+            * texel_size
+    }
+}
+
+type RecordFn<T> = Box<dyn Fn(&T, &GraphResources, &mut wgpu::CommandEncoder)>;
+
+struct PassNode<T> {
+    name: &'static str,
+    reads: Vec<&'static str>,
+    writes: Vec<&'static str>,
+    record: RecordFn<T>,
+}
+
+/// Minimal render graph. Passes declare the resources they read and write by name, the graph
+/// orders them and records them into a single encoder, or one per pass with `record_separately`.
+/// Resources can either be transient textures owned by the graph or resources owned elsewhere that
+/// are only named for ordering purposes.
+///
+/// Ordering rules: writes to the same resource keep the order in which the passes were added, and
+/// a pass reading a resource runs after the writers added before it (or after every writer, if it
+/// was added before any of them).
+///
+/// With aliasing enabled, transient textures with matching descriptors whose lifetimes (the span
+/// from the first to the last pass using them) don't overlap share a single texture. A pass can't
+/// rely on the contents of a texture it didn't write earlier in the frame, and the first writer
+/// has to clear or fully overwrite it.
+pub struct RenderGraph<T> {
+    passes: Vec<PassNode<T>>,
+    order: Vec<usize>,
+    transient_textures: Vec<TransientTexture>,
+    resources: GraphResources,
+    surface_size: (u32, u32),
+    aliasing: bool,
+    aliased_bytes: u64,
+}
+
+impl<T> RenderGraph<T> {
+    pub fn new(surface_width: u32, surface_height: u32) -> Self {
+        Self {
+            passes: Vec::new(),
+            order: Vec::new(),
+            transient_textures: Vec::new(),
+            resources: GraphResources {
+                textures: Vec::new(),
+                slots: HashMap::new(),
+            },
+            surface_size: (surface_width, surface_height),
+            aliasing: true,
+            aliased_bytes: 0,
+        }
+    }
+
+    /// The texture is created right away without aliasing, `allocate` has to run once every pass
+    /// was added for it to share memory with others.
+    pub fn add_texture(
+        &mut self,
+        device: &wgpu::Device,
+        name: &'static str,
+        size: TextureSize,
+        describe: impl Fn(u32, u32) -> wgpu::TextureDescriptor<'static> + 'static,
+    ) {
+        let transient = TransientTexture {
+            name,
+            size,
+            describe: Box::new(describe),
+            external: false,
+        };
+        let (width, height) = self.resolve_size(&transient.size);
+        self.resources
+            .slots
+            .insert(name, self.resources.textures.len());
+        self.resources.textures.push(Texture::from_descriptor(
+            device,
+            &(transient.describe)(width, height),
+        ));
+        self.transient_textures.push(transient);
+    }
+
+    /// Marks a texture as read outside of the graph's passes, e.g. by the post chain after
+    /// `execute`. The graph can't see those uses, so the texture is never aliased.
+    pub fn mark_external(&mut self, name: &str) {
+        self.transient_textures
+            .iter_mut()
+            .find(|transient| transient.name == name)
+            .unwrap_or_else(|| panic!("Render graph texture \"{}\" doesn't exist", name))
+            .external = true;
+    }
+
+    pub fn set_aliasing(&mut self, enabled: bool) {
+        self.aliasing = enabled;
+    }
+
+    /// Memory aliasing saved at the last `allocate`, in bytes.
+    pub fn aliased_bytes(&self) -> u64 {
+        self.aliased_bytes
+    }
+
+    /// (Re)creates the transient textures, sharing them between textures whose lifetimes don't
+    /// overlap if aliasing is enabled. Only the descriptors are compared, a texture is created for
+    /// every slot that isn't shared. Views and samplers of a shared texture are the ones of the
+    /// texture that was described first.
+    pub fn allocate(&mut self, device: &wgpu::Device) {
+        let mut textures: Vec<Texture> = Vec::new();
+        let mut slot_keys: Vec<TextureKey> = Vec::new();
+        // Lifetimes of the textures backed by each entry of `textures`
+        let mut slot_lifetimes: Vec<Vec<(usize, usize)>> = Vec::new();
+        let mut slots = HashMap::new();
+        let mut aliased_bytes = 0;
+
+        for transient in &self.transient_textures {
+            let (width, height) = self.resolve_size(&transient.size);
+            let descriptor = (transient.describe)(width, height);
+            let key = TextureKey::of(&descriptor);
+            let lifetime = match transient.external {
+                false => self.lifetime(transient.name),
+                true => None,
+            };
+
+            let shared_slot = lifetime
+                .filter(|_| self.aliasing)
+                .and_then(|(first, last)| {
+                    (0..textures.len()).find(|&slot| {
+                        slot_keys[slot] == key
+                            && !slot_lifetimes[slot].is_empty()
+                            && slot_lifetimes[slot]
+                                .iter()
+                                .all(|&(other_first, other_last)| {
+                                    last < other_first || first > other_last
+                                })
+                    })
+                });
+            match shared_slot {
+                Some(slot) => {
+                    aliased_bytes += key.byte_size();
+                    slot_lifetimes[slot].push(lifetime.unwrap());
+                    slots.insert(transient.name, slot);
+                }
+                None => {
+                    slots.insert(transient.name, textures.len());
+                    textures.push(Texture::from_descriptor(device, &descriptor));
+                    slot_keys.push(key);
+                    // Textures without a known lifetime are never shared
+                    slot_lifetimes.push(lifetime.into_iter().collect());
+                }
+            }
+        }
+
+        self.resources = GraphResources { textures, slots };
+        self.aliased_bytes = aliased_bytes;
+    }
+
+    /// Positions in the execution order of the first and last pass using `name`, `None` if no
+    /// pass does.
+    fn lifetime(&self, name: &str) -> Option<(usize, usize)> {
+        let mut uses = self.order.iter().enumerate().filter(|(_, &index)| {
+            let pass = &self.passes[index];
+            pass.reads.contains(&name) || pass.writes.contains(&name)
+        });
+        let (first, _) = uses.next()?;
+        let last = uses.next_back().map_or(first, |(position, _)| position);
+        Some((first, last))
+    }
+
+    pub fn add_pass(
+        &mut self,
+        name: &'static str,
+        reads: &[&'static str],
+        writes: &[&'static str],
+        record: impl Fn(&T, &GraphResources, &mut wgpu::CommandEncoder) + 'static,
+    ) {
+        self.passes.push(PassNode {
+            name,
+            reads: reads.to_vec(),
+            writes: writes.to_vec(),
+            record: Box::new(record),
+        });
+        self.order = self.sort_passes();
+    }
+
+    pub fn resources(&self) -> &GraphResources {
+        &self.resources
+    }
+
+    /// Reallocates the transient textures for the new surface size.
+    pub fn resize(&mut self, device: &wgpu::Device, width: u32, height: u32) {
+        if self.surface_size == (width, height) {
+            return;
+        }
+        self.surface_size = (width, height);
+        self.allocate(device);
+    }
+
+    /// Records every pass in execution order. `after_pass` is called with the name of each pass
+    /// once it's recorded, e.g. to write a timestamp.
+    pub fn execute(
+        &self,
+        context: &T,
+        encoder: &mut wgpu::CommandEncoder,
+        mut after_pass: impl FnMut(&'static str, &mut wgpu::CommandEncoder),
+    ) {
+        for &index in &self.order {
+            let pass = &self.passes[index];
+            (pass.record)(context, &self.resources, encoder);
+            after_pass(pass.name, encoder);
+        }
+    }
+
+    /// Records every pass into a command buffer of its own, in execution order. Submitting them
+    /// in order is equivalent to `execute`, but they can also be spread over several submissions
+    /// so a long pass doesn't hold up the frames in between. Recording happens on the calling
+    /// thread, the record functions borrow the context which isn't `Send`.
+    pub fn record_separately(
+        &self,
+        context: &T,
+        device: &wgpu::Device,
+    ) -> Vec<wgpu::CommandBuffer> {
+        self.order
+            .iter()
+            .map(|&index| {
+                let pass = &self.passes[index];
+                let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                    label: Some(format!("{} encoder", pass.name).as_str()),
+                });
+                (pass.record)(context, &self.resources, &mut encoder);
+                encoder.finish()
+            })
+            .collect()
+    }
+
+    fn resolve_size(&self, size: &TextureSize) -> (u32, u32) {
+        match size {
+            TextureSize::Surface => self.surface_size,
+            TextureSize::Downscaled(divisor) => (
+                self.surface_size.0.div_ceil(*divisor),
+                self.surface_size.1.div_ceil(*divisor),
+            ),
+        }
+    }
+
+    fn dependencies(&self, index: usize) -> Vec<usize> {
+        let pass = &self.passes[index];
+        let writers_of = |resource: &str| -> Vec<usize> {
+            self.passes
+                .iter()
+                .enumerate()
+                .filter(|(_, other)| other.writes.contains(&resource))
+                .map(|(other_index, _)| other_index)
+                .collect()
+        };
+
+        let mut dependencies = Vec::new();
+        for resource in &pass.writes {
+            dependencies.extend(
+                writers_of(resource)
+                    .into_iter()
+                    .filter(|&other| other < index),
+            );
+        }
+        for resource in pass.reads.iter().filter(|read| !pass.writes.contains(read)) {
+            let writers = writers_of(resource);
+            let earlier: Vec<usize> = writers
+                .iter()
+                .copied()
+                .filter(|&other| other < index)
+                .collect();
+            if earlier.is_empty() {
+                dependencies.extend(writers.into_iter().filter(|&other| other != index));
+            } else {
+                dependencies.extend(earlier);
+            }
+        }
+        dependencies
+    }
+
+    /// Kahn's algorithm, ties are broken by the order the passes were added in.
+    fn sort_passes(&self) -> Vec<usize> {
+        let dependencies: Vec<Vec<usize>> = (0..self.passes.len())
+            .map(|index| self.dependencies(index))
+            .collect();
+
+        let mut order = Vec::with_capacity(self.passes.len());
+        let mut scheduled = vec![false; self.passes.len()];
+        while order.len() < self.passes.len() {
+            let next = (0..self.passes.len()).find(|&index| {
+                !scheduled[index] && dependencies[index].iter().all(|&dep| scheduled[dep])
+            });
+            match next {
+                Some(index) => {
+                    scheduled[index] = true;
+                    order.push(index);
+                }
+                None => {
+                    let stuck: Vec<&str> = (0..self.passes.len())
+                        .filter(|&index| !scheduled[index])
+                        .map(|index| self.passes[index].name)
+                        .collect();
+                    panic!("Render graph has a dependency cycle between {:?}", stuck);
+                }
+            }
+        }
+        order
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{readback, util::test_device};
+
+    const SIZE: u32 = 16;
+    const RED: wgpu::Color = wgpu::Color::RED;
+    const GREEN: wgpu::Color = wgpu::Color::GREEN;
+    const BLUE: wgpu::Color = wgpu::Color::BLUE;
+
+    fn describe(width: u32, height: u32) -> wgpu::TextureDescriptor<'static> {
+        wgpu::TextureDescriptor {
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT
+                | wgpu::TextureUsages::COPY_SRC
+                | wgpu::TextureUsages::COPY_DST,
+            ..Texture::target_descriptor(
+                width,
+                height,
+                wgpu::TextureFormat::Rgba8Unorm,
+                "Graph test",
+            )
+        }
+    }
+
+    fn clear(encoder: &mut wgpu::CommandEncoder, texture: &Texture, color: wgpu::Color) {
+        encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Graph test clear"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: &texture.view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(color),
+                    store: true,
+                },
+            })],
+            depth_stencil_attachment: None,
+        });
+    }
+
+    /// Copies the `width` by `height` corner of `source` into `destination`.
+    fn copy(
+        encoder: &mut wgpu::CommandEncoder,
+        source: &Texture,
+        destination: &Texture,
+        width: u32,
+        height: u32,
+    ) {
+        encoder.copy_texture_to_texture(
+            source.texture.as_image_copy(),
+            destination.texture.as_image_copy(),
+            wgpu::Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
+            },
+        );
+    }
+
+    /// Renders a chain in which "first" and "third" can share a texture, returns the output and
+    /// the bytes aliasing saved.
+    fn render(device: &wgpu::Device, queue: &wgpu::Queue, aliasing: bool) -> (Vec<u8>, u64) {
+        let mut graph = RenderGraph::<()>::new(SIZE, SIZE);
+        for name in ["first", "second", "third", "output"] {
+            graph.add_texture(device, name, TextureSize::Surface, describe);
+        }
+        graph.add_pass("First", &[], &["first"], |_, resources, encoder| {
+            clear(encoder, resources.texture("first"), RED);
+        });
+        graph.add_pass(
+            "Second",
+            &["first"],
+            &["second"],
+            |_, resources, encoder| {
+                clear(encoder, resources.texture("second"), GREEN);
+                let (first, second) = (resources.texture("first"), resources.texture("second"));
+                copy(encoder, first, second, SIZE / 2, SIZE);
+            },
+        );
+        graph.add_pass("Third", &["second"], &["third"], |_, resources, encoder| {
+            clear(encoder, resources.texture("third"), BLUE);
+            let (second, third) = (resources.texture("second"), resources.texture("third"));
+            copy(encoder, second, third, SIZE, SIZE / 2);
+        });
+        graph.add_pass(
+            "Output",
+            &["third"],
+            &["output"],
+            |_, resources, encoder| {
+                copy(
+                    encoder,
+                    resources.texture("third"),
+                    resources.texture("output"),
+                    SIZE,
+                    SIZE,
+                );
+            },
+        );
+        graph.mark_external("output");
+        graph.set_aliasing(aliasing);
+        graph.allocate(device);
+
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Graph test encoder"),
+        });
+        graph.execute(&(), &mut encoder, |_, _| {});
+        queue.submit(std::iter::once(encoder.finish()));
+        let output = &graph.resources().texture("output").texture;
+        let image =
+            pollster::block_on(readback::read_texture(device, queue, output, 0, 0..1)).unwrap();
+        (image, graph.aliased_bytes())
+    }
+
+    #[test]
+    fn aliasing_renders_the_same_image() {
+        let Some((device, queue)) = test_device() else {
+            return;
+        };
+        let (aliased_image, aliased_bytes) = render(&device, &queue, true);
+        let (image, unaliased_bytes) = render(&device, &queue, false);
+        assert_eq!(aliased_bytes, (SIZE * SIZE * 4) as u64);
+        assert_eq!(unaliased_bytes, 0);
+        assert!(aliased_image == image);
+
+        // Top left quarter from the first pass, the rest of the top from the second and the
+        // bottom from the third
+        let pixel = |x: u32, y: u32| &image[((y * SIZE + x) * 4) as usize..][..4];
+        assert_eq!(pixel(0, 0), [255, 0, 0, 255]);
+        assert_eq!(pixel(SIZE - 1, 0), [0, 255, 0, 255]);
+        assert_eq!(pixel(0, SIZE - 1), [0, 0, 255, 255]);
+    }
+}
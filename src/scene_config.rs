@@ -0,0 +1,285 @@
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::util::query_param;
+
+/// Scene specific tuning, read from an optional `<model>.vxgi.json` next to the glTF file. Every
+/// field is optional in the file, missing ones keep the defaults, which are tuned for Sponza.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SceneConfig {
+    pub sun: SunConfig,
+    pub lights: Vec<PointLightConfig>,
+    /// Point lights the lights buffer starts out with room for, it's reallocated when more are
+    /// added. Scenes with more lights get a bigger buffer right away.
+    pub max_lights: usize,
+    pub voxel_grid: VoxelGridConfig,
+    pub camera: CameraConfig,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SunConfig {
+    /// Direction the light travels in, doesn't need to be normalized
+    pub direction: [f32; 3],
+    pub intensity: [f32; 3],
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PointLightConfig {
+    pub position: [f32; 3],
+    pub intensity: [f32; 3],
+    /// Exponent of the distance falloff, 2 is physically based
+    pub falloff: f32,
+    /// Restricts the light to a cone, without it the light shines in every direction
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub spot: Option<SpotConfig>,
+    /// Multiplies the light only where it's injected into the voxel grid, the direct lighting
+    /// keeps the full intensity. Defaults to 1 for spot lights and 0 for point lights, which
+    /// would otherwise leak through walls since they cast no shadows.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub gi_intensity: Option<f32>,
+    /// Distance in voxel cells under which the injected light stops getting brighter, spreads
+    /// small bright lights over several cells instead of a single hot one that flickers in the
+    /// mip chain
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub injection_radius: f32,
+}
+
+fn is_zero(value: &f32) -> bool {
+    *value == 0.0
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SpotConfig {
+    /// Direction the cone points in, doesn't need to be normalized
+    pub direction: [f32; 3],
+    /// Angle from the cone's axis in degrees up to which the light has its full intensity
+    pub inner_angle: f32,
+    /// Angle from the cone's axis in degrees where the light has faded out completely
+    pub outer_angle: f32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct VoxelGridConfig {
+    pub center: [f32; 3],
+    /// Half of the world space edge length of the voxel grid
+    pub half_extent: f32,
+    /// Cells along each edge, rounded up to a power of two. Every cell of the base level takes 8
+    /// bytes, 512 needs a gigabyte, 256 an eighth of that.
+    pub resolution: u32,
+    /// Caps the mip chain the cones sample, wide cones then stop at the coarsest level kept
+    pub max_mip_levels: Option<u32>,
+    pub sky_color: [f32; 3],
+    pub ground_color: [f32; 3],
+    /// Cells before the grid boundary over which cone samples fade out, so the sky fallback takes
+    /// over gradually instead of at the clamped border texels. 0 disables the fade.
+    pub edge_fade: f32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CameraConfig {
+    pub position: [f32; 3],
+    /// Euler angles in degrees
+    pub rotation: [f32; 3],
+}
+
+impl Default for SceneConfig {
+    fn default() -> Self {
+        Self {
+            sun: SunConfig::default(),
+            lights: vec![
+                PointLightConfig {
+                    position: [-9.87, 1.3, -0.22],
+                    intensity: [0.0, 0.0, 20.0],
+                    falloff: 2.0,
+                    spot: None,
+                    gi_intensity: None,
+                    injection_radius: 0.0,
+                },
+                PointLightConfig {
+                    position: [8.7, 1.6, -0.3],
+                    intensity: [10.0, 10.0, 10.0],
+                    falloff: 2.0,
+                    spot: None,
+                    gi_intensity: None,
+                    injection_radius: 0.0,
+                },
+            ],
+            max_lights: 64,
+            voxel_grid: VoxelGridConfig::default(),
+            camera: CameraConfig::default(),
+        }
+    }
+}
+
+impl Default for SunConfig {
+    fn default() -> Self {
+        Self {
+            direction: [1.0, -6.0, 2.0],
+            intensity: [30.0, 30.0, 30.0],
+        }
+    }
+}
+
+impl Default for VoxelGridConfig {
+    fn default() -> Self {
+        Self {
+            center: [0.0, 0.0, 0.0],
+            half_extent: 20.0,
+            resolution: 512,
+            max_mip_levels: None,
+            sky_color: [0.6, 0.75, 1.0],
+            ground_color: [0.2, 0.18, 0.15],
+            edge_fade: 2.0,
+        }
+    }
+}
+
+impl VoxelGridConfig {
+    /// Smallest grid the distance field and the cone tracing still work with
+    const MIN_RESOLUTION: u32 = 16;
+    /// `max_texture_dimension_3d` every WebGPU device supports
+    const MAX_RESOLUTION: u32 = 2048;
+
+    /// The mipmap passes halve every level exactly, so other resolutions are rounded up to the
+    /// next power of two.
+    fn validate(&mut self) {
+        let resolution = self
+            .resolution
+            .clamp(Self::MIN_RESOLUTION, Self::MAX_RESOLUTION)
+            .next_power_of_two();
+        if resolution != self.resolution {
+            log::warn!(
+                "Voxel grid resolution {} isn't supported, using {}",
+                self.resolution,
+                resolution
+            );
+            self.resolution = resolution;
+        }
+        self.edge_fade = self.edge_fade.max(0.0);
+    }
+
+    /// Halves the resolution until the grid fits the device's 3D textures. The anisotropic mips
+    /// pack three directions side by side at half the resolution, so they're the widest.
+    pub fn fit_to_limits(&mut self, limits: &wgpu::Limits) {
+        let mut resolution = self.resolution;
+        while resolution > Self::MIN_RESOLUTION
+            && resolution / 2 * 3 > limits.max_texture_dimension_3d
+        {
+            resolution /= 2;
+        }
+        if resolution != self.resolution {
+            log::warn!(
+                "Voxel grid resolution {} exceeds the device's 3D texture size of {}, using {}",
+                self.resolution,
+                limits.max_texture_dimension_3d,
+                resolution
+            );
+            self.resolution = resolution;
+        }
+    }
+}
+
+impl Default for CameraConfig {
+    fn default() -> Self {
+        Self {
+            position: [-1.8, 3.155, -0.3],
+            rotation: [0.0, -270.0, 0.0],
+        }
+    }
+}
+
+impl SceneConfig {
+    /// Fetches the sidecar of the glTF file at `model_path`, then applies the query parameter
+    /// overrides. A missing or malformed sidecar falls back to the defaults with a warning.
+    pub async fn load(model_path: &str) -> Self {
+        let path = Path::new(model_path).with_extension("vxgi.json");
+        let path = path.to_string_lossy();
+        let mut config = match crate::gltf_loader::load_binary(&path).await {
+            Ok(bytes) => serde_json::from_slice(&bytes).unwrap_or_else(|error| {
+                log::warn!("Ignoring malformed scene config {}: {}", path, error);
+                Self::default()
+            }),
+            Err(error) => {
+                log::warn!("No scene config at {} ({}), using defaults", path, error);
+                Self::default()
+            }
+        };
+        config.apply_query_overrides();
+        config.voxel_grid.validate();
+        config
+    }
+
+    /// `?sun=x,y,z` replaces the sun direction, `?camera=x,y,z` the camera spawn point,
+    /// `?voxels=<resolution>` the voxel grid resolution and `?voxel_edge_fade=<cells>` the fade
+    /// at the grid boundary.
+    fn apply_query_overrides(&mut self) {
+        let parse_vector = |name: &str| -> Option<[f32; 3]> {
+            let value = query_param(name)?;
+            let components: Result<Vec<f32>, _> = value
+                .split(',')
+                .map(|component| component.trim().parse())
+                .collect();
+            match components.as_deref() {
+                Ok(&[x, y, z]) => Some([x, y, z]),
+                _ => {
+                    log::warn!("Ignoring \"{}={}\", expected x,y,z", name, value);
+                    None
+                }
+            }
+        };
+        if let Some(direction) = parse_vector("sun") {
+            self.sun.direction = direction;
+        }
+        if let Some(position) = parse_vector("camera") {
+            self.camera.position = position;
+        }
+        if let Some(value) = query_param("voxels") {
+            match value.parse() {
+                Ok(resolution) => self.voxel_grid.resolution = resolution,
+                Err(_) => log::warn!("Ignoring \"voxels={}\", expected a resolution", value),
+            }
+        }
+        if let Some(value) = query_param("voxel_edge_fade") {
+            match value.parse() {
+                Ok(cells) => self.voxel_grid.edge_fade = cells,
+                Err(_) => log::warn!(
+                    "Ignoring \"voxel_edge_fade={}\", expected a number of cells",
+                    value
+                ),
+            }
+        }
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("Scene config is always serializable")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn voxel_grid_shrinks_to_the_3d_texture_limit() {
+        let fit = |resolution, max_texture_dimension_3d| {
+            let mut config = VoxelGridConfig {
+                resolution,
+                ..VoxelGridConfig::default()
+            };
+            config.fit_to_limits(&wgpu::Limits {
+                max_texture_dimension_3d,
+                ..wgpu::Limits::default()
+            });
+            config.resolution
+        };
+        assert_eq!(fit(512, 2048), 512);
+        assert_eq!(fit(2048, 2048), 1024);
+        assert_eq!(fit(512, 256), 128);
+        assert_eq!(fit(16, 8), VoxelGridConfig::MIN_RESOLUTION);
+    }
+}
@@ -13,5 +13,22 @@ pub enum Attribute {
 pub struct Shader {
     pub vs_entry: String,
     pub fs_entry: String,
+    /// Fragment entry point for `AlphaMode::Mask` primitives, the one doing the alpha cutoff.
+    /// Everything else uses `fs_entry`, which stays free of discards so early depth testing
+    /// works. `None` draws every primitive with `fs_entry`.
+    pub fs_mask_entry: Option<String>,
+    /// Fragment entry point for `AlphaMode::Blend` primitives, `None` draws them with `fs_entry`.
+    pub fs_blend_entry: Option<String>,
     pub module: wgpu::ShaderModule,
 }
+
+impl Shader {
+    /// Fragment entry point for primitives with a material of `alpha_mode`.
+    pub fn fs_entry_for(&self, alpha_mode: gltf::material::AlphaMode) -> &str {
+        match (alpha_mode, &self.fs_mask_entry, &self.fs_blend_entry) {
+            (gltf::material::AlphaMode::Mask, Some(fs_mask_entry), _) => fs_mask_entry,
+            (gltf::material::AlphaMode::Blend, _, Some(fs_blend_entry)) => fs_blend_entry,
+            _ => &self.fs_entry,
+        }
+    }
+}
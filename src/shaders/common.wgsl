@@ -0,0 +1,95 @@
+struct MaterialData {
+    base_color_factor: vec4<f32>,
+    metallic_factor: f32,
+    roughness_factor: f32,
+    alpha_cut_off: f32,
+    lod_bias: f32,
+    // Scale in xy and offset in zw into the texture atlas, see material_uv
+    base_color_uv_transform: vec4<f32>,
+    metallic_roughness_uv_transform: vec4<f32>,
+    normal_uv_transform: vec4<f32>,
+    // Linear radiance the surface emits on top of the light it reflects, w is unused
+    emissive_factor: vec4<f32>,
+    emissive_uv_transform: vec4<f32>,
+    // Strength of the planar reflection, 0 on surfaces that don't show it
+    planar_reflection: f32,
+    // 1 if back faces are lit like front faces
+    double_sided: f32,
+    filler: vec2<f32>,
+}
+
+// Atlased textures are clamped to their rectangle in the atlas. A scale of 1 means the texture
+// has the whole binding to itself and repeats as usual.
+fn material_uv(uv: vec2<f32>, transform: vec4<f32>) -> vec2<f32> {
+    if all(transform.xy == vec2(1.0)) {
+        return uv;
+    }
+    return clamp(uv, vec2(0.0), vec2(1.0)) * transform.xy + transform.zw;
+}
+
+struct Camera {
+    view_projection: mat4x4<f32>,
+    inverse_view_projection: mat4x4<f32>,
+    position: vec4<f32>,
+    near: f32,
+    far: f32,
+    // 1 while rendering the planar reflection, which has no prepass depth or reflection to sample
+    mirror: f32,
+    filler: f32,
+}
+
+struct FrameGlobals {
+    resolution: vec2<f32>,
+    time: f32,
+    frame_index: u32,
+}
+
+struct VoxelGridInfo {
+    center: vec3<f32>,
+    half_extent: f32,
+    sky_color: vec3<f32>,
+    resolution: f32,
+    ground_color: vec3<f32>,
+    // Cells before the boundary over which cone samples fade out
+    edge_fade: f32,
+}
+
+// Bits of RenderSettings.flags
+const NEUTRAL_MATERIAL: u32 = 1u;
+const MIP_LEVELS: u32 = 2u;
+const SOFT_SHADOWS: u32 = 4u;
+const CONTACT_SHADOWS: u32 = 8u;
+const PROBE_GI: u32 = 16u;
+const SDF_SKIPPING: u32 = 32u;
+const SDF_DISTANCE: u32 = 64u;
+const FOG: u32 = 128u;
+const FOG_GI_INSCATTER: u32 = 256u;
+
+struct RenderSettings {
+    flags: u32,
+    lod_bias: f32,
+    contact_shadow_steps: u32,
+    contact_shadow_distance: f32,
+    fog_color: vec3<f32>,
+    fog_density: f32,
+    fog_height_falloff: f32,
+    fog_base_height: f32,
+    cone_step_heatmap_max: f32,
+    filler: f32,
+}
+
+struct ConeTracingParams {
+    cone_count: u32,
+    aperture: f32,
+    step_size: f32,
+    max_distance: f32,
+    filler: vec3<u32>,
+    gi_intensity: f32,
+}
+
+struct SsrParams {
+    max_steps: u32,
+    max_distance: f32,
+    thickness: f32,
+    show_hit_mask: u32,
+}
@@ -0,0 +1,96 @@
+struct DofParams {
+    inv_projection: mat4x4<f32>,
+    focus_distance: f32,
+    aperture: f32,
+    max_radius: f32,
+    filler: u32,
+}
+
+// Pixel the focus is picked at, see cs_pick_focus
+struct FocusPick {
+    inv_projection: mat4x4<f32>,
+    uv: vec2<f32>,
+    filler: vec2<u32>,
+}
+
+@group(0) @binding(0)
+var input_texture: texture_2d<f32>;
+@group(0) @binding(1)
+var input_sampler: sampler;
+@group(0) @binding(2)
+var depth_texture: texture_depth_2d;
+@group(0) @binding(3)
+var<uniform> params: DofParams;
+@group(0) @binding(4)
+var blurred_texture: texture_2d<f32>;
+@group(0) @binding(5)
+var<uniform> focus_pick: FocusPick;
+@group(0) @binding(6)
+var<storage, read_write> picked_focus_distance: f32;
+
+const GOLDEN_ANGLE: f32 = 2.39996323;
+const SAMPLE_COUNT: i32 = 24;
+
+fn linear_depth(inv_projection: mat4x4<f32>, uv: vec2<f32>, depth: f32) -> f32 {
+    var view_pos = inv_projection * vec4(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0, depth, 1.0);
+    return -view_pos.z / view_pos.w;
+}
+
+// Distance along the view direction of whatever is under the picked pixel. It's read back once
+// and kept as the focus distance, instead of following whatever ends up under that pixel as the
+// camera moves.
+@compute @workgroup_size(1)
+fn cs_pick_focus() {
+    var size = vec2<f32>(textureDimensions(depth_texture));
+    var pixel = min(vec2<i32>(focus_pick.uv * size), vec2<i32>(size) - 1);
+    var depth = textureLoad(depth_texture, pixel, 0);
+    picked_focus_distance = linear_depth(focus_pick.inv_projection, focus_pick.uv, depth);
+}
+
+// Signed circle of confusion in half resolution pixels, negative in front of the focus plane
+@fragment
+fn fs_coc(in: FullscreenOutput) -> @location(0) vec4<f32> {
+    var depth = textureLoad(depth_texture, vec2<i32>(in.position.xy) * 2, 0);
+    var view_distance = linear_depth(params.inv_projection, in.uv, depth);
+    var coc = params.aperture * (view_distance - params.focus_distance) / max(view_distance, 0.0001);
+    coc = clamp(coc, -params.max_radius, params.max_radius);
+    var color = textureSampleLevel(input_texture, input_sampler, in.uv, 0.0);
+    return vec4(color.rgb, coc);
+}
+
+// Scatter-as-gather: a sample contributes if its own CoC reaches the center pixel. Samples behind
+// the center can't spread further than the center's CoC, so blurry backgrounds don't bleed over
+// sharp foregrounds.
+@fragment
+fn fs_blur(in: FullscreenOutput) -> @location(0) vec4<f32> {
+    var texel = 1.0 / vec2<f32>(textureDimensions(input_texture));
+    var center = textureSampleLevel(input_texture, input_sampler, in.uv, 0.0);
+
+    var color = center.rgb;
+    var total_weight = 1.0;
+    var coverage = abs(center.a);
+    for (var i = 0; i < SAMPLE_COUNT; i++) {
+        var radius = sqrt((f32(i) + 0.5) / f32(SAMPLE_COUNT)) * params.max_radius;
+        var angle = f32(i) * GOLDEN_ANGLE;
+        var offset = vec2(cos(angle), sin(angle)) * radius;
+        var tap = textureSampleLevel(input_texture, input_sampler, in.uv + offset * texel, 0.0);
+
+        var tap_coc = abs(tap.a);
+        if tap.a > center.a {
+            tap_coc = min(tap_coc, abs(center.a));
+        }
+        var weight = clamp(tap_coc - radius + 1.0, 0.0, 1.0);
+        color += tap.rgb * weight;
+        total_weight += weight;
+        coverage = max(coverage, tap_coc * weight);
+    }
+    return vec4(color / total_weight, coverage);
+}
+
+@fragment
+fn fs_composite(in: FullscreenOutput) -> @location(0) vec4<f32> {
+    var sharp = textureSampleLevel(input_texture, input_sampler, in.uv, 0.0);
+    var blurred = textureSampleLevel(blurred_texture, input_sampler, in.uv, 0.0);
+    var factor = smoothstep(0.5, 1.5, blurred.a);
+    return vec4(mix(sharp.rgb, blurred.rgb, factor), sharp.a);
+}
@@ -0,0 +1,15 @@
+struct FullscreenOutput {
+    @builtin(position) position: vec4<f32>,
+    @location(0) uv: vec2<f32>,
+}
+
+// Single triangle covering the whole screen, uv has its origin in the top left corner
+@vertex
+fn vs_fullscreen(@builtin(vertex_index) index: u32) -> FullscreenOutput {
+    var out: FullscreenOutput;
+    var uv = vec2<f32>(f32((index << 1u) & 2u), f32(index & 2u));
+    out.position = vec4(uv * vec2(2.0, -2.0) + vec2(-1.0, 1.0), 0.0, 1.0);
+    out.uv = uv;
+    return out;
+}
+
@@ -0,0 +1,106 @@
+@group(0) @binding(0)
+var<uniform> view_projection: mat4x4<f32>;
+
+struct GizmoParams {
+    // View space direction of the world X, Y and Z axes, w is unused
+    axes: array<vec4<f32>, 3>,
+}
+
+@group(1) @binding(0)
+var<uniform> gizmo: GizmoParams;
+
+// Has to match the constants in gizmo.rs, in units of half the gizmo viewport
+const GIZMO_AXIS_LENGTH: f32 = 0.7;
+const GIZMO_TIP_RADIUS: f32 = 0.16;
+const GIZMO_LINE_WIDTH: f32 = 0.035;
+
+// Lines of the world grid on each side of the origin, one world unit apart
+const GRID_HALF_LINES: u32 = 20u;
+
+fn axis_color(axis: u32) -> vec3<f32> {
+    var colors = array<vec3<f32>, 3>(
+        vec3(0.9, 0.2, 0.2),
+        vec3(0.3, 0.8, 0.2),
+        vec3(0.2, 0.4, 0.95),
+    );
+    return colors[axis];
+}
+
+fn segment_distance(p: vec2<f32>, end: vec2<f32>) -> f32 {
+    var t = clamp(dot(p, end) / max(dot(end, end), 1e-6), 0.0, 1.0);
+    return length(p - end * t);
+}
+
+// Drawn into a small square viewport, the arrows of the positive axes end in filled tips and the
+// negative ones only have a faint tip. Overlapping parts show whichever is closer to the viewer.
+@fragment
+fn fs_gizmo(in: FullscreenOutput) -> @location(0) vec4<f32> {
+    var p = (in.uv * 2.0 - 1.0) * vec2(1.0, -1.0);
+
+    var color = vec4(0.0);
+    var closest = -2.0;
+    for (var axis = 0u; axis < 3u; axis++) {
+        var direction = gizmo.axes[axis].xyz;
+        var tip = direction.xy * GIZMO_AXIS_LENGTH;
+        var base_color = axis_color(axis);
+
+        // The line halfway towards the tip is as close as the middle of the axis
+        if segment_distance(p, tip) < GIZMO_LINE_WIDTH && direction.z * 0.5 > closest {
+            closest = direction.z * 0.5;
+            color = vec4(base_color, 1.0);
+        }
+        if length(p - tip) < GIZMO_TIP_RADIUS && direction.z > closest {
+            closest = direction.z;
+            color = vec4(base_color, 1.0);
+        }
+        if length(p + tip) < GIZMO_TIP_RADIUS && -direction.z > closest {
+            closest = -direction.z;
+            color = vec4(base_color * 0.5, 0.6);
+        }
+    }
+    if color.a == 0.0 {
+        discard;
+    }
+    return color;
+}
+
+struct GridOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) color: vec4<f32>,
+}
+
+// Line list on the y = 0 plane, the lines through the origin are drawn in the color of the axis
+// they follow, and the last two vertices are the Y axis
+@vertex
+fn vs_grid(@builtin(vertex_index) index: u32) -> GridOutput {
+    var lines_per_direction = GRID_HALF_LINES * 2u + 1u;
+    var line = index / 2u;
+    var end = select(-1.0, 1.0, (index & 1u) != 0u) * f32(GRID_HALF_LINES);
+
+    var position: vec3<f32>;
+    var color = vec4(vec3(0.5), 0.5);
+    if line < lines_per_direction * 2u {
+        var offset = f32(line % lines_per_direction) - f32(GRID_HALF_LINES);
+        var along_x = line < lines_per_direction;
+        position = select(vec3(offset, 0.0, end), vec3(end, 0.0, offset), along_x);
+        if offset == 0.0 {
+            color = vec4(axis_color(select(2u, 0u, along_x)), 1.0);
+        } else {
+            // Fade out towards the edges, so the grid doesn't end abruptly
+            color.a *= 1.0 - abs(offset) / f32(GRID_HALF_LINES + 1u);
+        }
+    } else {
+        position = vec3(0.0, end, 0.0);
+        color = vec4(axis_color(1u), 1.0);
+    }
+
+    var out: GridOutput;
+    out.clip_position = view_projection * vec4(position, 1.0);
+    out.color = color;
+    return out;
+}
+
+@fragment
+fn fs_grid(in: GridOutput) -> @location(0) vec4<f32> {
+    return in.color;
+}
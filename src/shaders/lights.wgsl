@@ -0,0 +1,59 @@
+struct Light {
+    position: vec4<f32>,
+    intensity: vec3<f32>,
+    falloff: f32,
+    // Scale in xy and offset in zw from uv in the light's shadow map to its tile of the shadow
+    // atlas, zero without shadows
+    shadow_uv_transform: vec4<f32>,
+    spot_direction: vec3<f32>,
+    // -1 for lights that shine in every direction
+    spot_cos_outer: f32,
+    // Multiplier of the intensity in the voxel grid, 0 keeps the light out of it
+    gi_intensity: f32,
+    // Distance in voxel cells the injected light's falloff is clamped to
+    injection_radius: f32,
+    filler: f32,
+    spot_cos_inner: f32,
+    // Spot lights with a tile project into their shadow map with this, the sun's view projection
+    // is a uniform of its own
+    shadow_view_projection: mat4x4<f32>,
+}
+
+struct Lights {
+    filler: vec3<i32>,
+    count: i32,
+    lights: array<Light>,
+}
+
+// Clamps to the light's own shadow map first, like the clamped sampler did before the lights
+// shared an atlas
+fn shadow_atlas_uv(light: Light, uv: vec2<f32>) -> vec2<f32> {
+    return clamp(uv, vec2(0.0), vec2(1.0)) * light.shadow_uv_transform.xy + light.shadow_uv_transform.zw;
+}
+
+fn has_shadow_tile(light: Light) -> bool {
+    return light.shadow_uv_transform.x > 0.0;
+}
+
+// Where `world_pos` lands in the atlas tile of a spot light, uv in xy and depth in z. The point
+// is moved towards the light by a fraction of the distance first, a bias that grows with the
+// texels of the perspective shadow map.
+fn spot_shadow_position(light: Light, world_pos: vec3<f32>) -> vec3<f32> {
+    var biased = mix(world_pos, light.position.xyz, 0.01);
+    var clip = light.shadow_view_projection * vec4(biased, 1.0);
+    var screen = clip.xyz / clip.w * vec3(0.5, -0.5, 1.0) + vec3(0.5, 0.5, 0.0);
+    return vec3(shadow_atlas_uv(light, screen.xy), screen.z);
+}
+
+fn is_spot_light(light: Light) -> bool {
+    return light.spot_cos_outer > -1.0;
+}
+
+// Smooth falloff from the inner to the outer edge of a spot light's cone, 1 for other lights.
+// `to_light` is the normalized direction from the lit point to the light.
+fn spot_attenuation(light: Light, to_light: vec3<f32>) -> f32 {
+    if !is_spot_light(light) {
+        return 1.0;
+    }
+    return smoothstep(light.spot_cos_outer, light.spot_cos_inner, dot(-to_light, light.spot_direction));
+}
@@ -0,0 +1,63 @@
+// mip2d.rs prepends the REDUCTION constant and the output_texture binding, the storage format has
+// to be spelled out in the shader
+
+const REDUCTION_AVERAGE: u32 = 0u;
+const REDUCTION_MAX: u32 = 1u;
+const REDUCTION_KARIS_AVERAGE: u32 = 2u;
+
+@group(0) @binding(0)
+var input_texture: texture_2d<f32>;
+
+// Source texels reduced into the output texel at `pos`, 2x2 except along the last row and column
+// of odd sized levels, which also take the texel that would otherwise be dropped
+fn footprint_size(pos: vec2<u32>, input_size: vec2<u32>, output_size: vec2<u32>) -> vec2<u32> {
+    var size = vec2(2u, 2u);
+    if input_size.x % 2u == 1u && input_size.x > 1u && pos.x == output_size.x - 1u {
+        size.x = 3u;
+    }
+    if input_size.y % 2u == 1u && input_size.y > 1u && pos.y == output_size.y - 1u {
+        size.y = 3u;
+    }
+    return size;
+}
+
+fn sample_weight(color: vec4<f32>) -> f32 {
+    if REDUCTION == REDUCTION_KARIS_AVERAGE {
+        // Weighting by inverse luma keeps single very bright texels from dominating the average
+        return 1.0 / (1.0 + dot(color.rgb, vec3(0.2126, 0.7152, 0.0722)));
+    }
+    return 1.0;
+}
+
+@compute @workgroup_size(8, 8, 1)
+fn comp_main(@builtin(global_invocation_id) global_id: vec3<u32>) {
+    var output_size = vec2<u32>(textureDimensions(output_texture));
+    if global_id.x >= output_size.x || global_id.y >= output_size.y {
+        return;
+    }
+
+    var input_size = vec2<u32>(textureDimensions(input_texture));
+    var footprint = footprint_size(global_id.xy, input_size, output_size);
+    var base = vec2<i32>(global_id.xy * 2u);
+    // Levels that are a single texel wide along an axis only have one row or column to read
+    var max_coord = vec2<i32>(input_size) - 1;
+
+    var sum = vec4(0.0);
+    var total_weight = 0.0;
+    var maximum = textureLoad(input_texture, base, 0);
+    for (var y = 0; y < i32(footprint.y); y++) {
+        for (var x = 0; x < i32(footprint.x); x++) {
+            var color = textureLoad(input_texture, min(base + vec2(x, y), max_coord), 0);
+            var weight = sample_weight(color);
+            sum += color * weight;
+            total_weight += weight;
+            maximum = max(maximum, color);
+        }
+    }
+
+    if REDUCTION == REDUCTION_MAX {
+        textureStore(output_texture, vec2<i32>(global_id.xy), maximum);
+    } else {
+        textureStore(output_texture, vec2<i32>(global_id.xy), sum / total_weight);
+    }
+}
@@ -0,0 +1,12 @@
+@group(0) @binding(0)
+var input_texture: texture_2d<f32>;
+@group(0) @binding(1)
+var input_sampler: sampler;
+
+// The output is half the size of the input, so one bilinear tap in the middle of each output texel
+// averages the 2x2 input texels under it. sRGB textures are decoded before filtering and encoded
+// again when written, so the average is taken in linear space.
+@fragment
+fn fs_blit(in: FullscreenOutput) -> @location(0) vec4<f32> {
+    return textureSampleLevel(input_texture, input_sampler, in.uv, 0.0);
+}
@@ -0,0 +1,61 @@
+// Directional mip levels for cone tracing, one volume per direction a cone can travel along.
+// The six volumes are packed into a single texture as 3x2 tiles, the column is the axis and the
+// row is 0 for the positive and 1 for the negative direction.
+
+@group(0) @binding(0)
+var input_texture: texture_3d<f32>;
+
+@group(0) @binding(1)
+var output_texture: texture_storage_3d<rgba16float, write>;
+
+// What a cone passing through two cells in a row sees, the first one occludes the second. The
+// voxels are premultiplied by their coverage, averaging does that.
+fn composite(front: vec4<f32>, back: vec4<f32>) -> vec4<f32> {
+    return front + (1.0 - front.a) * back;
+}
+
+// One invocation per output texel of every tile, the dispatch is rounded up to whole workgroups
+fn downsample(global_id: vec3<u32>, tiled_input: bool) {
+    var size = vec3<u32>(textureDimensions(output_texture)) / vec3(3u, 2u, 1u);
+    var tile = global_id.xy / size.xy;
+    if (tile.x >= 3u || tile.y >= 2u || global_id.z >= size.z) {
+        return;
+    }
+
+    var pos = vec3<i32>(vec3(global_id.xy % size.xy, global_id.z));
+    var axis = tile.x;
+    var step = vec3(i32(axis == 0u), i32(axis == 1u), i32(axis == 2u));
+    var input_offset = vec3(0);
+    if tiled_input {
+        input_offset = vec3<i32>(vec3(tile * size.xy * 2u, 0u));
+    }
+
+    // The 2x2 pairs of children along the axis, each composited in the order the cone enters them
+    var sum = vec4(0.0);
+    for (var i = 0; i < 8; i++) {
+        var child = vec3(i & 1, (i >> 1u) & 1, (i >> 2u) & 1);
+        if dot(child, step) != 0 {
+            continue;
+        }
+        var first = textureLoad(input_texture, input_offset + pos * 2 + child, 0);
+        var second = textureLoad(input_texture, input_offset + pos * 2 + child + step, 0);
+        if tile.y == 0u {
+            sum += composite(first, second);
+        } else {
+            sum += composite(second, first);
+        }
+    }
+    textureStore(output_texture, vec3<i32>(global_id), sum / 4.0);
+}
+
+// Builds the first directional level from the isotropic base level
+@compute @workgroup_size(4, 4, 4)
+fn from_isotropic(@builtin(global_invocation_id) global_id: vec3<u32>) {
+    downsample(global_id, false);
+}
+
+// Builds every further level from the directional one above it
+@compute @workgroup_size(4, 4, 4)
+fn from_anisotropic(@builtin(global_invocation_id) global_id: vec3<u32>) {
+    downsample(global_id, true);
+}
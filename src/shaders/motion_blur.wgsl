@@ -0,0 +1,49 @@
+struct MotionBlurParams {
+    inv_view_projection: mat4x4<f32>,
+    prev_view_projection: mat4x4<f32>,
+    strength: f32,
+    max_radius: f32,
+    max_samples: u32,
+    filler: u32,
+}
+
+@group(0) @binding(0)
+var input_texture: texture_2d<f32>;
+@group(0) @binding(1)
+var input_sampler: sampler;
+@group(0) @binding(2)
+var depth_texture: texture_depth_2d;
+@group(0) @binding(3)
+var<uniform> params: MotionBlurParams;
+@group(0) @binding(4)
+var<uniform> frame_globals: FrameGlobals;
+
+@fragment
+fn fs_main(in: FullscreenOutput) -> @location(0) vec4<f32> {
+    var depth = textureLoad(depth_texture, vec2<i32>(in.position.xy), 0);
+    var ndc = vec4(in.uv.x * 2.0 - 1.0, 1.0 - in.uv.y * 2.0, depth, 1.0);
+    var world_pos = params.inv_view_projection * ndc;
+    world_pos /= world_pos.w;
+
+    var prev_clip = params.prev_view_projection * vec4(world_pos.xyz, 1.0);
+    var prev_uv = prev_clip.xy / prev_clip.w * vec2(0.5, -0.5) + 0.5;
+
+    var velocity = (in.uv - prev_uv) * frame_globals.resolution * params.strength;
+    var speed = length(velocity);
+    if speed < 0.5 {
+        return textureSampleLevel(input_texture, input_sampler, in.uv, 0.0);
+    }
+    if speed > params.max_radius {
+        velocity *= params.max_radius / speed;
+        speed = params.max_radius;
+    }
+
+    var samples = clamp(u32(speed), 2u, params.max_samples);
+    var step = velocity / frame_globals.resolution / f32(samples - 1u);
+    var start = in.uv - step * f32(samples - 1u) * 0.5;
+    var color = vec4(0.0);
+    for (var i = 0u; i < samples; i++) {
+        color += textureSampleLevel(input_texture, input_sampler, start + step * f32(i), 0.0);
+    }
+    return color / f32(samples);
+}
@@ -0,0 +1,39 @@
+@group(0) @binding(0)
+var<uniform> view_projection: mat4x4<f32>;
+@group(1) @binding(0)
+var<uniform> model: mat4x4<f32>;
+
+const OUTLINE_COLOR: vec4<f32> = vec4<f32>(1.0, 0.6, 0.1, 1.0);
+// In normalized device coordinates, so the outline keeps its width at any distance
+const OUTLINE_WIDTH: f32 = 0.006;
+
+struct VertexInput {
+    @location(0) position: vec3<f32>,
+    @location(2) normal: vec3<f32>,
+}
+
+@vertex
+fn vs_mask(input: VertexInput) -> @builtin(position) vec4<f32> {
+    return view_projection * model * vec4<f32>(input.position, 1.0);
+}
+
+// Only writes the stencil buffer
+@fragment
+fn fs_mask() {
+}
+
+// Pushes the silhouette outwards along the screen space normal
+@vertex
+fn vs_outline(input: VertexInput) -> @builtin(position) vec4<f32> {
+    var clip_position = view_projection * model * vec4<f32>(input.position, 1.0);
+    var clip_normal = (view_projection * model * vec4<f32>(input.normal, 0.0)).xy;
+    if length(clip_normal) > 0.0 {
+        clip_position += vec4(normalize(clip_normal) * OUTLINE_WIDTH * clip_position.w, 0.0, 0.0);
+    }
+    return clip_position;
+}
+
+@fragment
+fn fs_outline() -> @location(0) vec4<f32> {
+    return OUTLINE_COLOR;
+}
@@ -0,0 +1,82 @@
+@group(0) @binding(0)
+var voxel_texture: texture_3d<f32>;
+@group(0) @binding(1)
+var voxel_sampler: sampler;
+@group(0) @binding(2)
+var<uniform> voxel_grid: VoxelGridInfo;
+// L1 spherical harmonics per color channel, the coefficients are ordered L0, L1y, L1z, L1x
+@group(0) @binding(3)
+var probe_sh_r: texture_storage_3d<rgba16float, write>;
+@group(0) @binding(4)
+var probe_sh_g: texture_storage_3d<rgba16float, write>;
+@group(0) @binding(5)
+var probe_sh_b: texture_storage_3d<rgba16float, write>;
+
+const PI: f32 = 3.141592654;
+const SAMPLE_COUNT: u32 = 64u;
+const CONE_SLOPE: f32 = 0.577;
+const CONE_STEPS: i32 = 12;
+
+fn sky_radiance(direction: vec3<f32>) -> vec3<f32> {
+    return mix(voxel_grid.ground_color, voxel_grid.sky_color, direction.y * 0.5 + 0.5);
+}
+
+// Same march as cone_trace in shader.wgsl
+fn cone_radiance(start: vec3<f32>, direction: vec3<f32>) -> vec3<f32> {
+    var radiance = vec3(0.0);
+    var transmittance = 1.0;
+    var dist = 0.3;
+    for (var i = 0; i < CONE_STEPS; i++) {
+        var radius = CONE_SLOPE * dist;
+        var level = log2(radius / (2.0 * voxel_grid.half_extent) * voxel_grid.resolution) + 1.0;
+        var uvw = (start + direction * dist - voxel_grid.center) / voxel_grid.half_extent * 0.5 + 0.5;
+        dist += 0.3;
+        if any(uvw < vec3(0.0)) || any(uvw > vec3(1.0)) {
+            radiance += transmittance * sky_radiance(direction);
+            break;
+        }
+
+        var sample_color = textureSampleLevel(voxel_texture, voxel_sampler, uvw, level);
+        if sample_color.a <= 0.01 {
+            continue;
+        }
+        transmittance *= 1.0 - clamp(sample_color.a, 0.0, 1.0);
+        radiance += sample_color.rgb / sample_color.a;
+    }
+    return radiance;
+}
+
+// Evenly spread directions on the unit sphere
+fn fibonacci_direction(index: u32, count: u32) -> vec3<f32> {
+    var y = 1.0 - (f32(index) + 0.5) / f32(count) * 2.0;
+    var radius = sqrt(1.0 - y * y);
+    var angle = f32(index) * 2.39996323;
+    return vec3(cos(angle) * radius, y, sin(angle) * radius);
+}
+
+@compute @workgroup_size(4, 4, 4)
+fn bake(@builtin(global_invocation_id) id: vec3<u32>) {
+    var size = textureDimensions(probe_sh_r);
+    if any(id >= size) {
+        return;
+    }
+    // Probes sit in the centers of the cells the grid is divided into
+    var grid_pos = (vec3<f32>(id) + 0.5) / vec3<f32>(size) * 2.0 - 1.0;
+    var position = voxel_grid.center + grid_pos * voxel_grid.half_extent;
+
+    var sh_r = vec4(0.0);
+    var sh_g = vec4(0.0);
+    var sh_b = vec4(0.0);
+    for (var i = 0u; i < SAMPLE_COUNT; i++) {
+        var direction = fibonacci_direction(i, SAMPLE_COUNT);
+        var radiance = cone_radiance(position, direction);
+        var basis = vec4(0.282095, 0.488603 * direction.y, 0.488603 * direction.z, 0.488603 * direction.x);
+        sh_r += radiance.r * basis;
+        sh_g += radiance.g * basis;
+        sh_b += radiance.b * basis;
+    }
+    var weight = 4.0 * PI / f32(SAMPLE_COUNT);
+    textureStore(probe_sh_r, id, sh_r * weight);
+    textureStore(probe_sh_g, id, sh_g * weight);
+    textureStore(probe_sh_b, id, sh_b * weight);
+}
@@ -0,0 +1,56 @@
+@group(0) @binding(0)
+var<uniform> view_projection: mat4x4<f32>;
+
+@group(1) @binding(0)
+var probe_sh_r: texture_3d<f32>;
+@group(1) @binding(1)
+var probe_sh_g: texture_3d<f32>;
+@group(1) @binding(2)
+var probe_sh_b: texture_3d<f32>;
+@group(1) @binding(3)
+var<uniform> voxel_grid: VoxelGridInfo;
+@group(1) @binding(4)
+var<uniform> frame_globals: FrameGlobals;
+
+// Radius of a probe dot in pixels
+const DOT_RADIUS: f32 = 4.0;
+
+struct ProbeOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) corner: vec2<f32>,
+    @location(1) color: vec3<f32>,
+}
+
+// One screen aligned quad per probe, drawn instanced
+@vertex
+fn vs_probe(@builtin(vertex_index) vertex: u32, @builtin(instance_index) probe: u32) -> ProbeOutput {
+    var corners = array<vec2<f32>, 6>(
+        vec2(-1.0, -1.0), vec2(1.0, -1.0), vec2(1.0, 1.0),
+        vec2(-1.0, -1.0), vec2(1.0, 1.0), vec2(-1.0, 1.0),
+    );
+    var size = textureDimensions(probe_sh_r);
+    var id = vec3<u32>(probe % size.x, probe / size.x % size.y, probe / (size.x * size.y));
+    var grid_pos = (vec3<f32>(id) + 0.5) / vec3<f32>(size) * 2.0 - 1.0;
+    var position = voxel_grid.center + grid_pos * voxel_grid.half_extent;
+
+    var out: ProbeOutput;
+    out.corner = corners[vertex];
+    out.clip_position = view_projection * vec4(position, 1.0);
+    out.clip_position += vec4(out.corner * DOT_RADIUS * 2.0 / frame_globals.resolution * out.clip_position.w, 0.0, 0.0);
+    // Average radiance, the L0 term
+    var l0 = vec3(
+        textureLoad(probe_sh_r, id, 0).x,
+        textureLoad(probe_sh_g, id, 0).x,
+        textureLoad(probe_sh_b, id, 0).x,
+    );
+    out.color = max(l0 * 0.282095, vec3(0.0));
+    return out;
+}
+
+@fragment
+fn fs_probe(in: ProbeOutput) -> @location(0) vec4<f32> {
+    if dot(in.corner, in.corner) > 1.0 {
+        discard;
+    }
+    return vec4(in.color, 1.0);
+}
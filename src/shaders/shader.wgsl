@@ -1,27 +1,12 @@
-struct MaterialData {
-    base_color_factor: vec4<f32>,
-    metallic_factor: f32,
-    roughness_factor: f32,
-    alpha_cut_off: f32,
-    filler: u32,
-}
-
-struct Light {
-    position: vec4<f32>,
-    intensity: vec3<f32>,
-    falloff: f32,
-}
-
-struct Lights {
-    filler: vec3<i32>,
-    count: i32,
-    lights: array<Light, 8>,
-}
-
 @group(0) @binding(0)
-var<uniform> view_projection: mat4x4<f32>;
+var<uniform> camera: Camera;
 @group(0) @binding(1)
 var<uniform> shadow_view_projection: mat4x4<f32>;
+@group(0) @binding(2)
+var prepass_depth: texture_depth_2d;
+// The scene mirrored about the plane of the reflective materials, flipped horizontally
+@group(0) @binding(3)
+var planar_reflection: texture_2d<f32>;
 
 @group(1) @binding(0)
 var shadow_texture: texture_depth_2d;
@@ -32,13 +17,46 @@ var storage_texture: texture_3d<f32>;
 @group(1) @binding(3)
 var storage_sampler: sampler;
 @group(1) @binding(4)
-var<uniform> lights: Lights;
-
+var<storage, read> lights: Lights;
+@group(1) @binding(5)
+var<uniform> frame_globals: FrameGlobals;
+@group(1) @binding(6)
+var<uniform> voxel_grid: VoxelGridInfo;
+@group(1) @binding(7)
+var<uniform> render_settings: RenderSettings;
+@group(1) @binding(8)
+var shadow_moments: texture_2d<f32>;
+// L1 spherical harmonics of the light probes per color channel, ordered L0, L1y, L1z, L1x
+@group(1) @binding(9)
+var probe_sh_r: texture_3d<f32>;
+@group(1) @binding(10)
+var probe_sh_g: texture_3d<f32>;
+@group(1) @binding(11)
+var probe_sh_b: texture_3d<f32>;
+// Distance to the closest occupied cell of a coarse voxel mip, in cells of that mip
+@group(1) @binding(12)
+var voxel_sdf: texture_3d<f32>;
+// Directional mip levels below the base level, see mipmap_anisotropic.wgsl
+@group(1) @binding(13)
+var anisotropic_texture: texture_3d<f32>;
+@group(1) @binding(14)
+var<uniform> cone_params: ConeTracingParams;
 
 @group(2) @binding(0)
 var<uniform> model: mat4x4<f32>;
-@group(3) @binding(0)
+// At a dynamic offset into the buffer holding every material
+@group(2) @binding(1)
 var<uniform> material: MaterialData;
+// Only bound by the screen space reflection passes, which don't draw models
+@group(2) @binding(2)
+var gbuffer: texture_2d<f32>;
+// The lit scene for the trace pass, the traced reflections for the composite pass
+@group(2) @binding(3)
+var ssr_input: texture_2d<f32>;
+@group(2) @binding(4)
+var ssr_sampler: sampler;
+@group(2) @binding(5)
+var<uniform> ssr_params: SsrParams;
 @group(3) @binding(1)
 var base_color_texture: texture_2d<f32>;
 @group(3) @binding(2)
@@ -51,11 +69,17 @@ var metallic_roughness_sampler: sampler;
 var normal_texture: texture_2d<f32>;
 @group(3) @binding(6)
 var normal_sampler: sampler;
+@group(3) @binding(7)
+var emissive_texture: texture_2d<f32>;
+@group(3) @binding(8)
+var emissive_sampler: sampler;
 
 struct VertexInput {
     @location(0) position: vec3<f32>,
     @location(1) tex_coords: vec2<f32>,
     @location(2) normal: vec3<f32>,
+    // W is the handedness of the bitangent
+    @location(3) tangent: vec4<f32>,
 }
 struct VertexOutput {
     @builtin(position) clip_position: vec4<f32>,
@@ -63,6 +87,7 @@ struct VertexOutput {
     @location(1) normal: vec3<f32>,
     @location(2) shadow_clip_position: vec4<f32>,
     @location(3) model_pos: vec3<f32>,
+    @location(4) tangent: vec4<f32>,
 }
 
 @vertex
@@ -71,65 +96,182 @@ fn vs_main(
 ) -> VertexOutput {
     var out: VertexOutput;
     out.model_pos = (model * vec4<f32>(input.position, 1.0)).xyz;
-    out.clip_position = view_projection * vec4<f32>(out.model_pos, 1.0);
+    out.clip_position = camera.view_projection * vec4<f32>(out.model_pos, 1.0);
     out.normal = input.normal;
+    out.tangent = input.tangent;
     out.tex_coords = input.tex_coords;
     out.shadow_clip_position = shadow_view_projection * vec4<f32>(out.model_pos, 1.0);
     return out;
 }
 
-fn cone_trace(start: vec3<f32>, direction: vec3<f32>, slope: f32, steps: i32) -> vec4<f32> {
+fn sky_radiance(direction: vec3<f32>) -> vec3<f32> {
+    return mix(voxel_grid.ground_color, voxel_grid.sky_color, direction.y * 0.5 + 0.5);
+}
+
+// World space distance from uvw to the closest geometry, never more than the real one
+fn empty_space_distance(uvw: vec3<f32>) -> f32 {
+    var size = vec3<f32>(textureDimensions(voxel_sdf));
+    var cell = vec3<i32>(clamp(uvw * size, vec3(0.0), size - 1.0));
+    return textureLoad(voxel_sdf, cell, 0).r / size.x * 2.0 * voxel_grid.half_extent;
+}
+
+// Voxels seen by a cone travelling along `direction`, `level` counts from the isotropic base
+// level. Blends the tiles of the three directions facing the cone by how much they face it.
+fn sample_anisotropic(uvw: vec3<f32>, direction: vec3<f32>, level: f32) -> vec4<f32> {
+    var anisotropic_level = level - 1.0;
+    var coarser_level = min(u32(ceil(anisotropic_level)), textureNumLevels(anisotropic_texture) - 1u);
+    var tile_size = vec2<f32>(textureDimensions(anisotropic_texture, coarser_level).xy) / vec2(3.0, 2.0);
+    // Keeps the filter footprint of both levels sampled inside the tile
+    var margin = 0.5 / tile_size;
+    var inner = clamp(uvw.xy, margin, 1.0 - margin);
+    var weights = direction * direction;
+    var result = vec4(0.0);
+    for (var axis = 0; axis < 3; axis++) {
+        var row = f32(direction[axis] < 0.0);
+        var tile_uvw = vec3((f32(axis) + inner.x) / 3.0, (row + inner.y) / 2.0, uvw.z);
+        result += weights[axis] * textureSampleLevel(anisotropic_texture, storage_sampler, tile_uvw, anisotropic_level);
+    }
+    return result;
+}
+
+// Steps every cone of the fragment took, only counted by the heatmap variant of the shader
+var<private> cone_steps: u32 = 0u;
+
+// 0 at the grid boundary, rising to 1 over the last `edge_fade` cells
+fn grid_edge_fade(uvw: vec3<f32>) -> f32 {
+    if voxel_grid.edge_fade <= 0.0 {
+        return 1.0;
+    }
+    var edge = min(uvw, 1.0 - uvw) * voxel_grid.resolution;
+    return clamp(min(edge.x, min(edge.y, edge.z)) / voxel_grid.edge_fade, 0.0, 1.0);
+}
+
+// Direction of diffuse cone `index` out of `cone_count`. Seven cones keep the hand tuned pattern,
+// one along the normal and a ring of six 60 degrees off it, any other count is spread over the
+// hemisphere along a cosine weighted Fibonacci spiral.
+fn diffuse_cone_direction(index: u32, cone_count: u32, tangent: vec3<f32>, bitangent: vec3<f32>, normal: vec3<f32>) -> vec3<f32> {
+    if cone_count == 7u {
+        // Tangent, bitangent and normal components
+        var cones = array<vec3<f32>, 7>(
+            vec3(0.0, 0.0, 1.0),
+            vec3(0.866, 0.0, 0.5),
+            vec3(0.433, 0.75, 0.5),
+            vec3(-0.433, 0.75, 0.5),
+            vec3(-0.866, 0.0, 0.5),
+            vec3(-0.433, -0.75, 0.5),
+            vec3(0.433, -0.75, 0.5),
+        );
+        var cone = cones[index];
+        return cone.x * tangent + cone.y * bitangent + cone.z * normal;
+    }
+    var sin_theta = sqrt((f32(index) + 0.5) / f32(cone_count));
+    var phi = f32(index) * 2.399963;
+    return sin_theta * (cos(phi) * tangent + sin(phi) * bitangent) + sqrt(1.0 - sin_theta * sin_theta) * normal;
+}
+
+fn cone_trace(start: vec3<f32>, direction: vec3<f32>, slope: f32, max_distance: f32) -> vec4<f32> {
     var indirect_light = vec4(0.0, 0.0, 0.0, 1.0);
-    var dist: f32 = 0.3;
-    for (var i = 0; i < steps; i++) {
+    var transmittance = 1.0;
+    var step_size = cone_params.step_size;
+    var dist = step_size;
+    var skip_empty_space = (render_settings.flags & SDF_SKIPPING) != 0u;
+    while dist < max_distance {
+        if CONE_STEP_HEATMAP {
+            cone_steps += 1u;
+        }
         var radius = slope * dist;
-        var level = log2(radius / 40.0 * 512.0) + 1.0;
-        var sample_color = textureSampleLevel(
-            storage_texture,
-            storage_sampler,
-            (start + direction * dist) / 20.0 * 0.5 + 0.5,
-            level
-        );
-        if sample_color.a <= 0.01 {
+        var level = log2(radius / (2.0 * voxel_grid.half_extent) * voxel_grid.resolution) + 1.0;
+        var uvw = (start + direction * dist - voxel_grid.center) / voxel_grid.half_extent * 0.5 + 0.5;
+        if any(uvw < vec3(0.0)) || any(uvw > vec3(1.0)) {
+            // Whatever light is left unoccluded comes from the environment
+            indirect_light += vec4(transmittance * sky_radiance(direction), 0.0);
+            break;
+        }
+        if skip_empty_space {
+            // Jump as far as the cone can go before its growing footprint touches geometry, near
+            // geometry that is less than a regular step and the cone marches on as usual
+            var skip = (empty_space_distance(uvw) - radius) / (1.0 + slope);
+            if skip > step_size {
+                dist += skip;
+                continue;
+            }
+        }
+        dist += step_size;
+
+        var sample_color: vec4<f32>;
+        if level < 1.0 {
+            sample_color = textureSampleLevel(storage_texture, storage_sampler, uvw, level);
+        } else {
+            sample_color = sample_anisotropic(uvw, direction, level);
+        }
+        // The border texels get clamped, so geometry right at the boundary would otherwise
+        // stretch out over the last samples before the sky takes over
+        var fade = grid_edge_fade(uvw);
+        if sample_color.a * fade <= 0.01 {
             continue;
         }
 
+        transmittance *= 1.0 - clamp(sample_color.a * fade, 0.0, 1.0);
         sample_color /= sample_color.a;
-        var alpha_blending_fac = (1.0 - indirect_light.a) * sample_color.a;
-        // indirect_light += vec4(alpha_blending_fac * sample_color.rgb, alpha_blending_fac);
-        indirect_light += sample_color;
-
-        dist += 0.3;
+        indirect_light += sample_color * fade;
     }
     return indirect_light;
 }
 
-fn uchimura(x: vec3<f32>, P: f32, a: f32, m: f32, l: f32, c: f32, b: f32) -> vec3<f32> {
-    var l0 = ((P - m) * l) / a;
-    var L0 = m - m / a;
-    var L1 = m + (1.0 - m) / a;
-    var S0 = m + l0;
-    var S1 = m + a * l0;
-    var C2 = (a * P) / (P - S1);
-    var CP = -C2 / P;
-    var w0 = vec3(1.0 - smoothstep(vec3(0.0), vec3(m), x));
-    var w2 = vec3(step(vec3(m + l0), x));
-    var w1 = vec3(1.0 - w0 - w2);
-    var T = vec3(m * pow(x / m, vec3(c)) + b);
-    var S = vec3(P - (P - S1) * exp(CP * (x - S0)));
-    var L = vec3(m + a * (x - m));
-    return T * w0 + L * w1 + S * w2;
+// Irradiance from the trilinearly interpolated probes, using the cosine lobe convolution of the
+// L1 bands
+fn probe_irradiance(position: vec3<f32>, normal: vec3<f32>) -> vec3<f32> {
+    var uvw = (position - voxel_grid.center) / voxel_grid.half_extent * 0.5 + 0.5;
+    var basis = vec4(0.886227, 1.023328 * normal.y, 1.023328 * normal.z, 1.023328 * normal.x);
+    var irradiance = vec3(
+        dot(textureSampleLevel(probe_sh_r, storage_sampler, uvw, 0.0), basis),
+        dot(textureSampleLevel(probe_sh_g, storage_sampler, uvw, 0.0), basis),
+        dot(textureSampleLevel(probe_sh_b, storage_sampler, uvw, 0.0), basis),
+    );
+    return max(irradiance, vec3(0.0));
+}
+
+const FOG_INSCATTER_SAMPLES: i32 = 4;
+
+fn fog_density_at_camera() -> f32 {
+    return render_settings.fog_density * exp(-render_settings.fog_height_falloff * (camera.position.y - render_settings.fog_base_height));
+}
+
+// Exponential height fog integrated in closed form along the segment from the camera to camera + ray
+fn fog_optical_depth(ray: vec3<f32>) -> f32 {
+    var height_change = render_settings.fog_height_falloff * ray.y;
+    var integral = 1.0;
+    if abs(height_change) > 0.0001 {
+        integral = (1.0 - exp(-height_change)) / height_change;
+    }
+    return fog_density_at_camera() * length(ray) * integral;
 }
 
-fn uchimura_fixed(x: vec3<f32>) -> vec3<f32> {
-    var P = 1.0;  // max display brightness
-    var a = 1.0;  // contrast
-    var m = 0.22; // linear section start
-    var l = 0.4;  // linear section length
-    var c = 1.33; // black
-    var b = 0.0;  // pedestal
+// Light the fog scatters towards the camera. With GI inscattering a wide cone of voxel radiance
+// is sampled along the view ray, so fog glows near lit surfaces instead of being a flat color.
+fn fog_inscatter(direction: vec3<f32>, distance: f32) -> vec3<f32> {
+    if (render_settings.flags & FOG_GI_INSCATTER) == 0u {
+        return render_settings.fog_color;
+    }
+    var radiance = vec3(0.0);
+    for (var i = 0; i < FOG_INSCATTER_SAMPLES; i++) {
+        var dist = distance * (f32(i) + 0.5) / f32(FOG_INSCATTER_SAMPLES);
+        var uvw = (camera.position.xyz + direction * dist - voxel_grid.center) / voxel_grid.half_extent * 0.5 + 0.5;
+        if any(uvw < vec3(0.0)) || any(uvw > vec3(1.0)) {
+            continue;
+        }
+        // The cone widens with the distance from the camera, but never gets finer than a few
+        // voxels since the fog only needs the ambient light level
+        var level = log2(dist * 0.5 / (2.0 * voxel_grid.half_extent) * voxel_grid.resolution) + 1.0;
+        radiance += textureSampleLevel(storage_texture, storage_sampler, uvw, max(level, 3.0)).rgb;
+    }
+    return render_settings.fog_color + radiance / f32(FOG_INSCATTER_SAMPLES);
+}
 
-    return uchimura(x, P, a, m, l, c, b);
+fn apply_fog(color: vec3<f32>, position: vec3<f32>) -> vec3<f32> {
+    var ray = position - camera.position.xyz;
+    var fog_amount = 1.0 - exp(-fog_optical_depth(ray));
+    return mix(color, fog_inscatter(normalize(ray), length(ray)), fog_amount);
 }
 
 struct Hit {
@@ -167,42 +309,226 @@ fn trace(origin: vec3<f32>, direction: vec3<f32>) -> Hit {
     return Hit(vec3(0.0, 0.0, 0.0), vec3(0.0, 0.0, 0.0));
 }
 
+// Same level selection the sampler does, without the clamp to the available mips
+fn base_color_mip_level(uv: vec2<f32>, lod_bias: f32) -> f32 {
+    var texel = uv * vec2<f32>(textureDimensions(base_color_texture));
+    var dx = dpdx(texel);
+    var dy = dpdy(texel);
+    return 0.5 * log2(max(dot(dx, dx), dot(dy, dy))) + lod_bias;
+}
+
+// Polynomial fit of the viridis colormap, dark blue at 0 through green to yellow at 1
+fn viridis(t: f32) -> vec3<f32> {
+    var c0 = vec3(0.2777273, 0.0054073, 0.3340998);
+    var c1 = vec3(0.1050930, 1.4046135, 1.3845901);
+    var c2 = vec3(-0.3308618, 0.2148476, 0.0950952);
+    var c3 = vec3(-4.6342305, -5.7991009, -19.3324409);
+    var c4 = vec3(6.2282699, 14.1799336, 56.6905526);
+    var c5 = vec3(4.7763850, -13.7451453, -65.3530326);
+    var c6 = vec3(-5.4354559, 4.6458526, 26.3124352);
+    var x = clamp(t, 0.0, 1.0);
+    return c0 + x * (c1 + x * (c2 + x * (c3 + x * (c4 + x * (c5 + x * c6)))));
+}
+
+// Green at mip 0, then yellow, red, magenta and blue for every further level
+fn mip_level_color(level: f32) -> vec3<f32> {
+    var colors = array<vec3<f32>, 5>(
+        vec3(0.0, 1.0, 0.0),
+        vec3(1.0, 1.0, 0.0),
+        vec3(1.0, 0.0, 0.0),
+        vec3(1.0, 0.0, 1.0),
+        vec3(0.0, 0.0, 1.0),
+    );
+    var clamped = clamp(level, 0.0, 4.0);
+    var lower = u32(floor(clamped));
+    var upper = min(lower + 1u, 4u);
+    return mix(colors[lower], colors[upper], fract(clamped));
+}
+
+// Variance shadow mapping, the Chebyshev upper bound of the fraction of light reaching the
+// fragment given the blurred depth moments
+fn soft_shadow(shadow_screen_pos: vec3<f32>) -> f32 {
+    var size = vec2<i32>(textureDimensions(shadow_moments));
+    var texel = clamp(vec2<i32>(shadow_screen_pos.xy * vec2<f32>(size)), vec2(0), size - 1);
+    var moments = textureLoad(shadow_moments, texel, 0).xy;
+    var depth = shadow_screen_pos.z - 0.002;
+    if depth <= moments.x {
+        return 1.0;
+    }
+    var variance = max(moments.y - moments.x * moments.x, 0.00002);
+    var d = depth - moments.x;
+    // Remap to reduce light bleeding where occluders overlap
+    return clamp((variance / (variance + d * d) - 0.2) / 0.8, 0.0, 1.0);
+}
+
+// How far behind the depth buffer a ray can pass and still count as occluded, in world units
+const CONTACT_SHADOW_THICKNESS: f32 = 0.1;
+
+fn linear_depth(depth: f32) -> f32 {
+    return camera.near * camera.far / (camera.far - depth * (camera.far - camera.near));
+}
+
+// Marches a short ray towards the light through the depth prepass, 0 if it passes behind
+// something close to the fragment
+fn contact_shadow(world_pos: vec3<f32>, light_direction: vec3<f32>) -> f32 {
+    var size = vec2<f32>(textureDimensions(prepass_depth));
+    var steps = max(render_settings.contact_shadow_steps, 1u);
+    var step = normalize(light_direction) * render_settings.contact_shadow_distance / f32(steps);
+    for (var i = 1u; i <= steps; i++) {
+        var clip = camera.view_projection * vec4(world_pos + step * f32(i), 1.0);
+        var uv = clip.xy / clip.w * vec2(0.5, -0.5) + 0.5;
+        if any(uv < vec2(0.0)) || any(uv >= vec2(1.0)) {
+            break;
+        }
+        var scene_depth = linear_depth(textureLoad(prepass_depth, vec2<i32>(uv * size), 0));
+        // clip.w is the view space depth of the sample, the small bias keeps the surface the ray
+        // starts on from shadowing itself
+        var delta = clip.w - scene_depth;
+        if delta > 0.002 * clip.w && delta < CONTACT_SHADOW_THICKNESS {
+            return 0.0;
+        }
+    }
+    return 1.0;
+}
+
+// Alpha tested materials get an entry point of their own, any discard in a shader turns early
+// depth testing off for every primitive drawn with it
 @fragment
-fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+fn fs_main(in: VertexOutput, @builtin(front_facing) front_facing: bool) -> SceneOutput {
+    var color = shade(facing(in, front_facing), false);
+    return SceneOutput(vec4(color.rgb, 1.0), surface_data);
+}
+
+@fragment
+fn fs_masked(in: VertexOutput, @builtin(front_facing) front_facing: bool) -> SceneOutput {
+    var color = shade(facing(in, front_facing), true);
+    return SceneOutput(vec4(color.rgb, 1.0), surface_data);
+}
+
+// Keeps the alpha of the material for blending, the G-buffer target is masked off
+@fragment
+fn fs_blended(in: VertexOutput, @builtin(front_facing) front_facing: bool) -> SceneOutput {
+    var color = shade(facing(in, front_facing), false);
+    return SceneOutput(color, surface_data);
+}
+
+// Back faces only get drawn for double-sided materials, glTF lights them as if the normal
+// pointed the other way. The tangent and its bitangent sign flip with it, so mapped_normal
+// reverses the whole tangent frame and the normal mapped result along with it.
+fn facing(in: VertexOutput, front_facing: bool) -> VertexOutput {
+    var out = in;
+    if !front_facing {
+        out.normal = -in.normal;
+        out.tangent = -in.tangent;
+    }
+    return out;
+}
+
+struct SceneOutput {
+    @location(0) color: vec4<f32>,
+    // G-buffer the screen space reflections read, see surface_data
+    @location(1) surface: vec4<f32>,
+}
+
+// Octahedral encoded normal, roughness and metalness of the fragment, written by shade
+var<private> surface_data: vec4<f32>;
+
+fn octahedral_encode(normal: vec3<f32>) -> vec2<f32> {
+    var n = normal / (abs(normal.x) + abs(normal.y) + abs(normal.z));
+    if n.z < 0.0 {
+        return (1.0 - abs(n.yx)) * select(vec2(-1.0), vec2(1.0), n.xy >= vec2(0.0));
+    }
+    return n.xy;
+}
+
+fn octahedral_decode(encoded: vec2<f32>) -> vec3<f32> {
+    var n = vec3(encoded, 1.0 - abs(encoded.x) - abs(encoded.y));
+    if n.z < 0.0 {
+        n = vec3((1.0 - abs(n.yx)) * select(vec2(-1.0), vec2(1.0), n.xy >= vec2(0.0)), n.z);
+    }
+    return normalize(n);
+}
+
+// Every image is uploaded as sRGB, the normal texture has to be encoded again to get the stored
+// vectors back
+fn srgb_encode(c: vec3<f32>) -> vec3<f32> {
+    return select(1.055 * pow(c, vec3(1.0 / 2.4)) - 0.055, c * 12.92, c <= vec3(0.0031308));
+}
+
+// The vertex normal bent by the normal texture, in the tangent frame of the vertex
+fn mapped_normal(in: VertexOutput) -> vec3<f32> {
+    var normal = normalize(in.normal);
+    var tangent = normalize(in.tangent.xyz - normal * dot(normal, in.tangent.xyz));
+    var bitangent = cross(normal, tangent) * in.tangent.w;
+    var encoded = textureSample(normal_texture, normal_sampler, material_uv(in.tex_coords, material.normal_uv_transform)).rgb;
+    var mapped = srgb_encode(encoded) * 2.0 - 1.0;
+    return normalize(mapped.x * tangent + mapped.y * bitangent + mapped.z * normal);
+}
+
+// Alpha is the material's, only blended primitives keep it
+fn shade(in: VertexOutput, alpha_test: bool) -> vec4<f32> {
     var ray_origin = vec3(-1.8, 3.155, -0.3) / 20.0 * 256.0;
     var ray_dir = normalize(in.model_pos / 20.0 * 256.0 - ray_origin);
 
     var shadow_screen_pos = in.shadow_clip_position.xyz / in.shadow_clip_position.w * vec3<f32>(0.5, -0.5, 1.0) + vec3<f32>(0.5, 0.5, 0.0);
+    shadow_screen_pos = vec3(shadow_atlas_uv(lights.lights[0], shadow_screen_pos.xy), shadow_screen_pos.z);
     var shadow = textureSampleCompare(shadow_texture, shadow_sampler, shadow_screen_pos.xy, shadow_screen_pos.z - 0.002);
-    var color = textureSample(base_color_texture, base_color_sampler, in.tex_coords) * material.base_color_factor;
+    if (render_settings.flags & SOFT_SHADOWS) != 0u {
+        shadow = soft_shadow(shadow_screen_pos);
+    }
+    var lod_bias = render_settings.lod_bias + material.lod_bias;
+    var color = textureSampleBias(base_color_texture, base_color_sampler, material_uv(in.tex_coords, material.base_color_uv_transform), lod_bias) * material.base_color_factor;
 
     var indirect_light = vec4(0.0, 0.0, 0.0, 0.0);
     var PI = 3.141592654;
-    var ANGLE = PI / 6.0;
-    var slope = tan(ANGLE);
+    var slope = tan(cone_params.aperture);
 
     var tangent = vec3(1.0, 0.0, 0.0);
     var bitangent = vec3(0.0, 0.0, 1.0);
-    var normal = in.normal;
+    var normal = mapped_normal(in);
     if normal.y < 0.99 && normal.y > -0.99 {
         var tangent = normalize(cross(vec3(0.0, 1.0, 0.0), in.normal));
         var bitangent = normalize(cross(tangent, in.normal));
     }
 
-    indirect_light += cone_trace(in.model_pos, normal, slope, 8);
-    indirect_light += cone_trace(in.model_pos, 0.866 * tangent + 0.500 * normal + 0.000 * bitangent, slope, 8);
-    indirect_light += cone_trace(in.model_pos, 0.433 * tangent + 0.500 * normal + 0.750 * bitangent, slope, 8);
-    indirect_light += cone_trace(in.model_pos, -0.433 * tangent + 0.500 * normal + 0.750 * bitangent, slope, 8);
-    indirect_light += cone_trace(in.model_pos, -0.866 * tangent + 0.500 * normal + 0.000 * bitangent, slope, 8);
-    indirect_light += cone_trace(in.model_pos, -0.433 * tangent + 0.500 * normal + -0.750 * bitangent, slope, 8);
-    indirect_light += cone_trace(in.model_pos, 0.433 * tangent + 0.500 * normal + -0.750 * bitangent, slope, 8);
+    if (render_settings.flags & PROBE_GI) != 0u {
+        // The cones below sum seven radiance samples, irradiance / PI is the average one
+        indirect_light = vec4(probe_irradiance(in.model_pos, normal) * 7.0 / PI, 1.0);
+    } else {
+        // The sum is scaled to what the default seven cones add up to, so the cone count doesn't
+        // change the brightness
+        var cone_count = cone_params.cone_count;
+        for (var i = 0u; i < cone_count; i++) {
+            var direction = diffuse_cone_direction(i, cone_count, tangent, bitangent, normal);
+            indirect_light += cone_trace(in.model_pos, direction, slope, cone_params.max_distance);
+        }
+        indirect_light *= 7.0 / f32(cone_count);
+    }
+    indirect_light *= cone_params.gi_intensity;
 
-    var metallic_roughness = textureSample(metallic_roughness_texture, metallic_roughness_sampler, in.tex_coords);
-    var roughness = metallic_roughness.g;
+    var emission = textureSample(emissive_texture, emissive_sampler, material_uv(in.tex_coords, material.emissive_uv_transform)).rgb * material.emissive_factor.rgb;
+    var metallic_roughness = textureSample(metallic_roughness_texture, metallic_roughness_sampler, material_uv(in.tex_coords, material.metallic_roughness_uv_transform));
+    var roughness = metallic_roughness.g * material.roughness_factor;
+    var metallic = metallic_roughness.b * material.metallic_factor;
+    var planar_reflection_strength = material.planar_reflection;
+    if (render_settings.flags & NEUTRAL_MATERIAL) != 0u {
+        // Matches MaterialData::DEFAULT, alpha is kept so cutouts still work
+        color = vec4(0.5, 0.5, 0.5, color.a);
+        roughness = 1.0;
+        metallic = 0.0;
+        emission = vec3(0.0);
+        planar_reflection_strength = 0.0;
+    }
+    var albedo = color.rgb;
+    surface_data = vec4(octahedral_encode(normal), roughness, metallic);
 
     var direct_light_contribution = vec3(0.0, 0.0, 0.0);
     var sun = lights.lights[0];
-    var diffuse = clamp(dot(-sun.position.xyz, in.normal), 0.0, 1.0);
+    var diffuse = clamp(dot(-sun.position.xyz, normal), 0.0, 1.0);
+    // The prepass doesn't match what the mirrored camera sees
+    if (render_settings.flags & CONTACT_SHADOWS) != 0u && camera.mirror == 0.0 && shadow * diffuse > 0.0 {
+        shadow *= contact_shadow(in.model_pos, -sun.position.xyz);
+    }
     direct_light_contribution += shadow * diffuse * sun.intensity;
 
     for (var i = 1; i < lights.count; i++) {
@@ -210,15 +536,160 @@ fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
         var diff = light.position.xyz - in.model_pos * light.position.w;
         var dist = length(diff);
         diff /= dist;
-        var lambertian = clamp(dot(diff, in.normal), 0.0, 1.0);
+        var lambertian = clamp(dot(diff, normal), 0.0, 1.0) * spot_attenuation(light, diff);
         var falloff_amount = pow(dist, light.falloff);
-        direct_light_contribution += light.intensity * lambertian / falloff_amount;
+        var light_shadow = 1.0;
+        if has_shadow_tile(light) && lambertian > 0.0 {
+            var shadow_pos = spot_shadow_position(light, in.model_pos);
+            light_shadow = textureSampleCompareLevel(shadow_texture, shadow_sampler, shadow_pos.xy, shadow_pos.z);
+        }
+        direct_light_contribution += light.intensity * lambertian * light_shadow / falloff_amount;
     }
 
-    if color.a < material.alpha_cut_off {
+    // Only after the last implicit derivative, discarding makes the control flow non-uniform
+    if alpha_test && color.a < material.alpha_cut_off {
         discard;
     }
+    if (render_settings.flags & SDF_DISTANCE) != 0u {
+        // Free space half a meter above the surface, dark in corners and bright in open areas
+        var above = (in.model_pos + in.normal * 0.5 - voxel_grid.center) / voxel_grid.half_extent * 0.5 + 0.5;
+        return vec4(vec3(clamp(empty_space_distance(above) / 2.0, 0.0, 1.0)), 1.0);
+    }
+    if (render_settings.flags & MIP_LEVELS) != 0u {
+        return vec4(mip_level_color(base_color_mip_level(in.tex_coords, lod_bias)), 1.0);
+    }
+    if CONE_STEP_HEATMAP {
+        return vec4(viridis(f32(cone_steps) / render_settings.cone_step_heatmap_max), 1.0);
+    }
     color *= vec4(direct_light_contribution + indirect_light.rgb, 1.0);
-    color = vec4(uchimura_fixed(color.rgb), 1.0);
+    color += vec4(emission, 0.0);
+    if planar_reflection_strength > 0.0 && camera.mirror == 0.0 {
+        // The reflection was rendered with X flipped to keep the triangle winding
+        var uv = in.clip_position.xy / frame_globals.resolution;
+        var reflection = textureSampleLevel(planar_reflection, storage_sampler, vec2(1.0 - uv.x, uv.y), 0.0).rgb;
+        var view = normalize(camera.position.xyz - in.model_pos);
+        var f0 = mix(vec3(0.04), albedo, metallic);
+        var fresnel = f0 + (1.0 - f0) * pow(1.0 - clamp(dot(normal, view), 0.0, 1.0), 5.0);
+        var weight = fresnel * (1.0 - roughness) * (1.0 - roughness) * planar_reflection_strength;
+        color = vec4(mix(color.rgb, reflection, weight), color.a);
+    }
+    if (render_settings.flags & FOG) != 0u {
+        color = vec4(apply_fog(color.rgb, in.model_pos), color.a);
+    }
     return color;
-}
\ No newline at end of file
+}
+
+struct SkyOutput {
+    @builtin(position) position: vec4<f32>,
+    @location(0) ndc: vec2<f32>,
+}
+
+// Fullscreen triangle on the far plane, so only the background passes the depth test
+@vertex
+fn vs_sky(@builtin(vertex_index) index: u32) -> SkyOutput {
+    var out: SkyOutput;
+    var uv = vec2<f32>(f32((index << 1u) & 2u), f32(index & 2u));
+    out.ndc = uv * vec2(2.0, -2.0) + vec2(-1.0, 1.0);
+    out.position = vec4(out.ndc, 1.0, 1.0);
+    return out;
+}
+
+// Fog over the background, blended on top of the clear color. Rays that never climb out of the
+// fog reach full density at the horizon, same as distant geometry.
+@fragment
+fn fs_sky(in: SkyOutput) -> @location(0) vec4<f32> {
+    var far_point = camera.inverse_view_projection * vec4(in.ndc, 1.0, 1.0);
+    var direction = normalize(far_point.xyz / far_point.w - camera.position.xyz);
+    var height_change = render_settings.fog_height_falloff * direction.y;
+    var fog_amount = 1.0;
+    if height_change > 0.0001 {
+        fog_amount = 1.0 - exp(-fog_density_at_camera() / height_change);
+    }
+    return vec4(fog_inscatter(direction, 2.0 * voxel_grid.half_extent), fog_amount);
+}
+// Surfaces rougher than this get no reflections, their blurry ones aren't worth the march
+const SSR_MAX_ROUGHNESS: f32 = 0.6;
+// Fraction of the screen over which hits fade out towards its edges
+const SSR_EDGE_FADE: f32 = 0.1;
+
+// Reflections of one half resolution pixel: rgb is the reflected radiance, alpha how much of it
+// was found on screen. Misses trace a voxel cone as wide as the surface is rough.
+@fragment
+fn fs_ssr_trace(in: SkyOutput) -> @location(0) vec4<f32> {
+    var size = vec2<f32>(textureDimensions(prepass_depth));
+    var pixel = vec2<i32>((in.ndc * vec2(0.5, -0.5) + 0.5) * size);
+    var depth = textureLoad(prepass_depth, pixel, 0);
+    var surface = textureLoad(gbuffer, pixel, 0);
+    if depth >= 1.0 || surface.z > SSR_MAX_ROUGHNESS {
+        return vec4(0.0);
+    }
+
+    var world = camera.inverse_view_projection * vec4(in.ndc, depth, 1.0);
+    var position = world.xyz / world.w;
+    var normal = octahedral_decode(surface.xy);
+    var direction = reflect(normalize(position - camera.position.xyz), normal);
+
+    var steps = ssr_params.max_steps;
+    var step = direction * ssr_params.max_distance / f32(steps);
+    var confidence = 0.0;
+    var hit_uv = vec2(0.0);
+    for (var i = 1u; i <= steps; i++) {
+        var clip = camera.view_projection * vec4(position + step * f32(i), 1.0);
+        var uv = clip.xy / clip.w * vec2(0.5, -0.5) + 0.5;
+        if clip.w <= 0.0 || any(uv < vec2(0.0)) || any(uv >= vec2(1.0)) {
+            break;
+        }
+        var scene_depth = linear_depth(textureLoad(prepass_depth, vec2<i32>(uv * size), 0));
+        // Same test as the contact shadows, the bias keeps the ray from hitting its own surface
+        var delta = clip.w - scene_depth;
+        if delta > 0.002 * clip.w && delta < ssr_params.thickness {
+            // Hits near the screen edges or at the end of the ray are as likely to be wrong
+            var edge = min(uv, 1.0 - uv);
+            var edge_fade = clamp(min(edge.x, edge.y) / SSR_EDGE_FADE, 0.0, 1.0);
+            var distance_fade = clamp((1.0 - f32(i) / f32(steps)) * 4.0, 0.0, 1.0);
+            confidence = edge_fade * distance_fade;
+            hit_uv = uv;
+            break;
+        }
+    }
+
+    var reflected = vec3(0.0);
+    if confidence > 0.0 {
+        reflected = textureSampleLevel(ssr_input, ssr_sampler, hit_uv, 0.0).rgb;
+    }
+    if confidence < 1.0 {
+        // The cone runs until it leaves the grid, where it picks up the sky
+        var slope = tan(max(surface.z, 0.05) * 0.785398);
+        var fallback = cone_trace(position, direction, slope, 2.0 * voxel_grid.half_extent).rgb;
+        reflected = mix(fallback, reflected, confidence);
+    }
+    return vec4(reflected, confidence);
+}
+
+// Blends the traced reflections over the scene with premultiplied alpha, weighted by the Schlick
+// fresnel term and the smoothness of the surface
+@fragment
+fn fs_ssr_composite(in: SkyOutput) -> @location(0) vec4<f32> {
+    var size = vec2<f32>(textureDimensions(prepass_depth));
+    var uv = in.ndc * vec2(0.5, -0.5) + 0.5;
+    var pixel = vec2<i32>(uv * size);
+    var depth = textureLoad(prepass_depth, pixel, 0);
+    var surface = textureLoad(gbuffer, pixel, 0);
+    var reflection = textureSampleLevel(ssr_input, ssr_sampler, uv, 0.0);
+    if ssr_params.show_hit_mask != 0u {
+        return vec4(vec3(reflection.a), 1.0);
+    }
+    if depth >= 1.0 || surface.z > SSR_MAX_ROUGHNESS {
+        return vec4(0.0);
+    }
+
+    var world = camera.inverse_view_projection * vec4(in.ndc, depth, 1.0);
+    var view_direction = normalize(camera.position.xyz - world.xyz / world.w);
+    var normal = octahedral_decode(surface.xy);
+    // Metals reflect most of the light head on, without the base color the tint is lost
+    var f0 = mix(0.04, 1.0, surface.w);
+    var fresnel = f0 + (1.0 - f0) * pow(1.0 - clamp(dot(normal, view_direction), 0.0, 1.0), 5.0);
+    var smoothness = 1.0 - surface.z / SSR_MAX_ROUGHNESS;
+    var weight = fresnel * smoothness * smoothness;
+    return vec4(reflection.rgb * weight, weight);
+}
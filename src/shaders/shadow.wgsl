@@ -1,15 +1,8 @@
-struct MaterialData {
-    base_color_factor: vec4<f32>,
-    metallic_factor: f32,
-    roughness_factor: f32,
-    alpha_cut_off: f32,
-    filler: u32,
-}
 @group(0) @binding(0)
 var<uniform> view_projection: mat4x4<f32>;
 @group(1) @binding(0)
 var<uniform> model: mat4x4<f32>;
-@group(2) @binding(0)
+@group(1) @binding(1)
 var<uniform> material: MaterialData;
 @group(2) @binding(1)
 var base_color_texture: texture_2d<f32>;
@@ -36,9 +29,14 @@ fn vs_main(
 }
 
 
+// Opaque primitives write depth only, without a discard that would turn early depth testing off
 @fragment
 fn fs_main(in: VertexOutput) {
-    var color = textureSample(base_color_texture, base_color_sampler, in.texCoords) * material.base_color_factor;
+}
+
+@fragment
+fn fs_masked(in: VertexOutput) {
+    var color = textureSample(base_color_texture, base_color_sampler, material_uv(in.texCoords, material.base_color_uv_transform)) * material.base_color_factor;
     if (color.a < material.alpha_cut_off) {
         discard;
     }
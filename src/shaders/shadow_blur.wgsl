@@ -0,0 +1,58 @@
+struct ShadowBlurParams {
+    radius: i32,
+    sigma: f32,
+    filler: vec2<u32>,
+}
+
+@group(0) @binding(0)
+var<uniform> params: ShadowBlurParams;
+@group(0) @binding(1)
+var depth_texture: texture_depth_2d;
+@group(0) @binding(2)
+var moments_output: texture_storage_2d<rg32float, write>;
+@group(0) @binding(3)
+var moments_input: texture_2d<f32>;
+
+fn gaussian_weight(offset: i32) -> f32 {
+    var x = f32(offset);
+    return exp(-x * x / (2.0 * params.sigma * params.sigma));
+}
+
+// Turns depth into (depth, depth²) moments while blurring horizontally
+@compute @workgroup_size(8, 8, 1)
+fn blur_horizontal(@builtin(global_invocation_id) id: vec3<u32>) {
+    var size = vec2<i32>(textureDimensions(depth_texture));
+    var pixel = vec2<i32>(id.xy);
+    if pixel.x >= size.x || pixel.y >= size.y {
+        return;
+    }
+
+    var moments = vec2(0.0);
+    var total_weight = 0.0;
+    for (var i = -params.radius; i <= params.radius; i++) {
+        var depth = textureLoad(depth_texture, vec2(clamp(pixel.x + i, 0, size.x - 1), pixel.y), 0);
+        var weight = gaussian_weight(i);
+        moments += weight * vec2(depth, depth * depth);
+        total_weight += weight;
+    }
+    textureStore(moments_output, pixel, vec4(moments / total_weight, 0.0, 0.0));
+}
+
+@compute @workgroup_size(8, 8, 1)
+fn blur_vertical(@builtin(global_invocation_id) id: vec3<u32>) {
+    var size = vec2<i32>(textureDimensions(moments_input));
+    var pixel = vec2<i32>(id.xy);
+    if pixel.x >= size.x || pixel.y >= size.y {
+        return;
+    }
+
+    var moments = vec2(0.0);
+    var total_weight = 0.0;
+    for (var i = -params.radius; i <= params.radius; i++) {
+        var tap = textureLoad(moments_input, vec2(pixel.x, clamp(pixel.y + i, 0, size.y - 1)), 0).xy;
+        var weight = gaussian_weight(i);
+        moments += weight * tap;
+        total_weight += weight;
+    }
+    textureStore(moments_output, pixel, vec4(moments / total_weight, 0.0, 0.0));
+}
@@ -0,0 +1,44 @@
+@group(0) @binding(0)
+var<uniform> view_projection: mat4x4<f32>;
+
+@group(1) @binding(0)
+var shadow_texture: texture_depth_2d;
+@group(1) @binding(1)
+var shadow_sampler: sampler;
+@group(1) @binding(2)
+var<uniform> inverse_shadow_view_projection: mat4x4<f32>;
+
+// The shadow camera is orthographic, so the stored depth already is linear in view space and only
+// the cleared background needs to be told apart
+@fragment
+fn fs_depth(in: FullscreenOutput) -> @location(0) vec4<f32> {
+    var depth = textureSample(shadow_texture, shadow_sampler, in.uv);
+    if depth >= 1.0 {
+        return vec4(0.1, 0.1, 0.3, 1.0);
+    }
+    return vec4(vec3(depth), 1.0);
+}
+
+// Draws the 12 edges of the shadow camera's box as a line list
+@vertex
+fn vs_frustum(@builtin(vertex_index) index: u32) -> @builtin(position) vec4<f32> {
+    var edges = array<u32, 24>(
+        0u, 1u, 1u, 3u, 3u, 2u, 2u, 0u,
+        4u, 5u, 5u, 7u, 7u, 6u, 6u, 4u,
+        0u, 4u, 1u, 5u, 2u, 6u, 3u, 7u,
+    );
+    var corner = edges[index];
+    var clip_corner = vec4(
+        select(-1.0, 1.0, (corner & 1u) != 0u),
+        select(-1.0, 1.0, (corner & 2u) != 0u),
+        select(0.0, 1.0, (corner & 4u) != 0u),
+        1.0,
+    );
+    var world_position = inverse_shadow_view_projection * clip_corner;
+    return view_projection * vec4(world_position.xyz / world_position.w, 1.0);
+}
+
+@fragment
+fn fs_frustum() -> @location(0) vec4<f32> {
+    return vec4(1.0, 0.8, 0.0, 1.0);
+}
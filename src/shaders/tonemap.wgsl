@@ -0,0 +1,102 @@
+@group(0) @binding(0)
+var input_texture: texture_2d<f32>;
+@group(0) @binding(1)
+var input_sampler: sampler;
+@group(0) @binding(2)
+var<uniform> params: TonemapParams;
+@group(0) @binding(3)
+var<uniform> frame_globals: FrameGlobals;
+
+struct TonemapParams {
+    // Quantization steps of the output, 255 for 8 bit formats, 0 disables dithering
+    dither_levels: f32,
+    vignette_strength: f32,
+    grain_strength: f32,
+    // 1 if the output is quantized after sRGB encoding
+    srgb_output: u32,
+    // Brightest output value, 1 for SDR and the display's peak relative to paper white for HDR
+    max_brightness: f32,
+    // Scales the scene before the curve is applied
+    exposure: f32,
+    filler: vec2<f32>,
+}
+
+fn uchimura(x: vec3<f32>, P: f32, a: f32, m: f32, l: f32, c: f32, b: f32) -> vec3<f32> {
+    var l0 = ((P - m) * l) / a;
+    var L0 = m - m / a;
+    var L1 = m + (1.0 - m) / a;
+    var S0 = m + l0;
+    var S1 = m + a * l0;
+    var C2 = (a * P) / (P - S1);
+    var CP = -C2 / P;
+    var w0 = vec3(1.0 - smoothstep(vec3(0.0), vec3(m), x));
+    var w2 = vec3(step(vec3(m + l0), x));
+    var w1 = vec3(1.0 - w0 - w2);
+    var T = vec3(m * pow(x / m, vec3(c)) + b);
+    var S = vec3(P - (P - S1) * exp(CP * (x - S0)));
+    var L = vec3(m + a * (x - m));
+    return T * w0 + L * w1 + S * w2;
+}
+
+fn uchimura_fixed(x: vec3<f32>, max_brightness: f32) -> vec3<f32> {
+    var P = max_brightness;  // max display brightness
+    var a = 1.0;  // contrast
+    var m = 0.22; // linear section start
+    var l = 0.4;  // linear section length
+    var c = 1.33; // black
+    var b = 0.0;  // pedestal
+
+    return uchimura(x, P, a, m, l, c, b);
+}
+
+// pcg3d, uniform in [0, 1]
+fn hash(seed: vec3<u32>) -> f32 {
+    var v = seed * 1664525u + 1013904223u;
+    v.x += v.y * v.z;
+    v.y += v.z * v.x;
+    v.z += v.x * v.y;
+    v ^= v >> vec3(16u);
+    v.x += v.y * v.z;
+    v.y += v.z * v.x;
+    v.z += v.x * v.y;
+    return f32(v.x) / 4294967295.0;
+}
+
+fn linear_to_srgb(color: vec3<f32>) -> vec3<f32> {
+    var c = clamp(color, vec3(0.0), vec3(1.0));
+    return select(1.055 * pow(c, vec3(1.0 / 2.4)) - 0.055, c * 12.92, c <= vec3(0.0031308));
+}
+
+fn srgb_to_linear(color: vec3<f32>) -> vec3<f32> {
+    var c = clamp(color, vec3(0.0), vec3(1.0));
+    return select(pow((c + 0.055) / 1.055, vec3(2.4)), c / 12.92, c <= vec3(0.04045));
+}
+
+@fragment
+fn fs_main(in: FullscreenOutput) -> @location(0) vec4<f32> {
+    var hdr_color = textureSampleLevel(input_texture, input_sampler, in.uv, 0.0);
+    var color = uchimura_fixed(hdr_color.rgb * params.exposure, params.max_brightness);
+    var pixel = vec2<u32>(in.position.xy);
+
+    if params.vignette_strength > 0.0 {
+        var offset = in.uv - 0.5;
+        color *= 1.0 - params.vignette_strength * smoothstep(0.1, 0.5, dot(offset, offset));
+    }
+    if params.grain_strength > 0.0 {
+        // Scaled by the color so blacks stay black
+        var grain = hash(vec3(pixel, frame_globals.frame_index + 0x51ed27u)) - 0.5;
+        color += color * grain * params.grain_strength;
+    }
+    if params.dither_levels > 0.0 {
+        // Triangular noise of +-1 quantization step, applied in the space the output is
+        // quantized in. Keyed off the frame index so it averages out over time.
+        var noise = hash(vec3(pixel, frame_globals.frame_index))
+            + hash(vec3(pixel.yx, frame_globals.frame_index ^ 0x9e3779b9u)) - 1.0;
+        if params.srgb_output != 0u {
+            color = srgb_to_linear(linear_to_srgb(color) + noise / params.dither_levels);
+        } else {
+            color += noise / params.dither_levels;
+        }
+    }
+    return vec4(color, 1.0);
+}
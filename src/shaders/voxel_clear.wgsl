@@ -0,0 +1,15 @@
+@group(0) @binding(0)
+var output_texture: texture_storage_3d<rgba16float, write>;
+
+// Storage textures can't be cleared by a render pass and `clear_texture` isn't available on
+// WebGPU, so the base level is zeroed before every voxelization
+@compute @workgroup_size(4, 4, 4)
+fn comp_main(@builtin(global_invocation_id) global_id: vec3<u32>) {
+    var size = vec3<u32>(textureDimensions(output_texture));
+    
+    if (global_id.x >= size.x || global_id.y >= size.y || global_id.z >= size.z) {
+        return;
+    }
+    
+    textureStore(output_texture, vec3<i32>(global_id), vec4(0.0));
+}
@@ -0,0 +1,71 @@
+@group(0) @binding(0)
+var<uniform> camera: Camera;
+
+@group(1) @binding(0)
+var voxels: texture_3d<f32>;
+@group(1) @binding(1)
+var<uniform> voxel_grid: VoxelGridInfo;
+@group(1) @binding(2)
+var<uniform> params: VoxelDebugParams;
+
+struct VoxelDebugParams {
+    filler: vec3<u32>,
+    level: u32,
+}
+
+// Brightness of the faces facing along x, y and z, so neighbouring cells stay apart
+const FACE_SHADES: vec3<f32> = vec3<f32>(0.8, 1.0, 0.6);
+
+// Walks the cells of the selected mip level along the view ray and shows the first occupied one
+@fragment
+fn fs_voxels(in: FullscreenOutput) -> @location(0) vec4<f32> {
+    var ndc = vec2(in.uv.x * 2.0 - 1.0, 1.0 - in.uv.y * 2.0);
+    var far_point = camera.inverse_view_projection * vec4(ndc, 1.0, 1.0);
+    var direction = normalize(far_point.xyz / far_point.w - camera.position.xyz);
+
+    // In cells of the level, the grid spans [0, size) along every axis
+    var size = vec3<f32>(textureDimensions(voxels, params.level));
+    var grid_min = voxel_grid.center - voxel_grid.half_extent;
+    var origin = (camera.position.xyz - grid_min) / (2.0 * voxel_grid.half_extent) * size;
+    var inverse_direction = 1.0 / direction;
+    var t0 = -origin * inverse_direction;
+    var t1 = (size - origin) * inverse_direction;
+    var t_near = min(t0, t1);
+    var t_far = max(t0, t1);
+    var t_enter = max(max(t_near.x, t_near.y), max(t_near.z, 0.0));
+    var t_exit = min(t_far.x, min(t_far.y, t_far.z));
+    if t_enter >= t_exit {
+        return vec4(0.0, 0.0, 0.0, 1.0);
+    }
+
+    var cell = clamp(vec3<i32>(floor(origin + direction * t_enter)), vec3(0), vec3<i32>(size) - 1);
+    var cell_step = vec3<i32>(sign(direction));
+    var t_delta = abs(inverse_direction);
+    var t_next = (vec3<f32>(cell) + step(vec3(0.0), direction) - origin) * inverse_direction;
+    var face = select(select(vec3(0.0, 0.0, 1.0), vec3(0.0, 1.0, 0.0), t_near.y == t_enter), vec3(1.0, 0.0, 0.0), t_near.x == t_enter);
+    var max_steps = i32(size.x + size.y + size.z);
+    for (var i = 0; i < max_steps; i++) {
+        if any(cell < vec3(0)) || any(cell >= vec3<i32>(size)) {
+            break;
+        }
+        var voxel = textureLoad(voxels, cell, i32(params.level));
+        if voxel.a > 0.0 {
+            // Unlit cells still show up dimly, only empty space is black
+            return vec4((voxel.rgb / voxel.a + 0.05) * dot(face, FACE_SHADES), 1.0);
+        }
+        if t_next.x < t_next.y && t_next.x < t_next.z {
+            cell.x += cell_step.x;
+            t_next.x += t_delta.x;
+            face = vec3(1.0, 0.0, 0.0);
+        } else if t_next.y < t_next.z {
+            cell.y += cell_step.y;
+            t_next.y += t_delta.y;
+            face = vec3(0.0, 1.0, 0.0);
+        } else {
+            cell.z += cell_step.z;
+            t_next.z += t_delta.z;
+            face = vec3(0.0, 0.0, 1.0);
+        }
+    }
+    return vec4(0.0, 0.0, 0.0, 1.0);
+}
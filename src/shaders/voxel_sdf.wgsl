@@ -0,0 +1,86 @@
+// Jump flooding over the occupied cells of one voxel mip level. Every cell ends up with the packed
+// coordinate of the closest occupied cell it found, which the resolve pass turns into a distance.
+
+struct JumpStep {
+    step: u32,
+}
+
+@group(0) @binding(0)
+var voxels: texture_3d<f32>;
+@group(0) @binding(1)
+var seeds_in: texture_3d<u32>;
+@group(0) @binding(2)
+var seeds_out: texture_storage_3d<r32uint, write>;
+@group(0) @binding(3)
+var sdf_out: texture_storage_3d<r32float, write>;
+@group(0) @binding(4)
+var<uniform> jump: JumpStep;
+
+const NO_SEED: u32 = 0xffffffffu;
+
+fn pack_cell(cell: vec3<u32>) -> u32 {
+    return cell.x | (cell.y << 10u) | (cell.z << 20u);
+}
+
+fn unpack_cell(seed: u32) -> vec3<u32> {
+    return vec3(seed & 0x3ffu, (seed >> 10u) & 0x3ffu, seed >> 20u);
+}
+
+fn outside(cell: vec3<u32>) -> bool {
+    return any(cell >= vec3<u32>(textureDimensions(seeds_out)));
+}
+
+@compute @workgroup_size(4, 4, 4)
+fn seed(@builtin(global_invocation_id) cell: vec3<u32>) {
+    if outside(cell) {
+        return;
+    }
+    var occupied = textureLoad(voxels, vec3<i32>(cell), 0).a > 0.01;
+    textureStore(seeds_out, vec3<i32>(cell), vec4(select(NO_SEED, pack_cell(cell), occupied)));
+}
+
+@compute @workgroup_size(4, 4, 4)
+fn jump_flood(@builtin(global_invocation_id) cell: vec3<u32>) {
+    if outside(cell) {
+        return;
+    }
+    var size = vec3<i32>(textureDimensions(seeds_in));
+    var best_seed = NO_SEED;
+    var best_distance = 0xffffffffu;
+    for (var x = -1; x <= 1; x++) {
+        for (var y = -1; y <= 1; y++) {
+            for (var z = -1; z <= 1; z++) {
+                var neighbor = vec3<i32>(cell) + vec3(x, y, z) * i32(jump.step);
+                if any(neighbor < vec3(0)) || any(neighbor >= size) {
+                    continue;
+                }
+                var seed = textureLoad(seeds_in, neighbor, 0).r;
+                if seed == NO_SEED {
+                    continue;
+                }
+                var offset = vec3<i32>(unpack_cell(seed)) - vec3<i32>(cell);
+                var distance = u32(dot(offset, offset));
+                if distance < best_distance {
+                    best_distance = distance;
+                    best_seed = seed;
+                }
+            }
+        }
+    }
+    textureStore(seeds_out, vec3<i32>(cell), vec4(best_seed));
+}
+
+// Distances are in cells of the flooded mip level, one cell is taken off so the value stays a lower
+// bound for every voxel inside the occupied cell
+@compute @workgroup_size(4, 4, 4)
+fn resolve(@builtin(global_invocation_id) cell: vec3<u32>) {
+    if outside(cell) {
+        return;
+    }
+    var seed = textureLoad(seeds_in, vec3<i32>(cell), 0).r;
+    var distance = f32(textureDimensions(seeds_in).x);
+    if seed != NO_SEED {
+        distance = length(vec3<f32>(unpack_cell(seed)) - vec3<f32>(cell));
+    }
+    textureStore(sdf_out, vec3<i32>(cell), vec4(max(distance - 1.0, 0.0)));
+}
@@ -1,26 +1,10 @@
-
-struct MaterialData {
-    base_color_factor: vec4<f32>,
-    metallic_factor: f32,
-    roughness_factor: f32,
-    alpha_cut_off: f32,
-    filler: u32,
-}
-
-struct Light {
-    position: vec4<f32>,
-    intensity: vec3<f32>,
-    falloff: f32,
-}
-
-struct Lights {
-    filler: vec3<i32>,
-    count: i32,
-    lights: array<Light, 8>,
+struct VoxelizationCamera {
+    // Orthographic projections looking down the x, y and z axes
+    view_projections: array<mat4x4<f32>, 3>,
 }
 
 @group(0) @binding(0)
-var<uniform> view_projection: mat4x4<f32>;
+var<uniform> voxelization_camera: VoxelizationCamera;
 @group(0) @binding(1)
 var<uniform> shadow_view_projection: mat4x4<f32>;
 
@@ -31,13 +15,15 @@ var shadow_sampler: sampler_comparison;
 @group(1) @binding(2)
 var storage_texture: texture_storage_3d<rgba16float, write>;
 @group(1) @binding(3)
-var<uniform> lights: Lights;
+var<storage, read> lights: Lights;
+@group(1) @binding(4)
+var<uniform> voxel_grid: VoxelGridInfo;
 
 @group(2) @binding(0)
 var<uniform> model: mat4x4<f32>;
-
-@group(3) @binding(0)
+@group(2) @binding(1)
 var<uniform> material: MaterialData;
+
 @group(3) @binding(1)
 var base_color_texture: texture_2d<f32>;
 @group(3) @binding(2)
@@ -50,6 +36,10 @@ var metallic_roughness_sampler: sampler;
 var normal_texture: texture_2d<f32>;
 @group(3) @binding(6)
 var normal_sampler: sampler;
+@group(3) @binding(7)
+var emissive_texture: texture_2d<f32>;
+@group(3) @binding(8)
+var emissive_sampler: sampler;
 
 
 struct VertexInput {
@@ -71,50 +61,73 @@ fn vs_main(
 ) -> VertexOutput {
     var out: VertexOutput;
     out.model_pos = (model * vec4<f32>(input.position, 1.0)).xyz;
-    out.clip_position = view_projection * vec4<f32>(out.model_pos, 1.0);
     out.normal = input.normal;
     out.tex_coords = input.tex_coords;
     out.shadow_clip_position = shadow_view_projection * vec4<f32>(out.model_pos, 1.0);
+    // Project along the dominant axis of the normal to cover as many voxels as possible
     var abs_normal = abs(input.normal);
+    var axis = 2;
     if abs_normal.x > abs_normal.y && abs_normal.x > abs_normal.z {
-        out.clip_position = vec4(out.model_pos.yzx / vec3(20.0, 20.0, 20.0), 1.0);
+        axis = 0;
     } else if abs_normal.y > abs_normal.z {
-        out.clip_position = vec4(out.model_pos.xzy / vec3(20.0, 20.0, 20.0), 1.0);
-    } else {
-        out.clip_position = vec4(out.model_pos.zyx / vec3(20.0, 20.0, 20.0), 1.0);
+        axis = 1;
     }
-    out.clip_position.z *= 0.5;
-    out.clip_position.z += 0.5;
+    out.clip_position = voxelization_camera.view_projections[axis] * vec4<f32>(out.model_pos, 1.0);
     return out;
 }
 
 
+// The voxelization views have no front or back, double-sided surfaces take the light from
+// whichever side it comes
+fn facing_light(cos_angle: f32) -> f32 {
+    if material.double_sided != 0.0 {
+        return abs(cos_angle);
+    }
+    return cos_angle;
+}
+
 @fragment
 fn fs_main(in: VertexOutput) {
     var shadow_screen_pos = in.shadow_clip_position.xyz / in.shadow_clip_position.w * vec3<f32>(0.5, -0.5, 1.0) + vec3<f32>(0.5, 0.5, 0.0);
+    shadow_screen_pos = vec3(shadow_atlas_uv(lights.lights[0], shadow_screen_pos.xy), shadow_screen_pos.z);
     var shadow = textureSampleCompare(shadow_texture, shadow_sampler, shadow_screen_pos.xy, shadow_screen_pos.z - 0.004);
-    var color = textureSample(base_color_texture, base_color_sampler, in.tex_coords) * material.base_color_factor;
+    var color = textureSample(base_color_texture, base_color_sampler, material_uv(in.tex_coords, material.base_color_uv_transform)) * material.base_color_factor;
+    var emission = textureSample(emissive_texture, emissive_sampler, material_uv(in.tex_coords, material.emissive_uv_transform)).rgb * material.emissive_factor.rgb;
     if color.a < material.alpha_cut_off {
         discard;
     }
 
     var direct_light_contribution = vec3(0.0, 0.0, 0.0);
     var sun = lights.lights[0];
-    var diffuse = clamp(dot(-sun.position.xyz, in.normal), 0.0, 1.0);
+    var diffuse = clamp(facing_light(dot(-sun.position.xyz, in.normal)), 0.0, 1.0);
     direct_light_contribution += clamp(shadow * diffuse, 0.0, 1.0) * sun.intensity;
-    
-    // for (var i = 1; i < lights.count; i++) {
-    //     var light = lights.lights[i];
-    //     var diff = light.position.xyz - in.model_pos * light.position.w;
-    //     var dist = length(diff);
-    //     diff /= dist;
-    //     var lambertian = clamp(dot(diff, in.normal), 0.0, 1.0);
-    //     var falloff_amount = pow(dist, light.falloff);
-    //     direct_light_contribution += light.intensity * lambertian / falloff_amount;
-    // }
+
+    // Point lights cast no shadows, injected into the grid they'd light the voxels behind every
+    // wall around them, so by default only spot lights bounce. See `gi_intensity`. Spot lights
+    // without a tile of the shadow atlas leak the same way.
+    var cell_size = voxel_grid.half_extent * 2.0 / f32(textureDimensions(storage_texture).x);
+    for (var i = 1; i < lights.count; i++) {
+        var light = lights.lights[i];
+        if light.gi_intensity <= 0.0 {
+            continue;
+        }
+        var diff = light.position.xyz - in.model_pos * light.position.w;
+        var dist = length(diff);
+        diff /= dist;
+        var lambertian = clamp(facing_light(dot(diff, in.normal)), 0.0, 1.0) * spot_attenuation(light, diff);
+        var falloff_amount = pow(max(dist, light.injection_radius * cell_size), light.falloff);
+        if has_shadow_tile(light) && lambertian > 0.0 {
+            var shadow_pos = spot_shadow_position(light, in.model_pos);
+            lambertian *= textureSampleCompareLevel(shadow_texture, shadow_sampler, shadow_pos.xy, shadow_pos.z);
+        }
+        direct_light_contribution += light.intensity * light.gi_intensity * lambertian / falloff_amount;
+    }
 
     color *= vec4(direct_light_contribution, 1.0);
+    // Emission is stored like reflected light, so cones pick it up and it lights its surroundings
+    color += vec4(emission, 0.0);
     var texture_size = vec3<f32>(textureDimensions(storage_texture));
-    var pixel_pos = vec3<i32>(in.model_pos / 20.0 * texture_size / 2.0 + texture_size / 2.0);
+    var grid_pos = (in.model_pos - voxel_grid.center) / voxel_grid.half_extent;
+    var pixel_pos = vec3<i32>(grid_pos * texture_size / 2.0 + texture_size / 2.0);
     textureStore(storage_texture, pixel_pos, color);
 }
\ No newline at end of file
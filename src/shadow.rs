@@ -0,0 +1,461 @@
+use cgmath::{Matrix4, SquareMatrix};
+use wgpu::util::DeviceExt;
+
+use crate::texture::Texture;
+
+const MOMENTS_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rg32Float;
+const WORKGROUP_SIZE: u32 = 8;
+/// Distance of the shadow inspector from the screen edges in pixels
+const INSPECTOR_MARGIN: f32 = 16.0;
+
+pub struct ShadowSettings {
+    /// Sample the blurred shadow moments instead of doing a hard depth comparison
+    pub soft: bool,
+    /// Move the shadow camera in whole texels, see `ShadowCamera::set_texel_snapping`
+    pub texel_snapping: bool,
+    /// Gaussian blur radius in shadow map texels
+    pub blur_radius: u32,
+}
+
+impl Default for ShadowSettings {
+    fn default() -> Self {
+        Self {
+            soft: false,
+            texel_snapping: true,
+            blur_radius: 4,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct ShadowBlurParams {
+    radius: i32,
+    sigma: f32,
+    filler: [u32; 2],
+}
+
+crate::assert_uniform_layout!(ShadowBlurParams, size: 16, {
+    radius: 0,
+    sigma: 4,
+    filler: 8,
+});
+
+impl ShadowBlurParams {
+    fn new(settings: &ShadowSettings) -> Self {
+        Self {
+            radius: settings.blur_radius as i32,
+            sigma: (settings.blur_radius as f32 * 0.5).max(0.5),
+            filler: [0, 0],
+        }
+    }
+}
+
+/// Separable Gaussian blur over the shadow map. The depth is turned into (depth, depth²) moments
+/// on the way, so the blurred result can be used for variance shadow mapping. The shadow map is
+/// only rendered once, so the blur radius is fixed when this is created.
+pub struct ShadowBlur {
+    horizontal_pipeline: wgpu::ComputePipeline,
+    vertical_pipeline: wgpu::ComputePipeline,
+    horizontal_bind_group: wgpu::BindGroup,
+    vertical_bind_group: wgpu::BindGroup,
+    pub moments_view: wgpu::TextureView,
+    width: u32,
+    height: u32,
+}
+
+impl ShadowBlur {
+    pub fn new(
+        device: &wgpu::Device,
+        shadow_depth_texture: &Texture,
+        settings: &ShadowSettings,
+    ) -> Self {
+        let size = shadow_depth_texture.texture.size();
+        let create_moments_view = |label: &str| {
+            device
+                .create_texture(&wgpu::TextureDescriptor {
+                    label: Some(label),
+                    size,
+                    mip_level_count: 1,
+                    sample_count: 1,
+                    dimension: wgpu::TextureDimension::D2,
+                    format: MOMENTS_FORMAT,
+                    usage: wgpu::TextureUsages::STORAGE_BINDING
+                        | wgpu::TextureUsages::TEXTURE_BINDING,
+                    view_formats: &[MOMENTS_FORMAT],
+                })
+                .create_view(&wgpu::TextureViewDescriptor::default())
+        };
+        let intermediate_view = create_moments_view("Shadow blur intermediate texture");
+        let moments_view = create_moments_view("Shadow moments texture");
+
+        let params_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Shadow blur params buffer"),
+            contents: bytemuck::cast_slice(&[ShadowBlurParams::new(settings)]),
+            usage: wgpu::BufferUsages::UNIFORM,
+        });
+
+        let shader_module = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Shadow blur shader module"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("shaders/shadow_blur.wgsl").into()),
+        });
+
+        let params_entry = wgpu::BindGroupLayoutEntry {
+            binding: 0,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Uniform,
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        let output_entry = wgpu::BindGroupLayoutEntry {
+            binding: 2,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::StorageTexture {
+                access: wgpu::StorageTextureAccess::WriteOnly,
+                format: MOMENTS_FORMAT,
+                view_dimension: wgpu::TextureViewDimension::D2,
+            },
+            count: None,
+        };
+
+        let horizontal_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                label: Some("Shadow blur horizontal bind group layout"),
+                entries: &[
+                    params_entry,
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 1,
+                        visibility: wgpu::ShaderStages::COMPUTE,
+                        ty: wgpu::BindingType::Texture {
+                            sample_type: wgpu::TextureSampleType::Depth,
+                            view_dimension: wgpu::TextureViewDimension::D2,
+                            multisampled: false,
+                        },
+                        count: None,
+                    },
+                    output_entry,
+                ],
+            });
+        let vertical_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                label: Some("Shadow blur vertical bind group layout"),
+                entries: &[
+                    params_entry,
+                    output_entry,
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 3,
+                        visibility: wgpu::ShaderStages::COMPUTE,
+                        ty: wgpu::BindingType::Texture {
+                            sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                            view_dimension: wgpu::TextureViewDimension::D2,
+                            multisampled: false,
+                        },
+                        count: None,
+                    },
+                ],
+            });
+
+        let horizontal_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Shadow blur horizontal bind group"),
+            layout: &horizontal_bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: params_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(&shadow_depth_texture.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::TextureView(&intermediate_view),
+                },
+            ],
+        });
+        let vertical_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Shadow blur vertical bind group"),
+            layout: &vertical_bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: params_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::TextureView(&moments_view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: wgpu::BindingResource::TextureView(&intermediate_view),
+                },
+            ],
+        });
+
+        let create_pipeline = |label: &str, layout: &wgpu::BindGroupLayout, entry_point| {
+            let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some(format!("{} pipeline layout", label).as_str()),
+                bind_group_layouts: &[layout],
+                push_constant_ranges: &[],
+            });
+            device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                label: Some(format!("{} pipeline", label).as_str()),
+                layout: Some(&pipeline_layout),
+                module: &shader_module,
+                entry_point,
+            })
+        };
+
+        Self {
+            horizontal_pipeline: create_pipeline(
+                "Shadow blur horizontal",
+                &horizontal_bind_group_layout,
+                "blur_horizontal",
+            ),
+            vertical_pipeline: create_pipeline(
+                "Shadow blur vertical",
+                &vertical_bind_group_layout,
+                "blur_vertical",
+            ),
+            horizontal_bind_group,
+            vertical_bind_group,
+            moments_view,
+            width: size.width,
+            height: size.height,
+        }
+    }
+
+    pub fn record(&self, encoder: &mut wgpu::CommandEncoder) {
+        let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some("Shadow blur compute pass"),
+        });
+        let workgroups_x = self.width.div_ceil(WORKGROUP_SIZE);
+        let workgroups_y = self.height.div_ceil(WORKGROUP_SIZE);
+
+        compute_pass.set_pipeline(&self.horizontal_pipeline);
+        compute_pass.set_bind_group(0, &self.horizontal_bind_group, &[]);
+        compute_pass.dispatch_workgroups(workgroups_x, workgroups_y, 1);
+
+        compute_pass.set_pipeline(&self.vertical_pipeline);
+        compute_pass.set_bind_group(0, &self.vertical_bind_group, &[]);
+        compute_pass.dispatch_workgroups(workgroups_x, workgroups_y, 1);
+    }
+}
+
+/// Debug view of the shadow map in the bottom right corner of the screen, with the shadow
+/// camera's box drawn as lines into the scene.
+pub struct ShadowInspector {
+    inverse_buffer: wgpu::Buffer,
+    depth_pipeline: wgpu::RenderPipeline,
+    frustum_pipeline: wgpu::RenderPipeline,
+    bind_group: wgpu::BindGroup,
+}
+
+impl ShadowInspector {
+    /// `camera_bind_group_layout` has to start with the view projection of the main camera.
+    pub fn new(
+        device: &wgpu::Device,
+        shadow_depth_texture: &Texture,
+        shadow_view_projection: Matrix4<f32>,
+        camera_bind_group_layout: &wgpu::BindGroupLayout,
+        format: wgpu::TextureFormat,
+    ) -> Self {
+        let inverse_shadow_view_projection: [[f32; 4]; 4] = shadow_view_projection
+            .invert()
+            .expect("Shadow camera projection isn't invertible")
+            .into();
+        let inverse_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Shadow inspector inverse view projection buffer"),
+            contents: bytemuck::cast_slice(&[inverse_shadow_view_projection]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Shadow inspector bind group layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Depth,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::NonFiltering),
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::VERTEX,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Shadow inspector bind group"),
+            layout: &bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&shadow_depth_texture.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(
+                        shadow_depth_texture
+                            .depth_sampler
+                            .as_ref()
+                            .expect("Shadow map has no depth sampler"),
+                    ),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: inverse_buffer.as_entire_binding(),
+                },
+            ],
+        });
+
+        let shader_module = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Shadow inspector shader module"),
+            source: wgpu::ShaderSource::Wgsl(
+                concat!(
+                    include_str!("shaders/fullscreen.wgsl"),
+                    include_str!("shaders/shadow_inspector.wgsl")
+                )
+                .into(),
+            ),
+        });
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Shadow inspector pipeline layout"),
+            bind_group_layouts: &[camera_bind_group_layout, &bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let create_pipeline = |label: &str, vs_entry, fs_entry, topology| {
+            device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: Some(label),
+                layout: Some(&pipeline_layout),
+                vertex: wgpu::VertexState {
+                    module: &shader_module,
+                    entry_point: vs_entry,
+                    buffers: &[],
+                },
+                primitive: wgpu::PrimitiveState {
+                    topology,
+                    ..Default::default()
+                },
+                depth_stencil: None,
+                multisample: wgpu::MultisampleState::default(),
+                fragment: Some(wgpu::FragmentState {
+                    module: &shader_module,
+                    entry_point: fs_entry,
+                    targets: &[Some(wgpu::ColorTargetState {
+                        format,
+                        blend: None,
+                        write_mask: wgpu::ColorWrites::ALL,
+                    })],
+                }),
+                multiview: None,
+            })
+        };
+
+        Self {
+            inverse_buffer,
+            depth_pipeline: create_pipeline(
+                "Shadow inspector depth pipeline",
+                "vs_fullscreen",
+                "fs_depth",
+                wgpu::PrimitiveTopology::TriangleList,
+            ),
+            frustum_pipeline: create_pipeline(
+                "Shadow inspector frustum pipeline",
+                "vs_frustum",
+                "fs_frustum",
+                wgpu::PrimitiveTopology::LineList,
+            ),
+            bind_group,
+        }
+    }
+
+    /// Has to be called whenever the shadow camera moved, or the frustum is drawn where it was.
+    pub fn set_shadow_view_projection(
+        &self,
+        queue: &wgpu::Queue,
+        shadow_view_projection: Matrix4<f32>,
+    ) {
+        let inverse_shadow_view_projection: [[f32; 4]; 4] = shadow_view_projection
+            .invert()
+            .expect("Shadow camera projection isn't invertible")
+            .into();
+        queue.write_buffer(
+            &self.inverse_buffer,
+            0,
+            bytemuck::cast_slice(&[inverse_shadow_view_projection]),
+        );
+    }
+
+    /// Draws on top of `output`, which is `width` by `height` pixels.
+    pub fn record(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        camera_bind_group: &wgpu::BindGroup,
+        output: &wgpu::TextureView,
+        width: u32,
+        height: u32,
+    ) {
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Shadow inspector render pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: output,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: true,
+                },
+            })],
+            depth_stencil_attachment: None,
+        });
+        render_pass.set_bind_group(0, camera_bind_group, &[]);
+        render_pass.set_bind_group(1, &self.bind_group, &[]);
+
+        render_pass.set_pipeline(&self.frustum_pipeline);
+        render_pass.draw(0..24, 0..1);
+
+        let size = (width.min(height) / 3) as f32;
+        render_pass.set_viewport(
+            (width as f32 - size - INSPECTOR_MARGIN).max(0.0),
+            (height as f32 - size - INSPECTOR_MARGIN).max(0.0),
+            size,
+            size,
+            0.0,
+            1.0,
+        );
+        render_pass.set_pipeline(&self.depth_pipeline);
+        render_pass.draw(0..3, 0..1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn blur_params_match_wgsl() {
+        crate::util::assert_wgsl_layout::<ShadowBlurParams>(
+            include_str!("shaders/shadow_blur.wgsl"),
+            "ShadowBlurParams",
+        );
+    }
+}
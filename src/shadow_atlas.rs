@@ -0,0 +1,121 @@
+use std::collections::HashMap;
+
+use crate::gltf_loader::{DrawParams, Viewport};
+
+/// Smallest tile handed out, lights that can't get one this big cast no shadows
+const MIN_TILE_SIZE: u32 = 256;
+
+/// Square part of the shadow atlas a light renders its shadow map into, in texels.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct ShadowTile {
+    pub x: u32,
+    pub y: u32,
+    pub size: u32,
+}
+
+impl ShadowTile {
+    /// Scale and offset from uv in the light's own shadow map to uv in an atlas of `atlas_size`,
+    /// laid out like `shadow_uv_transform` of `Light` in lights.wgsl.
+    pub fn uv_transform(&self, atlas_size: u32) -> [f32; 4] {
+        let atlas_size = atlas_size as f32;
+        [
+            self.size as f32 / atlas_size,
+            self.size as f32 / atlas_size,
+            self.x as f32 / atlas_size,
+            self.y as f32 / atlas_size,
+        ]
+    }
+
+    /// Restricts the draws of a shadow pass to the tile.
+    pub fn draw_params(&self) -> DrawParams {
+        DrawParams {
+            viewport: Some(Viewport {
+                x: self.x as f32,
+                y: self.y as f32,
+                width: self.size as f32,
+                height: self.size as f32,
+            }),
+            scissor: Some([self.x, self.y, self.size, self.size]),
+            sort_origin: None,
+            frustum: None,
+        }
+    }
+}
+
+/// Hands out power of two tiles of one square depth texture, so every shadow casting light
+/// shares a single texture and the bind group layouts don't depend on the light count. Lights
+/// are keyed by their slot in the lights buffer, the sun is 0.
+pub struct ShadowAtlas {
+    size: u32,
+    /// Unassigned tiles, bigger ones are split into quarters as smaller ones are needed
+    free_tiles: Vec<ShadowTile>,
+    assigned: HashMap<usize, ShadowTile>,
+}
+
+impl ShadowAtlas {
+    pub fn new(size: u32) -> Self {
+        assert!(
+            size.is_power_of_two(),
+            "Shadow atlas size {} isn't a power of two",
+            size
+        );
+        Self {
+            size,
+            free_tiles: vec![ShadowTile { x: 0, y: 0, size }],
+            assigned: HashMap::new(),
+        }
+    }
+
+    pub fn size(&self) -> u32 {
+        self.size
+    }
+
+    /// Gives `light` a tile of `preferred_size`, or the biggest smaller one that is still free.
+    /// Allocating the most important lights first gives them the sharpest shadows. A tile the
+    /// light had before is freed first, `None` means it casts no shadows.
+    pub fn allocate(&mut self, light: usize, preferred_size: u32) -> Option<ShadowTile> {
+        self.free(light);
+        let mut size = preferred_size.next_power_of_two().min(self.size);
+        while size >= MIN_TILE_SIZE {
+            if let Some(tile) = self.take_tile(size) {
+                self.assigned.insert(light, tile);
+                return Some(tile);
+            }
+            size /= 2;
+        }
+        None
+    }
+
+    /// Returns the tile of `light` to the atlas, e.g. when the light was removed. Split tiles
+    /// aren't merged again, a new atlas with every light allocated again defragments it.
+    pub fn free(&mut self, light: usize) {
+        if let Some(tile) = self.assigned.remove(&light) {
+            self.free_tiles.push(tile);
+        }
+    }
+
+    pub fn tile(&self, light: usize) -> Option<ShadowTile> {
+        self.assigned.get(&light).copied()
+    }
+
+    /// Takes the smallest free tile that fits `size` and splits it down to exactly that size.
+    fn take_tile(&mut self, size: u32) -> Option<ShadowTile> {
+        let index = (0..self.free_tiles.len())
+            .filter(|&index| self.free_tiles[index].size >= size)
+            .min_by_key(|&index| self.free_tiles[index].size)?;
+        let mut tile = self.free_tiles.swap_remove(index);
+        while tile.size > size {
+            let half = tile.size / 2;
+            self.free_tiles
+                .extend(
+                    [(half, 0), (0, half), (half, half)].map(|(dx, dy)| ShadowTile {
+                        x: tile.x + dx,
+                        y: tile.y + dy,
+                        size: half,
+                    }),
+                );
+            tile.size = half;
+        }
+        Some(tile)
+    }
+}
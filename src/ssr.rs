@@ -0,0 +1,353 @@
+use wgpu::util::DeviceExt;
+
+use crate::{shader::Shader, texture::Texture, util};
+
+/// Format of the surface data the main pass writes next to the color: the octahedral encoded
+/// normal, the roughness and the metalness. Same as the HDR targets, so it's created like one.
+pub const GBUFFER_FORMAT: wgpu::TextureFormat = Texture::HDR_FORMAT;
+/// The reflections are traced at this fraction of the surface resolution
+pub const REFLECTION_SCALE: u32 = 2;
+
+pub struct SsrSettings {
+    pub enabled: bool,
+    /// Depth buffer samples each reflected ray takes before giving up
+    pub max_steps: u32,
+    /// World space length of the reflected rays
+    pub max_distance: f32,
+    /// How far behind the depth buffer a ray can pass and still hit it, in world units
+    pub thickness: f32,
+    /// Shows how confident the screen space hit is instead of the reflections, black pixels use
+    /// the voxel cone or the sky
+    pub show_hit_mask: bool,
+}
+
+impl Default for SsrSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            max_steps: 32,
+            max_distance: 8.0,
+            thickness: 0.2,
+            show_hit_mask: false,
+        }
+    }
+}
+
+impl SsrSettings {
+    /// Applies the `ssr`, `ssr_steps`, `ssr_distance` and `ssr_thickness` query parameters.
+    pub fn from_query() -> Self {
+        let mut settings = Self::default();
+        if let Some(enabled) = util::query_param("ssr") {
+            settings.enabled = enabled != "0";
+        }
+        if let Some(steps) = util::query_param("ssr_steps").and_then(|steps| steps.parse().ok()) {
+            settings.max_steps = steps;
+        }
+        if let Some(distance) =
+            util::query_param("ssr_distance").and_then(|distance| distance.parse().ok())
+        {
+            settings.max_distance = distance;
+        }
+        if let Some(thickness) =
+            util::query_param("ssr_thickness").and_then(|thickness| thickness.parse().ok())
+        {
+            settings.thickness = thickness;
+        }
+        settings
+    }
+}
+
+/// Matches `SsrParams` in common.wgsl.
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct SsrParams {
+    max_steps: u32,
+    max_distance: f32,
+    thickness: f32,
+    show_hit_mask: u32,
+}
+
+crate::assert_uniform_layout!(SsrParams, size: 16, {
+    max_steps: 0,
+    max_distance: 4,
+    thickness: 8,
+    show_hit_mask: 12,
+});
+
+impl SsrParams {
+    fn new(settings: &SsrSettings) -> Self {
+        Self {
+            max_steps: settings.max_steps.max(1),
+            max_distance: settings.max_distance,
+            thickness: settings.thickness,
+            show_hit_mask: settings.show_hit_mask as u32,
+        }
+    }
+}
+
+/// Screen space reflections on top of the main pass. A half resolution pass marches the depth
+/// prepass along the reflected view ray and samples the lit scene where it hits, rays that leave
+/// the screen or miss trace a voxel cone instead, which falls back to the sky once it leaves the
+/// grid. A full resolution pass then blends the reflections over the scene by the fresnel term
+/// and the smoothness of the surface.
+///
+/// Both passes are entry points of the main shader, since the fallback needs its cone tracing.
+/// They bind the main pass's groups 0 and 1, plus a group 2 of their own.
+pub struct Ssr {
+    params_buffer: wgpu::Buffer,
+    bind_group_layout: wgpu::BindGroupLayout,
+    sampler: wgpu::Sampler,
+    trace_pipeline: wgpu::RenderPipeline,
+    composite_pipeline: wgpu::RenderPipeline,
+    /// Reads the scene color, recreated on resize along with the textures it binds
+    trace_bind_group: wgpu::BindGroup,
+    /// Reads the traced reflections
+    composite_bind_group: wgpu::BindGroup,
+}
+
+/// Textures the SSR passes read, all of them are recreated when the surface is resized.
+pub struct SsrTargets<'a> {
+    pub scene_color: &'a wgpu::TextureView,
+    pub gbuffer: &'a Texture,
+    pub reflection: &'a Texture,
+}
+
+impl Ssr {
+    pub fn new(
+        device: &wgpu::Device,
+        shader: &Shader,
+        camera_bind_group_layout: &wgpu::BindGroupLayout,
+        texture_bind_group_layout: &wgpu::BindGroupLayout,
+        settings: &SsrSettings,
+        targets: &SsrTargets,
+    ) -> Self {
+        let params_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("SSR params buffer"),
+            contents: bytemuck::cast_slice(&[SsrParams::new(settings)]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        let texture_entry = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Texture {
+                sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                view_dimension: wgpu::TextureViewDimension::D2,
+                multisampled: false,
+            },
+            count: None,
+        };
+        // Bindings 0 and 1 of group 2 are the model matrix and material of the scene entry points
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("SSR bind group layout"),
+            entries: &[
+                texture_entry(2),
+                texture_entry(3),
+                wgpu::BindGroupLayoutEntry {
+                    binding: 4,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 5,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+        });
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("SSR sampler"),
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("SSR pipeline layout"),
+            bind_group_layouts: &[
+                camera_bind_group_layout,
+                texture_bind_group_layout,
+                &bind_group_layout,
+            ],
+            push_constant_ranges: &[],
+        });
+        let create_pipeline = |label: &str, fs_entry, blend| {
+            device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: Some(label),
+                layout: Some(&pipeline_layout),
+                vertex: wgpu::VertexState {
+                    module: &shader.module,
+                    entry_point: "vs_sky",
+                    buffers: &[],
+                },
+                primitive: wgpu::PrimitiveState::default(),
+                depth_stencil: None,
+                multisample: wgpu::MultisampleState::default(),
+                fragment: Some(wgpu::FragmentState {
+                    module: &shader.module,
+                    entry_point: fs_entry,
+                    targets: &[Some(wgpu::ColorTargetState {
+                        format: Texture::HDR_FORMAT,
+                        blend,
+                        write_mask: wgpu::ColorWrites::ALL,
+                    })],
+                }),
+                multiview: None,
+            })
+        };
+
+        let (trace_bind_group, composite_bind_group) = Self::create_bind_groups(
+            device,
+            &bind_group_layout,
+            &sampler,
+            &params_buffer,
+            targets,
+        );
+
+        Self {
+            params_buffer,
+            bind_group_layout,
+            sampler,
+            trace_pipeline: create_pipeline("SSR trace pipeline", "fs_ssr_trace", None),
+            composite_pipeline: create_pipeline(
+                "SSR composite pipeline",
+                "fs_ssr_composite",
+                Some(wgpu::BlendState::PREMULTIPLIED_ALPHA_BLENDING),
+            ),
+            trace_bind_group,
+            composite_bind_group,
+        }
+    }
+
+    /// Has to be called whenever any of the targets was recreated, e.g. on resize.
+    pub fn resize(&mut self, device: &wgpu::Device, targets: &SsrTargets) {
+        (self.trace_bind_group, self.composite_bind_group) = Self::create_bind_groups(
+            device,
+            &self.bind_group_layout,
+            &self.sampler,
+            &self.params_buffer,
+            targets,
+        );
+    }
+
+    fn create_bind_groups(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        sampler: &wgpu::Sampler,
+        params_buffer: &wgpu::Buffer,
+        targets: &SsrTargets,
+    ) -> (wgpu::BindGroup, wgpu::BindGroup) {
+        let create_bind_group = |label: &str, input: &wgpu::TextureView| {
+            device.create_bind_group(&wgpu::BindGroupDescriptor {
+                label: Some(label),
+                layout,
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: 2,
+                        resource: wgpu::BindingResource::TextureView(&targets.gbuffer.view),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 3,
+                        resource: wgpu::BindingResource::TextureView(input),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 4,
+                        resource: wgpu::BindingResource::Sampler(sampler),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 5,
+                        resource: params_buffer.as_entire_binding(),
+                    },
+                ],
+            })
+        };
+        (
+            create_bind_group("SSR trace bind group", targets.scene_color),
+            create_bind_group("SSR composite bind group", &targets.reflection.view),
+        )
+    }
+
+    pub fn update(&self, queue: &wgpu::Queue, settings: &SsrSettings) {
+        queue.write_buffer(
+            &self.params_buffer,
+            0,
+            bytemuck::cast_slice(&[SsrParams::new(settings)]),
+        );
+    }
+
+    /// Traces the reflections of the scene color into `reflection`, rgb is the reflected
+    /// radiance and alpha how much of it came from the screen.
+    pub fn record_trace(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        camera_bind_group: &wgpu::BindGroup,
+        texture_bind_group: &wgpu::BindGroup,
+        reflection: &wgpu::TextureView,
+    ) {
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("SSR trace render pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: reflection,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT),
+                    store: true,
+                },
+            })],
+            depth_stencil_attachment: None,
+        });
+        render_pass.set_pipeline(&self.trace_pipeline);
+        render_pass.set_bind_group(0, camera_bind_group, &[]);
+        render_pass.set_bind_group(1, texture_bind_group, &[]);
+        render_pass.set_bind_group(2, &self.trace_bind_group, &[]);
+        render_pass.draw(0..3, 0..1);
+    }
+
+    /// Blends the traced reflections over `scene_color`.
+    pub fn record_composite(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        camera_bind_group: &wgpu::BindGroup,
+        texture_bind_group: &wgpu::BindGroup,
+        scene_color: &wgpu::TextureView,
+    ) {
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("SSR composite render pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: scene_color,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: true,
+                },
+            })],
+            depth_stencil_attachment: None,
+        });
+        render_pass.set_pipeline(&self.composite_pipeline);
+        render_pass.set_bind_group(0, camera_bind_group, &[]);
+        render_pass.set_bind_group(1, texture_bind_group, &[]);
+        render_pass.set_bind_group(2, &self.composite_bind_group, &[]);
+        render_pass.draw(0..3, 0..1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn params_match_wgsl() {
+        crate::util::assert_wgsl_layout::<SsrParams>(
+            &crate::main_shader_source(false),
+            "SsrParams",
+        );
+    }
+}
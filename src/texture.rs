@@ -1,55 +1,115 @@
+#[cfg(target_arch = "wasm32")]
 use wasm_bindgen::JsCast;
-use web_sys::console;
 
+use crate::gltf_loader::LoadError;
+#[cfg(target_arch = "wasm32")]
 use crate::image_future::ImageFuture;
+use crate::mip2d::full_mip_level_count;
 
 pub struct Texture {
     pub texture: wgpu::Texture,
     pub view: wgpu::TextureView,
     pub sampler: wgpu::Sampler,
+    /// Only set for depth textures, a nearest neighbour sampler without comparison for reading
+    /// the stored depth itself while `sampler` does the depth test
+    pub depth_sampler: Option<wgpu::Sampler>,
 }
 
 impl Texture {
+    #[cfg(target_arch = "wasm32")]
     pub async fn from_url(
         device: &wgpu::Device,
         queue: &wgpu::Queue,
         uri: &str,
         label: &str,
-    ) -> Self {
-        let window = web_sys::window().expect("No global window");
-        let document = window.document().expect("No document");
-        console::log_1(&uri.into());
-        let img = ImageFuture::new(uri).await.unwrap();
+    ) -> Result<Self, LoadError> {
+        let decode_error = |reason: String| LoadError::ImageDecode {
+            uri: uri.to_string(),
+            reason,
+        };
+        let document = web_sys::window()
+            .and_then(|window| window.document())
+            .ok_or_else(|| decode_error("there's no document to decode it in".to_string()))?;
+        log::debug!("Loading texture {}", uri);
+        // The browser doesn't tell apart a missing file from one it can't decode
+        let img = ImageFuture::new(uri)
+            .await
+            .map_err(|()| decode_error("the browser couldn't load it".to_string()))?;
         let canvas: web_sys::HtmlCanvasElement = document
             .create_element("canvas")
-            .unwrap()
-            .dyn_into()
-            .unwrap();
+            .ok()
+            .and_then(|element| element.dyn_into().ok())
+            .ok_or_else(|| decode_error("couldn't create a canvas to draw it to".to_string()))?;
 
         canvas.set_width(img.width());
         canvas.set_height(img.height());
         let ctx: web_sys::CanvasRenderingContext2d = canvas
             .get_context("2d")
-            .unwrap()
-            .unwrap()
-            .dyn_into()
-            .unwrap();
+            .ok()
+            .flatten()
+            .and_then(|context| context.dyn_into().ok())
+            .ok_or_else(|| decode_error("the canvas has no 2D context".to_string()))?;
         ctx.draw_image_with_html_image_element(&img, 0.0, 0.0)
-            .unwrap();
+            .map_err(|error| decode_error(format!("drawing it failed: {:?}", error)))?;
         let image_data = ctx
             .get_image_data(0.0, 0.0, img.width() as f64, img.height() as f64)
-            .unwrap();
+            .map_err(|error| decode_error(format!("reading its pixels failed: {:?}", error)))?;
         let data = image_data.data();
-        Self::from_bytes(
+        Ok(Self::from_bytes(
             device,
             queue,
             &data,
             img.width(),
             img.height(),
             label,
-        )
+        ))
+    }
+
+    /// Native counterpart of the browser decoding above, `uri` is a path relative to the asset
+    /// directory.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub async fn from_url(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        uri: &str,
+        label: &str,
+    ) -> Result<Self, LoadError> {
+        log::debug!("Loading texture {}", uri);
+        let bytes = crate::gltf_loader::load_binary(uri).await?;
+        Self::from_encoded(device, queue, &bytes, None, label).map_err(|error| {
+            LoadError::ImageDecode {
+                uri: uri.to_string(),
+                reason: error.to_string(),
+            }
+        })
+    }
+
+    /// Decodes a PNG or JPEG file in memory, e.g. an image embedded in a GLB. The format is
+    /// picked by `mime_type` if it's a known one, guessed from the data otherwise.
+    pub fn from_encoded(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        bytes: &[u8],
+        mime_type: Option<&str>,
+        label: &str,
+    ) -> image::ImageResult<Self> {
+        let img = match mime_type.and_then(image::ImageFormat::from_mime_type) {
+            Some(format) => image::load_from_memory_with_format(bytes, format)?,
+            None => image::load_from_memory(bytes)?,
+        }
+        .to_rgba8();
+        Ok(Self::from_bytes(
+            device,
+            queue,
+            &img,
+            img.width(),
+            img.height(),
+            label,
+        ))
     }
 
+    /// Uploads RGBA8 sRGB pixels with room for a full mip chain. Only the first level is written,
+    /// the rest stays black until it's generated with `MipBlitter::record`.
     pub fn from_bytes(
         device: &wgpu::Device,
         queue: &wgpu::Queue,
@@ -66,15 +126,29 @@ impl Texture {
 
         let texture = device.create_texture(&wgpu::TextureDescriptor {
             size: texture_size,
-            mip_level_count: 1,
+            mip_level_count: full_mip_level_count(width, height),
             dimension: wgpu::TextureDimension::D2,
             format: wgpu::TextureFormat::Rgba8UnormSrgb,
             label: Some(label),
             sample_count: 1,
-            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+            // Copied from when packed into the texture atlas, the mip levels are rendered
+            usage: wgpu::TextureUsages::TEXTURE_BINDING
+                | wgpu::TextureUsages::COPY_DST
+                | wgpu::TextureUsages::COPY_SRC
+                | wgpu::TextureUsages::RENDER_ATTACHMENT,
             view_formats: &[wgpu::TextureFormat::Rgba8UnormSrgb],
         });
 
+        assert_eq!(
+            bytes.len(),
+            4 * width as usize * height as usize,
+            "{} isn't {}x{} RGBA pixels",
+            label,
+            width,
+            height
+        );
+        // Unlike copies between buffers and textures, write_texture takes tightly packed rows.
+        // Anything copied into a buffer has to go through `readback`, which pads them.
         queue.write_texture(
             wgpu::ImageCopyTexture {
                 texture: &texture,
@@ -82,7 +156,7 @@ impl Texture {
                 origin: wgpu::Origin3d::ZERO,
                 mip_level: 0,
             },
-            &bytes,
+            bytes,
             wgpu::ImageDataLayout {
                 offset: 0,
                 bytes_per_row: Some(4 * width),
@@ -106,6 +180,7 @@ impl Texture {
             texture,
             view,
             sampler,
+            depth_sampler: None,
         }
     }
 
@@ -119,11 +194,14 @@ impl Texture {
     }
 
     pub const DEPTH_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Depth32Float; // 1.
+    pub const DEPTH_STENCIL_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Depth24PlusStencil8;
+    pub const HDR_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
 
     pub fn create_depth_texture(
         device: &wgpu::Device,
         width: u32,
         height: u32,
+        format: wgpu::TextureFormat,
         compare: Option<wgpu::CompareFunction>,
         label: &str,
     ) -> Self {
@@ -138,9 +216,9 @@ impl Texture {
             mip_level_count: 1,
             sample_count: 1,
             dimension: wgpu::TextureDimension::D2,
-            format: Self::DEPTH_FORMAT,
+            format,
             usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
-            view_formats: &[wgpu::TextureFormat::Depth32Float],
+            view_formats: &[format],
         };
         let texture = device.create_texture(&desc);
 
@@ -157,13 +235,33 @@ impl Texture {
             lod_max_clamp: 100.0,
             ..Default::default()
         });
+        let depth_sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some(format!("{} depth sampler", label).as_str()),
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Nearest,
+            min_filter: wgpu::FilterMode::Nearest,
+            mipmap_filter: wgpu::FilterMode::Nearest,
+            ..Default::default()
+        });
 
         Self {
             texture,
             view,
             sampler,
+            depth_sampler: Some(depth_sampler),
         }
     }
+
+    /// View of only the depth aspect, which is what shaders have to bind when the texture also
+    /// has a stencil aspect. `view` covers both and is the one to use as an attachment.
+    pub fn create_depth_only_view(&self) -> wgpu::TextureView {
+        self.texture.create_view(&wgpu::TextureViewDescriptor {
+            aspect: wgpu::TextureAspect::DepthOnly,
+            ..Default::default()
+        })
+    }
     
     pub fn create_target_texture(
         device: &wgpu::Device,
@@ -201,6 +299,76 @@ impl Texture {
             texture,
             view,
             sampler,
+            depth_sampler: None,
+        }
+    }
+
+    pub fn create_hdr_target_texture(
+        device: &wgpu::Device,
+        width: u32,
+        height: u32,
+        label: &str,
+    ) -> Self {
+        let descriptor = Self::target_descriptor(width, height, Self::HDR_FORMAT, label);
+        Self::from_descriptor(device, &descriptor)
+    }
+
+    /// Single level 2D texture that's rendered to and then sampled, like the intermediate targets
+    /// of a frame.
+    pub fn target_descriptor(
+        width: u32,
+        height: u32,
+        format: wgpu::TextureFormat,
+        label: &str,
+    ) -> wgpu::TextureDescriptor<'_> {
+        wgpu::TextureDescriptor {
+            label: Some(label),
+            size: wgpu::Extent3d { width, height, depth_or_array_layers: 1 },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+            view_formats: &[],
+        }
+    }
+
+    /// Creates the texture with a clamped linear sampler. Depth formats also get the nearest
+    /// `depth_sampler`, like `create_depth_texture` without a compare function.
+    pub fn from_descriptor(device: &wgpu::Device, descriptor: &wgpu::TextureDescriptor) -> Self {
+        let texture = device.create_texture(descriptor);
+        let label = descriptor.label.unwrap_or("Texture");
+
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some(format!("{} sampler", label).as_str()),
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Nearest,
+            ..Default::default()
+        });
+        let depth_sampler = descriptor.format.has_depth_aspect().then(|| {
+            device.create_sampler(&wgpu::SamplerDescriptor {
+                label: Some(format!("{} depth sampler", label).as_str()),
+                address_mode_u: wgpu::AddressMode::ClampToEdge,
+                address_mode_v: wgpu::AddressMode::ClampToEdge,
+                address_mode_w: wgpu::AddressMode::ClampToEdge,
+                mag_filter: wgpu::FilterMode::Nearest,
+                min_filter: wgpu::FilterMode::Nearest,
+                mipmap_filter: wgpu::FilterMode::Nearest,
+                ..Default::default()
+            })
+        });
+
+        Self {
+            texture,
+            view,
+            sampler,
+            depth_sampler,
         }
     }
 }
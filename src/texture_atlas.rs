@@ -0,0 +1,135 @@
+use std::collections::HashMap;
+
+use crate::texture::Texture;
+
+/// Width and height of the atlas, textures that don't fit any more stay on their own
+const ATLAS_SIZE: u32 = 2048;
+/// Empty texels between packed textures
+const PADDING: u32 = 1;
+
+/// Scale and offset mapping texture coordinates onto a texture's rectangle in the atlas, laid out
+/// as `MaterialData`'s uv transforms. The identity leaves texture coordinates untouched.
+pub const IDENTITY_UV_TRANSFORM: [f32; 4] = [1.0, 1.0, 0.0, 0.0];
+
+/// Small textures packed into one, so materials using them bind the same texture. Only textures
+/// that are clamped to their edges can be packed, repeating would sample the neighbours.
+pub struct TextureAtlas {
+    pub texture: Texture,
+    /// Uv transform of each packed image, keyed by glTF image index
+    placements: HashMap<usize, [f32; 4]>,
+}
+
+impl TextureAtlas {
+    /// Shelf packs `candidates`, indices into `images`, tallest first. Returns `None` if nothing
+    /// could be packed.
+    pub fn pack(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        images: &[Texture],
+        candidates: &[usize],
+    ) -> Option<Self> {
+        let mut candidates = candidates.to_vec();
+        candidates.sort_by_key(|&index| std::cmp::Reverse(images[index].texture.height()));
+
+        let mut origins = Vec::new();
+        let (mut shelf_x, mut shelf_y, mut shelf_height) = (0, 0, 0);
+        for index in candidates {
+            let (width, height) = (
+                images[index].texture.width(),
+                images[index].texture.height(),
+            );
+            if shelf_x + width > ATLAS_SIZE {
+                shelf_x = 0;
+                shelf_y += shelf_height + PADDING;
+                shelf_height = 0;
+            }
+            if width > ATLAS_SIZE || shelf_y + height > ATLAS_SIZE {
+                continue;
+            }
+            origins.push((index, shelf_x, shelf_y));
+            shelf_x += width + PADDING;
+            shelf_height = shelf_height.max(height);
+        }
+        if origins.is_empty() {
+            return None;
+        }
+
+        let format = wgpu::TextureFormat::Rgba8UnormSrgb;
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Texture atlas"),
+            size: wgpu::Extent3d {
+                width: ATLAS_SIZE,
+                height: ATLAS_SIZE,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+            view_formats: &[format],
+        });
+
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Texture atlas encoder"),
+        });
+        let mut placements = HashMap::new();
+        for &(index, x, y) in &origins {
+            let source = &images[index].texture;
+            encoder.copy_texture_to_texture(
+                source.as_image_copy(),
+                wgpu::ImageCopyTexture {
+                    texture: &texture,
+                    mip_level: 0,
+                    origin: wgpu::Origin3d { x, y, z: 0 },
+                    aspect: wgpu::TextureAspect::All,
+                },
+                source.size(),
+            );
+            // Inset by half a texel, so bilinear filtering at the edges never reaches the
+            // neighbours and behaves like clamping to the edge
+            let atlas_size = ATLAS_SIZE as f32;
+            placements.insert(
+                index,
+                [
+                    (source.width() as f32 - 1.0) / atlas_size,
+                    (source.height() as f32 - 1.0) / atlas_size,
+                    (x as f32 + 0.5) / atlas_size,
+                    (y as f32 + 0.5) / atlas_size,
+                ],
+            );
+        }
+        queue.submit(std::iter::once(encoder.finish()));
+
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("Texture atlas sampler"),
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+
+        Some(Self {
+            texture: Texture {
+                texture,
+                view,
+                sampler,
+                depth_sampler: None,
+            },
+            placements,
+        })
+    }
+
+    /// Uv transform of a packed image, `None` if it wasn't packed.
+    pub fn placement(&self, image_index: usize) -> Option<[f32; 4]> {
+        self.placements.get(&image_index).copied()
+    }
+
+    pub fn packed_count(&self) -> usize {
+        self.placements.len()
+    }
+}
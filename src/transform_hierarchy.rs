@@ -0,0 +1,105 @@
+use cgmath::{Matrix4, SquareMatrix};
+
+struct HierarchyNode {
+    parent: Option<usize>,
+    children: Vec<usize>,
+    local_transform: Matrix4<f32>,
+    world_transform: Matrix4<f32>,
+    dirty: bool,
+}
+
+/// Local and world transforms of a node tree. Changing a node only marks it and everything
+/// below it, `update` then recomputes just the marked world transforms.
+#[derive(Default)]
+pub struct TransformHierarchy {
+    /// Parents always come before their children, so this is also the order to update them in
+    nodes: Vec<HierarchyNode>,
+    /// Nodes before this one are up to date
+    first_dirty: Option<usize>,
+}
+
+impl TransformHierarchy {
+    /// Adds a node below `parent`, which has to be added already, and returns its index. The
+    /// world transform is available right away.
+    pub fn add_node(&mut self, parent: Option<usize>, local_transform: Matrix4<f32>) -> usize {
+        let index = self.nodes.len();
+        let parent_transform = match parent {
+            Some(parent) => {
+                self.nodes[parent].children.push(index);
+                self.world_transform(parent)
+            }
+            None => Matrix4::identity(),
+        };
+        self.nodes.push(HierarchyNode {
+            parent,
+            children: Vec::new(),
+            local_transform,
+            world_transform: parent_transform * local_transform,
+            dirty: false,
+        });
+        index
+    }
+
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    /// As of the last `update`.
+    pub fn world_transform(&self, node: usize) -> Matrix4<f32> {
+        self.nodes[node].world_transform
+    }
+
+    /// Replaces the transform of `node` relative to its parent, takes effect with the next
+    /// `update`.
+    pub fn set_local_transform(&mut self, node: usize, local_transform: Matrix4<f32>) {
+        self.nodes[node].local_transform = local_transform;
+        let mut stack = vec![node];
+        while let Some(index) = stack.pop() {
+            let node = &mut self.nodes[index];
+            // An already marked node had its descendants marked along with it
+            if !std::mem::replace(&mut node.dirty, true) {
+                stack.extend_from_slice(&node.children);
+            }
+        }
+        self.first_dirty = Some(self.first_dirty.map_or(node, |first| first.min(node)));
+    }
+
+    /// Recomputes the world transforms of every marked node and returns their indices, in
+    /// hierarchy order.
+    pub fn update(&mut self) -> Vec<usize> {
+        let Some(first_dirty) = self.first_dirty.take() else {
+            return Vec::new();
+        };
+        let mut changed = Vec::new();
+        for index in first_dirty..self.nodes.len() {
+            if std::mem::take(&mut self.nodes[index].dirty) {
+                self.recompute(index);
+                changed.push(index);
+            }
+        }
+        changed
+    }
+
+    /// Recomputes every world transform whether it's marked or not, what `update` saves.
+    pub fn update_all(&mut self) {
+        for index in 0..self.nodes.len() {
+            self.nodes[index].dirty = false;
+            self.recompute(index);
+        }
+        self.first_dirty = None;
+    }
+
+    fn recompute(&mut self, index: usize) {
+        let parent_transform = self.nodes[index]
+            .parent
+            .map_or(Matrix4::identity(), |parent| {
+                self.nodes[parent].world_transform
+            });
+        let node = &mut self.nodes[index];
+        node.world_transform = parent_transform * node.local_transform;
+    }
+}
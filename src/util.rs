@@ -1,4 +1,150 @@
+/// Compile time check that a Pod struct matches the uniform buffer layout of its WGSL counterpart.
+/// Takes the expected size and the byte offset of every field as laid out by the WGSL rules. Tests
+/// compare the same numbers with the layout naga computes for the shader, see
+/// `assert_wgsl_layout`.
 #[macro_export]
-macro_rules! console_log {
-    ($($t:tt)*) => (console::log_1(&format_args!($($t)*).to_string().as_str().into()))
-}
\ No newline at end of file
+macro_rules! assert_uniform_layout {
+    ($type:ty, size: $size:expr, { $($field:ident: $offset:expr),* $(,)? }) => {
+        const _: () = {
+            assert!(std::mem::size_of::<$type>() == $size);
+            assert!(std::mem::size_of::<$type>() % 16 == 0);
+            $(assert!(std::mem::offset_of!($type, $field) == $offset);)*
+        };
+
+        #[cfg(test)]
+        impl $crate::util::UniformLayout for $type {
+            const FIELDS: &'static [(&'static str, u32)] = &[$((stringify!($field), $offset)),*];
+        }
+    };
+}
+
+/// First adapter that can run the voxelization, the one `test_device` opens.
+#[cfg(test)]
+pub fn test_adapter() -> Option<wgpu::Adapter> {
+    let instance = wgpu::Instance::new(wgpu::InstanceDescriptor::default());
+    let adapter = instance
+        .enumerate_adapters(wgpu::Backends::all())
+        .find(crate::is_adapter_supported);
+    if adapter.is_none() {
+        eprintln!("No supported adapter, skipping the test");
+    }
+    adapter
+}
+
+/// Device for tests that render or compute. `None` if there's no adapter, e.g. on a machine
+/// without a GPU or software rasterizer, the test should return early then.
+#[cfg(test)]
+pub fn test_device() -> Option<(wgpu::Device, wgpu::Queue)> {
+    let adapter = test_adapter()?;
+    pollster::block_on(adapter.request_device(&wgpu::DeviceDescriptor::default(), None)).ok()
+}
+
+/// Fields and offsets of a struct passed to `assert_uniform_layout`, in declaration order.
+#[cfg(test)]
+pub trait UniformLayout: Sized {
+    const FIELDS: &'static [(&'static str, u32)];
+}
+
+/// Checks that the struct `wgsl_name` in `source` has the members of `T` at the same offsets and
+/// the same size, as laid out by naga. A trailing runtime sized array, like the lights following
+/// their header, has to start right after `T`.
+#[cfg(test)]
+pub fn assert_wgsl_layout<T: UniformLayout>(source: &str, wgsl_name: &str) {
+    let module = naga::front::wgsl::parse_str(source)
+        .unwrap_or_else(|error| panic!("{}", error.emit_to_string(source)));
+    let ty = module
+        .types
+        .iter()
+        .map(|(_, ty)| ty)
+        .find(|ty| ty.name.as_deref() == Some(wgsl_name))
+        .unwrap_or_else(|| panic!("No struct {} in the shader", wgsl_name));
+    let naga::TypeInner::Struct { members, span } = &ty.inner else {
+        panic!("{} isn't a struct", wgsl_name);
+    };
+    let (members, size) = match members.last() {
+        Some(last)
+            if matches!(
+                module.types[last.ty].inner,
+                naga::TypeInner::Array {
+                    size: naga::ArraySize::Dynamic,
+                    ..
+                }
+            ) =>
+        {
+            (&members[..members.len() - 1], last.offset)
+        }
+        _ => (&members[..], *span),
+    };
+
+    let wgsl_fields: Vec<(&str, u32)> = members
+        .iter()
+        .map(|member| (member.name.as_deref().unwrap_or(""), member.offset))
+        .collect();
+    assert_eq!(
+        wgsl_fields,
+        T::FIELDS,
+        "Fields of {} don't match {}",
+        std::any::type_name::<T>(),
+        wgsl_name
+    );
+    assert_eq!(
+        size as usize,
+        std::mem::size_of::<T>(),
+        "Size of {} doesn't match {}",
+        std::any::type_name::<T>(),
+        wgsl_name
+    );
+}
+
+/// Value of the query parameter `name` in the page URL, e.g. `debug` for `?log=debug`.
+#[cfg(target_arch = "wasm32")]
+pub fn query_param(name: &str) -> Option<String> {
+    let search = web_sys::window()?.location().search().ok()?;
+    search.trim_start_matches('?').split('&').find_map(|pair| {
+        pair.strip_prefix(name)?
+            .strip_prefix('=')
+            .map(str::to_string)
+    })
+}
+
+/// Natively the query parameters are passed as command line arguments, e.g.
+/// `cargo run -- log=debug bench=1`.
+#[cfg(not(target_arch = "wasm32"))]
+pub fn query_param(name: &str) -> Option<String> {
+    std::env::args().skip(1).find_map(|arg| {
+        arg.strip_prefix(name)?
+            .strip_prefix('=')
+            .map(str::to_string)
+    })
+}
+
+/// Where assets are read from natively, relative paths resolve like URLs relative to the page
+/// do on the web. Defaults to the `client` directory, `assets=<dir>` changes it.
+#[cfg(not(target_arch = "wasm32"))]
+pub fn asset_path(path: &str) -> std::path::PathBuf {
+    let asset_dir = query_param("assets").unwrap_or_else(|| "client".to_string());
+    std::path::Path::new(&asset_dir).join(path)
+}
+
+/// Writes `text` to the browser console, or to stdout natively. Meant for output the user asked
+/// for, unlike logging it can't be filtered away.
+pub fn print_to_console(text: &str) {
+    #[cfg(target_arch = "wasm32")]
+    web_sys::console::log_1(&text.into());
+    #[cfg(not(target_arch = "wasm32"))]
+    println!("{}", text);
+}
+
+/// Converts the bits of an IEEE half float, like the texels of `Rgba16Float` textures read back
+/// from the GPU.
+pub fn f16_to_f32(bits: u16) -> f32 {
+    let sign = if bits & 0x8000 != 0 { -1.0 } else { 1.0 };
+    let exponent = ((bits >> 10) & 0x1f) as i32;
+    let mantissa = (bits & 0x3ff) as f32;
+    sign * match exponent {
+        0 => mantissa * 2f32.powi(-24),
+        0x1f if mantissa == 0.0 => f32::INFINITY,
+        0x1f => f32::NAN,
+        _ => (1.0 + mantissa / 1024.0) * 2f32.powi(exponent - 15),
+    }
+}
@@ -0,0 +1,189 @@
+use wgpu::util::DeviceExt;
+
+use crate::voxel_texture::VoxelTexture;
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct VoxelDebugParams {
+    // Ahead of `level`, a vec3 at the end would be aligned to 16 bytes in WGSL
+    filler: [u32; 3],
+    level: u32,
+}
+
+crate::assert_uniform_layout!(VoxelDebugParams, size: 16, {
+    filler: 0,
+    level: 12,
+});
+
+/// Shows the voxel grid itself instead of the shaded scene, to check what the voxelization
+/// captured. Every pixel walks the cells of one mip level and shows the radiance of the first
+/// occupied one.
+pub struct VoxelDebug {
+    pipeline: wgpu::RenderPipeline,
+    bind_group: wgpu::BindGroup,
+    params_buffer: wgpu::Buffer,
+}
+
+impl VoxelDebug {
+    pub fn new(
+        device: &wgpu::Device,
+        voxel_texture: &VoxelTexture,
+        voxel_grid_info_buffer: &wgpu::Buffer,
+        camera_bind_group_layout: &wgpu::BindGroupLayout,
+        color_format: wgpu::TextureFormat,
+    ) -> Self {
+        let params_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Voxel debug params buffer"),
+            contents: bytemuck::cast_slice(&[VoxelDebugParams {
+                level: 0,
+                filler: [0; 3],
+            }]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        let uniform_entry = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Uniform,
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Voxel debug bind group layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                        view_dimension: wgpu::TextureViewDimension::D3,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                uniform_entry(1),
+                uniform_entry(2),
+            ],
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Voxel debug bind group"),
+            layout: &bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&voxel_texture.main_view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: voxel_grid_info_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: params_buffer.as_entire_binding(),
+                },
+            ],
+        });
+
+        let module = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Voxel debug shader module"),
+            source: wgpu::ShaderSource::Wgsl(
+                concat!(
+                    include_str!("shaders/common.wgsl"),
+                    include_str!("shaders/fullscreen.wgsl"),
+                    include_str!("shaders/voxel_debug.wgsl")
+                )
+                .into(),
+            ),
+        });
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Voxel debug pipeline layout"),
+            bind_group_layouts: &[camera_bind_group_layout, &bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Voxel debug pipeline"),
+            layout: Some(&pipeline_layout),
+            vertex: wgpu::VertexState {
+                module: &module,
+                entry_point: "vs_fullscreen",
+                buffers: &[],
+            },
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+            fragment: Some(wgpu::FragmentState {
+                module: &module,
+                entry_point: "fs_voxels",
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: color_format,
+                    blend: None,
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+            }),
+            multiview: None,
+        });
+
+        Self {
+            pipeline,
+            bind_group,
+            params_buffer,
+        }
+    }
+
+    /// Mip level of the voxel texture the grid is shown at, 0 is the finest.
+    pub fn set_level(&self, queue: &wgpu::Queue, level: u32) {
+        queue.write_buffer(
+            &self.params_buffer,
+            0,
+            bytemuck::cast_slice(&[VoxelDebugParams {
+                level,
+                filler: [0; 3],
+            }]),
+        );
+    }
+
+    /// Replaces everything in `color_view` with the voxel grid.
+    pub fn record(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        camera_bind_group: &wgpu::BindGroup,
+        color_view: &wgpu::TextureView,
+    ) {
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Voxel debug render pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: color_view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: true,
+                },
+            })],
+            depth_stencil_attachment: None,
+        });
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_bind_group(0, camera_bind_group, &[]);
+        render_pass.set_bind_group(1, &self.bind_group, &[]);
+        render_pass.draw(0..3, 0..1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn params_match_wgsl() {
+        crate::util::assert_wgsl_layout::<VoxelDebugParams>(
+            concat!(
+                include_str!("shaders/common.wgsl"),
+                include_str!("shaders/fullscreen.wgsl"),
+                include_str!("shaders/voxel_debug.wgsl")
+            ),
+            "VoxelDebugParams",
+        );
+    }
+}
@@ -1,21 +1,54 @@
-use wgpu::TextureView;
+use wgpu::{util::DeviceExt, TextureView};
+
+use crate::{readback, util::f16_to_f32};
+
+/// Mip level the distance field is built from, the full grid would need gigabytes of seeds
+pub const SDF_MIP_LEVEL: u32 = 2;
+const SDF_WORKGROUP_SIZE: u32 = 4;
+/// Has to match the workgroup size of mipmap_3d.wgsl, mipmap_anisotropic.wgsl and voxel_clear.wgsl
+const MIPMAP_WORKGROUP_SIZE: u32 = 4;
 
 pub struct VoxelTexture {
+    texture: wgpu::Texture,
     views: Vec<wgpu::TextureView>,
     pub main_view: wgpu::TextureView,
     pub sampler: wgpu::Sampler,
+    /// Distance from each cell of `SDF_MIP_LEVEL` to the closest occupied one, in cells of that
+    /// level. `R32Float`, because `R16Float` can't be written from a compute shader.
+    pub sdf_view: wgpu::TextureView,
     mip_level_count: u32,
     pipelines: Vec<wgpu::ComputePipeline>,
     bind_groups: Vec<wgpu::BindGroup>,
+    /// Directional levels for cone tracing, see mipmap_anisotropic.wgsl. Level `n` covers the
+    /// grid at the resolution of level `n + 1` of the isotropic chain, the base level stays
+    /// isotropic.
+    anisotropic_texture: wgpu::Texture,
+    pub anisotropic_view: wgpu::TextureView,
+    anisotropic_pipelines: [wgpu::ComputePipeline; 2],
+    /// Writing each directional level, the first one is read from the isotropic base level
+    anisotropic_bind_groups: Vec<wgpu::BindGroup>,
+    sdf_size: u32,
+    sdf_seed_pipeline: wgpu::ComputePipeline,
+    sdf_jump_pipeline: wgpu::ComputePipeline,
+    sdf_resolve_pipeline: wgpu::ComputePipeline,
+    /// Seed pass, then one per jump step, then the resolve pass
+    sdf_bind_groups: Vec<wgpu::BindGroup>,
+    clear_pipeline: wgpu::ComputePipeline,
+    clear_bind_group: wgpu::BindGroup,
 }
 
 impl VoxelTexture {
+    /// `max_mip_levels` caps the mip chain, it always reaches past `SDF_MIP_LEVEL` though.
     pub fn new(
         device: &wgpu::Device,
         size: wgpu::Extent3d,
+        max_mip_levels: Option<u32>,
         label: &str,
     ) -> Self {
-        let mip_level_count = size.max_mips(wgpu::TextureDimension::D3);
+        let full_mip_level_count = size.max_mips(wgpu::TextureDimension::D3);
+        let mip_level_count = max_mip_levels.map_or(full_mip_level_count, |max| {
+            max.clamp(SDF_MIP_LEVEL + 1, full_mip_level_count)
+        });
 
         let texture = device.create_texture(&wgpu::TextureDescriptor {
             label: Some(label),
@@ -24,7 +57,9 @@ impl VoxelTexture {
             sample_count: 1,
             dimension: wgpu::TextureDimension::D3,
             format: wgpu::TextureFormat::Rgba16Float,
-            usage: wgpu::TextureUsages::STORAGE_BINDING | wgpu::TextureUsages::TEXTURE_BINDING,
+            usage: wgpu::TextureUsages::STORAGE_BINDING
+                | wgpu::TextureUsages::TEXTURE_BINDING
+                | wgpu::TextureUsages::COPY_SRC,
             view_formats: &[wgpu::TextureFormat::Rgba16Float],
         });
 
@@ -45,9 +80,11 @@ impl VoxelTexture {
 
         let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
             label: Some(format!("{} sampler", label).as_str()),
-            address_mode_u: wgpu::AddressMode::Repeat,
-            address_mode_v: wgpu::AddressMode::Repeat,
-            address_mode_w: wgpu::AddressMode::Repeat,
+            // Cones leaving the grid are handled in the shader, repeating would bleed light in
+            // from the opposite side of the scene
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
             mag_filter: wgpu::FilterMode::Linear,
             min_filter: wgpu::FilterMode::Linear,
             mipmap_filter: wgpu::FilterMode::Linear,
@@ -120,18 +157,345 @@ impl VoxelTexture {
             })
             .collect();
 
+        // The six directions are packed into 3x2 tiles of half the grid's resolution, so the
+        // shaders see a single texture and stay below the sampled texture limit
+        let anisotropic_texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some(format!("{} anisotropic", label).as_str()),
+            size: wgpu::Extent3d {
+                width: size.width / 2 * 3,
+                height: size.height / 2 * 2,
+                depth_or_array_layers: size.depth_or_array_layers / 2,
+            },
+            mip_level_count: mip_level_count - 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D3,
+            format: wgpu::TextureFormat::Rgba16Float,
+            usage: wgpu::TextureUsages::STORAGE_BINDING | wgpu::TextureUsages::TEXTURE_BINDING,
+            view_formats: &[wgpu::TextureFormat::Rgba16Float],
+        });
+        let anisotropic_views: Vec<TextureView> = (0..mip_level_count - 1)
+            .map(|i| {
+                anisotropic_texture.create_view(&wgpu::TextureViewDescriptor {
+                    label: Some(format!("{} anisotropic view mip #{}", label, i).as_str()),
+                    format: Some(wgpu::TextureFormat::Rgba16Float),
+                    dimension: Some(wgpu::TextureViewDimension::D3),
+                    aspect: wgpu::TextureAspect::All,
+                    base_mip_level: i,
+                    mip_level_count: Some(1),
+                    base_array_layer: 0,
+                    array_layer_count: Some(1),
+                })
+            })
+            .collect();
+        let anisotropic_view =
+            anisotropic_texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let anisotropic_bind_groups = (0..mip_level_count - 1)
+            .map(|i| {
+                let input = match i {
+                    0 => &views[0],
+                    _ => &anisotropic_views[i as usize - 1],
+                };
+                device.create_bind_group(&wgpu::BindGroupDescriptor {
+                    label: Some(format!("{} anisotropic bind group #{}", label, i).as_str()),
+                    layout: &bind_group_layout,
+                    entries: &[
+                        wgpu::BindGroupEntry {
+                            binding: 0,
+                            resource: wgpu::BindingResource::TextureView(input),
+                        },
+                        wgpu::BindGroupEntry {
+                            binding: 1,
+                            resource: wgpu::BindingResource::TextureView(
+                                &anisotropic_views[i as usize],
+                            ),
+                        },
+                    ],
+                })
+            })
+            .collect();
+        let anisotropic_module = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some(format!("{} anisotropic shader module", label).as_str()),
+            source: wgpu::ShaderSource::Wgsl(
+                include_str!("shaders/mipmap_anisotropic.wgsl").into(),
+            ),
+        });
+        let anisotropic_pipeline_layout =
+            device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some(format!("{} anisotropic pipeline layout", label).as_str()),
+                bind_group_layouts: &[&bind_group_layout],
+                push_constant_ranges: &[],
+            });
+        let anisotropic_pipelines = ["from_isotropic", "from_anisotropic"].map(|entry_point| {
+            device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                label: Some(format!("{} anisotropic {} pipeline", label, entry_point).as_str()),
+                layout: Some(&anisotropic_pipeline_layout),
+                module: &anisotropic_module,
+                entry_point,
+            })
+        });
+
+        let clear_module = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some(format!("{} clear shader module", label).as_str()),
+            source: wgpu::ShaderSource::Wgsl(include_str!("shaders/voxel_clear.wgsl").into()),
+        });
+        let clear_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                label: Some(format!("{} clear bind group layout", label).as_str()),
+                entries: &[wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::StorageTexture {
+                        access: wgpu::StorageTextureAccess::WriteOnly,
+                        format: wgpu::TextureFormat::Rgba16Float,
+                        view_dimension: wgpu::TextureViewDimension::D3,
+                    },
+                    count: None,
+                }],
+            });
+        let clear_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some(format!("{} clear bind group", label).as_str()),
+            layout: &clear_bind_group_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::TextureView(&views[0]),
+            }],
+        });
+        let clear_pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some(format!("{} clear pipeline layout", label).as_str()),
+            bind_group_layouts: &[&clear_bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let clear_pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some(format!("{} clear pipeline", label).as_str()),
+            layout: Some(&clear_pipeline_layout),
+            module: &clear_module,
+            entry_point: "comp_main",
+        });
+
+        let sdf_size = (size.width >> SDF_MIP_LEVEL).max(1);
+        let sdf_extent = wgpu::Extent3d {
+            width: sdf_size,
+            height: sdf_size,
+            depth_or_array_layers: sdf_size,
+        };
+        let create_sdf_texture = |name: &str, format: wgpu::TextureFormat| {
+            device
+                .create_texture(&wgpu::TextureDescriptor {
+                    label: Some(format!("{} {}", label, name).as_str()),
+                    size: sdf_extent,
+                    mip_level_count: 1,
+                    sample_count: 1,
+                    dimension: wgpu::TextureDimension::D3,
+                    format,
+                    usage: wgpu::TextureUsages::STORAGE_BINDING
+                        | wgpu::TextureUsages::TEXTURE_BINDING,
+                    view_formats: &[format],
+                })
+                .create_view(&wgpu::TextureViewDescriptor::default())
+        };
+        let seed_views = [
+            create_sdf_texture("SDF seeds A", wgpu::TextureFormat::R32Uint),
+            create_sdf_texture("SDF seeds B", wgpu::TextureFormat::R32Uint),
+        ];
+        let sdf_view = create_sdf_texture("SDF", wgpu::TextureFormat::R32Float);
+
+        let sdf_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                label: Some(format!("{} SDF bind group layout", label).as_str()),
+                entries: &[
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 0,
+                        visibility: wgpu::ShaderStages::COMPUTE,
+                        ty: wgpu::BindingType::Texture {
+                            sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                            view_dimension: wgpu::TextureViewDimension::D3,
+                            multisampled: false,
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 1,
+                        visibility: wgpu::ShaderStages::COMPUTE,
+                        ty: wgpu::BindingType::Texture {
+                            sample_type: wgpu::TextureSampleType::Uint,
+                            view_dimension: wgpu::TextureViewDimension::D3,
+                            multisampled: false,
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 2,
+                        visibility: wgpu::ShaderStages::COMPUTE,
+                        ty: wgpu::BindingType::StorageTexture {
+                            access: wgpu::StorageTextureAccess::WriteOnly,
+                            format: wgpu::TextureFormat::R32Uint,
+                            view_dimension: wgpu::TextureViewDimension::D3,
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 3,
+                        visibility: wgpu::ShaderStages::COMPUTE,
+                        ty: wgpu::BindingType::StorageTexture {
+                            access: wgpu::StorageTextureAccess::WriteOnly,
+                            format: wgpu::TextureFormat::R32Float,
+                            view_dimension: wgpu::TextureViewDimension::D3,
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 4,
+                        visibility: wgpu::ShaderStages::COMPUTE,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                ],
+            });
+
+        // Jump steps halve from half the grid down to a single cell, the seeds ping-pong between
+        // the two textures and `seed_views[read]` holds the result after each pass
+        let jump_steps: Vec<u32> = std::iter::successors(Some(sdf_size / 2), |step| Some(step / 2))
+            .take_while(|&step| step > 0)
+            .collect();
+        let sdf_bind_group = |pass: usize, step: u32, read: usize| {
+            let step_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some(format!("{} SDF step buffer #{}", label, pass).as_str()),
+                contents: bytemuck::cast_slice(&[step, 0, 0, 0]),
+                usage: wgpu::BufferUsages::UNIFORM,
+            });
+            device.create_bind_group(&wgpu::BindGroupDescriptor {
+                label: Some(format!("{} SDF bind group #{}", label, pass).as_str()),
+                layout: &sdf_bind_group_layout,
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: wgpu::BindingResource::TextureView(
+                            &views[SDF_MIP_LEVEL as usize],
+                        ),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 1,
+                        resource: wgpu::BindingResource::TextureView(&seed_views[read]),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 2,
+                        resource: wgpu::BindingResource::TextureView(&seed_views[1 - read]),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 3,
+                        resource: wgpu::BindingResource::TextureView(&sdf_view),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 4,
+                        resource: step_buffer.as_entire_binding(),
+                    },
+                ],
+            })
+        };
+        // The seed pass writes A, so jump pass i reads A when i is even
+        let mut sdf_bind_groups = vec![sdf_bind_group(0, 0, 1)];
+        for (i, &step) in jump_steps.iter().enumerate() {
+            sdf_bind_groups.push(sdf_bind_group(i + 1, step, i % 2));
+        }
+        sdf_bind_groups.push(sdf_bind_group(
+            jump_steps.len() + 1,
+            0,
+            jump_steps.len() % 2,
+        ));
+
+        let sdf_module = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some(format!("{} SDF shader module", label).as_str()),
+            source: wgpu::ShaderSource::Wgsl(include_str!("shaders/voxel_sdf.wgsl").into()),
+        });
+        let sdf_pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some(format!("{} SDF pipeline layout", label).as_str()),
+            bind_group_layouts: &[&sdf_bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let sdf_pipeline = |entry_point: &str| {
+            device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                label: Some(format!("{} SDF {} pipeline", label, entry_point).as_str()),
+                layout: Some(&sdf_pipeline_layout),
+                module: &sdf_module,
+                entry_point,
+            })
+        };
+
         Self {
+            texture,
             views,
             sampler,
             main_view,
+            sdf_view,
             mip_level_count,
             pipelines,
             bind_groups,
+            anisotropic_texture,
+            anisotropic_view,
+            anisotropic_pipelines,
+            anisotropic_bind_groups,
+            sdf_size,
+            sdf_seed_pipeline: sdf_pipeline("seed"),
+            sdf_jump_pipeline: sdf_pipeline("jump_flood"),
+            sdf_resolve_pipeline: sdf_pipeline("resolve"),
+            sdf_bind_groups,
+            clear_pipeline,
+            clear_bind_group,
         }
     }
 
     pub fn get_mip_0(&self) -> &TextureView {
-        return &self.views[0];
+        &self.views[0]
+    }
+
+    /// Side length of mip `level` in cells.
+    pub fn level_size(&self, level: u32) -> u32 {
+        (self.texture.width() >> level).max(1)
+    }
+
+    pub fn mip_level_count(&self) -> u32 {
+        self.mip_level_count
+    }
+
+    /// Reads back mip `level` as linear RGBA, the cell at `(x, y, z)` is at
+    /// `x + size * (y + size * z)` with `size = level_size(level)`. Every cell takes 8 bytes,
+    /// level 0 of a full sized grid is only worth reading back for small test scenes.
+    pub async fn read_level(
+        &self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        level: u32,
+    ) -> Result<Vec<[f32; 4]>, wgpu::BufferAsyncError> {
+        // Every depth slice in one copy, the rows are unpadded once it's mapped
+        let depth = (self.texture.depth_or_array_layers() >> level).max(1);
+        let bytes = readback::read_texture(device, queue, &self.texture, level, 0..depth).await?;
+        Ok(bytes
+            .chunks_exact(8)
+            .map(|texel| {
+                let channel =
+                    |i: usize| f16_to_f32(u16::from_le_bytes([texel[2 * i], texel[2 * i + 1]]));
+                [channel(0), channel(1), channel(2), channel(3)]
+            })
+            .collect())
+    }
+
+    /// Zeroes the base level, voxelization only writes the occupied cells.
+    pub fn clear(&self, encoder: &mut wgpu::CommandEncoder) {
+        let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some("Voxel clear compute pass"),
+        });
+        let size = self.texture.size();
+        compute_pass.set_pipeline(&self.clear_pipeline);
+        compute_pass.set_bind_group(0, &self.clear_bind_group, &[]);
+        compute_pass.dispatch_workgroups(
+            size.width.div_ceil(MIPMAP_WORKGROUP_SIZE),
+            size.height.div_ceil(MIPMAP_WORKGROUP_SIZE),
+            size.depth_or_array_layers.div_ceil(MIPMAP_WORKGROUP_SIZE),
+        );
     }
 
     pub fn run_generate_mipmaps(
@@ -143,9 +507,51 @@ impl VoxelTexture {
         });
         
         (0..self.mip_level_count - 1).for_each(|i| {
+            let size = self
+                .texture
+                .size()
+                .mip_level_size(i + 1, wgpu::TextureDimension::D3);
             compute_pass.set_pipeline(&self.pipelines[i as usize]);
             compute_pass.set_bind_group(0, &self.bind_groups[i as usize], &[]);
-            compute_pass.dispatch_workgroups(1, 1, 1);
+            compute_pass.dispatch_workgroups(
+                size.width.div_ceil(MIPMAP_WORKGROUP_SIZE),
+                size.height.div_ceil(MIPMAP_WORKGROUP_SIZE),
+                size.depth_or_array_layers.div_ceil(MIPMAP_WORKGROUP_SIZE),
+            );
+        });
+
+        for (i, bind_group) in self.anisotropic_bind_groups.iter().enumerate() {
+            let size = self
+                .anisotropic_texture
+                .size()
+                .mip_level_size(i as u32, wgpu::TextureDimension::D3);
+            compute_pass.set_pipeline(&self.anisotropic_pipelines[(i > 0) as usize]);
+            compute_pass.set_bind_group(0, bind_group, &[]);
+            compute_pass.dispatch_workgroups(
+                size.width.div_ceil(MIPMAP_WORKGROUP_SIZE),
+                size.height.div_ceil(MIPMAP_WORKGROUP_SIZE),
+                size.depth_or_array_layers.div_ceil(MIPMAP_WORKGROUP_SIZE),
+            );
+        }
+    }
+
+    /// Rebuilds the distance field from the occupancy of `SDF_MIP_LEVEL`, so it has to run after
+    /// the mipmaps whenever the base level changes.
+    pub fn run_generate_sdf(&self, encoder: &mut wgpu::CommandEncoder) {
+        let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some("SDF compute pass"),
         });
+        let workgroups = self.sdf_size.div_ceil(SDF_WORKGROUP_SIZE);
+        let last = self.sdf_bind_groups.len() - 1;
+        for (i, bind_group) in self.sdf_bind_groups.iter().enumerate() {
+            let pipeline = match i {
+                0 => &self.sdf_seed_pipeline,
+                i if i == last => &self.sdf_resolve_pipeline,
+                _ => &self.sdf_jump_pipeline,
+            };
+            compute_pass.set_pipeline(pipeline);
+            compute_pass.set_bind_group(0, bind_group, &[]);
+            compute_pass.dispatch_workgroups(workgroups, workgroups, workgroups);
+        }
     }
 }